@@ -1,42 +1,52 @@
-use std::io::Read;
 use std::sync::{Arc, Mutex, atomic::Ordering};
-use std::thread;
 use std::time::Duration;
 use std::collections::VecDeque;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, ReadHalf};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 use crate::redis::{Redis, RedisCommand};
 use crate::redis::core::RedisResponse;
-use crate::resp::parse_resp;
-use crate::redis::replication::TcpStreamTrait;
+use crate::resp::Context;
+use crate::redis::replication::{Connection, TcpStreamTrait};
 use crate::redis::xread_handler::{XReadHandler, XReadRequest};
+use crate::redis::blocking;
 
 // ClientHandler should ideally be an actor.
 #[derive(Clone)]
 pub struct ClientHandler {
-    client: Arc<Mutex<Box<dyn TcpStreamTrait>>>,
-    redis: Arc<Mutex<Redis>>,
+    reader: Arc<tokio::sync::Mutex<ReadHalf<Box<dyn TcpStreamTrait>>>>,
+    client: Connection,
+    redis: Arc<Redis>,
     in_transaction: Arc<Mutex<bool>>,
+    selected_db: Arc<Mutex<usize>>,
     queued_commands: Arc<Mutex<VecDeque<RedisCommand>>>,
     ready: Arc<Mutex<bool>>,
-    shutdown: Arc<Mutex<bool>>,
+    shutdown: Arc<Notify>,
     is_redis_connection: bool,
 }
 
 impl ClientHandler {
-    pub fn new<T: TcpStreamTrait + 'static>(client: T, redis: Arc<Mutex<Redis>>) -> Self {
+    /// Must be called from within a tokio runtime, the client's writer task is spawned right away.
+    pub fn new<T: TcpStreamTrait + 'static>(client: T, redis: Arc<Redis>) -> Self {
         Self::new_with_connection_type(client, redis, false)
     }
 
-    pub fn new_redis_handler<T: TcpStreamTrait + 'static>(client: T, redis: Arc<Mutex<Redis>>) -> Self {
+    pub fn new_redis_handler<T: TcpStreamTrait + 'static>(client: T, redis: Arc<Redis>) -> Self {
         Self::new_with_connection_type(client, redis, true)
     }
 
-    fn new_with_connection_type<T: TcpStreamTrait + 'static>(client: T, redis: Arc<Mutex<Redis>>, is_redis_connection: bool) -> Self {
+    fn new_with_connection_type<T: TcpStreamTrait + 'static>(client: T, redis: Arc<Redis>, is_redis_connection: bool) -> Self {
+        let peer_addr = client.peer_addr().unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+        let (reader, writer) = tokio::io::split(Box::new(client) as Box<dyn TcpStreamTrait>);
         ClientHandler {
-            client: Arc::new(Mutex::new(Box::new(client) as Box<dyn TcpStreamTrait>)),
+            reader: Arc::new(tokio::sync::Mutex::new(reader)),
+            client: Connection::from_writer(peer_addr, writer),
             redis,
             in_transaction: Arc::new(Mutex::new(false)),
+            selected_db: Arc::new(Mutex::new(0)),
             queued_commands: Arc::new(Mutex::new(VecDeque::new())),
-            shutdown: Arc::new(Mutex::new(false)),
+            shutdown: Arc::new(Notify::new()),
             ready: Arc::new(Mutex::new(false)),
             is_redis_connection,
         }
@@ -51,35 +61,36 @@ impl ClientHandler {
     pub fn shutdown(&self) {
         #[cfg(debug_assertions)]
         println!("[ClientHandler::shutdown] Setting shutdown flag");
-        *self.shutdown.lock().unwrap() = true;
+        self.shutdown.notify_one();
     }
 
-    pub fn execute_command(&mut self, command: &RedisCommand) -> RedisResponse {
+    pub async fn execute_command(&mut self, command: &RedisCommand) -> RedisResponse {
         let raw_response = match &command {
             RedisCommand::XRead { keys, ids, block, count } => {
                 #[cfg(debug_assertions)]
                 println!("[ClientHandler::execute_command] Handling XREAD command");
-                
+
                 let request = XReadRequest {
-                    keys: keys.iter().map(|s| s.to_string()).collect(),
-                    ids: ids.iter().map(|s| s.to_string()).collect(),
+                    db: *self.selected_db.lock().unwrap(),
+                    keys: keys.clone(),
+                    ids: ids.clone(),
                     block: *block,
                     count: *count,
                 };
-                
+
                 let mut handler = XReadHandler::new(Arc::clone(&self.redis), request);
-                match handler.run_loop() {
+                match handler.run_loop().await {
                     Ok(results) => {
                         if results.is_empty() {
-                            RedisResponse::Error("-1".to_string())
+                            RedisResponse::NullArray
                         } else {
                             let mut streams = Vec::new();
                             for (stream_key, entries) in results {
                                 let mut stream_entries = Vec::new();
                                 for entry in entries {
                                     let mut entry_data = Vec::new();
-                                    entry_data.push(RedisResponse::BulkString(entry.id));
-                                    
+                                    entry_data.push(RedisResponse::BulkString(entry.id.into()));
+
                                     let mut fields = Vec::new();
                                     for (field, value) in entry.fields {
                                         fields.push(RedisResponse::BulkString(field));
@@ -88,11 +99,11 @@ impl ClientHandler {
                                     entry_data.push(RedisResponse::Array(fields));
                                     stream_entries.push(RedisResponse::Array(entry_data));
                                 }
-                                
-                                let mut stream_data = Vec::new();
-                                stream_data.push(RedisResponse::BulkString(stream_key));
-                                stream_data.push(RedisResponse::Array(stream_entries));
-                                streams.push(RedisResponse::Array(stream_data));
+
+                                streams.push(RedisResponse::Array(vec![
+                                    RedisResponse::BulkString(stream_key),
+                                    RedisResponse::Array(stream_entries),
+                                ]));
                             }
                             RedisResponse::Array(streams)
                         }
@@ -103,7 +114,7 @@ impl ClientHandler {
             RedisCommand::Multi => {
                 let mut in_transaction = self.in_transaction.lock().unwrap();
                 if *in_transaction {
-                    RedisResponse::Error("MULTI calls can not be nested".to_string())
+                    RedisResponse::Error("ERR MULTI calls can not be nested".to_string())
                 } else {
                     *in_transaction = true;
                     RedisResponse::Ok("OK".to_string())
@@ -112,39 +123,18 @@ impl ClientHandler {
             RedisCommand::Exec => {
                 let mut in_transaction = self.in_transaction.lock().unwrap();
                 if !*in_transaction {
-                    RedisResponse::Error("EXEC without MULTI".to_string())
+                    RedisResponse::Error("ERR EXEC without MULTI".to_string())
                 } else {
                     *in_transaction = false;
-                    let mut responses = Vec::new();
-                    let mut queued_commands = self.queued_commands.lock().unwrap();
-                    let mut client = self.client.lock().unwrap();
-                    
-                    while let Some(cmd) = queued_commands.pop_front() {
-                        let result = self.redis.lock()
-                            .expect("failed to lock redis")
-                            .execute_command(&cmd, Some(&mut client));
-                        match result {
-                            RedisResponse::Ok(response) => responses.push(RedisResponse::Ok(response)),
-                            RedisResponse::Error(e) => {
-                                println!("{}", e.trim());
-                                responses.push(RedisResponse::Error(e))
-                            },
-                            RedisResponse::Retry => continue,
-                            RedisResponse::Array(items) => responses.push(RedisResponse::Array(items)),
-                            RedisResponse::BulkString(s) => responses.push(RedisResponse::BulkString(s)),
-                            RedisResponse::Integer(i) => responses.push(RedisResponse::Integer(i)),
-                            RedisResponse::SimpleString(s) => responses.push(RedisResponse::SimpleString(s)),
-                            RedisResponse::NullBulkString => responses.push(RedisResponse::NullBulkString),
-                        }
-                    }
-
-                    RedisResponse::Array(responses)
+                    let queued_commands: Vec<RedisCommand> = self.queued_commands.lock().unwrap().drain(..).collect();
+                    let mut selected_db = self.selected_db.lock().unwrap();
+                    RedisResponse::Array(self.redis.execute_transaction(&mut selected_db, &queued_commands, Some(&self.client)))
                 }
             },
             RedisCommand::Discard => {
                 let mut in_transaction = self.in_transaction.lock().unwrap();
                 if !*in_transaction {
-                    RedisResponse::Error("DISCARD without MULTI".to_string())
+                    RedisResponse::Error("ERR DISCARD without MULTI".to_string())
                 } else {
                     *in_transaction = false;
                     self.queued_commands.lock().unwrap().clear();
@@ -156,224 +146,157 @@ impl ClientHandler {
                     let owned_command = command.clone();
                     self.queued_commands.lock().unwrap().push_back(owned_command);
                     RedisResponse::Ok("QUEUED".to_string())
+                } else if let Some((keys, timeout)) = command.blocking_keys() {
+                    // Inside MULTI the same commands run once and don't wait, like any other.
+                    let db = *self.selected_db.lock().unwrap();
+                    blocking::serve(&self.redis, db, command, keys, timeout, Some(&self.client)).await
                 } else {
                     // defer to redis.execute_command()
-                    let mut redis_guard = self.redis.lock().unwrap();
-                    let mut client_guard = self.client.lock().unwrap();
-                    let response = redis_guard.execute_command(command, Some(&mut *client_guard));
-                    match response {
-                        RedisResponse::Ok(r) => RedisResponse::Ok(r),
-                        RedisResponse::Error(e) => RedisResponse::Error(e),
-                        RedisResponse::Retry => RedisResponse::Retry,
-                        RedisResponse::Array(items) => RedisResponse::Array(items),
-                        RedisResponse::BulkString(s) => RedisResponse::BulkString(s),
-                        RedisResponse::Integer(i) => RedisResponse::Integer(i),
-                        RedisResponse::SimpleString(s) => RedisResponse::SimpleString(s),
-                        RedisResponse::NullBulkString => RedisResponse::NullBulkString,
-                    }
+                    self.redis.execute_command(&mut self.selected_db.lock().unwrap(), command, Some(&self.client))
                 }
             }
         };
         raw_response
     }
 
-    pub fn start(&mut self) -> std::thread::JoinHandle<()> {
-        // Clone self to move into the thread
-        let mut handler = self.clone();
-        
-        thread::spawn(move || {
-            #[cfg(debug_assertions)]
-            println!("[CLIENT] Starting new client handler");
-
-            let mut buffer = Vec::new();
-            let mut read_buffer = [0; 1024];
-
-            loop {
-
-                // after entering the first loop, before blocking on anything, set the ready flag
-                *handler.ready.lock().unwrap() = true;
-
-                if *handler.shutdown.lock().unwrap() {
-                    break;
-                }
-
-                // Read from client with minimal lock scope
-                let read_result = {
-                    println!("[CLIENT] Attempting to acquire client lock for read");
-                    let mut client = handler.client.lock().unwrap();
-                    println!("[CLIENT] Acquired client lock for read");
-                    let result = client.read(&mut read_buffer);
-                    println!("[CLIENT] Released client lock after read");
-                    result
+    /// Runs a command to completion. WAIT is polled until Redis stops asking for a retry,
+    /// yielding between polls so other clients (and replica ACKs) get through.
+    async fn execute_until_done(&mut self, command: RedisCommand) -> RedisResponse {
+        if let RedisCommand::Wait { numreplicas, timeout, elapsed: _ } = command {
+            let start = tokio::time::Instant::now();
+            let mut resp = self.execute_command(&command).await;
+            while let RedisResponse::Retry = resp {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let updated_command = RedisCommand::Wait {
+                    numreplicas,
+                    timeout,
+                    // never report 0 again, it would trigger another GETACK round
+                    elapsed: (start.elapsed().as_millis() as i64).max(1),
                 };
+                resp = self.execute_command(&updated_command).await;
+            }
+            resp
+        } else {
+            self.execute_command(&command).await
+        }
+    }
 
-                match read_result {
-                    Ok(0) => {
-                        // Connection closed
-                        break;
-                    }
-                    Ok(n) => {
-                        println!("[CLIENT] Received {} bytes", n);
-                        
-                        buffer.extend_from_slice(&read_buffer[..n]);
-                        
-                        // Process any complete commands in buffer
-                        let commands = parse_resp(&buffer, buffer.len());
-                        if !commands.is_empty() {
-                            buffer.clear();
-
-                            println!("[CLIENT] Processing {} commands", commands.len());
-
-                            // Handle multiple commands differently for Redis connections
-                            if handler.is_redis_connection && commands.len() > 1 {
-                                let mut responses = Vec::new();
-                                
-                                for command in commands {
-                                    println!("[CLIENT] Executing command: {:?}", command);
+    pub fn start(&mut self) -> JoinHandle<()> {
+        // Clone self to move into the task
+        let handler = self.clone();
+        tokio::spawn(handler.run())
+    }
 
-                                    let response = if let RedisCommand::Wait { numreplicas, timeout, elapsed: _ } = command {
-                                        let start = std::time::Instant::now();
-                                        let mut sent_getack = false;
-                                        println!("[CLIENT] Attempting to acquire redis lock for command");
-                                        let mut resp = handler.execute_command(&command);
-                                        println!("[CLIENT] Released redis lock after command");
-                                        
-                                        while let RedisResponse::Retry = resp {
-                                            thread::sleep(Duration::from_millis(50));
-                                            let total_elapsed = start.elapsed().as_millis() as i64;
-                                            let updated_command = RedisCommand::Wait {
-                                                numreplicas,
-                                                timeout,
-                                                elapsed: total_elapsed,
-                                            };
-                                            
-                                            if !sent_getack {
-                                                println!("[CLIENT] Attempting to acquire redis lock for getack");
-                                                if let Ok(redis) = handler.redis.lock() {
-                                                    println!("[CLIENT] Acquired redis lock for getack");
-                                                    let _ = redis.replication.send_getack_to_replicas();
-                                                    sent_getack = true;
-                                                }
-                                                println!("[CLIENT] Released redis lock after getack");
-                                            }
-                                            
-                                            resp = handler.execute_command(&updated_command);
-                                        }
-                                        resp
-                                    } else {
-                                        println!("[CLIENT] Attempting to acquire redis lock for command");
-                                        let resp = handler.execute_command(&command);
-                                        println!("[CLIENT] Released redis lock after command");
-                                        resp
-                                    };
+    async fn run(mut self) {
+        #[cfg(debug_assertions)]
+        println!("[CLIENT] Starting new client handler");
 
-                                    println!("[CLIENT] Got response: {}", response.format().replace("\r\n", "\\r\\n"));
+        let reader = Arc::clone(&self.reader);
+        let mut reader = reader.lock().await;
+        let shutdown = Arc::clone(&self.shutdown);
+        let mut parser = Context::new();
+        let mut read_buffer = [0; 4096];
 
-                                    let formatted = response.format();
-                                    if !formatted.is_empty() {
-                                        responses.push(formatted);
-                                    }
-                                }
+        // before waiting on anything, set the ready flag
+        *self.ready.lock().unwrap() = true;
 
-                                // Format all responses as a single array
-                                if !responses.is_empty() {
-                                    let mut batch_response = format!("*{}\r\n", responses.len());
-                                    for resp in responses {
-                                        batch_response.push_str(&resp);
-                                    }
-                                    let mut client = handler.client.lock().unwrap();
-                                    client.write_all(batch_response.as_bytes()).unwrap();
-                                    client.flush().unwrap();
-                                }
+        loop {
+            let read_result = tokio::select! {
+                result = reader.read(&mut read_buffer) => result,
+                _ = shutdown.notified() => break,
+            };
 
-                                // Only count bytes after processing commands
-                                if handler.is_redis_connection {
-                                    if let Ok(redis) = handler.redis.lock() {
-                                        if redis.config.replicaof_host.is_some() {
-                                            redis.bytes_processed.fetch_add(n as u64, Ordering::SeqCst);
-                                            #[cfg(debug_assertions)]
-                                            println!("[CLIENT] Added {} bytes, total now: {}", 
-                                                n,
-                                                redis.bytes_processed.load(Ordering::SeqCst));
-                                        }
-                                    }
-                                }
-                            } else {
-                                // Handle single commands or non-Redis connections
-                                for command in commands {
-                                    println!("[CLIENT] Executing command: {:?}", command);
+            let n = match read_result {
+                // Connection closed
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(_e) => {
+                    #[cfg(debug_assertions)]
+                    println!("[CLIENT] Error reading from client: {}", _e);
+                    break;
+                }
+            };
 
-                                    let response = if let RedisCommand::Wait { numreplicas, timeout, elapsed: _ } = command {
-                                        let start = std::time::Instant::now();
-                                        let mut sent_getack = false;
-                                        println!("[CLIENT] Attempting to acquire redis lock for command");
-                                        let mut resp = handler.execute_command(&command);
-                                        println!("[CLIENT] Released redis lock after command");
-                                        
-                                        while let RedisResponse::Retry = resp {
-                                            thread::sleep(Duration::from_millis(50));
-                                            let total_elapsed = start.elapsed().as_millis() as i64;
-                                            let updated_command = RedisCommand::Wait {
-                                                numreplicas,
-                                                timeout,
-                                                elapsed: total_elapsed,
-                                            };
-                                            
-                                            if !sent_getack {
-                                                println!("[CLIENT] Attempting to acquire redis lock for getack");
-                                                if let Ok(redis) = handler.redis.lock() {
-                                                    println!("[CLIENT] Acquired redis lock for getack");
-                                                    let _ = redis.replication.send_getack_to_replicas();
-                                                    sent_getack = true;
-                                                }
-                                                println!("[CLIENT] Released redis lock after getack");
-                                            }
-                                            
-                                            resp = handler.execute_command(&updated_command);
-                                        }
-                                        resp
-                                    } else {
-                                        println!("[CLIENT] Attempting to acquire redis lock for command");
-                                        let resp = handler.execute_command(&command);
-                                        println!("[CLIENT] Released redis lock after command");
-                                        resp
-                                    };
+            #[cfg(debug_assertions)]
+            println!("[CLIENT] Received {} bytes", n);
 
-                                    println!("[CLIENT] Got response: {}", response.format().replace("\r\n", "\\r\\n"));
+            // A partial request stays in the parser until the rest of it arrives
+            parser.extend(&read_buffer[..n]);
 
-                                    let formatted = response.format();
-                                    if !formatted.is_empty() {
-                                        let mut client = handler.client.lock().unwrap();
-                                        client.write_all(formatted.as_bytes()).unwrap();
-                                        client.flush().unwrap();
-                                    }
-                                }
-                            }
-
-                            // Only count bytes after processing commands
-                            if handler.is_redis_connection {
-                                if let Ok(redis) = handler.redis.lock() {
-                                    if redis.config.replicaof_host.is_some() {
-                                        redis.bytes_processed.fetch_add(n as u64, Ordering::SeqCst);
-                                        #[cfg(debug_assertions)]
-                                        println!("[CLIENT] Added {} bytes, total now: {}", 
-                                            n,
-                                            redis.bytes_processed.load(Ordering::SeqCst));
-                                    }
-                                }
-                            }
+            let mut write_result = Ok(());
+            loop {
+                let command = match parser.next_command() {
+                    Ok(Some(command)) => command,
+                    Ok(None) => break,
+                    Err(e) => {
+                        #[cfg(debug_assertions)]
+                        println!("[CLIENT] {}", e);
+                        // The stream can't be resynchronized, answer and hang up
+                        if !self.is_redis_connection {
+                            let _ = self.client.write(format!("-{}\r\n", e).as_bytes());
                         }
+                        write_result = Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()));
+                        break;
                     }
-                    Err(e) => {
-                        if e.kind() != std::io::ErrorKind::WouldBlock {
-                            eprintln!("[CLIENT] Error reading from client: {}", e);
-                            break;
+                };
+                let command_len = command.len();
+                let command = command.into_redis_command();
+
+                let formatted = if command.blocking_keys().is_some() {
+                    // Keep reading while blocked: a client that hangs up must stop waiting, or it
+                    // would take an element nobody receives. Whatever else it sends waits its turn.
+                    let execution = self.execute_until_done(command);
+                    tokio::pin!(execution);
+                    let response = loop {
+                        tokio::select! {
+                            response = &mut execution => break Some(response),
+                            read_result = reader.read(&mut read_buffer) => match read_result {
+                                Ok(0) | Err(_) => break None,
+                                Ok(n) => parser.extend(&read_buffer[..n]),
+                            },
+                            _ = shutdown.notified() => break None,
                         }
-                        thread::sleep(Duration::from_millis(100));
-                        continue;
+                    };
+                    match response {
+                        Some(response) => response.format(),
+                        None => {
+                            write_result = Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted));
+                            break;
+                        },
                     }
+                } else {
+                    self.execute_until_done(command).await.format()
+                };
+
+                // Count what the master sent only once it has been applied, so a GETACK
+                // reports the offset up to (not including) itself.
+                if self.is_redis_connection && self.redis.is_replica() {
+                    self.redis.bytes_processed.fetch_add(command_len as u64, Ordering::SeqCst);
+                    #[cfg(debug_assertions)]
+                    println!("[CLIENT] Added {} bytes, total now: {}",
+                        command_len,
+                        self.redis.bytes_processed.load(Ordering::SeqCst));
+                }
+
+                // A replica never answers its master; REPLCONF ACK is written by Redis directly.
+                if self.is_redis_connection || formatted.is_empty() {
+                    continue;
                 }
+                write_result = self.client.write(&formatted);
+                if write_result.is_err() {
+                    break;
+                }
+            }
+
+            if let Err(_e) = write_result {
+                // The client went away while we were answering it, or sent garbage.
+                #[cfg(debug_assertions)]
+                println!("[CLIENT] Error writing to client: {}", _e);
+                break;
             }
-        })
+        }
+
+        #[cfg(debug_assertions)]
+        println!("[CLIENT] Client handler finished");
     }
 }
@@ -1,3 +1,4 @@
 pub mod redis;
 pub mod client_handler;
 pub mod resp;
+pub mod server;
@@ -1,10 +1,12 @@
 mod client_handler;
 mod resp;
 mod redis;
+mod server;
 use crate::redis::{Redis, RedisConfig, init_replica};
+use crate::server::Server;
 
-use std::net::TcpListener;
-use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use std::sync::Arc;
 
 /**
  * This is an implementation of a key value store that imitates Redis.
@@ -20,6 +22,9 @@ use std::sync::{Arc, Mutex};
  * - ReplicationManager (src/redis/replication.rs):
  *   Manages master/replica relationships and command replication
  * 
+ * - Server (src/server.rs):
+ *   Accepts connections and runs a bounded number of client handlers concurrently
+ * 
  * - ClientHandler (src/client_handler.rs):
  *   Handles client connections and command execution
  * 
@@ -29,10 +34,14 @@ use std::sync::{Arc, Mutex};
  * - XReadHandler (src/redis/xread_handler.rs):
  *   Specialized handler for Redis Streams XREAD command
  * 
+ * - BlockedClients (src/redis/blocking.rs):
+ *   Queues clients blocked in BLPOP, XREAD and friends until a push wakes them
+ * 
  * - RESP Parser (src/resp/command.rs):
  *   Parses Redis protocol messages
  */
-fn main() {
+#[tokio::main]
+async fn main() {
     #[cfg(debug_assertions)]
     println!("Logs from your program will appear here!");
     let args: Vec<String> = std::env::args().collect();
@@ -94,33 +103,62 @@ fn main() {
                     std::process::exit(1);
                 }
             }
+            "--maxclients" => {
+                match args.get(i + 1).and_then(|value| value.parse::<usize>().ok()) {
+                    Some(maxclients) if maxclients > 0 => config.maxclients = maxclients,
+                    _ => {
+                        eprintln!("--maxclients argument provided but no valid client limit was given");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--hz" => {
+                match args.get(i + 1).and_then(|value| value.parse::<u32>().ok()) {
+                    Some(hz) => config.hz = hz.clamp(1, 500),
+                    None => {
+                        eprintln!("--hz argument provided but no valid frequency was given");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--databases" => {
+                match args.get(i + 1).and_then(|value| value.parse::<usize>().ok()) {
+                    Some(databases) if databases > 0 => config.databases = databases,
+                    _ => {
+                        eprintln!("--databases argument provided but no valid database count was given");
+                        std::process::exit(1);
+                    }
+                }
+            }
             _ => {}
         }
     }
 
-    let redis = Arc::new(Mutex::new(Redis::new(config.clone())));
+    let redis = Arc::new(Redis::new(config.clone()));
 
     // Parse RDB file - handle errors gracefully
-    if let Err(e) = redis.lock().unwrap().parse_rdb_file() {
+    if let Err(e) = redis.parse_rdb_file() {
         println!("Error parsing RDB file: {}, starting with empty database", e);
     }
 
-    let listener = TcpListener::bind(format!("{}:{}", config.addr, config.port)).unwrap();
+    let listener = TcpListener::bind(format!("{}:{}", config.addr, config.port)).await.unwrap();
 
-    init_replica(&mut config, redis.clone());
+    init_replica(&mut config, redis.clone()).await;
 
     // if we are master and there are replicas connected, start replication sync
     if config.replicaof_host.is_none() {
         redis::replication::ReplicationManager::start_replication_sync(redis.clone());
     }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut _stream) => {
+    Redis::start_active_expire(redis.clone());
+
+    let mut server = Server::new(redis.clone());
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
                 #[cfg(debug_assertions)]
                 println!("accepted new connection");
-                let mut client_handler = client_handler::ClientHandler::new(_stream, redis.clone());
-                client_handler.start().join().unwrap();
+                server.accept(stream).await;
             }
             Err(_e) => {
                 #[cfg(debug_assertions)]
@@ -0,0 +1,309 @@
+//! Bit-level work on string values for the bitmap and BITFIELD commands. Bit 0 is the most
+//! significant bit of the first byte, and bits past the end of the string read as 0.
+
+/// Bit offsets must stay inside the largest string, 512MB.
+const MAX_BITS: usize = 1 << 32;
+
+/// A bit offset argument. With `width`, `#N` means the N-th field of that many bits, as BITFIELD
+/// allows.
+pub fn parse_offset(arg: &[u8], width: Option<u32>) -> Option<usize> {
+    let (index, multiplier) = match (arg.strip_prefix(b"#"), width) {
+        (Some(index), Some(width)) => (index, width as i64),
+        _ => (arg, 1),
+    };
+    let offset = std::str::from_utf8(index).ok()?.parse::<i64>().ok()?.checked_mul(multiplier)?;
+    usize::try_from(offset).ok().filter(|offset| *offset < MAX_BITS)
+}
+
+pub fn get_bit(data: &[u8], offset: usize) -> bool {
+    data.get(offset / 8).is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Sets or clears the bit at `offset`, growing `data` with zero bytes as needed. Returns the old bit.
+pub fn set_bit(data: &mut Vec<u8>, offset: usize, on: bool) -> bool {
+    let byte = offset / 8;
+    if data.len() <= byte {
+        data.resize(byte + 1, 0);
+    }
+    let mask = 0x80 >> (offset % 8);
+    let old = data[byte] & mask != 0;
+    if on {
+        data[byte] |= mask;
+    } else {
+        data[byte] &= !mask;
+    }
+    old
+}
+
+/// Resolves an inclusive `start..=end` range over `len` units, negative ends counting from the
+/// end, the way GETRANGE does. `None` if it selects nothing.
+fn resolve_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    if len == 0 || (start < 0 && end < 0 && start > end) {
+        return None;
+    }
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    (start <= end).then_some((start as usize, end as usize))
+}
+
+/// A BITCOUNT/BITPOS range as inclusive bit offsets. Ranges are in bytes unless `in_bits`.
+fn bit_range(data: &[u8], start: i64, end: i64, in_bits: bool) -> Option<(usize, usize)> {
+    if in_bits {
+        resolve_range(start, end, data.len() * 8)
+    } else {
+        resolve_range(start, end, data.len()).map(|(start, end)| (start * 8, end * 8 + 7))
+    }
+}
+
+/// BITCOUNT: the set bits in the whole string, or in `range`.
+pub fn count(data: &[u8], range: Option<(i64, i64)>, in_bits: bool) -> i64 {
+    let (first, last) = match range {
+        None if data.is_empty() => return 0,
+        None => (0, data.len() * 8 - 1),
+        Some((start, end)) => match bit_range(data, start, end, in_bits) {
+            Some(range) => range,
+            None => return 0,
+        },
+    };
+    let (first_byte, last_byte) = (first / 8, last / 8);
+    let mut count: u32 = data[first_byte..=last_byte].iter().map(|byte| byte.count_ones()).sum();
+    // Take back the bits of the edge bytes that fall outside the range.
+    count -= (data[first_byte] & !(0xFF >> (first % 8))).count_ones();
+    count -= (data[last_byte] & 0xFFu8.checked_shr((last % 8 + 1) as u32).unwrap_or(0)).count_ones();
+    count as i64
+}
+
+/// BITPOS: the first bit equal to `bit` from `start` to `end`, or -1. When looking for a clear bit
+/// without an explicit end, the string counts as padded with zeros, so the answer is never -1.
+pub fn position(data: &[u8], bit: bool, start: Option<i64>, end: Option<i64>, in_bits: bool) -> i64 {
+    if data.is_empty() {
+        return if bit { -1 } else { 0 };
+    }
+    let units = if in_bits { data.len() * 8 } else { data.len() } as i64;
+    let (first, last) = match bit_range(data, start.unwrap_or(0), end.unwrap_or(units - 1), in_bits) {
+        Some(range) => range,
+        None => return -1,
+    };
+    let skip = if bit { 0x00 } else { 0xFF };
+    let mut offset = first;
+    while offset <= last {
+        if offset % 8 == 0 && offset + 7 <= last && data[offset / 8] == skip {
+            offset += 8;
+        } else if get_bit(data, offset) == bit {
+            return offset as i64;
+        } else {
+            offset += 1;
+        }
+    }
+    if !bit && end.is_none() { last as i64 + 1 } else { -1 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// BITOP over `sources`, the shorter ones padded with zero bytes. NOT takes a single source.
+pub fn bitop(op: BitOp, sources: &[&[u8]]) -> Vec<u8> {
+    let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+    (0..len).map(|i| {
+        let mut bytes = sources.iter().map(|source| source.get(i).copied().unwrap_or(0));
+        let first = bytes.next().unwrap_or(0);
+        match op {
+            BitOp::And => bytes.fold(first, |acc, byte| acc & byte),
+            BitOp::Or => bytes.fold(first, |acc, byte| acc | byte),
+            BitOp::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+            BitOp::Not => !first,
+        }
+    }).collect()
+}
+
+/// A BITFIELD integer type: `i1` to `i64` or `u1` to `u63`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl FieldType {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        let (signed, max) = match arg.first()?.to_ascii_lowercase() {
+            b'i' => (true, 64),
+            b'u' => (false, 63),
+            _ => return None,
+        };
+        let bits = std::str::from_utf8(&arg[1..]).ok()?.parse::<u32>().ok()?;
+        (1..=max).contains(&bits).then_some(FieldType { signed, bits })
+    }
+
+    fn limits(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+
+    /// Brings `value` into range as `overflow` says, or `None` if it fails.
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = self.limits();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let wrapped = value.rem_euclid(1 << self.bits);
+                Some(if wrapped > max { wrapped - (1 << self.bits) } else { wrapped } as i64)
+            },
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// One BITFIELD subcommand. OVERFLOW changes how the SET and INCRBY after it behave.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldOp {
+    Get { field: FieldType, offset: usize },
+    Set { field: FieldType, offset: usize, value: i64 },
+    IncrBy { field: FieldType, offset: usize, increment: i64 },
+    Overflow(Overflow),
+}
+
+impl FieldOp {
+    pub fn is_write(&self) -> bool {
+        matches!(self, FieldOp::Set { .. } | FieldOp::IncrBy { .. })
+    }
+}
+
+pub fn read_field(data: &[u8], field: FieldType, offset: usize) -> i64 {
+    let mut value = (0..field.bits as usize)
+        .fold(0u64, |acc, i| acc << 1 | get_bit(data, offset + i) as u64);
+    if field.signed && field.bits < 64 && value >> (field.bits - 1) & 1 == 1 {
+        value |= u64::MAX << field.bits;
+    }
+    value as i64
+}
+
+fn write_field(data: &mut Vec<u8>, field: FieldType, offset: usize, value: i64) {
+    for i in 0..field.bits {
+        set_bit(data, offset + i as usize, (value as u64) >> (field.bits - 1 - i) & 1 == 1);
+    }
+}
+
+/// Runs BITFIELD's subcommands in order and returns a reply for each GET, SET and INCRBY: the
+/// value read, the value replaced and the new value respectively, or `None` where OVERFLOW FAIL
+/// stopped a write. Like Redis, the string grows to fit every write, even failed ones.
+pub fn bitfield(data: &mut Vec<u8>, ops: &[FieldOp]) -> Vec<Option<i64>> {
+    let needed = ops.iter().filter_map(|op| match op {
+        FieldOp::Set { field, offset, .. } | FieldOp::IncrBy { field, offset, .. } => {
+            Some((offset + field.bits as usize).div_ceil(8))
+        },
+        _ => None,
+    }).max();
+    if let Some(needed) = needed.filter(|needed| *needed > data.len()) {
+        data.resize(needed, 0);
+    }
+
+    let mut overflow = Overflow::Wrap;
+    let mut replies = Vec::new();
+    for op in ops {
+        match *op {
+            FieldOp::Overflow(mode) => overflow = mode,
+            FieldOp::Get { field, offset } => replies.push(Some(read_field(data, field, offset))),
+            FieldOp::Set { field, offset, value } => {
+                // Unsigned fields take the argument's bits as they are, as Redis does.
+                let value = if field.signed { value as i128 } else { value as u64 as i128 };
+                let old = read_field(data, field, offset);
+                replies.push(field.fit(value, overflow).map(|value| {
+                    write_field(data, field, offset, value);
+                    old
+                }));
+            },
+            FieldOp::IncrBy { field, offset, increment } => {
+                let value = read_field(data, field, offset) as i128 + increment as i128;
+                replies.push(field.fit(value, overflow).inspect(|value| {
+                    write_field(data, field, offset, *value);
+                }));
+            },
+        }
+    }
+    replies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_and_ranges() {
+        let mut data = Vec::new();
+        assert!(!set_bit(&mut data, 9, true));
+        assert_eq!(data, vec![0x00, 0x40]);
+        assert!(set_bit(&mut data, 9, false));
+        assert_eq!(data, vec![0x00, 0x00]);
+
+        let data = b"foobar";
+        assert_eq!(count(data, None, false), 26);
+        assert_eq!(count(data, Some((0, 0)), false), 4);
+        assert_eq!(count(data, Some((1, 1)), false), 6);
+        assert_eq!(count(data, Some((5, 30)), true), 17);
+        assert_eq!(count(data, Some((-2, -1)), false), 7);
+        assert_eq!(count(data, Some((3, 1)), false), 0);
+
+        assert_eq!(position(&[0xFF, 0xF0, 0x00], false, None, None, false), 12);
+        assert_eq!(position(&[0x00, 0xFF, 0xF0], true, Some(2), Some(-1), false), 16);
+        assert_eq!(position(&[0x00, 0xFF, 0xF0], true, Some(7), Some(15), true), 8);
+        assert_eq!(position(&[0xFF, 0xFF, 0xFF], false, None, None, false), 24);
+        assert_eq!(position(&[0xFF, 0xFF, 0xFF], false, Some(0), Some(-1), false), -1);
+        assert_eq!(position(&[0x00], true, None, None, false), -1);
+        assert_eq!(position(&[], false, None, None, false), 0);
+
+        assert_eq!(parse_offset(b"#2", Some(8)), Some(16));
+        assert_eq!(parse_offset(b"#2", None), None);
+        assert_eq!(parse_offset(b"4294967295", None), Some(4294967295));
+        assert_eq!(parse_offset(b"4294967296", None), None);
+        assert_eq!(parse_offset(b"-1", None), None);
+    }
+
+    #[test]
+    fn test_bitfield_overflow() {
+        let i8 = FieldType::parse(b"i8").unwrap();
+        let u2 = FieldType::parse(b"u2").unwrap();
+        assert_eq!(FieldType::parse(b"u64"), None);
+        assert_eq!(FieldType::parse(b"i0"), None);
+
+        let mut data = Vec::new();
+        let replies = bitfield(&mut data, &[
+            FieldOp::Set { field: i8, offset: 0, value: -100 },
+            FieldOp::IncrBy { field: i8, offset: 0, increment: -100 },
+            FieldOp::Overflow(Overflow::Sat),
+            FieldOp::IncrBy { field: i8, offset: 0, increment: -200 },
+            FieldOp::Overflow(Overflow::Fail),
+            FieldOp::IncrBy { field: i8, offset: 0, increment: -1 },
+            FieldOp::Get { field: i8, offset: 0 },
+        ]);
+        assert_eq!(replies, vec![Some(0), Some(56), Some(-128), None, Some(-128)]);
+
+        let replies = bitfield(&mut data, &[
+            FieldOp::IncrBy { field: u2, offset: 100, increment: 5 },
+            FieldOp::Overflow(Overflow::Sat),
+            FieldOp::Set { field: u2, offset: 100, value: -1 },
+            FieldOp::IncrBy { field: u2, offset: 100, increment: -7 },
+        ]);
+        assert_eq!(replies, vec![Some(1), Some(1), Some(0)]);
+        assert_eq!(data.len(), 13);
+        assert_eq!(read_field(&data, FieldType::parse(b"i64").unwrap(), 0), i64::MIN);
+    }
+}
@@ -0,0 +1,157 @@
+//! Clients blocked in BLPOP, BRPOP, BLMOVE, BLMPOP, XREAD and XREADGROUP.
+//!
+//! Every key has a queue of the clients waiting on it, in the order they blocked. A push signals
+//! the key, which wakes only the client at the head of its queue; that client retries its
+//! command and, once it leaves the queues (served, timed out or disconnected), passes the turn
+//! on to whoever is next. So clients are served first come first served, and nobody polls.
+//! A client woken for nothing passes the turn on too: a stream entry isn't taken by whoever
+//! reads it first, so an XADD works its way down to the readers of every group.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use bytes::Bytes;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::redis::Redis;
+use crate::redis::core::RedisResponse;
+use crate::redis::replication::Connection;
+use crate::redis::RedisCommand;
+
+/// The clients blocked on a key, oldest first.
+type Queue = VecDeque<Arc<Notify>>;
+
+#[derive(Default)]
+pub struct BlockedClients {
+    /// Keyed by (database, key).
+    queues: Mutex<HashMap<(usize, Bytes), Queue>>,
+}
+
+impl BlockedClients {
+    /// Wakes the client that has waited longest on `key`, if any.
+    pub fn signal(&self, db: usize, key: &[u8]) {
+        let queues = self.queues.lock().unwrap();
+        if let Some(first) = queues.get(&(db, Bytes::copy_from_slice(key))).and_then(|queue| queue.front()) {
+            first.notify_one();
+        }
+    }
+
+    /// Wakes the longest waiting client on every key of database `db`, for when the whole
+    /// database changed under them, as with SWAPDB.
+    pub fn signal_database(&self, db: usize) {
+        let queues = self.queues.lock().unwrap();
+        for (_, queue) in queues.iter().filter(|((index, _), _)| *index == db) {
+            if let Some(first) = queue.front() {
+                first.notify_one();
+            }
+        }
+    }
+
+    /// Queues a client on `keys`. It stays queued until the returned guard is dropped.
+    fn block(&self, db: usize, keys: &[Bytes]) -> Blocked<'_> {
+        let waiter = Arc::new(Notify::new());
+        let mut queues = self.queues.lock().unwrap();
+        for key in keys {
+            queues.entry((db, key.clone())).or_default().push_back(Arc::clone(&waiter));
+        }
+        Blocked { clients: self, db, keys: keys.to_vec(), waiter }
+    }
+
+    /// How many clients are blocked on `key`.
+    #[allow(dead_code)]
+    pub fn waiting(&self, db: usize, key: &[u8]) -> usize {
+        self.queues.lock().unwrap().get(&(db, Bytes::copy_from_slice(key))).map_or(0, Queue::len)
+    }
+}
+
+/// A client's place in the queues of the keys it blocks on.
+struct Blocked<'a> {
+    clients: &'a BlockedClients,
+    db: usize,
+    keys: Vec<Bytes>,
+    waiter: Arc<Notify>,
+}
+
+impl Blocked<'_> {
+    /// Wakes the client queued right behind this one on each of its keys.
+    fn pass_on(&self) {
+        let queues = self.clients.queues.lock().unwrap();
+        for key in &self.keys {
+            let next = queues.get(&(self.db, key.clone()))
+                .and_then(|queue| queue.iter().skip_while(|waiter| !Arc::ptr_eq(waiter, &self.waiter)).nth(1));
+            if let Some(next) = next {
+                next.notify_one();
+            }
+        }
+    }
+}
+
+impl Drop for Blocked<'_> {
+    fn drop(&mut self) {
+        {
+            let mut queues = self.clients.queues.lock().unwrap();
+            for key in &self.keys {
+                let entry = (self.db, key.clone());
+                if let Some(queue) = queues.get_mut(&entry) {
+                    queue.retain(|waiter| !Arc::ptr_eq(waiter, &self.waiter));
+                    if queue.is_empty() {
+                        queues.remove(&entry);
+                    }
+                }
+            }
+        }
+        // The signal may have been meant for this client, or what it left behind may serve the
+        // next one; either way it is their turn.
+        for key in &self.keys {
+            self.clients.signal(self.db, key);
+        }
+    }
+}
+
+/// Runs a blocking command outside MULTI: tries it, and while that finds nothing, waits for a
+/// push on one of `keys` or for `timeout` (`None` waits forever). A command that finds nothing
+/// replies the same as one that timed out, so that reply doubles as the timeout reply.
+pub async fn serve(redis: &Redis, db: usize, command: &RedisCommand, keys: &[Bytes], timeout: Option<Duration>, client: Option<&Connection>) -> RedisResponse {
+    let mut nothing = RedisResponse::NullArray;
+    let served = wait_until(redis, db, keys, timeout, || {
+        let response = redis.execute_command(&mut { db }, command, client);
+        if matches!(response, RedisResponse::NullArray | RedisResponse::NullBulkString) {
+            nothing = response;
+            None
+        } else {
+            Some(response)
+        }
+    }).await;
+    served.unwrap_or(nothing)
+}
+
+/// Calls `attempt` until it comes back with something, taking turns with the other clients
+/// blocked on `keys` and waiting for a push in between. `None` once `timeout` runs out.
+pub async fn wait_until<T>(redis: &Redis, db: usize, keys: &[Bytes], timeout: Option<Duration>, mut attempt: impl FnMut() -> Option<T>) -> Option<T> {
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    // Queued before the first try, so a push right after it isn't missed.
+    let blocked = redis.blocked.block(db, keys);
+    let mut woken = false;
+    loop {
+        if let Some(result) = attempt() {
+            return Some(result);
+        }
+        if woken {
+            // Whatever woke us wasn't for us, it may be for the next one
+            blocked.pass_on();
+        }
+        let pushed = blocked.waiter.notified();
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, pushed).await.is_err() {
+                    return None;
+                }
+            },
+            None => pushed.await,
+        }
+        woken = true;
+        #[cfg(debug_assertions)]
+        println!("[blocking::wait_until] Woken up on {:?}", keys);
+    }
+}
@@ -1,5 +1,12 @@
-use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use bytes::Bytes;
+use super::bitmap::{self, BitOp, FieldOp, FieldType, Overflow};
+use super::geo::{self, Origin, Query, Shape, Sort};
+use super::consumer_groups::{ClaimOptions, StreamId};
 use super::xread_parser;
+use super::storage::{ExpireOptions, ListEnd, SetOp, SetOptions, Storage, StreamTrim, TrimThreshold, STREAM_NODE_MAX_ENTRIES};
+use super::zset::{self, Aggregate, LexBound, RangeBy, RangeSpec, ScoreBound, ZAddOptions};
 
 #[derive(Debug, Clone)]
 pub enum RedisCommand {
@@ -10,36 +17,183 @@ pub enum RedisCommand {
     Multi,
     Exec,
     Discard,
-    Echo { data: String },
+    Echo { data: Bytes },
     Ping,
-    Set { key: String, value: String, ttl: Option<usize>, original_resp: String }, 
-    Get { key: String },
+    Set { key: Bytes, value: Bytes, ttl: Option<usize>, options: SetOptions, original_resp: Bytes },
+    Get { key: Bytes },
     Info { subcommand: String },
     Replconf { subcommand: String, params: Vec<String> },
     ReplconfGetack,
     Psync { replica_id: String, offset: i8 },
     Wait { numreplicas: i64, timeout: i64, elapsed: i64 },
     Config { subcommand: String, parameter: String },
+    /// Writes every database to the RDB file.
+    Save,
     Error { message: String },
-    Keys { pattern: String },
-    Type { key: String },
-    XAdd { key: String, id: String, fields: HashMap<String, String>, original_resp: String },
-    XRange { key: String, start: String, end: String },
-    XRead { keys: Vec<String>, ids: Vec<String>, block: Option<u64>, count: Option<usize> },
-    Incr { key: String },
-    FlushDB,
+    Keys { pattern: Bytes },
+    Scan { cursor: u64, pattern: Option<Bytes>, count: usize, type_name: Option<String> },
+    Type { key: Bytes },
+    /// `id` is `*`, `ms-*` or a full `ms-seq`. With `nomkstream` a missing stream isn't created.
+    XAdd { key: Bytes, id: String, fields: Vec<(Bytes, Bytes)>, nomkstream: bool, trim: Option<StreamTrim> },
+    /// XRANGE, and XREVRANGE when `rev` is set. `start` and `end` are inclusive, exclusive bounds
+    /// already moved to the next ID inward.
+    XRange { key: Bytes, start: StreamId, end: StreamId, count: Option<usize>, rev: bool },
+    XLen { key: Bytes },
+    XDel { key: Bytes, ids: Vec<(u64, u64)>, original_resp: Bytes },
+    XTrim { key: Bytes, trim: StreamTrim },
+    // Stream consumer groups
+    /// XGROUP CREATE and SETID take `$` as `id` None, the stream's last ID.
+    XGroupCreate { key: Bytes, group: Bytes, id: Option<StreamId>, mkstream: bool },
+    XGroupSetId { key: Bytes, group: Bytes, id: Option<StreamId> },
+    XGroupDestroy { key: Bytes, group: Bytes, original_resp: Bytes },
+    XGroupCreateConsumer { key: Bytes, group: Bytes, consumer: Bytes, original_resp: Bytes },
+    XGroupDelConsumer { key: Bytes, group: Bytes, consumer: Bytes, original_resp: Bytes },
+    /// An `ids` entry of None is `>`. Only a read of nothing but `>` IDs blocks.
+    XReadGroup { group: Bytes, consumer: Bytes, keys: Vec<Bytes>, ids: Vec<Option<StreamId>>, count: Option<usize>, block: Option<u64>, noack: bool },
+    XAck { key: Bytes, group: Bytes, ids: Vec<StreamId>, original_resp: Bytes },
+    XPendingSummary { key: Bytes, group: Bytes },
+    XPending { key: Bytes, group: Bytes, start: StreamId, end: StreamId, count: usize, consumer: Option<Bytes>, min_idle: Option<u64> },
+    XClaim { key: Bytes, group: Bytes, consumer: Bytes, min_idle: u64, ids: Vec<StreamId>, options: ClaimOptions },
+    XAutoClaim { key: Bytes, group: Bytes, consumer: Bytes, min_idle: u64, start: StreamId, count: usize, justid: bool },
+    XRead { keys: Vec<Bytes>, ids: Vec<String>, block: Option<u64>, count: Option<usize> },
+    /// INCR, DECR, INCRBY and DECRBY.
+    Incr { key: Bytes, delta: i64, original_resp: Bytes },
+    IncrByFloat { key: Bytes, increment: f64 },
+    // String commands
+    Append { key: Bytes, value: Bytes, original_resp: Bytes },
+    StrLen { key: Bytes },
+    GetRange { key: Bytes, start: i64, end: i64 },
+    SetRange { key: Bytes, offset: usize, value: Bytes, original_resp: Bytes },
+    MGet { keys: Vec<Bytes> },
+    /// MSET, and MSETNX when `nx` is set.
+    MSet { pairs: Vec<(Bytes, Bytes)>, nx: bool, original_resp: Bytes },
+    GetSet { key: Bytes, value: Bytes, original_resp: Bytes },
+    GetDel { key: Bytes },
+    /// GETEX. `milliseconds` is the new TTL, a unix time if `absolute`; `persist` removes it.
+    GetEx { key: Bytes, milliseconds: Option<i64>, absolute: bool, persist: bool },
+    SetNx { key: Bytes, value: Bytes, original_resp: Bytes },
+    /// SETEX and PSETEX. Replicated with the absolute expiration, like EXPIRE.
+    SetEx { key: Bytes, milliseconds: i64, value: Bytes },
+    Lcs { key1: Bytes, key2: Bytes, len: bool, idx: bool, min_match_len: usize, with_match_len: bool },
+    // Bitmap commands
+    SetBit { key: Bytes, offset: usize, value: bool, original_resp: Bytes },
+    GetBit { key: Bytes, offset: usize },
+    /// BITCOUNT. The range is in bytes unless `in_bits`.
+    BitCount { key: Bytes, range: Option<(i64, i64)>, in_bits: bool },
+    /// BITPOS. The range is in bytes unless `in_bits`.
+    BitPos { key: Bytes, bit: bool, start: Option<i64>, end: Option<i64>, in_bits: bool },
+    BitOp { op: BitOp, destination: Bytes, keys: Vec<Bytes>, original_resp: Bytes },
+    /// BITFIELD, and BITFIELD_RO when `read_only` is set.
+    BitField { key: Bytes, ops: Vec<FieldOp>, read_only: bool, original_resp: Bytes },
+    // HyperLogLog commands
+    PfAdd { key: Bytes, elements: Vec<Bytes>, original_resp: Bytes },
+    PfCount { keys: Vec<Bytes> },
+    PfMerge { destination: Bytes, sources: Vec<Bytes>, original_resp: Bytes },
+    FlushDB { original_resp: Bytes },
+    // Database commands
+    Select { index: i64 },
+    Move { key: Bytes, db: i64, original_resp: Bytes },
+    SwapDb { first: i64, second: i64, original_resp: Bytes },
+    FlushAll { original_resp: Bytes },
+    // Keyspace commands
+    /// DEL and UNLINK.
+    Del { keys: Vec<Bytes>, original_resp: Bytes },
+    Exists { keys: Vec<Bytes> },
+    Touch { keys: Vec<Bytes> },
+    /// RENAME, and RENAMENX when `nx` is set.
+    Rename { key: Bytes, new_key: Bytes, nx: bool, original_resp: Bytes },
+    Copy { source: Bytes, destination: Bytes, db: Option<i64>, replace: bool, original_resp: Bytes },
+    RandomKey,
+    DbSize,
+    // Expiration commands
+    /// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT. Replicated as a PEXPIREAT of the resulting
+    /// time, so replicas agree on it whatever their clocks say when it arrives.
+    Expire { key: Bytes, milliseconds: i64, absolute: bool, options: ExpireOptions },
+    /// TTL, PTTL, EXPIRETIME and PEXPIRETIME.
+    Ttl { key: Bytes, in_milliseconds: bool, absolute: bool },
+    Persist { key: Bytes, original_resp: Bytes },
     // List commands
-    LPush { key: String, value: String },
-    RPush { key: String, value: String },
-    LPop { key: String },
-    RPop { key: String },
-    LLen { key: String },
-    LRange { key: String, start: i64, stop: i64 },
-    LTrim { key: String, start: i64, stop: i64 },
-    LPos { key: String, element: String, count: Option<i64> },
-    LInsert { key: String, before: bool, pivot: String, element: String },
-    LSet { key: String, index: i64, element: String },
-    LIndex { key: String, index: i64 },
+    /// LPUSH, RPUSH, LPUSHX and RPUSHX.
+    Push { key: Bytes, end: ListEnd, values: Vec<Bytes>, only_if_exists: bool, original_resp: Bytes },
+    /// LPOP and RPOP.
+    Pop { key: Bytes, end: ListEnd, count: Option<usize>, original_resp: Bytes },
+    /// LMPOP. Replicated as the LPOP or RPOP it amounts to.
+    LMPop { keys: Vec<Bytes>, end: ListEnd, count: usize },
+    /// LMOVE and RPOPLPUSH.
+    LMove { source: Bytes, destination: Bytes, from: ListEnd, to: ListEnd, original_resp: Bytes },
+    LRem { key: Bytes, count: i64, element: Bytes, original_resp: Bytes },
+    /// BLPOP and BRPOP. A `timeout` of `None` waits forever; inside MULTI they never wait.
+    /// Replicated as the LPOP or RPOP they amount to, like the other blocking commands.
+    BPop { keys: Vec<Bytes>, end: ListEnd, timeout: Option<Duration> },
+    /// BLMPOP.
+    BLMPop { keys: Vec<Bytes>, end: ListEnd, count: usize, timeout: Option<Duration> },
+    /// BLMOVE and BRPOPLPUSH.
+    BLMove { source: Bytes, destination: Bytes, from: ListEnd, to: ListEnd, timeout: Option<Duration> },
+    LLen { key: Bytes },
+    LRange { key: Bytes, start: i64, stop: i64 },
+    LTrim { key: Bytes, start: i64, stop: i64 },
+    /// `rank` is never 0. A `count` of 0 asks for every match and `maxlen` 0 for no limit.
+    LPos { key: Bytes, element: Bytes, rank: i64, count: Option<usize>, maxlen: usize },
+    LInsert { key: Bytes, before: bool, pivot: Bytes, element: Bytes },
+    LSet { key: Bytes, index: i64, element: Bytes },
+    LIndex { key: Bytes, index: i64 },
+    // Hash commands
+    HSet { key: Bytes, fields: Vec<(Bytes, Bytes)>, original_resp: Bytes },
+    HSetNx { key: Bytes, field: Bytes, value: Bytes, original_resp: Bytes },
+    HGet { key: Bytes, field: Bytes },
+    HMGet { key: Bytes, fields: Vec<Bytes> },
+    HGetAll { key: Bytes },
+    HDel { key: Bytes, fields: Vec<Bytes>, original_resp: Bytes },
+    HExists { key: Bytes, field: Bytes },
+    HLen { key: Bytes },
+    HKeys { key: Bytes },
+    HVals { key: Bytes },
+    HIncrBy { key: Bytes, field: Bytes, increment: i64, original_resp: Bytes },
+    /// Replicated as an HSET of the resulting value, so replicas can't drift through rounding.
+    HIncrByFloat { key: Bytes, field: Bytes, increment: f64 },
+    HStrLen { key: Bytes, field: Bytes },
+    HRandField { key: Bytes, count: Option<i64>, with_values: bool },
+    // Set commands
+    SAdd { key: Bytes, members: Vec<Bytes>, original_resp: Bytes },
+    SRem { key: Bytes, members: Vec<Bytes>, original_resp: Bytes },
+    SMembers { key: Bytes },
+    SIsMember { key: Bytes, member: Bytes },
+    SMIsMember { key: Bytes, members: Vec<Bytes> },
+    SCard { key: Bytes },
+    /// Replicated as an SREM of the members that were popped.
+    SPop { key: Bytes, count: Option<usize> },
+    SRandMember { key: Bytes, count: Option<i64> },
+    SMove { source: Bytes, destination: Bytes, member: Bytes, original_resp: Bytes },
+    /// SINTER, SUNION and SDIFF.
+    SetOperation { op: SetOp, keys: Vec<Bytes> },
+    /// SINTERSTORE, SUNIONSTORE and SDIFFSTORE.
+    SetOperationStore { op: SetOp, destination: Bytes, keys: Vec<Bytes>, original_resp: Bytes },
+    SInterCard { keys: Vec<Bytes>, limit: usize },
+    // Sorted set commands
+    ZAdd { key: Bytes, options: ZAddOptions, pairs: Vec<(f64, Bytes)>, original_resp: Bytes },
+    ZIncrBy { key: Bytes, increment: f64, member: Bytes, original_resp: Bytes },
+    ZRem { key: Bytes, members: Vec<Bytes>, original_resp: Bytes },
+    ZScore { key: Bytes, member: Bytes },
+    ZMScore { key: Bytes, members: Vec<Bytes> },
+    ZCard { key: Bytes },
+    ZCount { key: Bytes, min: ScoreBound, max: ScoreBound },
+    ZRange { key: Bytes, spec: RangeSpec, with_scores: bool },
+    ZRangeStore { destination: Bytes, source: Bytes, spec: RangeSpec, original_resp: Bytes },
+    /// ZRANK and ZREVRANK.
+    ZRank { key: Bytes, member: Bytes, rev: bool, with_score: bool },
+    /// ZPOPMIN and ZPOPMAX.
+    ZPop { key: Bytes, count: Option<usize>, max: bool, original_resp: Bytes },
+    /// ZUNIONSTORE and ZINTERSTORE.
+    ZSetOperationStore { op: SetOp, destination: Bytes, keys: Vec<Bytes>, weights: Vec<f64>, aggregate: Aggregate, original_resp: Bytes },
+    // Geo commands, on sorted sets scored by geohash
+    /// GEOADD, as a ZADD of the positions' scores.
+    GeoAdd { key: Bytes, options: ZAddOptions, pairs: Vec<(f64, Bytes)>, original_resp: Bytes },
+    GeoPos { key: Bytes, members: Vec<Bytes> },
+    /// GEODIST. `unit` is in meters.
+    GeoDist { key: Bytes, member1: Bytes, member2: Bytes, unit: f64 },
+    GeoHash { key: Bytes, members: Vec<Bytes> },
+    GeoSearch { key: Bytes, query: Query },
+    GeoSearchStore { destination: Bytes, source: Bytes, query: Query, original_resp: Bytes },
 }
 
 impl RedisCommand {
@@ -55,18 +209,97 @@ impl RedisCommand {
     const PSYNC : &'static str = "PSYNC";
     const WAIT: &'static str = "WAIT";
     const CONFIG: &'static str = "CONFIG";
+    const SAVE: &'static str = "SAVE";
     const KEYS: &'static str = "KEYS";
+    const SCAN: &'static str = "SCAN";
     const TYPE: &'static str = "TYPE";
     const XADD: &'static str = "XADD";
     const XRANGE: &'static str = "XRANGE";
+    const XREVRANGE: &'static str = "XREVRANGE";
     const XREAD: &'static str = "XREAD";
+    const XLEN: &'static str = "XLEN";
+    const XDEL: &'static str = "XDEL";
+    const XTRIM: &'static str = "XTRIM";
+    const XGROUP: &'static str = "XGROUP";
+    const XREADGROUP: &'static str = "XREADGROUP";
+    const XACK: &'static str = "XACK";
+    const XPENDING: &'static str = "XPENDING";
+    const XCLAIM: &'static str = "XCLAIM";
+    const XAUTOCLAIM: &'static str = "XAUTOCLAIM";
     const INCR: &'static str = "INCR";
+    const DECR: &'static str = "DECR";
+    const INCRBY: &'static str = "INCRBY";
+    const DECRBY: &'static str = "DECRBY";
+    const INCRBYFLOAT: &'static str = "INCRBYFLOAT";
+    // String command constants
+    const APPEND: &'static str = "APPEND";
+    const STRLEN: &'static str = "STRLEN";
+    const GETRANGE: &'static str = "GETRANGE";
+    const SETRANGE: &'static str = "SETRANGE";
+    const MGET: &'static str = "MGET";
+    const MSET: &'static str = "MSET";
+    const MSETNX: &'static str = "MSETNX";
+    const GETSET: &'static str = "GETSET";
+    const GETDEL: &'static str = "GETDEL";
+    const GETEX: &'static str = "GETEX";
+    const SETNX: &'static str = "SETNX";
+    const SETEX: &'static str = "SETEX";
+    const PSETEX: &'static str = "PSETEX";
+    const LCS: &'static str = "LCS";
+    // Bitmap command constants
+    const SETBIT: &'static str = "SETBIT";
+    const GETBIT: &'static str = "GETBIT";
+    const BITCOUNT: &'static str = "BITCOUNT";
+    const BITPOS: &'static str = "BITPOS";
+    const BITOP: &'static str = "BITOP";
+    const BITFIELD: &'static str = "BITFIELD";
+    const BITFIELD_RO: &'static str = "BITFIELD_RO";
+    // HyperLogLog command constants
+    const PFADD: &'static str = "PFADD";
+    const PFCOUNT: &'static str = "PFCOUNT";
+    const PFMERGE: &'static str = "PFMERGE";
     const FLUSHDB: &'static str = "FLUSHDB";
+    // Database command constants
+    const SELECT: &'static str = "SELECT";
+    const MOVE: &'static str = "MOVE";
+    const SWAPDB: &'static str = "SWAPDB";
+    const FLUSHALL: &'static str = "FLUSHALL";
+    // Keyspace command constants
+    const DEL: &'static str = "DEL";
+    const UNLINK: &'static str = "UNLINK";
+    const EXISTS: &'static str = "EXISTS";
+    const TOUCH: &'static str = "TOUCH";
+    const RENAME: &'static str = "RENAME";
+    const RENAMENX: &'static str = "RENAMENX";
+    const COPY: &'static str = "COPY";
+    const RANDOMKEY: &'static str = "RANDOMKEY";
+    const DBSIZE: &'static str = "DBSIZE";
+    // Expiration command constants
+    const EXPIRE: &'static str = "EXPIRE";
+    const PEXPIRE: &'static str = "PEXPIRE";
+    const EXPIREAT: &'static str = "EXPIREAT";
+    const PEXPIREAT: &'static str = "PEXPIREAT";
+    const TTL: &'static str = "TTL";
+    const PTTL: &'static str = "PTTL";
+    const EXPIRETIME: &'static str = "EXPIRETIME";
+    const PEXPIRETIME: &'static str = "PEXPIRETIME";
+    const PERSIST: &'static str = "PERSIST";
     // List command constants
     const LPUSH: &'static str = "LPUSH";
     const RPUSH: &'static str = "RPUSH";
     const LPOP: &'static str = "LPOP";
     const RPOP: &'static str = "RPOP";
+    const LPUSHX: &'static str = "LPUSHX";
+    const RPUSHX: &'static str = "RPUSHX";
+    const LMPOP: &'static str = "LMPOP";
+    const LMOVE: &'static str = "LMOVE";
+    const RPOPLPUSH: &'static str = "RPOPLPUSH";
+    const LREM: &'static str = "LREM";
+    const BLPOP: &'static str = "BLPOP";
+    const BRPOP: &'static str = "BRPOP";
+    const BLMPOP: &'static str = "BLMPOP";
+    const BLMOVE: &'static str = "BLMOVE";
+    const BRPOPLPUSH: &'static str = "BRPOPLPUSH";
     const LLEN: &'static str = "LLEN";
     const LRANGE: &'static str = "LRANGE";
     const LTRIM: &'static str = "LTRIM";
@@ -74,53 +307,93 @@ impl RedisCommand {
     const LINSERT: &'static str = "LINSERT";
     const LSET: &'static str = "LSET";
     const LINDEX: &'static str = "LINDEX";
+    // Hash command constants
+    const HSET: &'static str = "HSET";
+    const HSETNX: &'static str = "HSETNX";
+    const HGET: &'static str = "HGET";
+    const HMGET: &'static str = "HMGET";
+    const HGETALL: &'static str = "HGETALL";
+    const HDEL: &'static str = "HDEL";
+    const HEXISTS: &'static str = "HEXISTS";
+    const HLEN: &'static str = "HLEN";
+    const HKEYS: &'static str = "HKEYS";
+    const HVALS: &'static str = "HVALS";
+    const HINCRBY: &'static str = "HINCRBY";
+    const HINCRBYFLOAT: &'static str = "HINCRBYFLOAT";
+    const HSTRLEN: &'static str = "HSTRLEN";
+    const HRANDFIELD: &'static str = "HRANDFIELD";
+    // Set command constants
+    const SADD: &'static str = "SADD";
+    const SREM: &'static str = "SREM";
+    const SMEMBERS: &'static str = "SMEMBERS";
+    const SISMEMBER: &'static str = "SISMEMBER";
+    const SMISMEMBER: &'static str = "SMISMEMBER";
+    const SCARD: &'static str = "SCARD";
+    const SPOP: &'static str = "SPOP";
+    const SRANDMEMBER: &'static str = "SRANDMEMBER";
+    const SMOVE: &'static str = "SMOVE";
+    const SINTER: &'static str = "SINTER";
+    const SUNION: &'static str = "SUNION";
+    const SDIFF: &'static str = "SDIFF";
+    const SINTERSTORE: &'static str = "SINTERSTORE";
+    const SUNIONSTORE: &'static str = "SUNIONSTORE";
+    const SDIFFSTORE: &'static str = "SDIFFSTORE";
+    const SINTERCARD: &'static str = "SINTERCARD";
+    // Sorted set command constants
+    const ZADD: &'static str = "ZADD";
+    const ZINCRBY: &'static str = "ZINCRBY";
+    const ZREM: &'static str = "ZREM";
+    const ZSCORE: &'static str = "ZSCORE";
+    const ZMSCORE: &'static str = "ZMSCORE";
+    const ZCARD: &'static str = "ZCARD";
+    const ZCOUNT: &'static str = "ZCOUNT";
+    const ZRANGE: &'static str = "ZRANGE";
+    const ZRANGESTORE: &'static str = "ZRANGESTORE";
+    const ZRANK: &'static str = "ZRANK";
+    const ZREVRANK: &'static str = "ZREVRANK";
+    const ZPOPMIN: &'static str = "ZPOPMIN";
+    const ZPOPMAX: &'static str = "ZPOPMAX";
+    const ZUNIONSTORE: &'static str = "ZUNIONSTORE";
+    const ZINTERSTORE: &'static str = "ZINTERSTORE";
+    // Geo command constants
+    const GEOADD: &'static str = "GEOADD";
+    const GEOPOS: &'static str = "GEOPOS";
+    const GEODIST: &'static str = "GEODIST";
+    const GEOHASH: &'static str = "GEOHASH";
+    const GEOSEARCH: &'static str = "GEOSEARCH";
+    const GEOSEARCHSTORE: &'static str = "GEOSEARCHSTORE";
 
     /// Create command from the data received from the client.
     /// It should check if the parameters are complete, otherwise return None.
     /// For example Set requires 2 parameters, key and value. When this method is called for
     /// a Set command, the first time it will return true to indicate that it expects another.
-    pub fn data(command: String, params: &[String], original_resp: String) -> Option<RedisCommand> {
+    pub fn data(command: String, params: &[Bytes], original_resp: Bytes) -> Option<RedisCommand> {
         match command.to_ascii_uppercase().as_str() {
-            ref command if command.eq_ignore_ascii_case(Self::MULTI) => Some(RedisCommand::Multi),
-            ref command if command.eq_ignore_ascii_case(Self::EXEC) => Some(RedisCommand::Exec),
-            ref command if command.eq_ignore_ascii_case(Self::DISCARD) => Some(RedisCommand::Discard),
-            ref command if command.eq_ignore_ascii_case(Self::PING) => Some(RedisCommand::Ping),
-            ref command if command.eq_ignore_ascii_case(Self::ECHO) => {
+            command if command.eq_ignore_ascii_case(Self::MULTI) => Some(RedisCommand::Multi),
+            command if command.eq_ignore_ascii_case(Self::EXEC) => Some(RedisCommand::Exec),
+            command if command.eq_ignore_ascii_case(Self::DISCARD) => Some(RedisCommand::Discard),
+            command if command.eq_ignore_ascii_case(Self::PING) => Some(RedisCommand::Ping),
+            command if command.eq_ignore_ascii_case(Self::ECHO) => {
                 if params.is_empty() {
                     None
                 } else {
                     Some(RedisCommand::Echo { data: params[0].clone() })
                 }
             },
-            ref command if command.eq_ignore_ascii_case(Self::SET) => {
+            command if command.eq_ignore_ascii_case(Self::SET) => {
                 if params.len() < 2 {
                     None
                 } else {
-                    let key = &params[0];
-                    let value = &params[1];
-                    let ttl = match params.get(2) {
-                        Some(param) if param.eq_ignore_ascii_case("EX") => match params.get(3) {
-                            Some(param) => match param.parse::<usize>() {
-                                Ok(value) => Some(value * 1000),
-                                Err(_) => None,
-                            },
-                            None => None,
-                        },
-                        Some(param) if param.eq_ignore_ascii_case("PX") => match params.get(3) {
-                            Some(param) => match param.parse::<usize>() {
-                                Ok(value) => Some(value),
-                                Err(_) => None,
-                            },
-                            None => None,
-                        },
-                        _ => None,
-                    };
-                    Some(RedisCommand::Set { 
-                        key: key.clone(), 
-                        value: value.clone(), 
-                        ttl, 
-                        original_resp 
-                    })
+                    match parse_set(&params[2..]) {
+                        Ok((ttl, options)) => Some(RedisCommand::Set {
+                            key: params[0].clone(),
+                            value: params[1].clone(),
+                            ttl,
+                            options,
+                            original_resp,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
                 }
             },
             command if command.eq_ignore_ascii_case(Self::GET) => {
@@ -135,16 +408,15 @@ impl RedisCommand {
                 if params.is_empty() {
                     None
                 } else {
-                    let _subcommand = params[0].clone();
-                    Some(RedisCommand::Info { subcommand: params[0].to_string() })
+                    Some(RedisCommand::Info { subcommand: params[0].text() })
                 }
             },
             command if command.eq_ignore_ascii_case(Self::REPLCONF) => {
                 if params.is_empty() {
                     None
                 } else {
-                    let _subcommand = params[0].clone();
-                    let params = params[1..].iter().filter(|&p| !p.is_empty()).cloned().collect();
+                    let _subcommand = params[0].text();
+                    let params = params[1..].iter().filter(|&p| !p.is_empty()).map(|p| p.text()).collect();
                     if _subcommand.is_empty() {
                         Some(RedisCommand::Error { message: "ERR Wrong number of arguments for 'replconf' command".to_string() })
                     } else {
@@ -160,7 +432,7 @@ impl RedisCommand {
                 if params.len() < 2 {
                     None
                 } else {
-                    let replica_id = params[0].clone();
+                    let replica_id = params[0].text();
                     match params[1].parse::<i8>() {
                         Ok(offset) => Some(RedisCommand::Psync { replica_id, offset }),
                         Err(_) => Some(RedisCommand::Error { message: "ERR Invalid offset".to_string() }),
@@ -171,17 +443,18 @@ impl RedisCommand {
                 if params.len() < 2 {
                     None
                 } else {
-                    let numreplicas = params[0].parse::<i64>().unwrap();
-                    let timeout = params[1].parse::<i64>().unwrap();
-                    Some(RedisCommand::Wait { numreplicas, timeout, elapsed: 0 })
+                    match (params[0].parse::<i64>(), params[1].parse::<i64>()) {
+                        (Ok(numreplicas), Ok(timeout)) => Some(RedisCommand::Wait { numreplicas, timeout, elapsed: 0 }),
+                        _ => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    }
                 }
             },
             command if command.eq_ignore_ascii_case(Self::CONFIG) => {
                 if params.len() < 2 {
                     None
                 } else {
-                    let _subcommand = params[0].clone();
-                    let parameter = params[1].clone();
+                    let _subcommand = params[0].text();
+                    let parameter = params[1].text();
                     if _subcommand.is_empty() || parameter.is_empty() {
                         None
                     } else {
@@ -189,6 +462,7 @@ impl RedisCommand {
                     }
                 }
             },
+            command if command.eq_ignore_ascii_case(Self::SAVE) => Some(RedisCommand::Save),
             command if command.eq_ignore_ascii_case(Self::KEYS) => {
                 if params.is_empty() {
                     None
@@ -196,6 +470,30 @@ impl RedisCommand {
                     Some(RedisCommand::Keys { pattern: params[0].clone() })
                 }
             },
+            command if command.eq_ignore_ascii_case(Self::SCAN) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    let cursor = match params[0].parse::<u64>() {
+                        Ok(cursor) => cursor,
+                        Err(_) => return Some(RedisCommand::Error { message: "ERR invalid cursor".to_string() }),
+                    };
+                    let (mut pattern, mut count, mut type_name) = (None, 10, None);
+                    for option in params[1..].chunks(2) {
+                        match option {
+                            [name, value] if name.eq_ignore_ascii_case(b"MATCH") => pattern = Some(value.clone()),
+                            [name, value] if name.eq_ignore_ascii_case(b"COUNT") => match value.parse::<usize>() {
+                                Ok(value) if value > 0 => count = value,
+                                Ok(_) => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                                Err(_) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                            },
+                            [name, value] if name.eq_ignore_ascii_case(b"TYPE") => type_name = Some(value.text()),
+                            _ => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                        }
+                    }
+                    Some(RedisCommand::Scan { cursor, pattern, count, type_name })
+                }
+            },
             command if command.eq_ignore_ascii_case(Self::TYPE) => {
                 if params.is_empty() {
                     None
@@ -205,44 +503,131 @@ impl RedisCommand {
                 }
             },
             command if command.eq_ignore_ascii_case(Self::XADD) => {
-                if params.len() < 3 {
+                if params.len() < 4 {
                     None
                 } else {
                     let key = params[0].clone();
-                    let id = params[1].clone();
-                    if key.is_empty() || id.is_empty() {
-                        None
-                    } else {
-                        let mut fields = HashMap::new();
-                        let mut i = 2;
-                        while i < params.len() - 1 && !params[i].is_empty() && !params[i+1].is_empty() {
-                            fields.insert(params[i].clone(), params[i+1].clone());
-                            i += 2;
+                    let (mut nomkstream, mut trim) = (false, None);
+                    let mut rest = &params[1..];
+                    loop {
+                        match rest[0].to_ascii_uppercase().as_slice() {
+                            b"NOMKSTREAM" => {
+                                nomkstream = true;
+                                rest = &rest[1..];
+                            },
+                            b"MAXLEN" | b"MINID" => match parse_stream_trim(rest) {
+                                Ok((parsed, taken)) => {
+                                    trim = Some(parsed);
+                                    rest = &rest[taken..];
+                                },
+                                Err(message) => return Some(RedisCommand::Error { message }),
+                            },
+                            _ => break,
                         }
-                        if fields.is_empty() {
-                            None
-                        } else {
-                            Some(RedisCommand::XAdd { key, id, fields, original_resp })
+                        if rest.is_empty() {
+                            return Some(RedisCommand::Error { message: "ERR syntax error".to_string() });
                         }
                     }
+                    let id = match parse_xadd_id(&rest[0]) {
+                        Some(id) => id,
+                        None => return Some(RedisCommand::Error { message: INVALID_STREAM_ID.to_string() }),
+                    };
+                    let pairs = &rest[1..];
+                    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                        None
+                    } else {
+                        let fields = pairs.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+                        Some(RedisCommand::XAdd { key, id, fields, nomkstream, trim })
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XLEN) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::XLen { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XDEL) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    match params[1..].iter().map(parse_entry_id).collect::<Option<Vec<_>>>() {
+                        Some(ids) => Some(RedisCommand::XDel { key: params[0].clone(), ids, original_resp }),
+                        None => Some(RedisCommand::Error { message: INVALID_STREAM_ID.to_string() }),
+                    }
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::XRANGE) => {
+            command if command.eq_ignore_ascii_case(Self::XTRIM) => {
                 if params.len() < 3 {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let start = params[1].clone();
-                    let end = params[2].clone();
-                    if key.is_empty() || start.is_empty() || end.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::XRange { key, start, end })
+                    match parse_stream_trim(&params[1..]) {
+                        Ok((trim, taken)) if taken == params.len() - 1 => Some(RedisCommand::XTrim { key: params[0].clone(), trim }),
+                        Ok(_) => Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XRANGE) || command.eq_ignore_ascii_case(Self::XREVRANGE) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    match parse_xrange(params, command.eq_ignore_ascii_case(Self::XREVRANGE)) {
+                        Ok(command) => Some(command),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XGROUP) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    Some(parse_xgroup(params, original_resp).unwrap_or_else(|message| RedisCommand::Error { message }))
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XREADGROUP) => {
+                if params.len() < 6 {
+                    None
+                } else {
+                    Some(parse_xreadgroup(params).unwrap_or_else(|message| RedisCommand::Error { message }))
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XACK) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    match params[2..].iter().map(parse_entry_id).collect::<Option<Vec<_>>>() {
+                        Some(ids) => Some(RedisCommand::XAck { key: params[0].clone(), group: params[1].clone(), ids, original_resp }),
+                        None => Some(RedisCommand::Error { message: INVALID_STREAM_ID.to_string() }),
                     }
                 }
             },
+            command if command.eq_ignore_ascii_case(Self::XPENDING) => {
+                if params.len() < 2 {
+                    None
+                } else if params.len() == 2 {
+                    Some(RedisCommand::XPendingSummary { key: params[0].clone(), group: params[1].clone() })
+                } else {
+                    Some(parse_xpending(params).unwrap_or_else(|message| RedisCommand::Error { message }))
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XCLAIM) => {
+                if params.len() < 5 {
+                    None
+                } else {
+                    Some(parse_xclaim(params).unwrap_or_else(|message| RedisCommand::Error { message }))
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::XAUTOCLAIM) => {
+                if params.len() < 5 {
+                    None
+                } else {
+                    Some(parse_xautoclaim(params).unwrap_or_else(|message| RedisCommand::Error { message }))
+                }
+            },
             command if command.eq_ignore_ascii_case(Self::XREAD) => {
-                let all_params: Vec<String> = params.iter()
+                let all_params: Vec<Bytes> = params.iter()
                     .take_while(|p| !p.is_empty())
                     .cloned()
                     .collect();
@@ -257,161 +642,2098 @@ impl RedisCommand {
                     Err(msg) => Some(RedisCommand::Error { message: msg }),
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::INCR) => {
+            // String commands
+            command if command.eq_ignore_ascii_case(Self::APPEND) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::Append { key: params[0].clone(), value: params[1].clone(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::STRLEN) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::StrLen { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GETRANGE) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match (params[1].parse::<i64>(), params[2].parse::<i64>()) {
+                        (Ok(start), Ok(end)) => Some(RedisCommand::GetRange { key: params[0].clone(), start, end }),
+                        _ => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SETRANGE) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match params[1].parse::<i64>() {
+                        Ok(offset) if offset >= 0 => Some(RedisCommand::SetRange {
+                            key: params[0].clone(),
+                            offset: offset as usize,
+                            value: params[2].clone(),
+                            original_resp,
+                        }),
+                        Ok(_) => Some(RedisCommand::Error { message: "ERR offset is out of range".to_string() }),
+                        Err(_) => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::MGET) => {
                 if params.is_empty() {
                     None
                 } else {
-                    let key = params[0].clone();
-                    Some(RedisCommand::Incr { key })
+                    Some(RedisCommand::MGet { keys: params.to_vec() })
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::FLUSHDB) => {
-                Some(RedisCommand::FlushDB)
+            command if command.eq_ignore_ascii_case(Self::MSET) || command.eq_ignore_ascii_case(Self::MSETNX) => {
+                if params.is_empty() || !params.len().is_multiple_of(2) {
+                    None
+                } else {
+                    let pairs = params.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+                    Some(RedisCommand::MSet { pairs, nx: command == Self::MSETNX, original_resp })
+                }
             },
-            // List commands
-            command if command.eq_ignore_ascii_case(Self::LPUSH) => {
+            command if command.eq_ignore_ascii_case(Self::GETSET) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::GetSet { key: params[0].clone(), value: params[1].clone(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GETDEL) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::GetDel { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GETEX) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    match parse_getex(&params[1..]) {
+                        Ok((milliseconds, absolute, persist)) => Some(RedisCommand::GetEx { key: params[0].clone(), milliseconds, absolute, persist }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SETNX) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::SetNx { key: params[0].clone(), value: params[1].clone(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SETEX) || command.eq_ignore_ascii_case(Self::PSETEX) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    let unit = if command == Self::SETEX { 1000 } else { 1 };
+                    match params[1].parse::<i64>() {
+                        Ok(time) => match time.checked_mul(unit).filter(|milliseconds| *milliseconds > 0) {
+                            Some(milliseconds) => Some(RedisCommand::SetEx { key: params[0].clone(), milliseconds, value: params[2].clone() }),
+                            None => Some(RedisCommand::Error { message: format!("ERR invalid expire time in '{}' command", command.to_ascii_lowercase()) }),
+                        },
+                        Err(_) => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LCS) => {
                 if params.len() < 2 {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let value = params[1].clone();
-                    if key.is_empty() || value.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LPush { key, value })
+                    let (mut len, mut idx, mut min_match_len, mut with_match_len) = (false, false, 0, false);
+                    let mut options = params[2..].iter();
+                    while let Some(option) = options.next() {
+                        if option.eq_ignore_ascii_case(b"LEN") {
+                            len = true;
+                        } else if option.eq_ignore_ascii_case(b"IDX") {
+                            idx = true;
+                        } else if option.eq_ignore_ascii_case(b"WITHMATCHLEN") {
+                            with_match_len = true;
+                        } else if option.eq_ignore_ascii_case(b"MINMATCHLEN") {
+                            match options.next().map(|value| value.parse::<i64>()) {
+                                Some(Ok(value)) => min_match_len = value.max(0) as usize,
+                                Some(Err(_)) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                                None => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                            }
+                        } else {
+                            return Some(RedisCommand::Error { message: "ERR syntax error".to_string() });
+                        }
                     }
+                    if len && idx {
+                        return Some(RedisCommand::Error { message: "ERR If you want both the length and indexes, please just use IDX.".to_string() });
+                    }
+                    Some(RedisCommand::Lcs { key1: params[0].clone(), key2: params[1].clone(), len, idx, min_match_len, with_match_len })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SETBIT) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    let Some(offset) = bitmap::parse_offset(&params[1], None) else {
+                        return Some(RedisCommand::Error { message: "ERR bit offset is not an integer or out of range".to_string() });
+                    };
+                    let value = match &params[2][..] {
+                        b"0" => false,
+                        b"1" => true,
+                        _ => return Some(RedisCommand::Error { message: "ERR bit is not an integer or out of range".to_string() }),
+                    };
+                    Some(RedisCommand::SetBit { key: params[0].clone(), offset, value, original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GETBIT) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    match bitmap::parse_offset(&params[1], None) {
+                        Some(offset) => Some(RedisCommand::GetBit { key: params[0].clone(), offset }),
+                        None => Some(RedisCommand::Error { message: "ERR bit offset is not an integer or out of range".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::BITCOUNT) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    let (range, in_bits) = match params.len() {
+                        1 => (None, false),
+                        3 | 4 => match parse_bit_range(&params[1..]) {
+                            Ok((start, end, in_bits)) => (start.zip(end), in_bits),
+                            Err(message) => return Some(RedisCommand::Error { message }),
+                        },
+                        _ => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                    };
+                    Some(RedisCommand::BitCount { key: params[0].clone(), range, in_bits })
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::RPUSH) => {
+            command if command.eq_ignore_ascii_case(Self::BITPOS) => {
                 if params.len() < 2 {
                     None
+                } else if params.len() > 5 {
+                    Some(RedisCommand::Error { message: "ERR syntax error".to_string() })
                 } else {
-                    let key = params[0].clone();
-                    let value = params[1].clone();
-                    if key.is_empty() || value.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::RPush { key, value })
+                    let bit = match params[1].parse::<i64>() {
+                        Ok(0) => false,
+                        Ok(1) => true,
+                        Ok(_) => return Some(RedisCommand::Error { message: "ERR The bit argument must be 1 or 0.".to_string() }),
+                        Err(_) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    };
+                    match parse_bit_range(&params[2..]) {
+                        Ok((start, end, in_bits)) => Some(RedisCommand::BitPos { key: params[0].clone(), bit, start, end, in_bits }),
+                        Err(message) => Some(RedisCommand::Error { message }),
                     }
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LPOP) => {
+            command if command.eq_ignore_ascii_case(Self::BITOP) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    let op = match params[0].to_ascii_uppercase().as_slice() {
+                        b"AND" => BitOp::And,
+                        b"OR" => BitOp::Or,
+                        b"XOR" => BitOp::Xor,
+                        b"NOT" if params.len() == 3 => BitOp::Not,
+                        b"NOT" => return Some(RedisCommand::Error { message: "ERR BITOP NOT must be called with a single source key.".to_string() }),
+                        _ => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                    };
+                    Some(RedisCommand::BitOp { op, destination: params[1].clone(), keys: params[2..].to_vec(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::BITFIELD) || command.eq_ignore_ascii_case(Self::BITFIELD_RO) => {
                 if params.is_empty() {
                     None
                 } else {
-                    let key = params[0].clone();
-                    Some(RedisCommand::LPop { key })
+                    let read_only = command == Self::BITFIELD_RO;
+                    match parse_bitfield(&params[1..], read_only) {
+                        Ok(ops) => Some(RedisCommand::BitField { key: params[0].clone(), ops, read_only, original_resp }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::RPOP) => {
+            command if command.eq_ignore_ascii_case(Self::PFADD) => {
                 if params.is_empty() {
                     None
                 } else {
-                    let key = params[0].clone();
-                    Some(RedisCommand::RPop { key })
+                    Some(RedisCommand::PfAdd { key: params[0].clone(), elements: params[1..].to_vec(), original_resp })
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LLEN) => {
+            command if command.eq_ignore_ascii_case(Self::PFCOUNT) => {
                 if params.is_empty() {
                     None
                 } else {
-                    let key = params[0].clone();
-                    Some(RedisCommand::LLen { key })
+                    Some(RedisCommand::PfCount { keys: params.to_vec() })
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LRANGE) => {
-                if params.len() < 3 {
+            command if command.eq_ignore_ascii_case(Self::PFMERGE) => {
+                if params.is_empty() {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let start = params[1].parse::<i64>().unwrap_or(0);
-                    let stop = params[2].parse::<i64>().unwrap_or(-1);
-                    if key.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LRange { key, start, stop })
-                    }
+                    Some(RedisCommand::PfMerge { destination: params[0].clone(), sources: params[1..].to_vec(), original_resp })
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LTRIM) => {
-                if params.len() < 3 {
+            command if command.eq_ignore_ascii_case(Self::INCR) || command.eq_ignore_ascii_case(Self::DECR) => {
+                if params.len() != 1 {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let start = params[1].parse::<i64>().unwrap_or(0);
-                    let stop = params[2].parse::<i64>().unwrap_or(-1);
-                    if key.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LTrim { key, start, stop })
+                    let delta = if command == Self::INCR { 1 } else { -1 };
+                    Some(RedisCommand::Incr { key: params[0].clone(), delta, original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::INCRBY) || command.eq_ignore_ascii_case(Self::DECRBY) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    let delta = match params[1].parse::<i64>() {
+                        Ok(delta) if command == Self::INCRBY => delta,
+                        Ok(delta) => match delta.checked_neg() {
+                            Some(delta) => delta,
+                            None => return Some(RedisCommand::Error { message: "ERR decrement would overflow".to_string() }),
+                        },
+                        Err(_) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    };
+                    Some(RedisCommand::Incr { key: params[0].clone(), delta, original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::INCRBYFLOAT) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    match params[1].parse::<f64>() {
+                        Ok(increment) if increment.is_finite() => Some(RedisCommand::IncrByFloat { key: params[0].clone(), increment }),
+                        _ => Some(RedisCommand::Error { message: "ERR value is not a valid float".to_string() }),
                     }
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LPOS) => {
-                if params.len() < 2 {
+            command if command.eq_ignore_ascii_case(Self::FLUSHDB) => {
+                Some(RedisCommand::FlushDB { original_resp })
+            },
+            // Database commands
+            command if command.eq_ignore_ascii_case(Self::SELECT) => {
+                if params.len() != 1 {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let element = params[1].clone();
-                    let mut count = None;
-                    let mut i = 2;
-                    while i < params.len() {
-                        if params[i].eq_ignore_ascii_case("COUNT") && i + 1 < params.len() {
-                            count = params[i + 1].parse::<i64>().ok();
-                            break;
-                        }
-                        i += 1;
+                    match params[0].parse::<i64>() {
+                        Ok(index) => Some(RedisCommand::Select { index }),
+                        Err(_) => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
                     }
-                    if key.is_empty() || element.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LPos { key, element, count })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::MOVE) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    match params[1].parse::<i64>() {
+                        Ok(db) => Some(RedisCommand::Move { key: params[0].clone(), db, original_resp }),
+                        Err(_) => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
                     }
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LINSERT) => {
-                if params.len() < 4 {
+            command if command.eq_ignore_ascii_case(Self::SWAPDB) => {
+                if params.len() != 2 {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let before = params[1].eq_ignore_ascii_case("BEFORE");
-                    let pivot = params[2].clone();
-                    let element = params[3].clone();
-                    if key.is_empty() || pivot.is_empty() || element.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LInsert { key, before, pivot, element })
+                    match (params[0].parse::<i64>(), params[1].parse::<i64>()) {
+                        (Err(_), _) => Some(RedisCommand::Error { message: "ERR invalid first DB index".to_string() }),
+                        (_, Err(_)) => Some(RedisCommand::Error { message: "ERR invalid second DB index".to_string() }),
+                        (Ok(first), Ok(second)) => Some(RedisCommand::SwapDb { first, second, original_resp }),
                     }
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LSET) => {
-                if params.len() < 3 {
+            command if command.eq_ignore_ascii_case(Self::FLUSHALL) => {
+                Some(RedisCommand::FlushAll { original_resp })
+            },
+            // Keyspace commands
+            command if command.eq_ignore_ascii_case(Self::DEL) || command.eq_ignore_ascii_case(Self::UNLINK) => {
+                if params.is_empty() {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let index = params[1].parse::<i64>().unwrap_or(0);
-                    let element = params[2].clone();
-                    if key.is_empty() || element.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LSet { key, index, element })
+                    Some(RedisCommand::Del { keys: params.to_vec(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::EXISTS) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    Some(RedisCommand::Exists { keys: params.to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::TOUCH) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    Some(RedisCommand::Touch { keys: params.to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::RENAME) || command.eq_ignore_ascii_case(Self::RENAMENX) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::Rename {
+                        key: params[0].clone(),
+                        new_key: params[1].clone(),
+                        nx: command == Self::RENAMENX,
+                        original_resp,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::COPY) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    let (mut db, mut replace) = (None, false);
+                    let mut options = params[2..].iter();
+                    while let Some(option) = options.next() {
+                        if option.eq_ignore_ascii_case(b"REPLACE") {
+                            replace = true;
+                        } else if option.eq_ignore_ascii_case(b"DB") {
+                            match options.next().map(|value| value.parse::<i64>()) {
+                                Some(Ok(index)) => db = Some(index),
+                                Some(Err(_)) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                                None => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                            }
+                        } else {
+                            return Some(RedisCommand::Error { message: "ERR syntax error".to_string() });
+                        }
                     }
+                    Some(RedisCommand::Copy { source: params[0].clone(), destination: params[1].clone(), db, replace, original_resp })
                 }
             },
-            command if command.eq_ignore_ascii_case(Self::LINDEX) => {
+            command if command.eq_ignore_ascii_case(Self::RANDOMKEY) => Some(RedisCommand::RandomKey),
+            command if command.eq_ignore_ascii_case(Self::DBSIZE) => Some(RedisCommand::DbSize),
+            // Expiration commands
+            command if command.eq_ignore_ascii_case(Self::EXPIRE)
+                || command.eq_ignore_ascii_case(Self::PEXPIRE)
+                || command.eq_ignore_ascii_case(Self::EXPIREAT)
+                || command.eq_ignore_ascii_case(Self::PEXPIREAT) => {
                 if params.len() < 2 {
                     None
                 } else {
-                    let key = params[0].clone();
-                    let index = params[1].parse::<i64>().unwrap_or(0);
-                    if key.is_empty() {
-                        None
-                    } else {
-                        Some(RedisCommand::LIndex { key, index })
+                    let in_milliseconds = command == Self::PEXPIRE || command == Self::PEXPIREAT;
+                    let absolute = command == Self::EXPIREAT || command == Self::PEXPIREAT;
+                    match parse_expire(&command.to_ascii_lowercase(), &params[1..], in_milliseconds, absolute) {
+                        Ok((milliseconds, options)) => Some(RedisCommand::Expire { key: params[0].clone(), milliseconds, absolute, options }),
+                        Err(message) => Some(RedisCommand::Error { message }),
                     }
                 }
             },
-            _ => Some(RedisCommand::Error { message: format!("Unknown command: {}", command) }),
+            command if command.eq_ignore_ascii_case(Self::TTL)
+                || command.eq_ignore_ascii_case(Self::PTTL)
+                || command.eq_ignore_ascii_case(Self::EXPIRETIME)
+                || command.eq_ignore_ascii_case(Self::PEXPIRETIME) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::Ttl {
+                        key: params[0].clone(),
+                        in_milliseconds: command == Self::PTTL || command == Self::PEXPIRETIME,
+                        absolute: command == Self::EXPIRETIME || command == Self::PEXPIRETIME,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::PERSIST) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::Persist { key: params[0].clone(), original_resp })
+                }
+            },
+            // List commands
+            command if command.eq_ignore_ascii_case(Self::LPUSH)
+                || command.eq_ignore_ascii_case(Self::RPUSH)
+                || command.eq_ignore_ascii_case(Self::LPUSHX)
+                || command.eq_ignore_ascii_case(Self::RPUSHX) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::Push {
+                        key: params[0].clone(),
+                        end: if command.starts_with('L') { ListEnd::Left } else { ListEnd::Right },
+                        values: params[1..].to_vec(),
+                        only_if_exists: command.ends_with('X'),
+                        original_resp,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LPOP)
+                || command.eq_ignore_ascii_case(Self::RPOP) => {
+                if params.is_empty() || params.len() > 2 {
+                    None
+                } else {
+                    let count = match params.get(1) {
+                        Some(param) => match param.parse::<usize>() {
+                            Ok(count) => Some(count),
+                            Err(_) => return Some(RedisCommand::Error { message: "ERR value is out of range, must be positive".to_string() }),
+                        },
+                        None => None,
+                    };
+                    let end = if command == Self::LPOP { ListEnd::Left } else { ListEnd::Right };
+                    Some(RedisCommand::Pop { key: params[0].clone(), end, count, original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LMPOP) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    match parse_lmpop(params) {
+                        Ok((keys, end, count)) => Some(RedisCommand::LMPop { keys, end, count }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LMOVE) => {
+                if params.len() != 4 {
+                    None
+                } else {
+                    match (parse_list_end(&params[2]), parse_list_end(&params[3])) {
+                        (Some(from), Some(to)) => Some(RedisCommand::LMove {
+                            source: params[0].clone(),
+                            destination: params[1].clone(),
+                            from,
+                            to,
+                            original_resp,
+                        }),
+                        _ => Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::RPOPLPUSH) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::LMove {
+                        source: params[0].clone(),
+                        destination: params[1].clone(),
+                        from: ListEnd::Right,
+                        to: ListEnd::Left,
+                        original_resp,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LREM) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match params[1].parse::<i64>() {
+                        Ok(count) => Some(RedisCommand::LRem { key: params[0].clone(), count, element: params[2].clone(), original_resp }),
+                        Err(_) => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::BLPOP)
+                || command.eq_ignore_ascii_case(Self::BRPOP) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    match parse_timeout(&params[params.len() - 1]) {
+                        Ok(timeout) => Some(RedisCommand::BPop {
+                            keys: params[..params.len() - 1].to_vec(),
+                            end: if command == Self::BLPOP { ListEnd::Left } else { ListEnd::Right },
+                            timeout,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::BLMPOP) => {
+                if params.len() < 4 {
+                    None
+                } else {
+                    match parse_timeout(&params[0]).and_then(|timeout| Ok((timeout, parse_lmpop(&params[1..])?))) {
+                        Ok((timeout, (keys, end, count))) => Some(RedisCommand::BLMPop { keys, end, count, timeout }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::BLMOVE) => {
+                if params.len() != 5 {
+                    None
+                } else {
+                    match (parse_list_end(&params[2]), parse_list_end(&params[3])) {
+                        (Some(from), Some(to)) => match parse_timeout(&params[4]) {
+                            Ok(timeout) => Some(RedisCommand::BLMove {
+                                source: params[0].clone(),
+                                destination: params[1].clone(),
+                                from,
+                                to,
+                                timeout,
+                            }),
+                            Err(message) => Some(RedisCommand::Error { message }),
+                        },
+                        _ => Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::BRPOPLPUSH) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match parse_timeout(&params[2]) {
+                        Ok(timeout) => Some(RedisCommand::BLMove {
+                            source: params[0].clone(),
+                            destination: params[1].clone(),
+                            from: ListEnd::Right,
+                            to: ListEnd::Left,
+                            timeout,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LLEN) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    Some(RedisCommand::LLen { key })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LRANGE) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let start = params[1].parse::<i64>().unwrap_or(0);
+                    let stop = params[2].parse::<i64>().unwrap_or(-1);
+                    if key.is_empty() {
+                        None
+                    } else {
+                        Some(RedisCommand::LRange { key, start, stop })
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LTRIM) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let start = params[1].parse::<i64>().unwrap_or(0);
+                    let stop = params[2].parse::<i64>().unwrap_or(-1);
+                    if key.is_empty() {
+                        None
+                    } else {
+                        Some(RedisCommand::LTrim { key, start, stop })
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LPOS) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    match parse_lpos(&params[2..]) {
+                        Ok((rank, count, maxlen)) => Some(RedisCommand::LPos {
+                            key: params[0].clone(),
+                            element: params[1].clone(),
+                            rank,
+                            count,
+                            maxlen,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LINSERT) => {
+                if params.len() < 4 {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let before = params[1].eq_ignore_ascii_case(b"BEFORE");
+                    let pivot = params[2].clone();
+                    let element = params[3].clone();
+                    if key.is_empty() || pivot.is_empty() || element.is_empty() {
+                        None
+                    } else {
+                        Some(RedisCommand::LInsert { key, before, pivot, element })
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LSET) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let index = params[1].parse::<i64>().unwrap_or(0);
+                    let element = params[2].clone();
+                    if key.is_empty() || element.is_empty() {
+                        None
+                    } else {
+                        Some(RedisCommand::LSet { key, index, element })
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::LINDEX) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let index = params[1].parse::<i64>().unwrap_or(0);
+                    if key.is_empty() {
+                        None
+                    } else {
+                        Some(RedisCommand::LIndex { key, index })
+                    }
+                }
+            },
+            // Hash commands
+            command if command.eq_ignore_ascii_case(Self::HSET) => {
+                if params.len() < 3 || params.len().is_multiple_of(2) {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let fields = params[1..].chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect();
+                    Some(RedisCommand::HSet { key, fields, original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HSETNX) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    Some(RedisCommand::HSetNx {
+                        key: params[0].clone(),
+                        field: params[1].clone(),
+                        value: params[2].clone(),
+                        original_resp,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HGET) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::HGet { key: params[0].clone(), field: params[1].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HMGET) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::HMGet { key: params[0].clone(), fields: params[1..].to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HGETALL) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::HGetAll { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HDEL) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::HDel { key: params[0].clone(), fields: params[1..].to_vec(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HEXISTS) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::HExists { key: params[0].clone(), field: params[1].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HLEN) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::HLen { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HKEYS) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::HKeys { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HVALS) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::HVals { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HINCRBY) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match params[2].parse::<i64>() {
+                        Ok(increment) => Some(RedisCommand::HIncrBy {
+                            key: params[0].clone(),
+                            field: params[1].clone(),
+                            increment,
+                            original_resp,
+                        }),
+                        Err(_) => Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HINCRBYFLOAT) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match params[2].parse::<f64>() {
+                        Ok(increment) if increment.is_finite() => Some(RedisCommand::HIncrByFloat {
+                            key: params[0].clone(),
+                            field: params[1].clone(),
+                            increment,
+                        }),
+                        _ => Some(RedisCommand::Error { message: "ERR value is not a valid float".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HSTRLEN) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::HStrLen { key: params[0].clone(), field: params[1].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::HRANDFIELD) => {
+                if params.is_empty() || params.len() > 3 {
+                    None
+                } else {
+                    let key = params[0].clone();
+                    let count = match params.get(1).map(|p| p.parse::<i64>()) {
+                        Some(Ok(count)) => Some(count),
+                        Some(Err(_)) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                        None => None,
+                    };
+                    let with_values = match params.get(2) {
+                        Some(param) if param.eq_ignore_ascii_case(b"WITHVALUES") => true,
+                        Some(_) => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                        None => false,
+                    };
+                    Some(RedisCommand::HRandField { key, count, with_values })
+                }
+            },
+            // Set commands
+            command if command.eq_ignore_ascii_case(Self::SADD) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::SAdd { key: params[0].clone(), members: params[1..].to_vec(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SREM) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::SRem { key: params[0].clone(), members: params[1..].to_vec(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SMEMBERS) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::SMembers { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SISMEMBER) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::SIsMember { key: params[0].clone(), member: params[1].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SMISMEMBER) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::SMIsMember { key: params[0].clone(), members: params[1..].to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SCARD) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::SCard { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SPOP) => {
+                if params.is_empty() || params.len() > 2 {
+                    None
+                } else {
+                    let count = match params.get(1) {
+                        Some(param) => match param.parse::<usize>() {
+                            Ok(count) => Some(count),
+                            Err(_) => return Some(RedisCommand::Error { message: "ERR value is out of range, must be positive".to_string() }),
+                        },
+                        None => None,
+                    };
+                    Some(RedisCommand::SPop { key: params[0].clone(), count })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SRANDMEMBER) => {
+                if params.is_empty() || params.len() > 2 {
+                    None
+                } else {
+                    let count = match params.get(1) {
+                        Some(param) => match param.parse::<i64>() {
+                            Ok(count) => Some(count),
+                            Err(_) => return Some(RedisCommand::Error { message: "ERR value is not an integer or out of range".to_string() }),
+                        },
+                        None => None,
+                    };
+                    Some(RedisCommand::SRandMember { key: params[0].clone(), count })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SMOVE) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    Some(RedisCommand::SMove {
+                        source: params[0].clone(),
+                        destination: params[1].clone(),
+                        member: params[2].clone(),
+                        original_resp,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SINTER)
+                || command.eq_ignore_ascii_case(Self::SUNION)
+                || command.eq_ignore_ascii_case(Self::SDIFF) => {
+                if params.is_empty() {
+                    None
+                } else {
+                    let op = match command {
+                        Self::SINTER => SetOp::Inter,
+                        Self::SUNION => SetOp::Union,
+                        _ => SetOp::Diff,
+                    };
+                    Some(RedisCommand::SetOperation { op, keys: params.to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SINTERSTORE)
+                || command.eq_ignore_ascii_case(Self::SUNIONSTORE)
+                || command.eq_ignore_ascii_case(Self::SDIFFSTORE) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    let op = match command {
+                        Self::SINTERSTORE => SetOp::Inter,
+                        Self::SUNIONSTORE => SetOp::Union,
+                        _ => SetOp::Diff,
+                    };
+                    Some(RedisCommand::SetOperationStore {
+                        op,
+                        destination: params[0].clone(),
+                        keys: params[1..].to_vec(),
+                        original_resp,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::SINTERCARD) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    let numkeys = match params[0].parse::<usize>() {
+                        Ok(numkeys) if numkeys > 0 => numkeys,
+                        _ => return Some(RedisCommand::Error { message: "ERR numkeys should be greater than 0".to_string() }),
+                    };
+                    if numkeys > params.len() - 1 {
+                        return Some(RedisCommand::Error { message: "ERR Number of keys can't be greater than number of args".to_string() });
+                    }
+                    let keys = params[1..=numkeys].to_vec();
+                    let limit = match &params[numkeys + 1..] {
+                        [] => 0,
+                        [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => match limit.parse::<usize>() {
+                            Ok(limit) => limit,
+                            Err(_) => return Some(RedisCommand::Error { message: "ERR LIMIT can't be negative".to_string() }),
+                        },
+                        _ => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                    };
+                    Some(RedisCommand::SInterCard { keys, limit })
+                }
+            },
+            // Sorted set commands
+            command if command.eq_ignore_ascii_case(Self::ZADD) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    match parse_zadd(&params[1..]) {
+                        Ok((options, pairs)) => Some(RedisCommand::ZAdd { key: params[0].clone(), options, pairs, original_resp }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZINCRBY) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match zset::parse_score(&params[1]) {
+                        Some(increment) => Some(RedisCommand::ZIncrBy {
+                            key: params[0].clone(),
+                            increment,
+                            member: params[2].clone(),
+                            original_resp,
+                        }),
+                        None => Some(RedisCommand::Error { message: "ERR value is not a valid float".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZREM) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::ZRem { key: params[0].clone(), members: params[1..].to_vec(), original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZSCORE) => {
+                if params.len() != 2 {
+                    None
+                } else {
+                    Some(RedisCommand::ZScore { key: params[0].clone(), member: params[1].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZMSCORE) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    Some(RedisCommand::ZMScore { key: params[0].clone(), members: params[1..].to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZCARD) => {
+                if params.len() != 1 {
+                    None
+                } else {
+                    Some(RedisCommand::ZCard { key: params[0].clone() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZCOUNT) => {
+                if params.len() != 3 {
+                    None
+                } else {
+                    match (ScoreBound::parse(&params[1]), ScoreBound::parse(&params[2])) {
+                        (Some(min), Some(max)) => Some(RedisCommand::ZCount { key: params[0].clone(), min, max }),
+                        _ => Some(RedisCommand::Error { message: "ERR min or max is not a float".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZRANGE) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    match parse_zrange(&params[1..]) {
+                        Ok((spec, with_scores)) => Some(RedisCommand::ZRange { key: params[0].clone(), spec, with_scores }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZRANGESTORE) => {
+                if params.len() < 4 {
+                    None
+                } else {
+                    match parse_zrange(&params[2..]) {
+                        Ok((_, true)) => Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                        Ok((spec, false)) => Some(RedisCommand::ZRangeStore {
+                            destination: params[0].clone(),
+                            source: params[1].clone(),
+                            spec,
+                            original_resp,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZRANK)
+                || command.eq_ignore_ascii_case(Self::ZREVRANK) => {
+                if params.len() < 2 || params.len() > 3 {
+                    None
+                } else {
+                    let with_score = match params.get(2) {
+                        Some(param) if param.eq_ignore_ascii_case(b"WITHSCORE") => true,
+                        Some(_) => return Some(RedisCommand::Error { message: "ERR syntax error".to_string() }),
+                        None => false,
+                    };
+                    Some(RedisCommand::ZRank {
+                        key: params[0].clone(),
+                        member: params[1].clone(),
+                        rev: command == Self::ZREVRANK,
+                        with_score,
+                    })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZPOPMIN)
+                || command.eq_ignore_ascii_case(Self::ZPOPMAX) => {
+                if params.is_empty() || params.len() > 2 {
+                    None
+                } else {
+                    let count = match params.get(1) {
+                        Some(param) => match param.parse::<usize>() {
+                            Ok(count) => Some(count),
+                            Err(_) => return Some(RedisCommand::Error { message: "ERR value is out of range, must be positive".to_string() }),
+                        },
+                        None => None,
+                    };
+                    Some(RedisCommand::ZPop { key: params[0].clone(), count, max: command == Self::ZPOPMAX, original_resp })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::ZUNIONSTORE)
+                || command.eq_ignore_ascii_case(Self::ZINTERSTORE) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    let op = match command {
+                        Self::ZINTERSTORE => SetOp::Inter,
+                        _ => SetOp::Union,
+                    };
+                    match parse_zstore(&command.to_ascii_lowercase(), &params[1..]) {
+                        Ok((keys, weights, aggregate)) => Some(RedisCommand::ZSetOperationStore {
+                            op,
+                            destination: params[0].clone(),
+                            keys,
+                            weights,
+                            aggregate,
+                            original_resp,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GEOADD) => {
+                if params.len() < 4 {
+                    None
+                } else {
+                    match parse_geoadd(&params[1..]) {
+                        Ok((options, pairs)) => Some(RedisCommand::GeoAdd { key: params[0].clone(), options, pairs, original_resp }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GEOPOS) || command.eq_ignore_ascii_case(Self::GEOHASH) => {
+                if params.is_empty() {
+                    None
+                } else if command == Self::GEOPOS {
+                    Some(RedisCommand::GeoPos { key: params[0].clone(), members: params[1..].to_vec() })
+                } else {
+                    Some(RedisCommand::GeoHash { key: params[0].clone(), members: params[1..].to_vec() })
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GEODIST) => {
+                if params.len() < 3 {
+                    None
+                } else if params.len() > 4 {
+                    Some(RedisCommand::Error { message: "ERR syntax error".to_string() })
+                } else {
+                    match params.get(3).map_or(Some(1.0), |unit| geo::parse_unit(unit)) {
+                        Some(unit) => Some(RedisCommand::GeoDist {
+                            key: params[0].clone(),
+                            member1: params[1].clone(),
+                            member2: params[2].clone(),
+                            unit,
+                        }),
+                        None => Some(RedisCommand::Error { message: "ERR unsupported unit provided. please use M, KM, FT, MI".to_string() }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GEOSEARCH) => {
+                if params.len() < 2 {
+                    None
+                } else {
+                    match parse_geosearch(command, &params[1..], false) {
+                        Ok(query) => Some(RedisCommand::GeoSearch { key: params[0].clone(), query }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            command if command.eq_ignore_ascii_case(Self::GEOSEARCHSTORE) => {
+                if params.len() < 3 {
+                    None
+                } else {
+                    match parse_geosearch(command, &params[2..], true) {
+                        Ok(query) => Some(RedisCommand::GeoSearchStore {
+                            destination: params[0].clone(),
+                            source: params[1].clone(),
+                            query,
+                            original_resp,
+                        }),
+                        Err(message) => Some(RedisCommand::Error { message }),
+                    }
+                }
+            },
+            _ => Some(RedisCommand::Error { message: format!("Unknown command: {}", command) }),
+        }
+    }
+
+    /// The keys this command touches, so the keyspace locks can be taken before it runs.
+    /// Commands that only look at server state (or, like KEYS and SCAN, walk the keyspace
+    /// without needing a consistent snapshot) don't lock anything.
+    pub fn key_access(&self) -> KeyAccess<'_> {
+        match self {
+            RedisCommand::Get { key }
+            | RedisCommand::Type { key }
+            | RedisCommand::Ttl { key, .. }
+            | RedisCommand::XRange { key, .. }
+            | RedisCommand::XLen { key }
+            | RedisCommand::XPendingSummary { key, .. }
+            | RedisCommand::XPending { key, .. }
+            | RedisCommand::LLen { key }
+            | RedisCommand::LRange { key, .. }
+            | RedisCommand::LPos { key, .. }
+            | RedisCommand::LIndex { key, .. }
+            | RedisCommand::HGet { key, .. }
+            | RedisCommand::HMGet { key, .. }
+            | RedisCommand::HGetAll { key }
+            | RedisCommand::HExists { key, .. }
+            | RedisCommand::HLen { key }
+            | RedisCommand::HKeys { key }
+            | RedisCommand::HVals { key }
+            | RedisCommand::HStrLen { key, .. }
+            | RedisCommand::HRandField { key, .. }
+            | RedisCommand::SMembers { key }
+            | RedisCommand::SIsMember { key, .. }
+            | RedisCommand::SMIsMember { key, .. }
+            | RedisCommand::SCard { key }
+            | RedisCommand::SRandMember { key, .. }
+            | RedisCommand::ZScore { key, .. }
+            | RedisCommand::ZMScore { key, .. }
+            | RedisCommand::ZCard { key }
+            | RedisCommand::ZCount { key, .. }
+            | RedisCommand::ZRange { key, .. }
+            | RedisCommand::ZRank { key, .. }
+            | RedisCommand::StrLen { key }
+            | RedisCommand::GetRange { key, .. }
+            | RedisCommand::GetBit { key, .. }
+            | RedisCommand::BitCount { key, .. }
+            | RedisCommand::BitPos { key, .. }
+            | RedisCommand::BitField { key, read_only: true, .. }
+            | RedisCommand::GeoPos { key, .. }
+            | RedisCommand::GeoDist { key, .. }
+            | RedisCommand::GeoHash { key, .. }
+            | RedisCommand::GeoSearch { key, .. } => KeyAccess::Read(vec![key]),
+            RedisCommand::Lcs { key1, key2, .. } => KeyAccess::Read(vec![key1, key2]),
+            RedisCommand::SetOperation { keys, .. }
+            | RedisCommand::SInterCard { keys, .. }
+            | RedisCommand::Exists { keys }
+            | RedisCommand::Touch { keys }
+            | RedisCommand::PfCount { keys }
+            | RedisCommand::MGet { keys } => KeyAccess::Read(keys.iter().map(|key| &key[..]).collect()),
+            RedisCommand::Set { key, .. }
+            | RedisCommand::Incr { key, .. }
+            | RedisCommand::IncrByFloat { key, .. }
+            | RedisCommand::Append { key, .. }
+            | RedisCommand::SetRange { key, .. }
+            | RedisCommand::GetSet { key, .. }
+            | RedisCommand::GetDel { key }
+            | RedisCommand::GetEx { key, .. }
+            | RedisCommand::SetNx { key, .. }
+            | RedisCommand::SetEx { key, .. }
+            | RedisCommand::SetBit { key, .. }
+            | RedisCommand::BitField { key, .. }
+            | RedisCommand::PfAdd { key, .. }
+            | RedisCommand::Expire { key, .. }
+            | RedisCommand::Persist { key, .. }
+            | RedisCommand::Move { key, .. }
+            | RedisCommand::XAdd { key, .. }
+            | RedisCommand::XDel { key, .. }
+            | RedisCommand::XGroupCreate { key, .. }
+            | RedisCommand::XGroupSetId { key, .. }
+            | RedisCommand::XGroupDestroy { key, .. }
+            | RedisCommand::XGroupCreateConsumer { key, .. }
+            | RedisCommand::XGroupDelConsumer { key, .. }
+            | RedisCommand::XAck { key, .. }
+            | RedisCommand::XClaim { key, .. }
+            | RedisCommand::XAutoClaim { key, .. }
+            | RedisCommand::XTrim { key, .. }
+            | RedisCommand::Push { key, .. }
+            | RedisCommand::Pop { key, .. }
+            | RedisCommand::LRem { key, .. }
+            | RedisCommand::LTrim { key, .. }
+            | RedisCommand::LInsert { key, .. }
+            | RedisCommand::LSet { key, .. }
+            | RedisCommand::HSet { key, .. }
+            | RedisCommand::HSetNx { key, .. }
+            | RedisCommand::HDel { key, .. }
+            | RedisCommand::HIncrBy { key, .. }
+            | RedisCommand::HIncrByFloat { key, .. }
+            | RedisCommand::SAdd { key, .. }
+            | RedisCommand::SRem { key, .. }
+            | RedisCommand::SPop { key, .. }
+            | RedisCommand::ZAdd { key, .. }
+            | RedisCommand::ZIncrBy { key, .. }
+            | RedisCommand::ZRem { key, .. }
+            | RedisCommand::ZPop { key, .. }
+            | RedisCommand::GeoAdd { key, .. } => KeyAccess::Write(vec![key]),
+            RedisCommand::Del { keys, .. }
+            | RedisCommand::LMPop { keys, .. }
+            | RedisCommand::BPop { keys, .. }
+            | RedisCommand::BLMPop { keys, .. }
+            | RedisCommand::XReadGroup { keys, .. } => KeyAccess::Write(keys.iter().map(|key| &key[..]).collect()),
+            RedisCommand::MSet { pairs, .. } => KeyAccess::Write(pairs.iter().map(|(key, _)| &key[..]).collect()),
+            RedisCommand::Rename { key: source, new_key: destination, .. }
+            | RedisCommand::Copy { source, destination, .. }
+            | RedisCommand::SMove { source, destination, .. }
+            | RedisCommand::LMove { source, destination, .. }
+            | RedisCommand::BLMove { source, destination, .. }
+            | RedisCommand::ZRangeStore { source, destination, .. }
+            | RedisCommand::GeoSearchStore { source, destination, .. } => KeyAccess::Write(vec![source, destination]),
+            // The sources are only read, but locking them for writing keeps this a single access.
+            RedisCommand::SetOperationStore { destination, keys, .. }
+            | RedisCommand::ZSetOperationStore { destination, keys, .. }
+            | RedisCommand::BitOp { destination, keys, .. }
+            | RedisCommand::PfMerge { destination, sources: keys, .. } => {
+                KeyAccess::Write(std::iter::once(destination).chain(keys).map(|key| &key[..]).collect())
+            },
+            RedisCommand::FlushDB { .. }
+            | RedisCommand::SwapDb { .. }
+            | RedisCommand::FlushAll { .. }
+            | RedisCommand::Save => KeyAccess::All,
+            _ => KeyAccess::None,
+        }
+    }
+
+    /// The keys a blocking list command waits on and for how long, or `None` for any other
+    /// command.
+    pub fn blocking_keys(&self) -> Option<(&[Bytes], Option<Duration>)> {
+        match self {
+            RedisCommand::BPop { keys, timeout, .. }
+            | RedisCommand::BLMPop { keys, timeout, .. } => Some((keys, *timeout)),
+            RedisCommand::BLMove { source, timeout, .. } => Some((std::slice::from_ref(source), *timeout)),
+            RedisCommand::XReadGroup { keys, ids, block: Some(block), .. } if ids.iter().all(Option::is_none) => {
+                Some((keys, (*block > 0).then(|| Duration::from_millis(*block))))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// How a command uses the keyspace, see `RedisCommand::key_access`.
+pub enum KeyAccess<'a> {
+    None,
+    Read(Vec<&'a [u8]>),
+    Write(Vec<&'a [u8]>),
+    /// Every key, e.g. FLUSHDB.
+    All,
+}
+
+/// Arguments arrive as raw bytes. Most of them are data and stay that way, but counts,
+/// indices, IDs and option names have to be read as text.
+trait Param {
+    fn parse<T: FromStr>(&self) -> Result<T, ()>;
+    fn text(&self) -> String;
+}
+
+impl Param for Bytes {
+    fn parse<T: FromStr>(&self) -> Result<T, ()> {
+        std::str::from_utf8(self).map_err(|_| ())?.parse().map_err(|_| ())
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(self).into_owned()
+    }
+}
+
+/// The arguments of the EXPIRE family after the key: the time and the NX/XX/GT/LT flags.
+/// The time comes back in milliseconds.
+fn parse_expire(command: &str, params: &[Bytes], in_milliseconds: bool, absolute: bool) -> Result<(i64, ExpireOptions), String> {
+    let invalid = || format!("ERR invalid expire time in '{}' command", command);
+    let time = params[0].parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+    let milliseconds = if in_milliseconds { time } else { time.checked_mul(1000).ok_or_else(invalid)? };
+    if !absolute {
+        milliseconds.checked_add(Storage::get_current_time_ms() as i64).ok_or_else(invalid)?;
+    }
+
+    let mut options = ExpireOptions::default();
+    for flag in &params[1..] {
+        match flag.to_ascii_uppercase().as_slice() {
+            b"NX" => options.nx = true,
+            b"XX" => options.xx = true,
+            b"GT" => options.gt = true,
+            b"LT" => options.lt = true,
+            _ => return Err(format!("ERR Unsupported option {}", flag.text())),
+        }
+    }
+    if options.nx && (options.xx || options.gt || options.lt) {
+        return Err("ERR NX and XX, GT or LT options at the same time are not compatible".to_string());
+    }
+    if options.gt && options.lt {
+        return Err("ERR GT and LT options at the same time are not compatible".to_string());
+    }
+    Ok((milliseconds, options))
+}
+
+/// The reply to a malformed entry ID in XADD, XDEL or XTRIM.
+const INVALID_STREAM_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// A stream entry ID, `ms-seq` or just `ms` for `ms-0`.
+fn parse_entry_id(param: &Bytes) -> Option<(u64, u64)> {
+    let id = std::str::from_utf8(param).ok()?;
+    match id.split_once('-') {
+        Some((ms, seq)) => Some((ms.parse().ok()?, seq.parse().ok()?)),
+        None => Some((id.parse().ok()?, 0)),
+    }
+}
+
+/// XADD's ID: `*`, `ms-*`, or an entry ID, which is spelled out in full.
+fn parse_xadd_id(param: &Bytes) -> Option<String> {
+    if param.as_ref() == b"*" {
+        return Some("*".to_string());
+    }
+    if let Some(ms) = param.strip_suffix(b"-*") {
+        return std::str::from_utf8(ms).ok()?.parse::<u64>().ok().map(|ms| format!("{}-*", ms));
+    }
+    parse_entry_id(param).map(|(ms, seq)| format!("{}-{}", ms, seq))
+}
+
+/// `MAXLEN|MINID [=|~] threshold [LIMIT count]` at the start of `params`, as XTRIM and XADD take
+/// it. Returns the trim and how many params it took.
+fn parse_stream_trim(params: &[Bytes]) -> Result<(StreamTrim, usize), String> {
+    let syntax_error = || "ERR syntax error".to_string();
+    let approximate = params.get(1).is_some_and(|param| param.as_ref() == b"~");
+    let mut taken = if params.get(1).is_some_and(|param| param.as_ref() == b"~" || param.as_ref() == b"=") { 2 } else { 1 };
+    let threshold = params.get(taken).ok_or_else(syntax_error)?;
+    let threshold = match params[0].to_ascii_uppercase().as_slice() {
+        b"MAXLEN" => match threshold.parse::<i64>() {
+            Ok(maxlen) if maxlen >= 0 => TrimThreshold::MaxLen(maxlen as usize),
+            Ok(_) => return Err("ERR The MAXLEN argument must be >= 0.".to_string()),
+            Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+        },
+        b"MINID" => {
+            let (ms, seq) = parse_entry_id(threshold).ok_or_else(|| INVALID_STREAM_ID.to_string())?;
+            TrimThreshold::MinId(ms, seq)
+        },
+        _ => return Err(syntax_error()),
+    };
+    taken += 1;
+    // Redis' default for `~`: a hundred nodes' worth per trim
+    let mut limit = if approximate { 100 * STREAM_NODE_MAX_ENTRIES } else { 0 };
+    if params.get(taken).is_some_and(|param| param.eq_ignore_ascii_case(b"LIMIT")) {
+        let count = params.get(taken + 1).ok_or_else(syntax_error)?;
+        limit = match count.parse::<i64>() {
+            Ok(count) if count >= 0 => count as usize,
+            Ok(_) => return Err("ERR The LIMIT argument must be >= 0.".to_string()),
+            Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+        };
+        if !approximate {
+            return Err("ERR syntax error, LIMIT cannot be used without the special ~ option".to_string());
+        }
+        taken += 2;
+    }
+    Ok((StreamTrim { threshold, approximate, limit }, taken))
+}
+
+/// The lowest ID a range starting at `param` includes: `-` is the very first, and an ID without
+/// a sequence starts at sequence 0.
+fn parse_range_start(param: &Bytes) -> Option<StreamId> {
+    if param.as_ref() == b"-" {
+        return Some((0, 0));
+    }
+    parse_entry_id(param)
+}
+
+/// The highest ID a range ending at `param` includes: `+` is the very last, and an ID without a
+/// sequence takes in all of that millisecond.
+fn parse_range_end(param: &Bytes) -> Option<StreamId> {
+    if param.as_ref() == b"+" {
+        return Some((u64::MAX, u64::MAX));
+    }
+    if !param.contains(&b'-') {
+        return parse_entry_id(param).map(|(ms, _)| (ms, u64::MAX));
+    }
+    parse_entry_id(param)
+}
+
+/// A bound of XRANGE, XREVRANGE or XPENDING, as the lowest (`start`) or highest ID the range
+/// includes. `(id` excludes `id`, so the range starts right after it or ends right before it.
+fn parse_range_bound(param: &Bytes, start: bool) -> Result<StreamId, String> {
+    let invalid = || INVALID_STREAM_ID.to_string();
+    if param.len() < 2 || param[0] != b'(' {
+        return if start { parse_range_start(param) } else { parse_range_end(param) }.ok_or_else(invalid);
+    }
+    let id = param.slice(1..);
+    if matches!(id.as_ref(), b"-" | b"+") {
+        return Err(invalid());
+    }
+    if start {
+        match parse_range_start(&id).ok_or_else(invalid)? {
+            (u64::MAX, u64::MAX) => Err("ERR invalid start ID for the interval".to_string()),
+            (ms, u64::MAX) => Ok((ms + 1, 0)),
+            (ms, seq) => Ok((ms, seq + 1)),
+        }
+    } else {
+        match parse_range_end(&id).ok_or_else(invalid)? {
+            (0, 0) => Err("ERR invalid end ID for the interval".to_string()),
+            (ms, 0) => Ok((ms - 1, u64::MAX)),
+            (ms, seq) => Ok((ms, seq - 1)),
+        }
+    }
+}
+
+/// `key start end [COUNT count]`, XREVRANGE taking `end` first.
+fn parse_xrange(params: &[Bytes], rev: bool) -> Result<RedisCommand, String> {
+    let key = params[0].clone();
+    let (start, end) = if rev { (&params[2], &params[1]) } else { (&params[1], &params[2]) };
+    let start = parse_range_bound(start, true)?;
+    let end = parse_range_bound(end, false)?;
+    let count = match &params[3..] {
+        [] => None,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+            Some(count.parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?.max(0) as usize)
+        },
+        _ => return Err("ERR syntax error".to_string()),
+    };
+    Ok(RedisCommand::XRange { key, start, end, count, rev })
+}
+
+/// XGROUP's subcommands, `params` starting with the subcommand's name.
+fn parse_xgroup(params: &[Bytes], original_resp: Bytes) -> Result<RedisCommand, String> {
+    let subcommand = params[0].text().to_ascii_lowercase();
+    let arity_error = || format!("ERR wrong number of arguments for 'xgroup|{}' command", subcommand);
+    // `$` or an entry ID
+    let group_start = |param: &Bytes| match param.as_ref() {
+        b"$" => Ok(None),
+        _ => parse_entry_id(param).map(Some).ok_or_else(|| INVALID_STREAM_ID.to_string()),
+    };
+    let args = &params[1..];
+    match subcommand.as_str() {
+        "create" => {
+            let [key, group, id, options @ ..] = args else {
+                return Err(arity_error());
+            };
+            let mkstream = match options {
+                [] => false,
+                [option] if option.eq_ignore_ascii_case(b"MKSTREAM") => true,
+                _ => return Err("ERR syntax error".to_string()),
+            };
+            Ok(RedisCommand::XGroupCreate { key: key.clone(), group: group.clone(), id: group_start(id)?, mkstream })
+        },
+        "setid" => {
+            let [key, group, id] = args else {
+                return Err(arity_error());
+            };
+            Ok(RedisCommand::XGroupSetId { key: key.clone(), group: group.clone(), id: group_start(id)? })
+        },
+        "destroy" => {
+            let [key, group] = args else {
+                return Err(arity_error());
+            };
+            Ok(RedisCommand::XGroupDestroy { key: key.clone(), group: group.clone(), original_resp })
+        },
+        "createconsumer" | "delconsumer" => {
+            let [key, group, consumer] = args else {
+                return Err(arity_error());
+            };
+            let (key, group, consumer) = (key.clone(), group.clone(), consumer.clone());
+            Ok(if subcommand == "createconsumer" {
+                RedisCommand::XGroupCreateConsumer { key, group, consumer, original_resp }
+            } else {
+                RedisCommand::XGroupDelConsumer { key, group, consumer, original_resp }
+            })
+        },
+        _ => Err(format!("ERR unknown subcommand '{}'. Try XGROUP HELP.", params[0].text())),
+    }
+}
+
+/// `GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key [key ...] id [id ...]`
+fn parse_xreadgroup(params: &[Bytes]) -> Result<RedisCommand, String> {
+    let syntax_error = || "ERR syntax error".to_string();
+    if !params[0].eq_ignore_ascii_case(b"GROUP") {
+        return Err(syntax_error());
+    }
+    let (group, consumer) = (params[1].clone(), params[2].clone());
+    let (mut count, mut block, mut noack) = (None, None, false);
+    let mut rest = &params[3..];
+    loop {
+        let Some((option, tail)) = rest.split_first() else {
+            return Err(syntax_error());
+        };
+        match option.to_ascii_uppercase().as_slice() {
+            b"STREAMS" => {
+                rest = tail;
+                break;
+            },
+            b"NOACK" => {
+                noack = true;
+                rest = tail;
+                continue;
+            },
+            b"COUNT" | b"BLOCK" => (),
+            _ => return Err(syntax_error()),
+        }
+        let value = tail.first().ok_or_else(syntax_error)?;
+        if option.eq_ignore_ascii_case(b"COUNT") {
+            let value = value.parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+            // Like Redis, 0 or less means no limit
+            count = (value > 0).then_some(value as usize);
+        } else {
+            let value = value.parse::<i64>().map_err(|_| "ERR timeout is not an integer or out of range".to_string())?;
+            if value < 0 {
+                return Err("ERR timeout is negative".to_string());
+            }
+            block = Some(value as u64);
+        }
+        rest = &tail[1..];
+    }
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err("ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.".to_string());
+    }
+    let (keys, ids) = rest.split_at(rest.len() / 2);
+    let ids = ids.iter()
+        .map(|id| match id.as_ref() {
+            b">" => Ok(None),
+            _ => parse_entry_id(id).map(Some).ok_or_else(|| INVALID_STREAM_ID.to_string()),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(RedisCommand::XReadGroup { group, consumer, keys: keys.to_vec(), ids, count, block, noack })
+}
+
+/// XPENDING's extended form: `key group [IDLE min-idle-time] start end count [consumer]`.
+fn parse_xpending(params: &[Bytes]) -> Result<RedisCommand, String> {
+    let integer_error = || "ERR value is not an integer or out of range".to_string();
+    let (key, group) = (params[0].clone(), params[1].clone());
+    let mut rest = &params[2..];
+    let mut min_idle = None;
+    if rest[0].eq_ignore_ascii_case(b"IDLE") {
+        let idle = rest.get(1).ok_or_else(|| "ERR syntax error".to_string())?;
+        min_idle = Some(idle.parse::<i64>().map_err(|_| integer_error())?.max(0) as u64);
+        rest = &rest[2..];
+    }
+    let (start, end, count, consumer) = match rest {
+        [start, end, count] => (start, end, count, None),
+        [start, end, count, consumer] => (start, end, count, Some(consumer.clone())),
+        _ => return Err("ERR syntax error".to_string()),
+    };
+    let start = parse_range_bound(start, true)?;
+    let end = parse_range_bound(end, false)?;
+    let count = count.parse::<i64>().map_err(|_| integer_error())?.max(0) as usize;
+    Ok(RedisCommand::XPending { key, group, start, end, count, consumer, min_idle })
+}
+
+/// `key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+/// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]`. The IDs run up to the first argument
+/// that isn't one.
+fn parse_xclaim(params: &[Bytes]) -> Result<RedisCommand, String> {
+    let (key, group, consumer) = (params[0].clone(), params[1].clone(), params[2].clone());
+    let min_idle = params[3].parse::<i64>()
+        .map_err(|_| "ERR Invalid min-idle-time argument for XCLAIM".to_string())?
+        .max(0) as u64;
+    let ids: Vec<StreamId> = params[4..].iter().map_while(parse_entry_id).collect();
+    if ids.is_empty() {
+        return Err(INVALID_STREAM_ID.to_string());
+    }
+    let mut options = ClaimOptions::default();
+    let mut rest = &params[4 + ids.len()..];
+    while let Some((option, tail)) = rest.split_first() {
+        let name = option.to_ascii_uppercase();
+        match name.as_slice() {
+            b"FORCE" => options.force = true,
+            b"JUSTID" => options.justid = true,
+            b"IDLE" | b"TIME" | b"RETRYCOUNT" | b"LASTID" => {
+                let name = option.text().to_ascii_uppercase();
+                let invalid = || format!("ERR Invalid {} option argument for XCLAIM", name);
+                let value = tail.first().ok_or_else(|| "ERR syntax error".to_string())?;
+                if name == "LASTID" {
+                    options.last_id = Some(parse_entry_id(value).ok_or_else(|| INVALID_STREAM_ID.to_string())?);
+                } else {
+                    let value = value.parse::<i64>().map_err(|_| invalid())?.max(0) as u64;
+                    match name.as_str() {
+                        "IDLE" => options.idle = Some(value),
+                        "TIME" => options.time = Some(value),
+                        _ => options.retry_count = Some(value),
+                    }
+                }
+                rest = &tail[1..];
+                continue;
+            },
+            _ => return Err(format!("ERR Unrecognized XCLAIM option '{}'", option.text())),
+        }
+        rest = tail;
+    }
+    Ok(RedisCommand::XClaim { key, group, consumer, min_idle, ids, options })
+}
+
+/// `key group consumer min-idle-time start [COUNT count] [JUSTID]`
+fn parse_xautoclaim(params: &[Bytes]) -> Result<RedisCommand, String> {
+    let (key, group, consumer) = (params[0].clone(), params[1].clone(), params[2].clone());
+    let min_idle = params[3].parse::<i64>()
+        .map_err(|_| "ERR Invalid min-idle-time argument for XAUTOCLAIM".to_string())?
+        .max(0) as u64;
+    let start = parse_range_start(&params[4]).ok_or_else(|| INVALID_STREAM_ID.to_string())?;
+    let (mut count, mut justid) = (100, false);
+    let mut rest = &params[5..];
+    while let Some((option, tail)) = rest.split_first() {
+        if option.eq_ignore_ascii_case(b"JUSTID") {
+            justid = true;
+            rest = tail;
+        } else if option.eq_ignore_ascii_case(b"COUNT") {
+            let value = tail.first().ok_or_else(|| "ERR syntax error".to_string())?;
+            count = value.parse::<usize>().ok()
+                .filter(|count| (1..=usize::MAX / 10).contains(count))
+                .ok_or_else(|| "ERR COUNT must be > 0".to_string())?;
+            rest = &tail[1..];
+        } else {
+            return Err("ERR syntax error".to_string());
+        }
+    }
+    Ok(RedisCommand::XAutoClaim { key, group, consumer, min_idle, start, count, justid })
+}
+
+/// A blocking command's timeout in seconds, which may be fractional. 0 means no timeout.
+fn parse_timeout(param: &Bytes) -> Result<Option<Duration>, String> {
+    let seconds = param.parse::<f64>().ok()
+        .filter(|seconds| seconds.is_finite())
+        .ok_or_else(|| "ERR timeout is not a float or out of range".to_string())?;
+    if seconds < 0.0 {
+        return Err("ERR timeout is negative".to_string());
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds).map(Some).map_err(|_| "ERR timeout is out of range".to_string())
+}
+
+/// LEFT or RIGHT, for LMOVE and LMPOP.
+fn parse_list_end(param: &Bytes) -> Option<ListEnd> {
+    match param.to_ascii_uppercase().as_slice() {
+        b"LEFT" => Some(ListEnd::Left),
+        b"RIGHT" => Some(ListEnd::Right),
+        _ => None,
+    }
+}
+
+/// LMPOP's `numkeys key [key ...] LEFT|RIGHT [COUNT count]`.
+fn parse_lmpop(params: &[Bytes]) -> Result<(Vec<Bytes>, ListEnd, usize), String> {
+    let syntax_error = || "ERR syntax error".to_string();
+    let numkeys = params[0].parse::<usize>().ok()
+        .filter(|numkeys| *numkeys > 0)
+        .ok_or_else(|| "ERR numkeys should be greater than 0".to_string())?;
+    if numkeys > params.len() - 2 {
+        return Err(syntax_error());
+    }
+    let keys = params[1..=numkeys].to_vec();
+    let end = parse_list_end(&params[numkeys + 1]).ok_or_else(syntax_error)?;
+    let count = match &params[numkeys + 2..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => count.parse::<usize>().ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| "ERR count should be greater than 0".to_string())?,
+        _ => return Err(syntax_error()),
+    };
+    Ok((keys, end, count))
+}
+
+/// LPOS's options after the element, in any order: RANK, COUNT and MAXLEN. Returns the rank
+/// (1 by default), the count if given, and the maximum length (0 for none).
+fn parse_lpos(params: &[Bytes]) -> Result<(i64, Option<usize>, usize), String> {
+    let (mut rank, mut count, mut maxlen) = (1, None, 0);
+    for pair in params.chunks(2) {
+        let [option, value] = pair else {
+            return Err("ERR syntax error".to_string());
+        };
+        let value = value.parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+        match option.to_ascii_uppercase().as_slice() {
+            b"RANK" if value == 0 || value == i64::MIN => return Err("ERR RANK can't be zero: use 1 to start from the first match, \
+                2 from the second ... or use negative to start from the end of the list".to_string()),
+            b"RANK" => rank = value,
+            b"COUNT" if value < 0 => return Err("ERR COUNT can't be negative".to_string()),
+            b"COUNT" => count = Some(value as usize),
+            b"MAXLEN" if value < 0 => return Err("ERR MAXLEN can't be negative".to_string()),
+            b"MAXLEN" => maxlen = value as usize,
+            _ => return Err("ERR syntax error".to_string()),
+        }
+    }
+    Ok((rank, count, maxlen))
+}
+
+/// ZADD's arguments after the key: flags first, then score/member pairs.
+fn parse_zadd(params: &[Bytes]) -> Result<(ZAddOptions, Vec<(f64, Bytes)>), String> {
+    let mut options = ZAddOptions::default();
+    let mut rest = params;
+    while let Some((flag, tail)) = rest.split_first() {
+        match flag.to_ascii_uppercase().as_slice() {
+            b"NX" => options.nx = true,
+            b"XX" => options.xx = true,
+            b"GT" => options.gt = true,
+            b"LT" => options.lt = true,
+            b"CH" => options.ch = true,
+            b"INCR" => options.incr = true,
+            _ => break,
+        }
+        rest = tail;
+    }
+
+    if options.nx && options.xx {
+        return Err("ERR XX and NX options at the same time are not compatible".to_string());
+    }
+    if (options.gt && options.lt) || ((options.gt || options.lt) && options.nx) {
+        return Err("ERR GT, LT, and/or NX options at the same time are not compatible".to_string());
+    }
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err("ERR syntax error".to_string());
+    }
+    if options.incr && rest.len() != 2 {
+        return Err("ERR INCR option supports a single increment-element pair".to_string());
+    }
+
+    let mut pairs = Vec::with_capacity(rest.len() / 2);
+    for pair in rest.chunks(2) {
+        match zset::parse_score(&pair[0]) {
+            Some(score) => pairs.push((score, pair[1].clone())),
+            None => return Err("ERR value is not a valid float".to_string()),
+        }
+    }
+    Ok((options, pairs))
+}
+
+/// GEOADD's arguments after the key: NX/XX/CH, then longitude, latitude, member triples, turned
+/// into ZADD's score/member pairs.
+fn parse_geoadd(params: &[Bytes]) -> Result<(ZAddOptions, Vec<(f64, Bytes)>), String> {
+    let mut options = ZAddOptions::default();
+    let mut rest = params;
+    while let Some((flag, tail)) = rest.split_first() {
+        match flag.to_ascii_uppercase().as_slice() {
+            b"NX" => options.nx = true,
+            b"XX" => options.xx = true,
+            b"CH" => options.ch = true,
+            _ => break,
         }
+        rest = tail;
+    }
+    if options.nx && options.xx {
+        return Err("ERR XX and NX options at the same time are not compatible".to_string());
+    }
+    if rest.is_empty() || !rest.len().is_multiple_of(3) {
+        return Err("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".to_string());
+    }
+
+    let mut pairs = Vec::with_capacity(rest.len() / 3);
+    for triple in rest.chunks(3) {
+        let (longitude, latitude) = parse_position(&triple[0], &triple[1])?;
+        pairs.push((geo::encode(longitude, latitude) as f64, triple[2].clone()));
+    }
+    Ok((options, pairs))
+}
+
+/// A longitude, latitude pair that fits the geo index.
+fn parse_position(longitude: &Bytes, latitude: &Bytes) -> Result<(f64, f64), String> {
+    let (longitude, latitude) = match (longitude.parse::<f64>(), latitude.parse::<f64>()) {
+        (Ok(longitude), Ok(latitude)) => (longitude, latitude),
+        _ => return Err("ERR value is not a valid float".to_string()),
+    };
+    if !geo::valid_coordinates(longitude, latitude) {
+        return Err(format!("ERR invalid longitude,latitude pair {:.6},{:.6}", longitude, latitude));
+    }
+    Ok((longitude, latitude))
+}
+
+/// GEOSEARCH's options after the key, or GEOSEARCHSTORE's after the source when `store` is set.
+fn parse_geosearch(command: &str, params: &[Bytes], store: bool) -> Result<Query, String> {
+    let syntax_error = || "ERR syntax error".to_string();
+    let not_float = |_| "ERR need numeric radius".to_string();
+    let unit = |arg: &Bytes| geo::parse_unit(arg).ok_or_else(|| "ERR unsupported unit provided. please use M, KM, FT, MI".to_string());
+    let (mut origin, mut shape, mut unit_factor) = (None, None, 1.0);
+    let (mut sort, mut count, mut any) = (Sort::None, None, false);
+    let (mut with_coord, mut with_dist, mut with_hash, mut store_dist) = (false, false, false, false);
+    let mut origins = 0;
+    let mut shapes = 0;
+
+    let mut rest = params;
+    while let Some((option, tail)) = rest.split_first() {
+        rest = tail;
+        match option.to_ascii_uppercase().as_slice() {
+            b"FROMMEMBER" => {
+                let (member, tail) = rest.split_first().ok_or_else(syntax_error)?;
+                rest = tail;
+                origin = Some(Origin::Member(member.clone()));
+                origins += 1;
+            },
+            b"FROMLONLAT" if rest.len() >= 2 => {
+                let (longitude, latitude) = parse_position(&rest[0], &rest[1])?;
+                rest = &rest[2..];
+                origin = Some(Origin::Position(longitude, latitude));
+                origins += 1;
+            },
+            b"BYRADIUS" if rest.len() >= 2 => {
+                let radius = rest[0].parse::<f64>().map_err(not_float)?;
+                if radius < 0.0 {
+                    return Err("ERR radius cannot be negative".to_string());
+                }
+                unit_factor = unit(&rest[1])?;
+                rest = &rest[2..];
+                shape = Some(Shape::Radius(radius * unit_factor));
+                shapes += 1;
+            },
+            b"BYBOX" if rest.len() >= 3 => {
+                let (width, height) = match (rest[0].parse::<f64>(), rest[1].parse::<f64>()) {
+                    (Ok(width), Ok(height)) => (width, height),
+                    _ => return Err("ERR need numeric width and height".to_string()),
+                };
+                if width < 0.0 || height < 0.0 {
+                    return Err("ERR height or width cannot be negative".to_string());
+                }
+                unit_factor = unit(&rest[2])?;
+                rest = &rest[3..];
+                shape = Some(Shape::Box { width: width * unit_factor, height: height * unit_factor });
+                shapes += 1;
+            },
+            b"ASC" => sort = Sort::Asc,
+            b"DESC" => sort = Sort::Desc,
+            b"COUNT" => {
+                let (value, tail) = rest.split_first().ok_or_else(syntax_error)?;
+                rest = tail;
+                match value.parse::<i64>() {
+                    Ok(value) if value > 0 => count = Some(value as usize),
+                    Ok(_) => return Err("ERR COUNT must be > 0".to_string()),
+                    Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                }
+            },
+            b"ANY" => any = true,
+            b"WITHCOORD" if !store => with_coord = true,
+            b"WITHDIST" if !store => with_dist = true,
+            b"WITHHASH" if !store => with_hash = true,
+            b"STOREDIST" if store => store_dist = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let (Some(origin), 1) = (origin, origins) else {
+        return Err(format!("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {}", command));
+    };
+    let (Some(shape), 1) = (shape, shapes) else {
+        return Err(format!("ERR exactly one of BYRADIUS and BYBOX can be specified for {}", command));
+    };
+    if any && count.is_none() {
+        return Err("ERR the ANY argument requires COUNT argument".to_string());
+    }
+    // Without ANY, COUNT asks for the nearest ones.
+    if count.is_some() && !any && sort == Sort::None {
+        sort = Sort::Asc;
+    }
+    Ok(Query {
+        origin,
+        shape,
+        unit: unit_factor,
+        sort,
+        count: count.map(|count| (count, any)),
+        with_coord,
+        with_dist,
+        with_hash,
+        store_dist,
+    })
+}
+
+/// ZRANGE's arguments after the key: start, stop and the options. Also returns whether
+/// WITHSCORES was given.
+fn parse_zrange(params: &[Bytes]) -> Result<(RangeSpec, bool), String> {
+    let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+    let mut limit = None;
+    let mut i = 2;
+    while i < params.len() {
+        match params[i].to_ascii_uppercase().as_slice() {
+            b"BYSCORE" => by_score = true,
+            b"BYLEX" => by_lex = true,
+            b"REV" => rev = true,
+            b"WITHSCORES" => with_scores = true,
+            b"LIMIT" if i + 2 < params.len() => {
+                match (params[i + 1].parse::<i64>(), params[i + 2].parse::<i64>()) {
+                    (Ok(offset), Ok(count)) => limit = Some((offset, count)),
+                    _ => return Err("ERR value is not an integer or out of range".to_string()),
+                }
+                i += 2;
+            },
+            _ => return Err("ERR syntax error".to_string()),
+        }
+        i += 1;
+    }
+
+    if by_score && by_lex {
+        return Err("ERR syntax error".to_string());
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string());
+    }
+    if with_scores && by_lex {
+        return Err("ERR syntax error, WITHSCORES not supported in combination with BYLEX".to_string());
+    }
+
+    // With REV the range is given from max to min.
+    let (min, max) = if rev && (by_score || by_lex) { (&params[1], &params[0]) } else { (&params[0], &params[1]) };
+    let by = if by_score {
+        match (ScoreBound::parse(min), ScoreBound::parse(max)) {
+            (Some(min), Some(max)) => RangeBy::Score(min, max),
+            _ => return Err("ERR min or max is not a float".to_string()),
+        }
+    } else if by_lex {
+        match (LexBound::parse(min), LexBound::parse(max)) {
+            (Some(min), Some(max)) => RangeBy::Lex(min, max),
+            _ => return Err("ERR min or max not valid string range item".to_string()),
+        }
+    } else {
+        match (min.parse::<i64>(), max.parse::<i64>()) {
+            (Ok(start), Ok(stop)) => RangeBy::Rank(start, stop),
+            _ => return Err("ERR value is not an integer or out of range".to_string()),
+        }
+    };
+    Ok((RangeSpec { by, rev, limit }, with_scores))
+}
+
+/// ZUNIONSTORE/ZINTERSTORE arguments after the destination: numkeys, the keys, then
+/// WEIGHTS and AGGREGATE.
+fn parse_zstore(command: &str, params: &[Bytes]) -> Result<(Vec<Bytes>, Vec<f64>, Aggregate), String> {
+    let numkeys = match params[0].parse::<usize>() {
+        Ok(0) => return Err(format!("ERR at least 1 input key is needed for '{}' command", command)),
+        Ok(numkeys) => numkeys,
+        Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+    };
+    if numkeys > params.len() - 1 {
+        return Err("ERR syntax error".to_string());
+    }
+    let keys = params[1..=numkeys].to_vec();
+
+    let mut weights = Vec::new();
+    let mut aggregate = Aggregate::Sum;
+    let mut rest = &params[numkeys + 1..];
+    while let Some((option, tail)) = rest.split_first() {
+        if option.eq_ignore_ascii_case(b"WEIGHTS") && tail.len() >= numkeys {
+            weights.clear();
+            for weight in &tail[..numkeys] {
+                match zset::parse_score(weight) {
+                    Some(weight) => weights.push(weight),
+                    None => return Err("ERR weight value is not a float".to_string()),
+                }
+            }
+            rest = &tail[numkeys..];
+        } else if option.eq_ignore_ascii_case(b"AGGREGATE") && !tail.is_empty() {
+            aggregate = match tail[0].to_ascii_uppercase().as_slice() {
+                b"SUM" => Aggregate::Sum,
+                b"MIN" => Aggregate::Min,
+                b"MAX" => Aggregate::Max,
+                _ => return Err("ERR syntax error".to_string()),
+            };
+            rest = &tail[1..];
+        } else {
+            return Err("ERR syntax error".to_string());
+        }
+    }
+    Ok((keys, weights, aggregate))
+}
+
+/// SET's options after the value, in any order: NX or XX, GET, and at most one of EX, PX, EXAT,
+/// PXAT and KEEPTTL. Returns the relative TTL in milliseconds separately from the other options.
+fn parse_set(params: &[Bytes]) -> Result<(Option<usize>, SetOptions), String> {
+    let syntax_error = || "ERR syntax error".to_string();
+    let mut ttl = None;
+    let mut options = SetOptions::default();
+    let mut rest = params;
+    while let Some((option, tail)) = rest.split_first() {
+        rest = tail;
+        let option = option.to_ascii_uppercase();
+        let has_expiry = ttl.is_some() || options.expire_at.is_some() || options.keep_ttl;
+        match option.as_slice() {
+            b"NX" if !options.xx => options.nx = true,
+            b"XX" if !options.nx => options.xx = true,
+            b"GET" => options.get = true,
+            b"KEEPTTL" if !has_expiry => options.keep_ttl = true,
+            b"EX" | b"PX" | b"EXAT" | b"PXAT" if !has_expiry => {
+                let (time, tail) = rest.split_first().ok_or_else(syntax_error)?;
+                rest = tail;
+                let time = time.parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+                let unit = if option.starts_with(b"E") { 1000 } else { 1 };
+                let milliseconds = time.checked_mul(unit)
+                    .filter(|milliseconds| *milliseconds > 0)
+                    .ok_or_else(|| "ERR invalid expire time in 'set' command".to_string())?;
+                if option.ends_with(b"AT") {
+                    options.expire_at = Some(milliseconds as u64);
+                } else {
+                    ttl = Some(milliseconds as usize);
+                }
+            },
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok((ttl, options))
+}
+
+/// BITCOUNT's and BITPOS's optional `start [end [BYTE|BIT]]`.
+fn parse_bit_range(params: &[Bytes]) -> Result<(Option<i64>, Option<i64>, bool), String> {
+    let not_integer = |_| "ERR value is not an integer or out of range".to_string();
+    let start = params.first().map(|start| start.parse::<i64>().map_err(not_integer)).transpose()?;
+    let end = params.get(1).map(|end| end.parse::<i64>().map_err(not_integer)).transpose()?;
+    let in_bits = match params.get(2) {
+        None => false,
+        Some(unit) if unit.eq_ignore_ascii_case(b"BYTE") => false,
+        Some(unit) if unit.eq_ignore_ascii_case(b"BIT") => true,
+        Some(_) => return Err("ERR syntax error".to_string()),
+    };
+    Ok((start, end, in_bits))
+}
+
+/// BITFIELD's subcommands. BITFIELD_RO only takes GET (and OVERFLOW, which does nothing there).
+fn parse_bitfield(params: &[Bytes], read_only: bool) -> Result<Vec<FieldOp>, String> {
+    let syntax_error = || "ERR syntax error".to_string();
+    let mut ops = Vec::new();
+    let mut rest = params;
+    while let Some((subcommand, tail)) = rest.split_first() {
+        let subcommand = subcommand.to_ascii_uppercase();
+        let arity = match subcommand.as_slice() {
+            b"OVERFLOW" => 1,
+            b"GET" => 2,
+            b"SET" | b"INCRBY" => 3,
+            _ => return Err(syntax_error()),
+        };
+        if tail.len() < arity {
+            return Err(syntax_error());
+        }
+        let (args, tail) = tail.split_at(arity);
+        rest = tail;
+
+        if subcommand == b"OVERFLOW" {
+            let overflow = match args[0].to_ascii_uppercase().as_slice() {
+                b"WRAP" => Overflow::Wrap,
+                b"SAT" => Overflow::Sat,
+                b"FAIL" => Overflow::Fail,
+                _ => return Err("ERR Invalid OVERFLOW type specified".to_string()),
+            };
+            ops.push(FieldOp::Overflow(overflow));
+            continue;
+        }
+        let field = FieldType::parse(&args[0])
+            .ok_or_else(|| "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".to_string())?;
+        let offset = bitmap::parse_offset(&args[1], Some(field.bits))
+            .ok_or_else(|| "ERR bit offset is not an integer or out of range".to_string())?;
+        if subcommand == b"GET" {
+            ops.push(FieldOp::Get { field, offset });
+            continue;
+        }
+        if read_only {
+            return Err("ERR BITFIELD_RO only supports the GET subcommand".to_string());
+        }
+        let value = args[2].parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+        ops.push(if subcommand == b"SET" {
+            FieldOp::Set { field, offset, value }
+        } else {
+            FieldOp::IncrBy { field, offset, increment: value }
+        });
+    }
+    Ok(ops)
+}
+
+/// Parses the options of GETEX into (milliseconds, absolute, persist).
+fn parse_getex(params: &[Bytes]) -> Result<(Option<i64>, bool, bool), String> {
+    match params {
+        [] => Ok((None, false, false)),
+        [option] if option.eq_ignore_ascii_case(b"PERSIST") => Ok((None, false, true)),
+        [option, time] => {
+            let (unit, absolute) = match option.to_ascii_uppercase().as_slice() {
+                b"EX" => (1000, false),
+                b"PX" => (1, false),
+                b"EXAT" => (1000, true),
+                b"PXAT" => (1, true),
+                _ => return Err("ERR syntax error".to_string()),
+            };
+            let time = time.parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+            match time.checked_mul(unit).filter(|milliseconds| *milliseconds > 0) {
+                Some(milliseconds) => Ok((Some(milliseconds), absolute, false)),
+                None => Err("ERR invalid expire time in 'getex' command".to_string()),
+            }
+        },
+        _ => Err("ERR syntax error".to_string()),
     }
 }
@@ -6,6 +6,11 @@ pub struct RedisConfig {
     pub replicaof_port: Option<String>,
     pub dir: String,
     pub dbfilename: String,
+    pub maxclients: usize,
+    /// How many times per second background tasks such as the active expire cycle run.
+    pub hz: u32,
+    /// How many logical databases SELECT can choose from.
+    pub databases: usize,
 }
 
 impl RedisConfig {
@@ -17,6 +22,9 @@ impl RedisConfig {
             replicaof_port: None,
             dir: ".".to_string(),
             dbfilename: "dump.rdb".to_string(),
+            maxclients: 10000,
+            hz: 10,
+            databases: 16,
         }
     }
 }
@@ -0,0 +1,136 @@
+//! Stream consumer groups. A group remembers the last entry it handed out to `>` reads, and
+//! every entry it delivered that hasn't been acknowledged yet: the pending entries list (PEL),
+//! which says who has each entry, since when, and how many times it was delivered. Groups are
+//! part of the stream's metadata, so they go wherever the stream goes.
+
+use std::collections::{BTreeMap, HashMap};
+use bytes::Bytes;
+
+/// A stream entry ID as (milliseconds, sequence).
+pub type StreamId = (u64, u64);
+
+/// An entry's fields, or None where a pending entry has since been deleted from the stream.
+pub type DeliveredEntry = (StreamId, Option<Vec<(Bytes, Bytes)>>);
+
+/// A claimed entry, with its fields unless the claim was JUSTID, and its pending state after.
+pub type ClaimedEntry = (StreamId, Option<Vec<(Bytes, Bytes)>>, PendingEntry);
+
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerGroup {
+    /// The last entry handed out by a `>` read.
+    pub last_delivered: StreamId,
+    /// Delivered but not yet acknowledged entries, in ID order.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: HashMap<Bytes, Consumer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Consumer {
+    /// Unix time in milliseconds of its last read or claim.
+    pub seen_at: u64,
+}
+
+/// XCLAIM's options. `idle` and `time` both set the delivery time, `retry_count` the delivery
+/// count, `last_id` moves the group's last delivered ID forward.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClaimOptions {
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub justid: bool,
+    pub last_id: Option<StreamId>,
+}
+
+/// What XCLAIM or XAUTOCLAIM did, for the reply and for replication.
+#[derive(Debug, Default)]
+pub struct Claimed {
+    pub entries: Vec<ClaimedEntry>,
+    /// Pending entries dropped because they are no longer in the stream.
+    pub deleted: Vec<StreamId>,
+    /// Where XAUTOCLAIM should continue, 0-0 once it went through the whole PEL.
+    pub next: StreamId,
+    pub last_delivered: StreamId,
+}
+
+/// What XREADGROUP got from one stream.
+#[derive(Debug)]
+pub struct GroupRead {
+    pub entries: Vec<DeliveredEntry>,
+    pub consumer_created: bool,
+    /// When the entries of a `>` read were delivered.
+    pub delivered_at: u64,
+    /// The entries of a history read that still exist, as they are pending after being
+    /// delivered again.
+    pub redelivered: Vec<(StreamId, PendingEntry)>,
+    pub last_delivered: StreamId,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId) -> Self {
+        ConsumerGroup { last_delivered, ..Default::default() }
+    }
+
+    /// Adds `name` unless it's there already, and marks it as seen. Returns whether it was added.
+    pub fn touch_consumer(&mut self, name: &Bytes, now: u64) -> bool {
+        let created = !self.consumers.contains_key(name);
+        self.consumers.entry(name.clone()).or_default().seen_at = now;
+        created
+    }
+
+    /// Removes `name` along with its pending entries. Returns how many it had.
+    pub fn delete_consumer(&mut self, name: &[u8]) -> usize {
+        if self.consumers.remove(name).is_none() {
+            return 0;
+        }
+        let before = self.pending.len();
+        self.pending.retain(|_, pending| pending.consumer != name);
+        before - self.pending.len()
+    }
+
+    /// Acknowledges `ids`. Returns how many were pending.
+    pub fn ack(&mut self, ids: &[StreamId]) -> i64 {
+        ids.iter().filter(|id| self.pending.remove(id).is_some()).count() as i64
+    }
+
+    /// How many pending entries each consumer has, for those with any, by name.
+    pub fn pending_by_consumer(&self) -> Vec<(Bytes, usize)> {
+        let mut counts = BTreeMap::new();
+        for pending in self.pending.values() {
+            *counts.entry(pending.consumer.clone()).or_insert(0) += 1;
+        }
+        counts.into_iter().collect()
+    }
+
+    /// Hands `id` to `consumer` as a claim does: a new delivery time, and one more delivery
+    /// unless the claim is JUSTID or sets the count outright.
+    pub fn claim(&mut self, id: StreamId, consumer: &Bytes, delivered_at: u64, options: &ClaimOptions, now: u64) -> PendingEntry {
+        self.touch_consumer(consumer, now);
+        let pending = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.clone(),
+            delivered_at,
+            deliveries: 1,
+        });
+        pending.consumer = consumer.clone();
+        pending.delivered_at = delivered_at;
+        match options.retry_count {
+            Some(count) => pending.deliveries = count,
+            None if !options.justid => pending.deliveries += 1,
+            None => (),
+        }
+        pending.clone()
+    }
+}
+
+/// An ID as Redis writes it.
+pub fn format_id((ms, seq): StreamId) -> String {
+    format!("{}-{}", ms, seq)
+}
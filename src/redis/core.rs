@@ -1,18 +1,25 @@
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::io::Write;
+use std::sync::{RwLock, RwLockReadGuard};
 use base64::engine::general_purpose;
 use base64::Engine;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
-use std::thread;
-use std::collections::{HashMap, BTreeMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
 
 use crate::redis::config::RedisConfig;
-use crate::redis::storage::Storage;
-use crate::redis::replication::{ReplicationManager, TcpStreamTrait};
-use crate::redis::commands::RedisCommand;
+use crate::redis::storage::{ExpireOptions, ListEnd, Storage, SetOp, ValueWrapper};
+use crate::redis::zset::{self, SortedSet, ZAddOptions};
+use crate::redis::lcs;
+use crate::redis::bitmap::{self, FieldOp};
+use crate::redis::geo;
+use crate::redis::consumer_groups::{format_id, Claimed, ClaimedEntry, PendingEntry, StreamId};
+use crate::redis::replication::{ReplicationManager, Connection};
+use crate::redis::commands::{KeyAccess, RedisCommand};
+use crate::redis::key_locks::KeyLocks;
+use crate::redis::blocking::BlockedClients;
 use crate::redis::utils::gen_replid;
-use crate::redis::rdb::RdbParser;
+use crate::redis::rdb::{RdbParser, RdbWriter};
 
 #[derive(Debug)]
 pub enum RedisResponse {
@@ -20,77 +27,147 @@ pub enum RedisResponse {
     Retry,
     Error(String),
     Array(Vec<RedisResponse>),
-    BulkString(String),
+    BulkString(Bytes),
     NullBulkString,
+    NullArray,
     Integer(i64),
     SimpleString(String),
 }
 
 impl RedisResponse {
-    pub fn format(&self) -> String {
+    pub fn format(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_to(&mut out);
+        out
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
         match self {
-            RedisResponse::Ok(s) => format!("+{}\r\n", s),
-            RedisResponse::Error(e) => format!("-{}\r\n", e),
-            RedisResponse::SimpleString(s) => format!("+{}\r\n", s),
-            RedisResponse::Integer(i) => format!(":{}\r\n", i),
-            RedisResponse::BulkString(s) => format!("${}\r\n{}\r\n", s.len(), s),
-            RedisResponse::NullBulkString => "$-1\r\n".to_string(),
+            RedisResponse::Ok(s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            RedisResponse::Error(e) => out.extend_from_slice(format!("-{}\r\n", e).as_bytes()),
+            RedisResponse::SimpleString(s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            RedisResponse::Integer(i) => out.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+            RedisResponse::BulkString(s) => {
+                out.extend_from_slice(format!("${}\r\n", s.len()).as_bytes());
+                out.extend_from_slice(s);
+                out.extend_from_slice(b"\r\n");
+            },
+            RedisResponse::NullBulkString => out.extend_from_slice(b"$-1\r\n"),
+            RedisResponse::NullArray => out.extend_from_slice(b"*-1\r\n"),
             RedisResponse::Array(arr) => {
-                let mut result = format!("*{}\r\n", arr.len());
+                out.extend_from_slice(format!("*{}\r\n", arr.len()).as_bytes());
                 for item in arr {
-                    result.push_str(&item.format());
+                    item.write_to(out);
                 }
-                result
             },
-            RedisResponse::Retry => String::new(),
+            RedisResponse::Retry => {},
         }
     }
 }
 
+/// Shared by every connection as an `Arc<Redis>`; there is no lock around the whole server.
+/// Keys are guarded by the striped `key_locks`, config by its own RwLock, and the replication
+/// state and counters synchronize themselves. The `databases` lock is only taken for writing
+/// by SWAPDB, which also holds every key lock.
 pub struct Redis {
-    pub config: RedisConfig,
-    pub storage: Storage,
+    config: RwLock<RedisConfig>,
+    databases: RwLock<Vec<Arc<Storage>>>,
     pub bytes_processed: AtomicU64, // bytes processed by the server. important for a replica    
     pub replication: ReplicationManager,
+    key_locks: KeyLocks,
+    /// Clients waiting in BLPOP and friends, see `blocking::serve`.
+    pub blocked: BlockedClients,
 }
 
 impl Redis {
     pub fn new(config: RedisConfig) -> Self {
+        let databases = Self::create_databases(config.databases);
         Redis {
-            config,
-            storage: Storage::new(),
+            config: RwLock::new(config),
+            databases,
             bytes_processed: AtomicU64::new(0),
             replication: ReplicationManager::new(),
+            key_locks: KeyLocks::default(),
+            blocked: BlockedClients::default(),
         }
     }
 
     #[allow(dead_code)]
     pub fn new_with_replication(replication: ReplicationManager) -> Self {
+        let config = RedisConfig::default();
         Redis {
-            config: RedisConfig::default(),
-            storage: Storage::new(),
+            databases: Self::create_databases(config.databases),
+            config: RwLock::new(config),
             bytes_processed: AtomicU64::new(0),
             replication,
+            key_locks: KeyLocks::default(),
+            blocked: BlockedClients::default(),
         }
     }
 
-    pub fn set(&mut self, key: &str, value: &str, ttl: Option<usize>) {
-        self.storage.set(key, value, ttl);
+    fn create_databases(count: usize) -> RwLock<Vec<Arc<Storage>>> {
+        RwLock::new((0..count).map(|_| Arc::new(Storage::new())).collect())
+    }
+
+    /// The database with the given index, which must be below `database_count`.
+    pub fn db(&self, index: usize) -> Arc<Storage> {
+        Arc::clone(&self.databases.read().unwrap()[index])
+    }
+
+    pub fn database_count(&self) -> usize {
+        self.databases.read().unwrap().len()
+    }
+
+    /// Checks a DB index given by a client.
+    fn db_index(&self, index: i64) -> Result<usize, RedisResponse> {
+        usize::try_from(index).ok()
+            .filter(|index| *index < self.database_count())
+            .ok_or_else(|| RedisResponse::Error("ERR DB index is out of range".to_string()))
+    }
+
+    pub fn config(&self) -> RwLockReadGuard<'_, RedisConfig> {
+        self.config.read().unwrap()
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.config().replicaof_host.is_some()
     }
 
-    pub fn get(&self, key: &str) -> Option<String> {
-        self.storage.get(key)
+    // set, get, xadd and keys work on database 0.
+    #[allow(dead_code)]
+    pub fn set(&self, key: &[u8], value: Bytes, ttl: Option<usize>) {
+        self.db(0).set(key, value, ttl);
     }
 
-    pub fn xadd(&mut self, key: &str, id: &str, fields: HashMap<String, String>) -> Result<String, String> {
-        self.storage.xadd(key, id, fields).map_err(|e| e.into_owned())
+    #[allow(dead_code)]
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.db(0).get(key)
     }
 
-    pub fn enqueue_for_replication(&mut self, command: &str) {
-        self.replication.enqueue_for_replication(command);
+    #[allow(dead_code)]
+    pub fn xadd(&self, key: &[u8], id: &str, fields: Vec<(Bytes, Bytes)>) -> Result<String, String> {
+        self.db(0).xadd(key, id, fields).map_err(|e| e.into_owned())
     }
 
-    pub fn update_replica_offset(&mut self, replica_key: &str, offset: u64) {
+    pub fn enqueue_for_replication(&self, db: usize, command: &[u8]) {
+        self.replication.enqueue_for_replication(db, command);
+    }
+
+    /// Replicates what XCLAIM or XAUTOCLAIM did: each claimed entry as an XCLAIM that sets it
+    /// up as is, and the pending entries dropped for being deleted as an XACK.
+    fn replicate_claims(&self, db: usize, key: &[u8], group: &[u8], claimed: &Claimed) {
+        for (id, _, pending) in &claimed.entries {
+            self.enqueue_for_replication(db, &xclaim_resp(key, group, *id, pending, claimed.last_delivered));
+        }
+        if !claimed.deleted.is_empty() {
+            let ids: Vec<String> = claimed.deleted.iter().map(|id| format_id(*id)).collect();
+            let mut args: Vec<&[u8]> = vec![b"XACK", key, group];
+            args.extend(ids.iter().map(|id| id.as_bytes()));
+            self.enqueue_for_replication(db, &command_resp(&args));
+        }
+    }
+
+    pub fn update_replica_offset(&self, replica_key: &str, offset: u64) {
         self.replication.update_replica_offset(replica_key, offset);
     }
 
@@ -98,32 +175,136 @@ impl Redis {
         self.bytes_processed.load(Ordering::SeqCst)
     }
 
-    pub fn keys(&self, pattern: &str) -> Vec<String> {
-        self.storage.keys(pattern)
+    #[allow(dead_code)]
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Bytes> {
+        self.db(0).keys(pattern)
     }
 
-    pub fn parse_rdb_file(&mut self) -> std::io::Result<()> {
-        let path = Path::new(&self.config.dir).join(&self.config.dbfilename);
-        let key_value_pairs = RdbParser::parse(&path)?;
-        
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        
-        for (key, value, expiry) in key_value_pairs {
-            let ttl = expiry.map(|exp| {
-                let exp_secs = exp / 1000; // Convert milliseconds to seconds
-                if exp_secs > now {
-                    (exp_secs - now) as usize
-                } else {
-                    0
-                }
-            });
-            self.set(&key, &value, ttl);
+    pub fn parse_rdb_file(&self) -> std::io::Result<()> {
+        let path = {
+            let config = self.config();
+            Path::new(&config.dir).join(&config.dbfilename)
+        };
+        let entries = RdbParser::parse(&path)?;
+
+        let databases = self.database_count();
+        for (db, (key, value, expiry)) in entries {
+            if db >= databases {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                    format!("RDB file uses database {} but only {} are configured", db, databases)));
+            }
+            let storage = self.db(db);
+            storage.insert(Bytes::from(key.clone()), value, None);
+            // The file stores absolute unix milliseconds; keys that expired meanwhile are dropped here.
+            if let Some(expiry) = expiry {
+                storage.expire_at(&key, expiry as i64, ExpireOptions::default());
+            }
         }
 
         Ok(())
     }
 
-    pub fn execute_command(&mut self, command: &RedisCommand, client: Option<&mut Box<dyn TcpStreamTrait>>) -> RedisResponse {
+    /// Writes every database to the RDB file `parse_rdb_file` loads, leaving out keys that
+    /// have expired. The caller holds every key lock, so the file is one point in time.
+    pub fn save_rdb_file(&self) -> std::io::Result<()> {
+        let path = {
+            let config = self.config();
+            Path::new(&config.dir).join(&config.dbfilename)
+        };
+        let mut writer = RdbWriter::new();
+        for db in 0..self.database_count() {
+            let storage = self.db(db);
+            if storage.dbsize() == 0 {
+                continue;
+            }
+            writer.select_db(db);
+            storage.for_each_entry(|key, value, expiry| writer.write_entry(key, value, expiry));
+        }
+        writer.finish(&path)
+    }
+
+    /// How many keys with a TTL one step of the active expire cycle looks at.
+    const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+
+    /// Removes expired keys nobody is reading, for at most `time_limit`. Samples keys with a TTL
+    /// and keeps going while more than a quarter of a sample turns out expired, like Redis does.
+    /// Returns how many keys were removed.
+    /// Every database gets an equal share of the time. Each removal goes to the replicas as a
+    /// DEL; a replica doesn't run the cycle itself, so it never drops a key its master still has.
+    pub fn active_expire_cycle(&self, time_limit: Duration) -> usize {
+        if self.is_replica() {
+            return 0;
+        }
+        let databases = self.database_count();
+        let share = time_limit / databases as u32;
+        let mut removed = 0;
+        for db in 0..databases {
+            let start = Instant::now();
+            loop {
+                let (expired, sampled) = self.db(db).sample_expired(Self::ACTIVE_EXPIRE_SAMPLE);
+                for key in &expired {
+                    let _guards = self.key_locks.lock(&[KeyAccess::Write(vec![key])]);
+                    // Looked up again under the lock, in case SWAPDB moved the database.
+                    if self.db(db).expire_if_due(key) {
+                        self.enqueue_for_replication(db, &command_resp(&[b"DEL", key]));
+                        removed += 1;
+                    }
+                }
+                if sampled == 0 || expired.len() * 4 <= sampled || start.elapsed() >= share {
+                    break;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Runs the active expire cycle `hz` times per second, each run using at most a quarter of
+    /// the time until the next one.
+    pub fn start_active_expire(redis: Arc<Redis>) {
+        tokio::spawn(async move {
+            loop {
+                let period = Duration::from_millis(1000 / redis.config().hz.max(1) as u64);
+                let _removed = redis.active_expire_cycle(period / 4);
+                #[cfg(debug_assertions)]
+                if _removed > 0 {
+                    println!("DEBUG: Active expire cycle removed {} keys", _removed);
+                }
+                tokio::time::sleep(period).await;
+            }
+        });
+    }
+
+    /// Runs `command` against the client's selected database `db`, which SELECT changes.
+    pub fn execute_command(&self, db: &mut usize, command: &RedisCommand, client: Option<&Connection>) -> RedisResponse {
+        let _guards = self.key_locks.lock(&[command.key_access()]);
+        self.apply(db, command, client)
+    }
+
+    /// Runs the commands queued by MULTI as one unit: the keys of all of them are locked up front,
+    /// so no other client observes the transaction half applied.
+    pub fn execute_transaction(&self, db: &mut usize, commands: &[RedisCommand], client: Option<&Connection>) -> Vec<RedisResponse> {
+        let accesses: Vec<_> = commands.iter().map(|command| command.key_access()).collect();
+        let _guards = self.key_locks.lock(&accesses);
+        commands.iter()
+            .map(|command| self.apply(db, command, client))
+            .filter(|response| !matches!(response, RedisResponse::Retry))
+            .collect()
+    }
+
+    /// Executes a command; the caller holds the key locks it needs.
+    fn apply(&self, selected_db: &mut usize, command: &RedisCommand, client: Option<&Connection>) -> RedisResponse {
+        let db = *selected_db;
+        let storage = self.db(db);
+        // Expired keys are removed when a command touches them, before it gets to look. The
+        // replicas get a DEL ahead of the command, so they see the same keyspace it did.
+        if let KeyAccess::Read(keys) | KeyAccess::Write(keys) = command.key_access() {
+            for key in keys {
+                if storage.expire_if_due(key) && !self.is_replica() {
+                    self.enqueue_for_replication(db, &command_resp(&[b"DEL", key]));
+                }
+            }
+        }
+
         match command {
             RedisCommand::None => {
                 RedisResponse::Error("Unknown command".to_string())
@@ -132,7 +313,7 @@ impl Redis {
             RedisCommand::Exec => RedisResponse::Ok("*0".to_string()),
             RedisCommand::Discard => RedisResponse::Ok("OK".to_string()),
             RedisCommand::Ping => {
-                if self.config.replicaof_host.is_some() {
+                if self.is_replica() {
                     // We're a replica, respond with REPLCONF ACK
                     if let Some(client) = client {
                         let bytes_processed = self.get_bytes_processed();
@@ -140,7 +321,6 @@ impl Redis {
                         let response = format!("*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{}\r\n", 
                             num_digits, bytes_processed);
                         let _ = client.write(response.as_bytes());
-                        let _ = client.flush();
                         RedisResponse::Ok("".to_string())  // Return empty string to indicate response was sent directly
                     } else {
                         RedisResponse::Error("No stream client to send REPLCONF ACK".to_string())
@@ -152,131 +332,1084 @@ impl Redis {
             },
             RedisCommand::Echo { data } => RedisResponse::BulkString(data.clone()),
             RedisCommand::Get { key } => {
-                match self.get(key) {
-                    Some(value) => RedisResponse::BulkString(value),
-                    None => RedisResponse::BulkString("".to_string()),
+                match storage.read_string(key) {
+                    Ok(Some(value)) => RedisResponse::BulkString(value),
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
-            RedisCommand::Set { key, value, ttl, original_resp } => {
-                self.set(key, value, *ttl);
-                self.enqueue_for_replication(original_resp);
-                RedisResponse::Ok("OK".to_string())
+            RedisCommand::Set { key, value, ttl, options, original_resp } => {
+                let old = if options.get {
+                    match storage.read_string(key) {
+                        Ok(old) => old,
+                        Err(e) => return RedisResponse::Error(e),
+                    }
+                } else {
+                    None
+                };
+                let reply = if options.get {
+                    old.map_or(RedisResponse::NullBulkString, RedisResponse::BulkString)
+                } else {
+                    RedisResponse::Ok("OK".to_string())
+                };
+                let exists = storage.contains_key(key);
+                if (options.nx && exists) || (options.xx && !exists) {
+                    return if options.get { reply } else { RedisResponse::NullBulkString };
+                }
+
+                let expiration = match ttl {
+                    Some(ttl) => Some(Storage::get_current_time_ms().saturating_add(*ttl as u64)),
+                    None if options.keep_ttl => u64::try_from(storage.expire_time(key)).ok(),
+                    None => options.expire_at,
+                };
+                storage.insert(key.clone(), ValueWrapper::String { value: value.clone() }, expiration);
+                // Conditions and relative times could come out differently on a replica, so those
+                // are sent as an unconditional SET with an absolute expiration.
+                if ttl.is_none() && !options.nx && !options.xx && !options.get {
+                    self.enqueue_for_replication(db, original_resp);
+                } else {
+                    let when = expiration.map(|when| when.to_string());
+                    let mut args: Vec<&[u8]> = vec![b"SET", key, value];
+                    if let Some(when) = &when {
+                        args.extend([&b"PXAT"[..], when.as_bytes()]);
+                    }
+                    self.enqueue_for_replication(db, &command_resp(&args));
+                }
+                reply
             },
             RedisCommand::Type { key } => {
-                let type_str = self.storage.get_type(key).into_owned();
-                RedisResponse::BulkString(type_str)
+                let type_str = storage.get_type(key).into_owned();
+                RedisResponse::BulkString(type_str.into())
             },
-            RedisCommand::Incr { key } => {
-                match self.storage.incr(key) {
-                    Ok(value) => RedisResponse::Integer(value),
-                    Err(e) => RedisResponse::Error(format!("{}", e)),
+            RedisCommand::Append { key, value, original_resp } => {
+                match storage.append(key, value) {
+                    Ok(len) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
-            RedisCommand::LPush { key, value } => {
-                match self.storage.lpush(key, value) {
+            RedisCommand::StrLen { key } => {
+                match storage.strlen(key) {
                     Ok(len) => RedisResponse::Integer(len),
-                    Err(e) => RedisResponse::Error(format!("{}", e)),
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
-            RedisCommand::RPush { key, value } => {
-                match self.storage.rpush(key, value) {
-                    Ok(len) => RedisResponse::Integer(len),
-                    Err(e) => RedisResponse::Error(format!("{}", e)),
+            RedisCommand::GetRange { key, start, end } => {
+                match storage.getrange(key, *start, *end) {
+                    Ok(value) => RedisResponse::BulkString(value),
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
-            RedisCommand::LPop { key } => {
-                match self.storage.lpop(key) {
-                    Some(value) => RedisResponse::BulkString(value),
-                    None => RedisResponse::NullBulkString,
+            RedisCommand::SetRange { key, offset, value, original_resp } => {
+                match storage.setrange(key, *offset, value) {
+                    Ok(len) => {
+                        if !value.is_empty() {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
-            RedisCommand::RPop { key } => {
-                match self.storage.rpop(key) {
-                    Some(value) => RedisResponse::BulkString(value),
-                    None => RedisResponse::NullBulkString,
+            RedisCommand::MGet { keys } => {
+                RedisResponse::Array(storage.mget(keys).into_iter()
+                    .map(|value| value.map_or(RedisResponse::NullBulkString, RedisResponse::BulkString))
+                    .collect())
+            },
+            RedisCommand::MSet { pairs, nx, original_resp } => {
+                let set = storage.mset(pairs, *nx);
+                if set {
+                    self.enqueue_for_replication(db, original_resp);
+                }
+                if *nx { RedisResponse::Integer(set as i64) } else { RedisResponse::Ok("OK".to_string()) }
+            },
+            RedisCommand::GetSet { key, value, original_resp } => {
+                match storage.getset(key, value.clone()) {
+                    Ok(old) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        old.map_or(RedisResponse::NullBulkString, RedisResponse::BulkString)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GetDel { key } => {
+                match storage.getdel(key) {
+                    Ok(Some(value)) => {
+                        self.enqueue_for_replication(db, &command_resp(&[b"DEL", key]));
+                        RedisResponse::BulkString(value)
+                    },
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GetEx { key, milliseconds, absolute, persist } => {
+                let value = match storage.read_string(key) {
+                    Ok(Some(value)) => value,
+                    Ok(None) => return RedisResponse::NullBulkString,
+                    Err(e) => return RedisResponse::Error(e),
+                };
+                if let Some(milliseconds) = milliseconds {
+                    let now = Storage::get_current_time_ms() as i64;
+                    let when = if *absolute { *milliseconds } else { now.saturating_add(*milliseconds) };
+                    storage.expire_at(key, when, ExpireOptions::default());
+                    self.enqueue_for_replication(db, &command_resp(&[b"PEXPIREAT", key, when.to_string().as_bytes()]));
+                } else if *persist && storage.persist(key) {
+                    self.enqueue_for_replication(db, &command_resp(&[b"PERSIST", key]));
+                }
+                RedisResponse::BulkString(value)
+            },
+            RedisCommand::SetNx { key, value, original_resp } => {
+                if storage.contains_key(key) {
+                    return RedisResponse::Integer(0);
+                }
+                storage.set(key, value.clone(), None);
+                self.enqueue_for_replication(db, original_resp);
+                RedisResponse::Integer(1)
+            },
+            RedisCommand::SetEx { key, milliseconds, value } => {
+                let when = (Storage::get_current_time_ms() as i64).saturating_add(*milliseconds);
+                storage.insert(key.clone(), ValueWrapper::String { value: value.clone() }, Some(when as u64));
+                self.enqueue_for_replication(db, &command_resp(&[b"SET", key, value, b"PXAT", when.to_string().as_bytes()]));
+                RedisResponse::Ok("OK".to_string())
+            },
+            RedisCommand::Lcs { key1, key2, len, idx, min_match_len, with_match_len } => {
+                let (a, b) = match (storage.read_string(key1), storage.read_string(key2)) {
+                    (Ok(a), Ok(b)) => (a.unwrap_or_default(), b.unwrap_or_default()),
+                    _ => return RedisResponse::Error("ERR The specified keys must contain string values".to_string()),
+                };
+                let (sequence, matches) = lcs::lcs(&a, &b);
+                if *len {
+                    return RedisResponse::Integer(sequence.len() as i64);
+                }
+                if !*idx {
+                    return RedisResponse::BulkString(sequence.into());
+                }
+                let range = |(start, end): (usize, usize)| RedisResponse::Array(vec![
+                    RedisResponse::Integer(start as i64),
+                    RedisResponse::Integer(end as i64),
+                ]);
+                let matches = matches.into_iter()
+                    .filter(|found| found.match_len() >= *min_match_len)
+                    .map(|found| {
+                        let mut item = vec![range(found.a), range(found.b)];
+                        if *with_match_len {
+                            item.push(RedisResponse::Integer(found.match_len() as i64));
+                        }
+                        RedisResponse::Array(item)
+                    })
+                    .collect();
+                RedisResponse::Array(vec![
+                    RedisResponse::BulkString(Bytes::from_static(b"matches")),
+                    RedisResponse::Array(matches),
+                    RedisResponse::BulkString(Bytes::from_static(b"len")),
+                    RedisResponse::Integer(sequence.len() as i64),
+                ])
+            },
+            RedisCommand::Incr { key, delta, original_resp } => {
+                match storage.incr_by(key, *delta) {
+                    Ok(value) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(value)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SetBit { key, offset, value, original_resp } => {
+                match storage.setbit(key, *offset, *value) {
+                    Ok(old) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(old as i64)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GetBit { key, offset } => {
+                match storage.read_string(key) {
+                    Ok(value) => RedisResponse::Integer(bitmap::get_bit(&value.unwrap_or_default(), *offset) as i64),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::BitCount { key, range, in_bits } => {
+                match storage.read_string(key) {
+                    Ok(value) => RedisResponse::Integer(bitmap::count(&value.unwrap_or_default(), *range, *in_bits)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::BitPos { key, bit, start, end, in_bits } => {
+                match storage.read_string(key) {
+                    Ok(value) => RedisResponse::Integer(bitmap::position(&value.unwrap_or_default(), *bit, *start, *end, *in_bits)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::BitOp { op, destination, keys, original_resp } => {
+                match storage.bitop(*op, destination, keys) {
+                    Ok(len) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(len as i64)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::BitField { key, ops, original_resp, .. } => {
+                match storage.bitfield(key, ops) {
+                    Ok(replies) => {
+                        if ops.iter().any(FieldOp::is_write) {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Array(replies.into_iter()
+                            .map(|reply| reply.map_or(RedisResponse::NullBulkString, RedisResponse::Integer))
+                            .collect())
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::PfAdd { key, elements, original_resp } => {
+                match storage.pfadd(key, elements) {
+                    Ok(changed) => {
+                        if changed {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(changed as i64)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::PfCount { keys } => {
+                match storage.pfcount(keys) {
+                    Ok(count) => RedisResponse::Integer(count as i64),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::PfMerge { destination, sources, original_resp } => {
+                match storage.pfmerge(destination, sources) {
+                    Ok(()) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Ok("OK".to_string())
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::IncrByFloat { key, increment } => {
+                match storage.incr_by_float(key, *increment) {
+                    Ok(value) => {
+                        // Float formatting may differ on the replica, so the result is sent instead.
+                        self.enqueue_for_replication(db, &command_resp(&[b"SET", key, &value, b"KEEPTTL"]));
+                        RedisResponse::BulkString(value)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::Push { key, end, values, only_if_exists, original_resp } => {
+                match storage.push(key, *end, values, *only_if_exists) {
+                    Ok(len) => {
+                        if len > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                            self.blocked.signal(db, key);
+                        }
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::Pop { key, end, count, original_resp } => {
+                match storage.pop(key, *end, count.unwrap_or(1)) {
+                    Ok(popped) => {
+                        if popped.as_ref().is_some_and(|popped| !popped.is_empty()) {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        match (popped, count) {
+                            (Some(popped), Some(_)) => RedisResponse::Array(popped.into_iter().map(RedisResponse::BulkString).collect()),
+                            (Some(popped), None) => popped.into_iter().next().map_or(RedisResponse::NullBulkString, RedisResponse::BulkString),
+                            (None, Some(_)) => RedisResponse::NullArray,
+                            (None, None) => RedisResponse::NullBulkString,
+                        }
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::LMPop { keys, end, count }
+            | RedisCommand::BLMPop { keys, end, count, .. } => {
+                match storage.lmpop(keys, *end, *count) {
+                    Ok(Some((key, popped))) => {
+                        let pop: &[u8] = if *end == ListEnd::Left { b"LPOP" } else { b"RPOP" };
+                        self.enqueue_for_replication(db, &command_resp(&[pop, &key, popped.len().to_string().as_bytes()]));
+                        RedisResponse::Array(vec![
+                            RedisResponse::BulkString(key),
+                            RedisResponse::Array(popped.into_iter().map(RedisResponse::BulkString).collect()),
+                        ])
+                    },
+                    Ok(None) => RedisResponse::NullArray,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::LMove { source, destination, from, to, original_resp } => {
+                match storage.lmove(source, destination, *from, *to) {
+                    Ok(Some(element)) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        self.blocked.signal(db, destination);
+                        RedisResponse::BulkString(element)
+                    },
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::BLMove { source, destination, from, to, .. } => {
+                match storage.lmove(source, destination, *from, *to) {
+                    Ok(Some(element)) => {
+                        let name = |end: &ListEnd| -> &[u8] { if *end == ListEnd::Left { b"LEFT" } else { b"RIGHT" } };
+                        self.enqueue_for_replication(db, &command_resp(&[b"LMOVE", source, destination, name(from), name(to)]));
+                        self.blocked.signal(db, destination);
+                        RedisResponse::BulkString(element)
+                    },
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::BPop { keys, end, .. } => {
+                match storage.lmpop(keys, *end, 1) {
+                    Ok(Some((key, popped))) => {
+                        let pop: &[u8] = if *end == ListEnd::Left { b"LPOP" } else { b"RPOP" };
+                        self.enqueue_for_replication(db, &command_resp(&[pop, &key]));
+                        let element = popped.into_iter().next().expect("popped from an existing list");
+                        RedisResponse::Array(vec![RedisResponse::BulkString(key), RedisResponse::BulkString(element)])
+                    },
+                    Ok(None) => RedisResponse::NullArray,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::LRem { key, count, element, original_resp } => {
+                match storage.lrem(key, *count, element) {
+                    Ok(removed) => {
+                        if removed > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(removed)
+                    },
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
             RedisCommand::LLen { key } => {
-                let len = self.storage.llen(key);
-                RedisResponse::Integer(len as i64)
+                let len = storage.llen(key);
+                RedisResponse::Integer(len)
             },
             RedisCommand::LRange { key, start, stop } => {
-                let values = self.storage.lrange(key, *start, *stop);
+                let values = storage.lrange(key, *start, *stop);
                 if values.is_empty() {
                     RedisResponse::Array(vec![])
                 } else {
-                    RedisResponse::Array(
-                        values.iter()
-                            .map(|value| RedisResponse::BulkString(value.to_string()))
-                            .collect()
-                    )
+                    RedisResponse::Array(values.into_iter().map(RedisResponse::BulkString).collect())
                 }
             },
             RedisCommand::LTrim { key, start, stop } => {
-                match self.storage.ltrim(key, *start, *stop) {
+                match storage.ltrim(key, *start, *stop) {
                     Ok(_) => RedisResponse::Ok("OK".to_string()),
                     Err(e) => RedisResponse::Error(e),
                 }
             },
             RedisCommand::LIndex { key, index } => {
-                match self.storage.lindex(key, *index) {
+                match storage.lindex(key, *index) {
                     Some(value) => RedisResponse::BulkString(value),
                     None => RedisResponse::NullBulkString,
                 }
             },
-            RedisCommand::LPos { key, element, count } => {
-                match self.storage.lpos(key, element, count.map(|c| c as usize)) {
-                    Some(positions) if positions.is_empty() => RedisResponse::Integer(-1),
-                    Some(positions) => {
-                        if count.is_some() {
-                            RedisResponse::Array(positions.iter().map(|&pos| RedisResponse::Integer(pos as i64)).collect())
-                        } else {
-                            RedisResponse::Integer(positions[0] as i64)
-                        }
+            RedisCommand::LPos { key, element, rank, count, maxlen } => {
+                match storage.lpos(key, element, *rank, count.unwrap_or(1), *maxlen) {
+                    Ok(positions) => match count {
+                        Some(_) => RedisResponse::Array(positions.into_iter().map(|pos| RedisResponse::Integer(pos as i64)).collect()),
+                        None => RedisResponse::Integer(positions.first().map_or(-1, |&pos| pos as i64)),
                     },
-                    None => RedisResponse::Integer(-1)
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
             RedisCommand::LInsert { key, before, pivot, element } => {
-                match self.storage.linsert(key, *before, pivot, element) {
+                match storage.linsert(key, *before, pivot, element.clone()) {
                     Some(len) => RedisResponse::Integer(len as i64),
                     None => RedisResponse::Integer(-1),
                 }
             },
             RedisCommand::LSet { key, index, element } => {
-                match self.storage.lset(key, *index, element) {
+                match storage.lset(key, *index, element.clone()) {
                     Ok(_) => RedisResponse::SimpleString("OK".to_string()),
                     Err(e) => RedisResponse::Error(e),
                 }
             },
-            RedisCommand::XAdd { key, id, fields, original_resp } => {
-                match self.xadd(key, id, fields.clone()) {
+            RedisCommand::HSet { key, fields, original_resp } => {
+                match storage.hset(key, fields) {
+                    Ok(added) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(added)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HSetNx { key, field, value, original_resp } => {
+                match storage.hsetnx(key, field, value) {
+                    Ok(true) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(1)
+                    },
+                    Ok(false) => RedisResponse::Integer(0),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HGet { key, field } => {
+                match storage.hget(key, field) {
+                    Ok(Some(value)) => RedisResponse::BulkString(value),
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HMGet { key, fields } => {
+                match storage.hmget(key, fields) {
+                    Ok(values) => RedisResponse::Array(values.into_iter()
+                        .map(|value| value.map_or(RedisResponse::NullBulkString, RedisResponse::BulkString))
+                        .collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HGetAll { key } => {
+                match storage.hgetall(key) {
+                    Ok(pairs) => RedisResponse::Array(pairs.into_iter()
+                        .flat_map(|(field, value)| [RedisResponse::BulkString(field), RedisResponse::BulkString(value)])
+                        .collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HDel { key, fields, original_resp } => {
+                match storage.hdel(key, fields) {
+                    Ok(removed) => {
+                        if removed > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(removed)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HExists { key, field } => {
+                match storage.hexists(key, field) {
+                    Ok(exists) => RedisResponse::Integer(exists as i64),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HLen { key } => {
+                match storage.hlen(key) {
+                    Ok(len) => RedisResponse::Integer(len),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HKeys { key } => {
+                match storage.hkeys(key) {
+                    Ok(fields) => RedisResponse::Array(fields.into_iter().map(RedisResponse::BulkString).collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HVals { key } => {
+                match storage.hvals(key) {
+                    Ok(values) => RedisResponse::Array(values.into_iter().map(RedisResponse::BulkString).collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HIncrBy { key, field, increment, original_resp } => {
+                match storage.hincrby(key, field, *increment) {
+                    Ok(value) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(value)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HIncrByFloat { key, field, increment } => {
+                match storage.hincrbyfloat(key, field, *increment) {
+                    Ok(value) => {
+                        self.enqueue_for_replication(db, &command_resp(&[b"HSET", key, field, &value]));
+                        RedisResponse::BulkString(value)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HStrLen { key, field } => {
+                match storage.hstrlen(key, field) {
+                    Ok(len) => RedisResponse::Integer(len),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::HRandField { key, count, with_values } => {
+                match (storage.hrandfield(key, count.unwrap_or(1)), count) {
+                    (Ok(mut picked), None) => match picked.pop() {
+                        Some((field, _)) => RedisResponse::BulkString(field),
+                        None => RedisResponse::NullBulkString,
+                    },
+                    (Ok(picked), Some(_)) => RedisResponse::Array(picked.into_iter()
+                        .flat_map(|(field, value)| {
+                            let mut items = vec![RedisResponse::BulkString(field)];
+                            if *with_values {
+                                items.push(RedisResponse::BulkString(value));
+                            }
+                            items
+                        })
+                        .collect()),
+                    (Err(e), _) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SAdd { key, members, original_resp } => {
+                match storage.sadd(key, members) {
+                    Ok(added) => {
+                        if added > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(added)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SRem { key, members, original_resp } => {
+                match storage.srem(key, members) {
+                    Ok(removed) => {
+                        if removed > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(removed)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SMembers { key } => {
+                match storage.smembers(key) {
+                    Ok(members) => RedisResponse::Array(members.into_iter().map(RedisResponse::BulkString).collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SIsMember { key, member } => {
+                match storage.sismember(key, member) {
+                    Ok(is_member) => RedisResponse::Integer(is_member as i64),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SMIsMember { key, members } => {
+                match storage.smismember(key, members) {
+                    Ok(flags) => RedisResponse::Array(flags.into_iter().map(|flag| RedisResponse::Integer(flag as i64)).collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SCard { key } => {
+                match storage.scard(key) {
+                    Ok(len) => RedisResponse::Integer(len),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SPop { key, count } => {
+                match storage.spop(key, count.unwrap_or(1)) {
+                    Ok(popped) => {
+                        if !popped.is_empty() {
+                            let mut args: Vec<&[u8]> = vec![b"SREM", key];
+                            args.extend(popped.iter().map(|member| member.as_ref()));
+                            self.enqueue_for_replication(db, &command_resp(&args));
+                        }
+                        match count {
+                            Some(_) => RedisResponse::Array(popped.into_iter().map(RedisResponse::BulkString).collect()),
+                            None => popped.into_iter().next().map_or(RedisResponse::NullBulkString, RedisResponse::BulkString),
+                        }
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SRandMember { key, count } => {
+                match storage.srandmember(key, count.unwrap_or(1)) {
+                    Ok(picked) => match count {
+                        Some(_) => RedisResponse::Array(picked.into_iter().map(RedisResponse::BulkString).collect()),
+                        None => picked.into_iter().next().map_or(RedisResponse::NullBulkString, RedisResponse::BulkString),
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SMove { source, destination, member, original_resp } => {
+                match storage.smove(source, destination, member) {
+                    Ok(true) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(1)
+                    },
+                    Ok(false) => RedisResponse::Integer(0),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SetOperation { op, keys } => {
+                match storage.set_operation(*op, keys) {
+                    Ok(members) => RedisResponse::Array(members.into_iter().map(RedisResponse::BulkString).collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SetOperationStore { op, destination, keys, original_resp } => {
+                match storage.set_operation(*op, keys) {
+                    Ok(members) => {
+                        let len = storage.store_set(destination, members);
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::SInterCard { keys, limit } => {
+                match storage.set_operation(SetOp::Inter, keys) {
+                    Ok(members) if *limit > 0 => RedisResponse::Integer(members.len().min(*limit) as i64),
+                    Ok(members) => RedisResponse::Integer(members.len() as i64),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZAdd { key, options, pairs, original_resp } if options.incr => {
+                let (increment, member) = &pairs[0];
+                match storage.zadd_incr(key, *options, *increment, member) {
+                    Ok(Some(score)) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::BulkString(zset::format_double(score))
+                    },
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZAdd { key, options, pairs, original_resp } => {
+                match storage.zadd(key, *options, pairs) {
+                    Ok((added, updated)) => {
+                        if added + updated > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(if options.ch { added + updated } else { added })
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZIncrBy { key, increment, member, original_resp } => {
+                match storage.zadd_incr(key, ZAddOptions::default(), *increment, member) {
+                    Ok(score) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        score.map_or(RedisResponse::NullBulkString, |score| RedisResponse::BulkString(zset::format_double(score)))
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZRem { key, members, original_resp } => {
+                match storage.zrem(key, members) {
+                    Ok(removed) => {
+                        if removed > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(removed)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZScore { key, member } => {
+                match storage.zscore(key, member) {
+                    Ok(score) => score.map_or(RedisResponse::NullBulkString, |score| RedisResponse::BulkString(zset::format_double(score))),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZMScore { key, members } => {
+                match storage.zmscore(key, members) {
+                    Ok(scores) => RedisResponse::Array(scores.into_iter()
+                        .map(|score| score.map_or(RedisResponse::NullBulkString, |score| RedisResponse::BulkString(zset::format_double(score))))
+                        .collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZCard { key } => {
+                match storage.zcard(key) {
+                    Ok(len) => RedisResponse::Integer(len),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZCount { key, min, max } => {
+                match storage.zcount(key, *min, *max) {
+                    Ok(count) => RedisResponse::Integer(count),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZRange { key, spec, with_scores } => {
+                match storage.zrange(key, spec) {
+                    Ok(items) => scored_members(items, *with_scores),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZRangeStore { destination, source, spec, original_resp } => {
+                match storage.zrange(source, spec) {
+                    Ok(items) => {
+                        let mut members = SortedSet::new();
+                        for (member, score) in items {
+                            members.insert(member, score);
+                        }
+                        let len = storage.store_zset(destination, members);
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZRank { key, member, rev, with_score } => {
+                match storage.zrank(key, member, *rev) {
+                    Ok(Some((rank, score))) if *with_score => RedisResponse::Array(vec![
+                        RedisResponse::Integer(rank as i64),
+                        RedisResponse::BulkString(zset::format_double(score)),
+                    ]),
+                    Ok(Some((rank, _))) => RedisResponse::Integer(rank as i64),
+                    Ok(None) if *with_score => RedisResponse::NullArray,
+                    Ok(None) => RedisResponse::NullBulkString,
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZPop { key, count, max, original_resp } => {
+                match storage.zpop(key, count.unwrap_or(1), *max) {
+                    Ok(popped) => {
+                        if !popped.is_empty() {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        scored_members(popped, true)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::ZSetOperationStore { op, destination, keys, weights, aggregate, original_resp } => {
+                match storage.zset_operation(*op, keys, weights, *aggregate) {
+                    Ok(members) => {
+                        let len = storage.store_zset(destination, members);
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GeoAdd { key, options, pairs, original_resp } => {
+                match storage.zadd(key, *options, pairs) {
+                    Ok((added, updated)) => {
+                        if added + updated > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(if options.ch { added + updated } else { added })
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GeoPos { key, members } => {
+                match storage.geopos(key, members) {
+                    Ok(positions) => RedisResponse::Array(positions.into_iter()
+                        .map(|position| position.map_or(RedisResponse::NullArray, |(longitude, latitude)| coordinates(longitude, latitude)))
+                        .collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GeoDist { key, member1, member2, unit } => {
+                match storage.geopos(key, &[member1.clone(), member2.clone()]) {
+                    Ok(positions) => match (positions[0], positions[1]) {
+                        (Some((lon1, lat1)), Some((lon2, lat2))) => {
+                            let distance = geo::distance(lon1, lat1, lon2, lat2) / unit;
+                            RedisResponse::BulkString(format!("{:.4}", distance).into())
+                        },
+                        _ => RedisResponse::NullBulkString,
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GeoHash { key, members } => {
+                match storage.zmscore(key, members) {
+                    Ok(scores) => RedisResponse::Array(scores.into_iter()
+                        .map(|score| score.map_or(RedisResponse::NullBulkString, |score| {
+                            RedisResponse::BulkString(geo::geohash_string(score as u64).into())
+                        }))
+                        .collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GeoSearch { key, query } => {
+                match storage.geosearch(key, query) {
+                    Ok(found) => RedisResponse::Array(found.into_iter().map(|(member, distance, score)| {
+                        if !query.with_dist && !query.with_hash && !query.with_coord {
+                            return RedisResponse::BulkString(member);
+                        }
+                        let mut item = vec![RedisResponse::BulkString(member)];
+                        if query.with_dist {
+                            item.push(RedisResponse::BulkString(format!("{:.4}", distance / query.unit).into()));
+                        }
+                        if query.with_hash {
+                            item.push(RedisResponse::Integer(score as i64));
+                        }
+                        if query.with_coord {
+                            let (longitude, latitude) = geo::decode(score as u64);
+                            item.push(coordinates(longitude, latitude));
+                        }
+                        RedisResponse::Array(item)
+                    }).collect()),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::GeoSearchStore { destination, source, query, original_resp } => {
+                match storage.geosearch(source, query) {
+                    Ok(found) => {
+                        let mut members = SortedSet::new();
+                        for (member, distance, score) in found {
+                            members.insert(member, if query.store_dist { distance / query.unit } else { score });
+                        }
+                        let len = storage.store_zset(destination, members);
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(len)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XAdd { key, id, fields, nomkstream, trim } => {
+                if *nomkstream && !storage.contains_key(key) {
+                    return RedisResponse::NullBulkString;
+                }
+                match storage.xadd(key, id, fields.clone()) {
                     Ok(entry_id) => {
-                        self.enqueue_for_replication(original_resp);
-                        RedisResponse::BulkString(entry_id)
-                    },
-                    Err(e) => RedisResponse::Error(format!("{}", e)),
-                }
-            },
-            RedisCommand::XRange { key, start, end } => {
-                match self.storage.xrange(key, start, end) {
-                    Ok(entries) => {
-                        let mut response = Vec::new();
-                        for entry in entries {
-                            // Format each entry as an array containing the ID and field-value pairs
-                            let mut entry_response = Vec::new();
-                            entry_response.push(RedisResponse::BulkString(entry.id.clone()));
-                            // Convert HashMap to BTreeMap to ensure consistent ordering
-                            let ordered_fields: BTreeMap<_, _> = entry.fields.into_iter().collect();
-                            for (key, value) in ordered_fields {
-                                entry_response.push(RedisResponse::BulkString(key));
-                                entry_response.push(RedisResponse::BulkString(value));
+                        let trimmed = match trim {
+                            Some(trim) => storage.xtrim(key, trim).unwrap_or(0),
+                            None => 0,
+                        };
+                        // Replicas add under the ID generated here, and trim to the length the
+                        // stream ended up with rather than redo an approximate trim.
+                        let len = storage.xlen(key).unwrap_or(0).to_string();
+                        let mut args: Vec<&[u8]> = vec![b"XADD", key];
+                        if trimmed > 0 {
+                            args.extend([b"MAXLEN".as_slice(), b"=", len.as_bytes()]);
+                        }
+                        args.push(entry_id.as_bytes());
+                        for (field, value) in fields {
+                            args.extend([field.as_ref(), value.as_ref()]);
+                        }
+                        self.enqueue_for_replication(db, &command_resp(&args));
+                        self.blocked.signal(db, key);
+                        RedisResponse::BulkString(entry_id.into())
+                    },
+                    Err(e) => RedisResponse::Error(e.to_string()),
+                }
+            },
+            RedisCommand::XLen { key } => {
+                match storage.xlen(key) {
+                    Ok(len) => RedisResponse::Integer(len),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XDel { key, ids, original_resp } => {
+                match storage.xdel(key, ids) {
+                    Ok(deleted) => {
+                        if deleted > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(deleted)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XGroupCreate { key, group, id, mkstream } => {
+                match storage.xgroup_create(key, group, *id, *mkstream) {
+                    Ok(start) => {
+                        // `$` is replicated as the ID it stood for
+                        let start = format_id(start);
+                        let mut args: Vec<&[u8]> = vec![b"XGROUP", b"CREATE", key, group, start.as_bytes()];
+                        if *mkstream {
+                            args.push(b"MKSTREAM");
+                        }
+                        self.enqueue_for_replication(db, &command_resp(&args));
+                        RedisResponse::Ok("OK".to_string())
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XGroupSetId { key, group, id } => {
+                match storage.xgroup_setid(key, group, *id) {
+                    Ok(Some(start)) => {
+                        self.enqueue_for_replication(db, &command_resp(&[b"XGROUP", b"SETID", key, group, format_id(start).as_bytes()]));
+                        // Moved back, the group may have entries to deliver again
+                        self.blocked.signal(db, key);
+                        RedisResponse::Ok("OK".to_string())
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XGroupDestroy { key, group, original_resp } => {
+                match storage.xgroup_destroy(key, group) {
+                    Ok(destroyed) => {
+                        if destroyed {
+                            self.enqueue_for_replication(db, original_resp);
+                            // Its blocked readers get their NOGROUP error now
+                            self.blocked.signal(db, key);
+                        }
+                        RedisResponse::Integer(destroyed as i64)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XGroupCreateConsumer { key, group, consumer, original_resp } => {
+                match storage.xgroup_createconsumer(key, group, consumer) {
+                    Ok(Some(created)) => {
+                        if created {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(created as i64)
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XGroupDelConsumer { key, group, consumer, original_resp } => {
+                match storage.xgroup_delconsumer(key, group, consumer) {
+                    Ok(Some(pending)) => {
+                        self.enqueue_for_replication(db, original_resp);
+                        RedisResponse::Integer(pending as i64)
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XReadGroup { group, consumer, keys, ids, count, noack, .. } => {
+                // Every stream must have the group before anything is delivered from any of them
+                for key in keys {
+                    match storage.has_group(key, group) {
+                        Ok(true) => (),
+                        Ok(false) => return RedisResponse::Error(format!(
+                            "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                            String::from_utf8_lossy(key), String::from_utf8_lossy(group))),
+                        Err(e) => return RedisResponse::Error(e),
+                    }
+                }
+                let mut streams = Vec::new();
+                for (key, id) in keys.iter().zip(ids) {
+                    let read = match storage.xreadgroup(key, group, consumer, *id, *count, *noack) {
+                        Ok(Some(read)) => read,
+                        Ok(None) => continue,
+                        Err(e) => return RedisResponse::Error(e),
+                    };
+                    if read.consumer_created {
+                        self.enqueue_for_replication(db, &command_resp(&[b"XGROUP", b"CREATECONSUMER", key, group, consumer]));
+                    }
+                    if id.is_none() && !read.entries.is_empty() {
+                        // Replicas are told what was delivered rather than redo the read
+                        if *noack {
+                            self.enqueue_for_replication(db, &command_resp(&[b"XGROUP", b"SETID", key, group, format_id(read.last_delivered).as_bytes()]));
+                        } else {
+                            for (entry_id, _) in &read.entries {
+                                let pending = PendingEntry { consumer: consumer.clone(), delivered_at: read.delivered_at, deliveries: 1 };
+                                self.enqueue_for_replication(db, &xclaim_resp(key, group, *entry_id, &pending, read.last_delivered));
                             }
-                            response.push(RedisResponse::Array(entry_response));
                         }
-                        RedisResponse::Array(response)
+                    }
+                    for (entry_id, pending) in &read.redelivered {
+                        self.enqueue_for_replication(db, &xclaim_resp(key, group, *entry_id, pending, read.last_delivered));
+                    }
+                    // A history read always answers for its stream, a `>` read only with news
+                    if id.is_some() || !read.entries.is_empty() {
+                        let entries = read.entries.into_iter().map(|(id, fields)| stream_entry(id, fields)).collect();
+                        streams.push(RedisResponse::Array(vec![RedisResponse::BulkString(key.clone()), RedisResponse::Array(entries)]));
+                    }
+                }
+                if streams.is_empty() {
+                    RedisResponse::NullArray
+                } else {
+                    RedisResponse::Array(streams)
+                }
+            },
+            RedisCommand::XAck { key, group, ids, original_resp } => {
+                match storage.xack(key, group, ids) {
+                    Ok(acked) => {
+                        if acked > 0 {
+                            self.enqueue_for_replication(db, original_resp);
+                        }
+                        RedisResponse::Integer(acked)
                     },
-                    Err(e) => RedisResponse::Error(format!("{}", e)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XPendingSummary { key, group } => {
+                match storage.xpending_summary(key, group) {
+                    Ok(Some((count, bounds, consumers))) => {
+                        let id = |id: Option<StreamId>| id.map_or(RedisResponse::NullBulkString, |id| RedisResponse::BulkString(format_id(id).into()));
+                        let consumers = if consumers.is_empty() {
+                            RedisResponse::NullArray
+                        } else {
+                            RedisResponse::Array(consumers.into_iter()
+                                .map(|(name, count)| RedisResponse::Array(vec![
+                                    RedisResponse::BulkString(name),
+                                    RedisResponse::BulkString(count.to_string().into()),
+                                ]))
+                                .collect())
+                        };
+                        RedisResponse::Array(vec![
+                            RedisResponse::Integer(count as i64),
+                            id(bounds.map(|(first, _)| first)),
+                            id(bounds.map(|(_, last)| last)),
+                            consumers,
+                        ])
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_key_or_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XPending { key, group, start, end, count, consumer, min_idle } => {
+                match storage.xpending(key, group, *start, *end, *count, consumer.as_deref(), *min_idle) {
+                    Ok(Some(pending)) => {
+                        let now = Storage::get_current_time_ms();
+                        RedisResponse::Array(pending.into_iter()
+                            .map(|(id, pending)| RedisResponse::Array(vec![
+                                RedisResponse::BulkString(format_id(id).into()),
+                                RedisResponse::BulkString(pending.consumer),
+                                RedisResponse::Integer(now.saturating_sub(pending.delivered_at) as i64),
+                                RedisResponse::Integer(pending.deliveries as i64),
+                            ]))
+                            .collect())
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_key_or_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XClaim { key, group, consumer, min_idle, ids, options } => {
+                match storage.xclaim(key, group, consumer, *min_idle, ids, options) {
+                    Ok(Some(claimed)) => {
+                        self.replicate_claims(db, key, group, &claimed);
+                        if claimed.entries.is_empty() && options.last_id.is_some() {
+                            self.enqueue_for_replication(db, &command_resp(&[b"XGROUP", b"SETID", key, group, format_id(claimed.last_delivered).as_bytes()]));
+                        }
+                        RedisResponse::Array(claimed_entries(claimed.entries, options.justid))
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_key_or_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XAutoClaim { key, group, consumer, min_idle, start, count, justid } => {
+                match storage.xautoclaim(key, group, consumer, *min_idle, *start, *count, *justid) {
+                    Ok(Some(claimed)) => {
+                        self.replicate_claims(db, key, group, &claimed);
+                        let deleted = claimed.deleted.iter().map(|id| RedisResponse::BulkString(format_id(*id).into())).collect();
+                        RedisResponse::Array(vec![
+                            RedisResponse::BulkString(format_id(claimed.next).into()),
+                            RedisResponse::Array(claimed_entries(claimed.entries, *justid)),
+                            RedisResponse::Array(deleted),
+                        ])
+                    },
+                    Ok(None) => RedisResponse::Error(no_such_key_or_group(key, group)),
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XTrim { key, trim } => {
+                match storage.xtrim(key, trim) {
+                    Ok(trimmed) => {
+                        if trimmed > 0 {
+                            let len = storage.xlen(key).unwrap_or(0).to_string();
+                            self.enqueue_for_replication(db, &command_resp(&[b"XTRIM", key, b"MAXLEN", b"=", len.as_bytes()]));
+                        }
+                        RedisResponse::Integer(trimmed)
+                    },
+                    Err(e) => RedisResponse::Error(e),
+                }
+            },
+            RedisCommand::XRange { count: Some(0), .. } => RedisResponse::NullArray,
+            RedisCommand::XRange { key, start, end, count, rev } => {
+                match storage.xrange(key, *start, *end, *count, *rev) {
+                    Ok(entries) => RedisResponse::Array(entries.into_iter()
+                        .map(|entry| stream_entry(Storage::parse_stream_id(&entry.id).unwrap_or_default(), Some(entry.fields)))
+                        .collect()),
+                    Err(e) => RedisResponse::Error(e),
                 }
             },
             RedisCommand::XRead { .. } => {
@@ -285,50 +1418,55 @@ impl Redis {
             },
             RedisCommand::Info { subcommand } => {
                 let mut info = String::new();
+                // Replication is the only section we have
+                if !matches!(subcommand.to_ascii_lowercase().as_str(), "replication" | "all" | "default" | "everything") {
+                    return RedisResponse::BulkString(info.into());
+                }
                 
                 // Add replication info
                 info.push_str("# Replication\n");
-                if self.config.replicaof_host.is_some() {
+                let config = self.config();
+                if let (Some(host), Some(port)) = (&config.replicaof_host, &config.replicaof_port) {
                     info.push_str("role:slave\n");
                     info.push_str(&format!("master_replid:{}\n", gen_replid()));
-                    info.push_str(&format!("master_repl_offset:0\n"));
-                    info.push_str(&format!("master_host:{}\n", self.config.replicaof_host.as_ref().unwrap()));
-                    info.push_str(&format!("master_port:{}\n", self.config.replicaof_port.as_ref().unwrap()));
+                    info.push_str("master_repl_offset:0\n");
+                    info.push_str(&format!("master_host:{}\n", host));
+                    info.push_str(&format!("master_port:{}\n", port));
                 } else {
                     info.push_str("role:master\n");
                     info.push_str(&format!("master_replid:{}\n", gen_replid()));
-                    info.push_str(&format!("master_repl_offset:0\n"));
-                    info.push_str(&format!("connected_slaves:0\n"));
+                    info.push_str("master_repl_offset:0\n");
+                    info.push_str("connected_slaves:0\n");
                 }
                 
-                RedisResponse::BulkString(info)
+                RedisResponse::BulkString(info.into())
             },
             RedisCommand::Replconf { subcommand, params } => {
                 match subcommand.to_lowercase().as_str() {
                     "listening-port" => {
-                        if let Some(_port) = params.get(0) {
+                        if let Some(_port) = params.first() {
                             if let Some(client) = client {
-                                let peer = client.peer_addr().unwrap();
+                                let peer = client.peer_addr();
                                 let replica_host = peer.ip().to_string();
                                 let real_port = peer.port();
                                 #[cfg(debug_assertions)]
                                 println!("replica_host: {} replica_port: {}", replica_host, _port);
 
-                                self.replication.add_replica(replica_host, real_port.to_string(), client.try_clone().unwrap());
-                                return RedisResponse::Ok("+OK".to_string());
+                                self.replication.add_replica(replica_host, real_port.to_string(), client.clone());
+                                return RedisResponse::Ok("OK".to_string());
                             }
                         }
                         RedisResponse::Error("Cannot establish replica connection".to_string())
                     },
                     "capa" => {
                         // TODO: Implement the actual logic for these subcommands
-                        RedisResponse::Ok("+OK".to_string())
+                        RedisResponse::Ok("OK".to_string())
                     },
                     "ack" => {
-                        if let Some(offset_str) = params.get(0) {
+                        if let Some(offset_str) = params.first() {
                             if let Ok(offset) = offset_str.parse::<u64>() {
                                 if let Some(client) = client {
-                                    let addr = client.peer_addr().unwrap();
+                                    let addr = client.peer_addr();
                                     let replica_key = format!("{}:{}", addr.ip(), addr.port());
                                     self.update_replica_offset(&replica_key, offset);
                                     return RedisResponse::Ok("".to_string());
@@ -348,7 +1486,6 @@ impl Redis {
                         bytes_processed.to_string().len(), 
                         bytes_processed);
                     let _ = client.write(response.as_bytes());
-                    let _ = client.flush();
                     // Reset counter after reporting
                     self.bytes_processed.store(0, Ordering::SeqCst);
                     RedisResponse::Ok("".to_string())  // Return empty string to indicate response was sent directly
@@ -359,7 +1496,7 @@ impl Redis {
             RedisCommand::Psync { replica_id, offset } => {
                 if *offset == -1 && *replica_id == "?" {
                     if let Some(client) = client {
-                        let _ = client.write(format!("+FULLRESYNC {} {}", gen_replid(), 0).as_bytes());
+                        let _ = client.write(format!("+FULLRESYNC {} {}\r\n", gen_replid(), 0).as_bytes());
 
                         let rdb_file_base64 = "UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==";
                         // Decode the base64 string into a byte array
@@ -367,7 +1504,6 @@ impl Redis {
                         let length = rdb_file.len();
                         let _ = client.write(format!("${}\r\n", length).as_bytes());
                         let _ = client.write(rdb_file.as_slice());
-                        let _ = client.flush();
                     }
 
                     RedisResponse::Ok("".to_string())  // Return empty string to indicate response was sent directly
@@ -376,49 +1512,26 @@ impl Redis {
                 }
             },
             RedisCommand::Wait { numreplicas, timeout, elapsed } => {
+                // WAIT is polled by the client handler: every call checks the acknowledgements once
+                // and returns Retry until enough replicas caught up or the timeout elapsed, so the
+                // connection's task can wait between polls without holding anything.
                 #[cfg(debug_assertions)]
-                println!("[WAIT] Starting WAIT command execution: target_replicas={}, timeout={}ms, elapsed={}ms", numreplicas, timeout, elapsed);
-                
-                // Send initial GETACK to replicas to get their current offsets
-                #[cfg(debug_assertions)]
-                println!("[WAIT] Sending GETACK to replicas");
-                
-                let mut elapsed_time = *elapsed;
-                let start = std::time::Instant::now();
-
-                while elapsed_time < *timeout {
-                    // Send GETACK to replicas
-                    if let Err(_) = self.replication.send_getack_to_replicas() {
-                        #[cfg(debug_assertions)]
-                        println!("[WAIT] Failed to send GETACK to replicas");
-                        return RedisResponse::Integer(0);
-                    }
+                println!("[WAIT] target_replicas={}, timeout={}ms, elapsed={}ms", numreplicas, timeout, elapsed);
 
-                    // Give replicas a chance to respond
-                    thread::sleep(Duration::from_millis(10));
-
-                    // Check if we have enough replicas
+                if *elapsed == 0 && self.replication.send_getack_to_replicas().is_err() {
                     #[cfg(debug_assertions)]
-                    println!("[WAIT] Checking replica acknowledgments");
-                    
-                    let acks = self.replication.count_up_to_date_replicas();
-                    if acks >= *numreplicas as usize {
-                        #[cfg(debug_assertions)]
-                        println!("[WAIT] Found {} up-to-date replicas (target: {})", acks, numreplicas);
-                        return RedisResponse::Integer(acks as i64);
-                    }
-
-                    // Update elapsed time
-                    elapsed_time = start.elapsed().as_millis() as u64;
+                    println!("[WAIT] Failed to send GETACK to replicas");
+                    return RedisResponse::Integer(0);
+                }
 
+                let acks = self.replication.count_up_to_date_replicas();
+                if acks >= *numreplicas as usize || *elapsed >= *timeout {
                     #[cfg(debug_assertions)]
-                    println!("[WAIT] Not enough replicas acknowledged (got {}, need {}). Elapsed: {}ms", acks, numreplicas, elapsed_time);
+                    println!("[WAIT] Done with {} up-to-date replicas (target: {})", acks, numreplicas);
+                    RedisResponse::Integer(acks as i64)
+                } else {
+                    RedisResponse::Retry
                 }
-
-                // If we've exceeded the timeout, return 0
-                #[cfg(debug_assertions)]
-                println!("[WAIT] Command timed out after {}ms", elapsed_time);
-                RedisResponse::Integer(0)
             },
             RedisCommand::Config { subcommand, parameter } => {
                 match subcommand.as_str() {
@@ -426,35 +1539,261 @@ impl Redis {
                         match parameter.as_str() {
                             "dir" => {
                                 // Return the current directory
-                                let dir = self.config.dir.clone();
-                                RedisResponse::BulkString(dir)
+                                let dir = self.config().dir.clone();
+                                RedisResponse::BulkString(dir.into())
                             },
                             "dbfilename" => {
                                 // Return the current DB filename
-                                let dbfilename = self.config.dbfilename.clone();
-                                RedisResponse::BulkString(dbfilename)
+                                let dbfilename = self.config().dbfilename.clone();
+                                RedisResponse::BulkString(dbfilename.into())
                             },
+                            "hz" => RedisResponse::BulkString(self.config().hz.to_string().into()),
+                            "databases" => RedisResponse::BulkString(self.config().databases.to_string().into()),
                             _ => RedisResponse::Error(format!("Unknown config parameter '{}'", parameter)),
                         }
                     },
                     _ => RedisResponse::Error(format!("Unknown CONFIG subcommand '{}'", subcommand)),
                 }
             },
+            RedisCommand::Save => match self.save_rdb_file() {
+                Ok(()) => RedisResponse::Ok("OK".to_string()),
+                Err(e) => RedisResponse::Error(format!("ERR {}", e)),
+            },
             RedisCommand::Keys { pattern } => {
-                let keys = self.keys(pattern);
+                let keys = storage.keys(pattern);
                 let mut response = Vec::new();
                 for key in keys {
                     response.push(RedisResponse::BulkString(key));
                 }
                 RedisResponse::Array(response)
             },
-            RedisCommand::FlushDB => {
-                self.storage.flushdb();
+            RedisCommand::Scan { cursor, pattern, count, type_name } => {
+                let (next, keys) = storage.scan(*cursor, *count, pattern.as_deref(), type_name.as_deref());
+                RedisResponse::Array(vec![
+                    RedisResponse::BulkString(next.to_string().into()),
+                    RedisResponse::Array(keys.into_iter().map(RedisResponse::BulkString).collect()),
+                ])
+            },
+            RedisCommand::FlushDB { original_resp } => {
+                storage.flushdb();
+                self.enqueue_for_replication(db, original_resp);
+                RedisResponse::Ok("OK".to_string())
+            },
+            RedisCommand::Select { index } => {
+                match self.db_index(*index) {
+                    Ok(index) => {
+                        *selected_db = index;
+                        RedisResponse::Ok("OK".to_string())
+                    },
+                    Err(e) => e,
+                }
+            },
+            RedisCommand::Move { key, db: target, original_resp } => {
+                let target = match self.db_index(*target) {
+                    Ok(target) => target,
+                    Err(e) => return e,
+                };
+                if target == db {
+                    return RedisResponse::Error("ERR source and destination objects are the same".to_string());
+                }
+                let destination = self.db(target);
+                destination.expire_if_due(key);
+                if destination.contains_key(key) {
+                    return RedisResponse::Integer(0);
+                }
+                match storage.remove(key) {
+                    Some((value, expiration)) => {
+                        destination.insert(key.clone(), value, expiration);
+                        self.enqueue_for_replication(db, original_resp);
+                        self.blocked.signal(target, key);
+                        RedisResponse::Integer(1)
+                    },
+                    None => RedisResponse::Integer(0),
+                }
+            },
+            RedisCommand::SwapDb { first, second, original_resp } => {
+                let (first, second) = match (self.db_index(*first), self.db_index(*second)) {
+                    (Ok(first), Ok(second)) => (first, second),
+                    (Err(e), _) | (_, Err(e)) => return e,
+                };
+                self.databases.write().unwrap().swap(first, second);
+                // Clients blocked in either database may find what they wait for in the other one
+                self.blocked.signal_database(first);
+                self.blocked.signal_database(second);
+                self.enqueue_for_replication(db, original_resp);
                 RedisResponse::Ok("OK".to_string())
             },
+            RedisCommand::FlushAll { original_resp } => {
+                for index in 0..self.database_count() {
+                    self.db(index).flushdb();
+                }
+                self.enqueue_for_replication(db, original_resp);
+                RedisResponse::Ok("OK".to_string())
+            },
+            RedisCommand::Del { keys, original_resp } => {
+                let removed = keys.iter().filter(|key| storage.remove(key).is_some()).count();
+                if removed > 0 {
+                    self.enqueue_for_replication(db, original_resp);
+                }
+                RedisResponse::Integer(removed as i64)
+            },
+            RedisCommand::Exists { keys } | RedisCommand::Touch { keys } => {
+                // A key given twice counts twice, as in Redis.
+                RedisResponse::Integer(keys.iter().filter(|key| storage.contains_key(key)).count() as i64)
+            },
+            RedisCommand::Rename { key, new_key, nx, original_resp } => {
+                if !storage.contains_key(key) {
+                    return RedisResponse::Error("ERR no such key".to_string());
+                }
+                if *nx && storage.contains_key(new_key) {
+                    return RedisResponse::Integer(0);
+                }
+                if key != new_key {
+                    let (value, expiration) = storage.remove(key).expect("checked above");
+                    storage.insert(new_key.clone(), value, expiration);
+                    self.enqueue_for_replication(db, original_resp);
+                    self.blocked.signal(db, new_key);
+                }
+                if *nx { RedisResponse::Integer(1) } else { RedisResponse::Ok("OK".to_string()) }
+            },
+            RedisCommand::Copy { source, destination, db: target, replace, original_resp } => {
+                let target = match target.map(|target| self.db_index(target)) {
+                    Some(Ok(target)) => target,
+                    Some(Err(e)) => return e,
+                    None => db,
+                };
+                if target == db && source == destination {
+                    return RedisResponse::Error("ERR source and destination objects are the same".to_string());
+                }
+                let Some((value, expiration)) = storage.clone_value(source) else {
+                    return RedisResponse::Integer(0);
+                };
+                let target_storage = self.db(target);
+                target_storage.expire_if_due(destination);
+                if !*replace && target_storage.contains_key(destination) {
+                    return RedisResponse::Integer(0);
+                }
+                target_storage.insert(destination.clone(), value, expiration);
+                self.enqueue_for_replication(db, original_resp);
+                self.blocked.signal(target, destination);
+                RedisResponse::Integer(1)
+            },
+            RedisCommand::RandomKey => {
+                match storage.random_key() {
+                    Some(key) => RedisResponse::BulkString(key),
+                    None => RedisResponse::NullBulkString,
+                }
+            },
+            RedisCommand::DbSize => RedisResponse::Integer(storage.dbsize() as i64),
+            RedisCommand::Expire { key, milliseconds, absolute, options } => {
+                let now = Storage::get_current_time_ms() as i64;
+                let when = if *absolute { *milliseconds } else { now.saturating_add(*milliseconds) };
+                if !storage.expire_at(key, when, *options) {
+                    return RedisResponse::Integer(0);
+                }
+                self.enqueue_for_replication(db, &command_resp(&[b"PEXPIREAT", key, when.to_string().as_bytes()]));
+                RedisResponse::Integer(1)
+            },
+            RedisCommand::Ttl { key, in_milliseconds, absolute } => {
+                let expiration = storage.expire_time(key);
+                if expiration < 0 {
+                    return RedisResponse::Integer(expiration);
+                }
+                let milliseconds = if *absolute {
+                    expiration
+                } else {
+                    (expiration - Storage::get_current_time_ms() as i64).max(0)
+                };
+                match (in_milliseconds, absolute) {
+                    (true, _) => RedisResponse::Integer(milliseconds),
+                    (false, true) => RedisResponse::Integer(milliseconds / 1000),
+                    (false, false) => RedisResponse::Integer((milliseconds + 500) / 1000),
+                }
+            },
+            RedisCommand::Persist { key, original_resp } => {
+                if storage.persist(key) {
+                    self.enqueue_for_replication(db, original_resp);
+                    RedisResponse::Integer(1)
+                } else {
+                    RedisResponse::Integer(0)
+                }
+            },
             RedisCommand::Error { message } => {
                 RedisResponse::Error(message.clone())
             },
         }
     }
 }
+
+/// Sorted set members as a flat array, each followed by its score if `with_scores` is set.
+fn scored_members(items: Vec<(Bytes, f64)>, with_scores: bool) -> RedisResponse {
+    RedisResponse::Array(items.into_iter()
+        .flat_map(|(member, score)| {
+            let mut items = vec![RedisResponse::BulkString(member)];
+            if with_scores {
+                items.push(RedisResponse::BulkString(zset::format_double(score)));
+            }
+            items
+        })
+        .collect())
+}
+
+/// A command as the replication stream carries it: an array of bulk strings.
+fn command_resp(args: &[&[u8]]) -> Vec<u8> {
+    RedisResponse::Array(args.iter()
+        .map(|arg| RedisResponse::BulkString(Bytes::copy_from_slice(arg)))
+        .collect())
+        .format()
+}
+
+/// XGROUP's reply for a group that isn't there.
+fn no_such_group(key: &[u8], group: &[u8]) -> String {
+    format!("NOGROUP No such consumer group '{}' for key name '{}'", String::from_utf8_lossy(group), String::from_utf8_lossy(key))
+}
+
+/// XPENDING's, XCLAIM's and XAUTOCLAIM's reply for a stream or group that isn't there.
+fn no_such_key_or_group(key: &[u8], group: &[u8]) -> String {
+    format!("NOGROUP No such key '{}' or consumer group '{}'", String::from_utf8_lossy(key), String::from_utf8_lossy(group))
+}
+
+/// A stream entry the way XREADGROUP and XCLAIM reply with it: its ID and its fields, or nil
+/// for one that has been deleted since it was delivered.
+fn stream_entry(id: StreamId, fields: Option<Vec<(Bytes, Bytes)>>) -> RedisResponse {
+    let fields = match fields {
+        Some(fields) => RedisResponse::Array(fields.into_iter()
+            .flat_map(|(field, value)| [RedisResponse::BulkString(field), RedisResponse::BulkString(value)])
+            .collect()),
+        None => RedisResponse::NullArray,
+    };
+    RedisResponse::Array(vec![RedisResponse::BulkString(format_id(id).into()), fields])
+}
+
+/// The entries XCLAIM and XAUTOCLAIM took, or just their IDs with JUSTID.
+fn claimed_entries(entries: Vec<ClaimedEntry>, justid: bool) -> Vec<RedisResponse> {
+    entries.into_iter()
+        .map(|(id, fields, _)| if justid {
+            RedisResponse::BulkString(format_id(id).into())
+        } else {
+            stream_entry(id, fields)
+        })
+        .collect()
+}
+
+/// An XCLAIM that sets up a pending entry on a replica exactly as it is here.
+fn xclaim_resp(key: &[u8], group: &[u8], id: StreamId, pending: &PendingEntry, last_delivered: StreamId) -> Vec<u8> {
+    command_resp(&[
+        b"XCLAIM", key, group, &pending.consumer, b"0", format_id(id).as_bytes(),
+        b"TIME", pending.delivered_at.to_string().as_bytes(),
+        b"RETRYCOUNT", pending.deliveries.to_string().as_bytes(),
+        b"FORCE", b"JUSTID",
+        b"LASTID", format_id(last_delivered).as_bytes(),
+    ])
+}
+
+/// A GEOPOS/WITHCOORD position reply.
+fn coordinates(longitude: f64, latitude: f64) -> RedisResponse {
+    RedisResponse::Array(vec![
+        RedisResponse::BulkString(longitude.to_string().into()),
+        RedisResponse::BulkString(latitude.to_string().into()),
+    ])
+}
@@ -0,0 +1,267 @@
+//! Geospatial indexes the way Redis keeps them: a sorted set whose scores are 52-bit geohashes,
+//! latitude and longitude bits interleaved over a Web Mercator friendly latitude range.
+
+use bytes::Bytes;
+
+pub const LONGITUDE_MIN: f64 = -180.0;
+pub const LONGITUDE_MAX: f64 = 180.0;
+pub const LATITUDE_MIN: f64 = -85.05112878;
+pub const LATITUDE_MAX: f64 = 85.05112878;
+/// Bits per coordinate; the score holds twice as many.
+const STEP_MAX: u32 = 26;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Meters per unit of a distance argument: M, KM, FT or MI.
+pub fn parse_unit(arg: &[u8]) -> Option<f64> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Some(1.0),
+        b"km" => Some(1000.0),
+        b"ft" => Some(0.3048),
+        b"mi" => Some(1609.34),
+        _ => None,
+    }
+}
+
+pub fn valid_coordinates(longitude: f64, latitude: f64) -> bool {
+    (LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude) && (LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+}
+
+/// Spreads the low 32 bits of `x` over the even bits of the result.
+fn spread(x: u32) -> u64 {
+    (0..32).fold(0, |acc, bit| acc | ((x as u64 >> bit) & 1) << (2 * bit))
+}
+
+/// Gathers the even bits of `x`.
+fn squash(x: u64) -> u32 {
+    (0..32).fold(0, |acc, bit| acc | (((x >> (2 * bit)) & 1) as u32) << bit)
+}
+
+/// Cell indexes at `step` bits per coordinate, latitude first.
+fn cell(longitude: f64, latitude: f64, step: u32, latitude_range: (f64, f64)) -> (u32, u32) {
+    let cells = (1u64 << step) as f64;
+    let lat = (latitude - latitude_range.0) / (latitude_range.1 - latitude_range.0) * cells;
+    let lon = (longitude - LONGITUDE_MIN) / (LONGITUDE_MAX - LONGITUDE_MIN) * cells;
+    // The maximum itself would land one past the last cell.
+    let last = (1u64 << step) - 1;
+    ((lat as u64).min(last) as u32, (lon as u64).min(last) as u32)
+}
+
+fn interleave(lat: u32, lon: u32) -> u64 {
+    spread(lat) | spread(lon) << 1
+}
+
+fn encode_with(longitude: f64, latitude: f64, latitude_range: (f64, f64)) -> u64 {
+    let (lat, lon) = cell(longitude, latitude, STEP_MAX, latitude_range);
+    interleave(lat, lon)
+}
+
+/// The score of a position.
+pub fn encode(longitude: f64, latitude: f64) -> u64 {
+    encode_with(longitude, latitude, (LATITUDE_MIN, LATITUDE_MAX))
+}
+
+/// The center of the cell a score stands for, as (longitude, latitude).
+pub fn decode(bits: u64) -> (f64, f64) {
+    let (lat, lon) = (squash(bits), squash(bits >> 1));
+    let cells = (1u64 << STEP_MAX) as f64;
+    let lat_scale = LATITUDE_MAX - LATITUDE_MIN;
+    let lon_scale = LONGITUDE_MAX - LONGITUDE_MIN;
+    let lat_min = LATITUDE_MIN + lat as f64 / cells * lat_scale;
+    let lat_max = LATITUDE_MIN + (lat as f64 + 1.0) / cells * lat_scale;
+    let lon_min = LONGITUDE_MIN + lon as f64 / cells * lon_scale;
+    let lon_max = LONGITUDE_MIN + (lon as f64 + 1.0) / cells * lon_scale;
+    (
+        ((lon_min + lon_max) / 2.0).clamp(LONGITUDE_MIN, LONGITUDE_MAX),
+        ((lat_min + lat_max) / 2.0).clamp(LATITUDE_MIN, LATITUDE_MAX),
+    )
+}
+
+/// The standard 11 character geohash of a score, which unlike the score itself covers
+/// latitudes from -90 to 90.
+pub fn geohash_string(bits: u64) -> String {
+    let (longitude, latitude) = decode(bits);
+    let bits = encode_with(longitude, latitude, (-90.0, 90.0));
+    (0..11).map(|i| {
+        // 52 bits make 10 characters and a bit; the last one is padded with zeros.
+        let index = if i == 10 { 0 } else { (bits >> (52 - (i + 1) * 5)) & 0x1f };
+        BASE32[index as usize] as char
+    }).collect()
+}
+
+/// Great circle distance in meters, using the haversine formula.
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lon1, lat2, lon2) = (lat1.to_radians(), lon1.to_radians(), lat2.to_radians(), lon2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// Where a search is centered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Member(Bytes),
+    Position(f64, f64),
+}
+
+/// The area a search covers, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl Shape {
+    /// The distance from (`lon1`, `lat1`), the center, to a position inside the shape, or
+    /// `None` if it is outside.
+    pub fn distance_if_inside(&self, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<f64> {
+        match *self {
+            Shape::Radius(radius) => Some(distance(lon1, lat1, lon2, lat2)).filter(|distance| *distance <= radius),
+            Shape::Box { width, height } => {
+                // The latitude check is the cheaper one.
+                let lat_distance = EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs();
+                if lat_distance > height / 2.0 || distance(lon2, lat2, lon1, lat2) > width / 2.0 {
+                    return None;
+                }
+                Some(distance(lon1, lat1, lon2, lat2))
+            },
+        }
+    }
+
+    /// How far from the center the shape reaches at most.
+    fn reach(&self) -> f64 {
+        match *self {
+            Shape::Radius(radius) => radius,
+            Shape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sort {
+    None,
+    Asc,
+    Desc,
+}
+
+/// GEOSEARCH and GEOSEARCHSTORE options. `unit` is in meters, for replies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub origin: Origin,
+    pub shape: Shape,
+    pub unit: f64,
+    pub sort: Sort,
+    /// COUNT, and whether ANY was given with it.
+    pub count: Option<(usize, bool)>,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+    pub store_dist: bool,
+}
+
+/// Bits per coordinate for cells about as large as `reach` meters around `latitude`.
+fn estimate_step(reach: f64, latitude: f64) -> u32 {
+    if reach == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    let mut range = reach;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    // Make sure the range is included in most of the base cases.
+    step -= 2;
+    // Cells shrink towards the poles.
+    if latitude.abs() > 66.0 {
+        step -= 1;
+        if latitude.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+/// The score ranges, as half-open intervals, of the 3x3 cells around (`longitude`, `latitude`)
+/// that together contain `shape`. Members found there still have to be checked against it.
+pub fn score_ranges(longitude: f64, latitude: f64, shape: &Shape) -> Vec<(u64, u64)> {
+    let reach = shape.reach();
+    let mut step = estimate_step(reach, latitude);
+    let cells = |step: u32| (1u64 << step) as f64;
+    let lat_edge = |index: i64, step: u32| LATITUDE_MIN + index as f64 / cells(step) * (LATITUDE_MAX - LATITUDE_MIN);
+    let lon_edge = |index: i64, step: u32| LONGITUDE_MIN + index as f64 / cells(step) * (LONGITUDE_MAX - LONGITUDE_MIN);
+
+    // If the neighbouring cells end before the shape does, go one size up.
+    if step >= 2 {
+        let (lat, lon) = cell(longitude, latitude, step, (LATITUDE_MIN, LATITUDE_MAX));
+        let (lat, lon) = (lat as i64, lon as i64);
+        if distance(longitude, latitude, longitude, lat_edge(lat + 2, step)) < reach
+            || distance(longitude, latitude, longitude, lat_edge(lat - 1, step)) < reach
+            || distance(longitude, latitude, lon_edge(lon + 2, step), latitude) < reach
+            || distance(longitude, latitude, lon_edge(lon - 1, step), latitude) < reach {
+            step -= 1;
+        }
+    }
+
+    let (lat, lon) = cell(longitude, latitude, step, (LATITUDE_MIN, LATITUDE_MAX));
+    let count = 1i64 << step;
+    let shift = 2 * (STEP_MAX - step);
+    let mut ranges = Vec::with_capacity(9);
+    for lat in (lat as i64 - 1)..=(lat as i64 + 1) {
+        if !(0..count).contains(&lat) {
+            continue;
+        }
+        for lon in (lon as i64 - 1)..=(lon as i64 + 1) {
+            // Longitude wraps around the antimeridian.
+            let bits = interleave(lat as u32, lon.rem_euclid(count) as u32);
+            ranges.push((bits << shift, (bits + 1) << shift));
+        }
+    }
+    ranges.sort_unstable();
+    ranges.dedup();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        // Palermo, from the Redis documentation.
+        let score = encode(13.361389, 38.115556);
+        assert_eq!(score, 3479099956230698);
+        let (longitude, latitude) = decode(score);
+        assert!((longitude - 13.361389).abs() < 1e-5 && (latitude - 38.115556).abs() < 1e-5);
+        assert_eq!(geohash_string(score), "sqc8b49rny0");
+        assert_eq!(geohash_string(encode(15.087269, 37.502669)), "sqdtr74hyu0");
+
+        let catania = decode(encode(15.087269, 37.502669));
+        let d = distance(longitude, latitude, catania.0, catania.1);
+        assert!((d - 166274.1516).abs() < 0.01, "{}", d);
+    }
+
+    #[test]
+    fn test_score_ranges_cover_the_shape() {
+        let shape = Shape::Radius(200_000.0);
+        let ranges = score_ranges(15.0, 37.0, &shape);
+        for (longitude, latitude) in [(13.361389, 38.115556), (15.087269, 37.502669), (16.5, 38.2)] {
+            let score = encode(longitude, latitude);
+            assert!(ranges.iter().any(|(start, end)| (*start..*end).contains(&score)));
+        }
+        // Near the antimeridian the cells wrap around.
+        let ranges = score_ranges(179.99, 0.0, &Shape::Radius(10_000.0));
+        let score = encode(-179.99, 0.0);
+        assert!(ranges.iter().any(|(start, end)| (*start..*end).contains(&score)));
+        // A radius larger than the world covers all of it.
+        assert_eq!(score_ranges(0.0, 0.0, &Shape::Radius(1e8)), vec![(0, 1 << 52)]);
+    }
+}
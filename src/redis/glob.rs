@@ -0,0 +1,115 @@
+/// Matches `string` against a Redis glob `pattern`, byte by byte: `*` matches any run of bytes,
+/// `?` any single byte, `[abc]`, `[a-z]` and `[^a]` a byte from (or not from) a class, and `\`
+/// makes the next byte literal.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // After a mismatch, retry from the last `*` with it swallowing one more byte:
+    // (pattern position after the `*`, string position it resumes at).
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            backtrack = Some((p, s));
+            continue;
+        }
+        let next = if p < pattern.len() { match_one(pattern, p, string[s]) } else { None };
+        if let Some(next) = next {
+            p = next;
+            s += 1;
+            continue;
+        }
+        match backtrack {
+            Some((star_p, star_s)) => {
+                backtrack = Some((star_p, star_s + 1));
+                p = star_p;
+                s = star_s + 1;
+            },
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `byte` against the pattern element starting at `p`, which isn't a `*`. Returns where
+/// the next element starts if it matches.
+fn match_one(pattern: &[u8], p: usize, byte: u8) -> Option<usize> {
+    match pattern[p] {
+        b'?' => Some(p + 1),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == byte).then_some(p + 2),
+        b'[' => {
+            let mut i = p + 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            loop {
+                match pattern.get(i) {
+                    // Like Redis, an unterminated class ends with the pattern.
+                    None => break,
+                    Some(b']') => {
+                        i += 1;
+                        break;
+                    },
+                    Some(b'\\') if i + 1 < pattern.len() => {
+                        matched |= pattern[i + 1] == byte;
+                        i += 2;
+                    },
+                    Some(&start) if pattern.get(i + 1) == Some(&b'-') && i + 2 < pattern.len() => {
+                        let end = pattern[i + 2];
+                        let (low, high) = if start <= end { (start, end) } else { (end, start) };
+                        matched |= (low..=high).contains(&byte);
+                        i += 3;
+                    },
+                    Some(&c) => {
+                        matched |= c == byte;
+                        i += 1;
+                    },
+                }
+            }
+            (matched != negate).then_some(i)
+        },
+        c => (c == byte).then_some(p + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"user:*", b"user:42"));
+        assert!(!glob_match(b"user:*", b"users:42"));
+        assert!(glob_match(b"*:*:name", b"a:b:c:name"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"a**b", b"axxb"));
+        assert!(!glob_match(b"a*b", b"axxbc"));
+        assert!(glob_match(b"", b""));
+        assert!(!glob_match(b"", b"a"));
+    }
+
+    #[test]
+    fn test_classes_and_escapes() {
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(glob_match(b"h[z-x]llo", b"hyllo"));
+        assert!(glob_match(b"[\\]]", b"]"));
+        assert!(glob_match(b"\\*", b"*"));
+        assert!(!glob_match(b"\\*", b"a"));
+        assert!(glob_match(b"a\\?b*", b"a?bc"));
+        assert!(glob_match(b"x\\", b"x\\"));
+        assert!(glob_match(b"[abc", b"b"));
+    }
+}
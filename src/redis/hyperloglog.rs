@@ -0,0 +1,297 @@
+//! HyperLogLogs in the same string format Redis uses, so they survive RDB files and replication
+//! unchanged: a 16 byte header ("HYLL", the encoding, three unused bytes and a cached cardinality)
+//! followed by 16384 6-bit registers, either packed (dense) or run-length encoded (sparse).
+
+use bytes::Bytes;
+
+/// Bits of the hash that pick the register.
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits left for the run of zeros.
+const Q: u32 = 64 - P;
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * 6).div_ceil(8);
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// A sparse HyperLogLog growing past this is converted to dense, like Redis's default
+/// hll-sparse-max-bytes.
+const SPARSE_MAX_LEN: usize = 3000;
+/// The bias correction constant for an unbounded number of registers, 1 / (2 ln 2).
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+/// The largest register value a sparse VAL opcode holds.
+const SPARSE_MAX_VALUE: u8 = 32;
+
+pub const NOT_A_HLL: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+pub const CORRUPTED: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+/// An empty HyperLogLog: sparse, all registers zero, and a cached cardinality of 0.
+pub fn empty() -> Vec<u8> {
+    let mut data = header(SPARSE);
+    data[15] = 0;
+    data.extend_from_slice(&xzero(REGISTERS));
+    data
+}
+
+/// A header for `encoding` whose cached cardinality is marked stale.
+fn header(encoding: u8) -> Vec<u8> {
+    let mut header = b"HYLL".to_vec();
+    header.extend_from_slice(&[encoding, 0, 0, 0]);
+    header.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80]);
+    header
+}
+
+fn xzero(len: usize) -> [u8; 2] {
+    let len = len - 1;
+    [0x40 | (len >> 8) as u8, len as u8]
+}
+
+/// The 16384 register values of a HyperLogLog string.
+pub fn registers(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < HEADER_LEN || &data[..4] != b"HYLL" {
+        return Err(NOT_A_HLL.to_string());
+    }
+    match data[4] {
+        DENSE if data.len() == DENSE_LEN => {
+            Ok((0..REGISTERS).map(|index| dense_register(&data[HEADER_LEN..], index)).collect())
+        },
+        SPARSE => sparse_registers(&data[HEADER_LEN..]).ok_or_else(|| CORRUPTED.to_string()),
+        _ => Err(NOT_A_HLL.to_string()),
+    }
+}
+
+fn dense_register(registers: &[u8], index: usize) -> u8 {
+    let (byte, shift) = (index * 6 / 8, index * 6 % 8);
+    let low = registers[byte] >> shift;
+    let high = registers.get(byte + 1).map_or(0, |next| next.checked_shl(8 - shift as u32).unwrap_or(0));
+    (low | high) & 63
+}
+
+fn set_dense_register(registers: &mut [u8], index: usize, value: u8) {
+    let (byte, shift) = (index * 6 / 8, index * 6 % 8);
+    registers[byte] &= !(63 << shift);
+    registers[byte] |= value << shift;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !(63u8.checked_shr(8 - shift as u32).unwrap_or(0));
+        *next |= value.checked_shr(8 - shift as u32).unwrap_or(0);
+    }
+}
+
+/// Decodes the sparse opcodes: ZERO (00xxxxxx) and XZERO (01xxxxxx yyyyyyyy) are runs of zero
+/// registers, VAL (1vvvvvxx) a run of up to 4 registers holding vvvvv + 1.
+fn sparse_registers(opcodes: &[u8]) -> Option<Vec<u8>> {
+    let mut registers = Vec::with_capacity(REGISTERS);
+    let mut i = 0;
+    while i < opcodes.len() {
+        let opcode = opcodes[i];
+        let (value, len) = if opcode & 0xC0 == 0 {
+            i += 1;
+            (0, (opcode & 0x3F) as usize + 1)
+        } else if opcode & 0x80 == 0 {
+            let next = *opcodes.get(i + 1)?;
+            i += 2;
+            (0, (((opcode & 0x3F) as usize) << 8 | next as usize) + 1)
+        } else {
+            i += 1;
+            ((opcode >> 2 & 0x1F) + 1, (opcode & 0x03) as usize + 1)
+        };
+        if registers.len() + len > REGISTERS {
+            return None;
+        }
+        registers.extend(std::iter::repeat_n(value, len));
+    }
+    (registers.len() == REGISTERS).then_some(registers)
+}
+
+/// Encodes `registers`, sparse if `sparse` is allowed and the result is small enough.
+pub fn encode(registers: &[u8], sparse: bool) -> Vec<u8> {
+    if sparse && registers.iter().all(|value| *value <= SPARSE_MAX_VALUE) {
+        let mut data = header(SPARSE);
+        let mut index = 0;
+        while index < REGISTERS && data.len() <= SPARSE_MAX_LEN {
+            let value = registers[index];
+            let run = registers[index..].iter().take_while(|other| **other == value).count();
+            if value == 0 {
+                let len = run;
+                if len <= 64 {
+                    data.push((len - 1) as u8);
+                } else {
+                    data.extend_from_slice(&xzero(len));
+                }
+                index += len;
+            } else {
+                let len = run.min(4);
+                data.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                index += len;
+            }
+        }
+        if data.len() <= SPARSE_MAX_LEN {
+            return data;
+        }
+    }
+    let mut data = header(DENSE);
+    data.resize(DENSE_LEN, 0);
+    for (index, value) in registers.iter().enumerate() {
+        set_dense_register(&mut data[HEADER_LEN..], index, *value);
+    }
+    data
+}
+
+/// Adds `elements` to the HyperLogLog in `data`. Returns whether any register changed.
+pub fn add(data: &mut Vec<u8>, elements: &[Bytes]) -> Result<bool, String> {
+    let mut registers = registers(data)?;
+    let mut changed = false;
+    for element in elements {
+        let (index, count) = pattern_len(element);
+        if count > registers[index] {
+            registers[index] = count;
+            changed = true;
+        }
+    }
+    if changed {
+        *data = encode(&registers, data[4] == SPARSE);
+    }
+    Ok(changed)
+}
+
+/// The registers of the union of the HyperLogLogs in `strings`.
+pub fn union(strings: &[Bytes]) -> Result<Vec<u8>, String> {
+    let mut union = vec![0; REGISTERS];
+    for string in strings {
+        for (max, value) in union.iter_mut().zip(registers(string)?) {
+            *max = value.max(*max);
+        }
+    }
+    Ok(union)
+}
+
+/// Which register `element` lands in, and the position of the first set bit in the rest of its
+/// hash, counting from 1.
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc83b19);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // The extra bit caps the count at Q + 1.
+    let rest = hash >> P | 1 << Q;
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// MurmurHash64A, reading the input as little endian words like Redis does on every platform.
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// The estimated cardinality, using the estimator from Otmar Ertl's "New cardinality estimation
+/// algorithms for HyperLogLog sketches", as Redis does.
+pub fn count(registers: &[u8]) -> u64 {
+    let m = REGISTERS as f64;
+    // Registers loaded from a file can hold anything up to 63.
+    let mut histogram = [0u32; 64];
+    for value in registers {
+        histogram[*value as usize] += 1;
+    }
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let mut data = empty();
+        assert_eq!(data.len(), HEADER_LEN + 2);
+        assert_eq!(registers(&data).unwrap(), vec![0; REGISTERS]);
+
+        let elements: Vec<Bytes> = (0..100).map(|i| Bytes::from(format!("element:{}", i))).collect();
+        assert!(add(&mut data, &elements).unwrap());
+        assert!(!add(&mut data, &elements[..10]).unwrap());
+        assert_eq!(data[4], SPARSE);
+        let sparse = registers(&data).unwrap();
+        let dense = encode(&sparse, false);
+        assert_eq!(dense.len(), DENSE_LEN);
+        assert_eq!(registers(&dense).unwrap(), sparse);
+        assert!((98..=102).contains(&count(&sparse)));
+
+        let mut registers = vec![0; REGISTERS];
+        registers[REGISTERS - 1] = 63;
+        registers[0] = 1;
+        let data = encode(&registers, false);
+        assert_eq!(super::registers(&data).unwrap(), registers);
+
+        assert_eq!(super::registers(b"HYLL").unwrap_err(), NOT_A_HLL);
+        let mut truncated = empty();
+        truncated.pop();
+        assert_eq!(super::registers(&truncated).unwrap_err(), CORRUPTED);
+    }
+
+    #[test]
+    fn test_sparse_turns_dense_when_it_grows() {
+        let mut data = empty();
+        for chunk in 0..20 {
+            let elements: Vec<Bytes> = (0..1000).map(|i| Bytes::from(format!("{}-{}", chunk, i))).collect();
+            add(&mut data, &elements).unwrap();
+        }
+        assert_eq!(data[4], DENSE);
+        let estimate = count(&registers(&data).unwrap()) as f64;
+        assert!((estimate - 20000.0).abs() / 20000.0 < 0.03, "{}", estimate);
+    }
+}
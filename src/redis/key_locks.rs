@@ -0,0 +1,83 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::redis::commands::KeyAccess;
+
+/// Striped reader/writer locks over the keyspace.
+///
+/// Storage is a DashMap, so a single operation on a key is already safe. What the stripes add is
+/// that a whole command runs against its keys without interleaving: read-modify-write commands
+/// don't lose updates, a write reaches the replication queue in the order it was applied, and
+/// MULTI/EXEC sees (and leaves) its keys in a consistent state. Commands on keys in different
+/// stripes never wait on each other.
+pub struct KeyLocks {
+    stripes: Vec<RwLock<()>>,
+}
+
+pub enum KeyGuard<'a> {
+    Read(#[allow(dead_code)] RwLockReadGuard<'a, ()>),
+    Write(#[allow(dead_code)] RwLockWriteGuard<'a, ()>),
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_STRIPES)
+    }
+}
+
+impl KeyLocks {
+    pub const DEFAULT_STRIPES: usize = 256;
+
+    pub fn new(stripes: usize) -> Self {
+        KeyLocks {
+            stripes: (0..stripes.max(1)).map(|_| RwLock::new(())).collect(),
+        }
+    }
+
+    fn stripe(&self, key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.stripes.len() as u64) as usize
+    }
+
+    /// Takes every stripe the given accesses need. Stripes are always taken in ascending order,
+    /// so two commands locking overlapping keys can't deadlock; a stripe that is both read and
+    /// written is locked for writing.
+    pub fn lock<'a>(&'a self, accesses: &[KeyAccess<'_>]) -> Vec<KeyGuard<'a>> {
+        // stripe -> needs write
+        let mut wanted: BTreeMap<usize, bool> = BTreeMap::new();
+        for access in accesses {
+            match access {
+                KeyAccess::None => {},
+                KeyAccess::Read(keys) => {
+                    for key in keys {
+                        wanted.entry(self.stripe(key)).or_insert(false);
+                    }
+                },
+                KeyAccess::Write(keys) => {
+                    for key in keys {
+                        wanted.insert(self.stripe(key), true);
+                    }
+                },
+                KeyAccess::All => {
+                    wanted = (0..self.stripes.len()).map(|stripe| (stripe, true)).collect();
+                    break;
+                },
+            }
+        }
+
+        wanted.into_iter()
+            .map(|(stripe, write)| {
+                let lock = &self.stripes[stripe];
+                // A panicking command can't leave the unit value behind the lock inconsistent.
+                if write {
+                    KeyGuard::Write(lock.write().unwrap_or_else(|e| e.into_inner()))
+                } else {
+                    KeyGuard::Read(lock.read().unwrap_or_else(|e| e.into_inner()))
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,305 @@
+use bytes::Bytes;
+use dashmap::mapref::entry::Entry as MapEntry;
+use dashmap::mapref::multiple::RefMulti;
+use dashmap::mapref::one::{Ref, RefMut};
+use dashmap::DashMap;
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+/// A DashMap keyed by Redis keys that also keeps the keys sorted by `scan_hash`.
+///
+/// The order is what lets SCAN and the active expire cycle look at a slice of the keyspace
+/// without walking all of it. It is split into buckets by the top bits of the hash, so writers
+/// of unrelated keys rarely meet on the same lock, and a bucket covers a contiguous range of
+/// hashes. Every change to the order is made while the map's shard lock for that key is held,
+/// so the two never disagree about which keys exist; the bucket locks are only ever taken
+/// last, and never held while touching the map.
+pub struct Keyspace<V> {
+    map: DashMap<Bytes, V>,
+    order: Order,
+}
+
+struct Order {
+    buckets: Vec<Mutex<BTreeSet<(u64, Bytes)>>>,
+}
+
+pub enum Entry<'a, V> {
+    Occupied(OccupiedEntry<'a, V>),
+    Vacant(VacantEntry<'a, V>),
+}
+
+pub struct OccupiedEntry<'a, V> {
+    entry: dashmap::mapref::entry::OccupiedEntry<'a, Bytes, V>,
+    order: &'a Order,
+}
+
+pub struct VacantEntry<'a, V> {
+    entry: dashmap::mapref::entry::VacantEntry<'a, Bytes, V>,
+    order: &'a Order,
+}
+
+/// The position of `key` in SCAN order. `DefaultHasher::new` always uses the same keys, so it
+/// doesn't change while a client is iterating.
+fn scan_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Order {
+    const BUCKET_BITS: u32 = 6;
+
+    fn new() -> Self {
+        Order {
+            buckets: (0..1 << Self::BUCKET_BITS).map(|_| Mutex::new(BTreeSet::new())).collect(),
+        }
+    }
+
+    fn bucket_of(hash: u64) -> usize {
+        (hash >> (u64::BITS - Self::BUCKET_BITS)) as usize
+    }
+
+    fn bucket(&self, index: usize) -> MutexGuard<'_, BTreeSet<(u64, Bytes)>> {
+        self.buckets[index].lock().unwrap()
+    }
+
+    fn insert(&self, key: &Bytes) {
+        let hash = scan_hash(key);
+        self.bucket(Self::bucket_of(hash)).insert((hash, key.clone()));
+    }
+
+    fn remove(&self, key: &[u8]) {
+        let hash = scan_hash(key);
+        self.bucket(Self::bucket_of(hash)).remove(&(hash, Bytes::copy_from_slice(key)));
+    }
+}
+
+impl<V> Default for Keyspace<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Keyspace<V> {
+    pub fn new() -> Self {
+        Keyspace {
+            map: DashMap::new(),
+            order: Order::new(),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Ref<'_, Bytes, V>> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut(&self, key: &[u8]) -> Option<RefMut<'_, Bytes, V>> {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = RefMulti<'_, Bytes, V>> {
+        self.map.iter()
+    }
+
+    pub fn entry(&self, key: Bytes) -> Entry<'_, V> {
+        match self.map.entry(key) {
+            MapEntry::Occupied(entry) => Entry::Occupied(OccupiedEntry { entry, order: &self.order }),
+            MapEntry::Vacant(entry) => Entry::Vacant(VacantEntry { entry, order: &self.order }),
+        }
+    }
+
+    /// Stores `value` under `key` and returns what was there.
+    pub fn insert(&self, key: Bytes, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(std::mem::replace(entry.get_mut(), value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            },
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) -> Option<(Bytes, V)> {
+        self.remove_if(key, |_, _| true)
+    }
+
+    /// Removes `key` if `f` holds for it, the same as `DashMap::remove_if`.
+    pub fn remove_if(&self, key: &[u8], f: impl FnOnce(&Bytes, &V) -> bool) -> Option<(Bytes, V)> {
+        // `f` runs under the shard lock, which is where the order has to change too.
+        self.map.remove_if(key, |key, value| {
+            let remove = f(key, value);
+            if remove {
+                self.order.remove(key);
+            }
+            remove
+        })
+    }
+
+    pub fn clear(&self) {
+        self.map.retain(|key, _| {
+            self.order.remove(key);
+            false
+        });
+    }
+
+    /// The keys of up to `count` distinct hashes at or after `cursor`, in hash order, and the
+    /// cursor to continue from, 0 once there is nothing left. Keys sharing a hash are returned
+    /// together, so a cursor never splits them.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        let count = count.max(1);
+        let mut keys = Vec::new();
+        let mut hashes = 0;
+        let mut last = None;
+        for index in Order::bucket_of(cursor)..self.order.buckets.len() {
+            let bucket = self.order.bucket(index);
+            for (hash, key) in bucket.range((cursor, Bytes::new())..) {
+                if last != Some(*hash) {
+                    if hashes == count {
+                        let next = last.and_then(|last: u64| last.checked_add(1)).unwrap_or(0);
+                        return (next, keys);
+                    }
+                    hashes += 1;
+                    last = Some(*hash);
+                }
+                keys.push(key.clone());
+            }
+        }
+        (0, keys)
+    }
+
+    /// Up to `count` keys that follow each other in hash order, starting at a random hash and
+    /// wrapping around at the end.
+    pub fn sample(&self, count: usize) -> Vec<Bytes> {
+        let start: u64 = rand::thread_rng().gen();
+        let first = Order::bucket_of(start);
+        let buckets = self.order.buckets.len();
+        let mut keys = Vec::with_capacity(count);
+        // The start bucket is visited twice: from `start` on, then up to it after wrapping.
+        for step in 0..=buckets {
+            if keys.len() >= count {
+                break;
+            }
+            let bucket = self.order.bucket((first + step) % buckets);
+            let from = (start, Bytes::new());
+            let wanted = count - keys.len();
+            match step {
+                0 => keys.extend(bucket.range(from..).take(wanted).map(|(_, key)| key.clone())),
+                _ if step == buckets => keys.extend(bucket.range(..from).take(wanted).map(|(_, key)| key.clone())),
+                _ => keys.extend(bucket.iter().take(wanted).map(|(_, key)| key.clone())),
+            }
+        }
+        keys
+    }
+}
+
+impl<'a, V> Entry<'a, V> {
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> RefMut<'a, Bytes, V> {
+        match self {
+            Entry::Occupied(entry) => entry.entry.into_ref(),
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+}
+
+impl<'a, V> OccupiedEntry<'a, V> {
+    pub fn key(&self) -> &Bytes {
+        self.entry.key()
+    }
+
+    pub fn get(&self) -> &V {
+        self.entry.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.entry.get_mut()
+    }
+
+    pub fn remove(self) -> V {
+        self.order.remove(self.entry.key());
+        self.entry.remove()
+    }
+}
+
+impl<'a, V> VacantEntry<'a, V> {
+    pub fn insert(self, value: V) -> RefMut<'a, Bytes, V> {
+        self.order.insert(self.entry.key());
+        self.entry.insert(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan_all(keyspace: &Keyspace<()>, count: usize) -> Vec<Bytes> {
+        let mut keys = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = keyspace.scan(cursor, count);
+            keys.extend(batch);
+            if next == 0 {
+                return keys;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn test_scan_visits_every_key_once_in_hash_order() {
+        let keyspace = Keyspace::new();
+        for i in 0..1000 {
+            keyspace.insert(Bytes::from(format!("key:{}", i)), ());
+        }
+        let keys = scan_all(&keyspace, 7);
+        assert_eq!(keys.len(), 1000);
+        assert!(keys.windows(2).all(|pair| scan_hash(&pair[0]) < scan_hash(&pair[1])));
+    }
+
+    #[test]
+    fn test_order_follows_removes_and_clear() {
+        let keyspace = Keyspace::new();
+        for i in 0..100 {
+            keyspace.insert(Bytes::from(format!("key:{}", i)), i);
+        }
+        keyspace.remove(b"key:1");
+        assert!(keyspace.remove_if(b"key:2", |_, value| *value == 3).is_none());
+        assert!(keyspace.remove_if(b"key:3", |_, value| *value == 3).is_some());
+        if let Entry::Occupied(entry) = keyspace.entry(Bytes::from("key:4")) {
+            entry.remove();
+        }
+        keyspace.entry(Bytes::from("other")).or_insert_with(|| 0);
+        let (_, keys) = keyspace.scan(0, 1000);
+        assert_eq!(keys.len(), 98);
+        assert!(keys.iter().all(|key| keyspace.contains_key(key)));
+
+        keyspace.clear();
+        assert_eq!(keyspace.scan(0, 1000), (0, vec![]));
+        assert!(keyspace.sample(10).is_empty());
+    }
+
+    #[test]
+    fn test_sample_wraps_around() {
+        let keyspace = Keyspace::new();
+        for i in 0..50 {
+            keyspace.insert(Bytes::from(format!("key:{}", i)), ());
+        }
+        let sample = keyspace.sample(20);
+        assert_eq!(sample.len(), 20);
+        assert_eq!(sample.iter().collect::<BTreeSet<_>>().len(), 20);
+        assert_eq!(keyspace.sample(100).len(), 50);
+    }
+}
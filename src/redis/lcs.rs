@@ -0,0 +1,87 @@
+/// One run of consecutive bytes shared by both strings, as inclusive ranges into each of them.
+#[derive(Debug, PartialEq)]
+pub struct LcsMatch {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+}
+
+impl LcsMatch {
+    pub fn match_len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+/// The longest common subsequence of `a` and `b`, and the runs it is made of. Like Redis, the
+/// runs are listed from the end of the strings towards the start.
+pub fn lcs(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<LcsMatch>) {
+    // table[i * width + j] is the LCS length of a[..i] and b[..j].
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut sequence = vec![0; table[a.len() * width + b.len()] as usize];
+    let mut matches = Vec::new();
+    let (mut i, mut j, mut idx) = (a.len(), b.len(), sequence.len());
+    // The run being collected, growing backwards.
+    let mut current: Option<LcsMatch> = None;
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            sequence[idx - 1] = a[i - 1];
+            match &mut current {
+                None => current = Some(LcsMatch { a: (i - 1, i - 1), b: (j - 1, j - 1) }),
+                Some(run) if run.a.0 == i && run.b.0 == j => {
+                    run.a.0 -= 1;
+                    run.b.0 -= 1;
+                },
+                Some(_) => emit = true,
+            }
+            // A run touching the start of either string can't grow any further.
+            if current.as_ref().is_some_and(|run| run.a.0 == 0 || run.b.0 == 0) {
+                emit = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = current.is_some();
+        }
+        if emit {
+            matches.extend(current.take());
+        }
+    }
+    (sequence, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lcs, LcsMatch};
+
+    #[test]
+    fn test_lcs() {
+        let (sequence, matches) = lcs(b"ohmytext", b"mynewtext");
+        assert_eq!(sequence, b"mytext");
+        assert_eq!(matches, vec![
+            LcsMatch { a: (4, 7), b: (5, 8) },
+            LcsMatch { a: (2, 3), b: (0, 1) },
+        ]);
+        assert_eq!(matches[0].match_len(), 4);
+
+        assert_eq!(lcs(b"", b"abc"), (vec![], vec![]));
+        assert_eq!(lcs(b"abc", b"xyz"), (vec![], vec![]));
+        assert_eq!(lcs(b"abc", b"abc").1, vec![LcsMatch { a: (0, 2), b: (0, 2) }]);
+    }
+}
@@ -1,13 +1,303 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::Path;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, BufReader, Error, ErrorKind};
+use bytes::Bytes;
+
+use crate::redis::consumer_groups::{format_id, Consumer, ConsumerGroup, PendingEntry, StreamId};
+use crate::redis::storage::{Storage, StreamEntry, StreamMetadata, ValueWrapper, STREAM_NODE_MAX_ENTRIES};
+use crate::redis::zset::SortedSet;
+
+/// Key, value and expiry (unix time in ms) of one entry. The key is raw bytes.
+pub type RdbEntry = (Vec<u8>, ValueWrapper, Option<u64>);
+
+// Value types, numbered as Redis numbers them.
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Flags of an entry in a stream node.
+const STREAM_ITEM_DELETED: i64 = 1;
+const STREAM_ITEM_SAMEFIELDS: i64 = 2;
+
+/// A group's entries-read counter when it isn't known; Redis works it out again on load.
+const INVALID_ENTRIES_READ: u64 = u64::MAX;
+
+fn corrupt(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// `len` bytes of `buffer` from `pos`, or an error if it ends before that.
+fn take(buffer: &[u8], pos: usize, len: usize) -> std::io::Result<&[u8]> {
+    pos.checked_add(len)
+        .and_then(|end| buffer.get(pos..end))
+        .ok_or_else(|| corrupt("Value exceeds buffer size"))
+}
+
+fn byte_at(buffer: &[u8], pos: usize) -> std::io::Result<u8> {
+    Ok(take(buffer, pos, 1)?[0])
+}
+
+fn read_u64_le(buffer: &[u8], pos: usize) -> std::io::Result<u64> {
+    Ok(u64::from_le_bytes(take(buffer, pos, 8)?.try_into().unwrap()))
+}
+
+/// A stream ID in the 16 byte big endian form of node keys and pending entries.
+fn read_raw_id(buffer: &[u8], pos: usize) -> std::io::Result<StreamId> {
+    let bytes = take(buffer, pos, 16)?;
+    Ok((u64::from_be_bytes(bytes[..8].try_into().unwrap()), u64::from_be_bytes(bytes[8..].try_into().unwrap())))
+}
+
+/// A little endian signed integer as wide as `bytes`.
+fn read_int_le(bytes: &[u8]) -> i64 {
+    let value = bytes.iter().rev().fold(0u64, |value, byte| (value << 8) | *byte as u64);
+    sign_extend(value, bytes.len() as u32 * 8)
+}
+
+/// Reads the low `bits` bits of `value` as a signed integer.
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+/// Expands a string compressed with LZF, as Redis stores long strings unless
+/// `rdbcompression` is off.
+fn lzf_decompress(input: &[u8], len: usize) -> std::io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+        if ctrl < 32 {
+            // A run of ctrl + 1 literal bytes
+            output.extend_from_slice(take(input, pos, ctrl + 1)?);
+            pos += ctrl + 1;
+            continue;
+        }
+        // A back reference into what has been output so far
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += byte_at(input, pos)? as usize;
+            pos += 1;
+        }
+        let back = ((ctrl & 0x1F) << 8) + byte_at(input, pos)? as usize + 1;
+        pos += 1;
+        let start = output.len().checked_sub(back).ok_or_else(|| corrupt("Invalid LZF back reference"))?;
+        // The copy can overlap the bytes it appends, so it goes one byte at a time.
+        for i in start..start + run + 2 {
+            output.push(output[i]);
+        }
+    }
+    if output.len() != len {
+        return Err(corrupt("Invalid LZF string length"));
+    }
+    Ok(output)
+}
+
+/// The elements of a listpack, the compact encoding of small hashes, sets, sorted sets and
+/// list nodes since Redis 7. Integers are returned as their decimal text.
+fn listpack_elements(listpack: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut elements = Vec::new();
+    let mut pos = 6; // Skip the total size and the element count
+    loop {
+        let first = byte_at(listpack, pos)?;
+        let (element, len) = match first {
+            0xFF => return Ok(elements),
+            0x00..=0x7F => (first.to_string().into_bytes(), 1),
+            0x80..=0xBF => {
+                let len = (first & 0x3F) as usize;
+                (take(listpack, pos + 1, len)?.to_vec(), 1 + len)
+            }
+            0xC0..=0xDF => {
+                let value = ((first as u64 & 0x1F) << 8) | byte_at(listpack, pos + 1)? as u64;
+                (sign_extend(value, 13).to_string().into_bytes(), 2)
+            }
+            0xE0..=0xEF => {
+                let len = ((first as usize & 0x0F) << 8) | byte_at(listpack, pos + 1)? as usize;
+                (take(listpack, pos + 2, len)?.to_vec(), 2 + len)
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(take(listpack, pos + 1, 4)?.try_into().unwrap()) as usize;
+                (take(listpack, pos + 5, len)?.to_vec(), 5 + len)
+            }
+            0xF1..=0xF4 => {
+                let width = [2, 3, 4, 8][(first - 0xF1) as usize];
+                (read_int_le(take(listpack, pos + 1, width)?).to_string().into_bytes(), 1 + width)
+            }
+            _ => return Err(corrupt("Invalid listpack encoding")),
+        };
+        elements.push(element);
+        // Each element is followed by its own length, for walking the listpack backwards
+        pos += len + backlen_size(len);
+    }
+}
+
+/// How many bytes the back length of a listpack element `len` bytes long takes.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// The elements of a ziplist, the compact encoding before Redis 7.
+fn ziplist_elements(ziplist: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut elements = Vec::new();
+    let mut pos = 10; // Skip the total size, the tail offset and the element count
+    loop {
+        let first = byte_at(ziplist, pos)?;
+        if first == 0xFF {
+            return Ok(elements);
+        }
+        // The previous element's length takes one byte, or five from 254 on
+        pos += if first < 254 { 1 } else { 5 };
+        let encoding = byte_at(ziplist, pos)?;
+        let (element, len) = match encoding >> 6 {
+            0 => {
+                let len = (encoding & 0x3F) as usize;
+                (take(ziplist, pos + 1, len)?.to_vec(), 1 + len)
+            }
+            1 => {
+                let len = ((encoding as usize & 0x3F) << 8) | byte_at(ziplist, pos + 1)? as usize;
+                (take(ziplist, pos + 2, len)?.to_vec(), 2 + len)
+            }
+            2 => {
+                let len = u32::from_be_bytes(take(ziplist, pos + 1, 4)?.try_into().unwrap()) as usize;
+                (take(ziplist, pos + 5, len)?.to_vec(), 5 + len)
+            }
+            _ => {
+                let width = match encoding {
+                    0xC0 => 2,
+                    0xD0 => 4,
+                    0xE0 => 8,
+                    0xF0 => 3,
+                    0xFE => 1,
+                    0xF1..=0xFD => 0,
+                    _ => return Err(corrupt("Invalid ziplist encoding")),
+                };
+                let value = match width {
+                    // 0 to 12, kept in the encoding itself
+                    0 => (encoding & 0x0F) as i64 - 1,
+                    _ => read_int_le(take(ziplist, pos + 1, width)?),
+                };
+                (value.to_string().into_bytes(), 1 + width)
+            }
+        };
+        elements.push(element);
+        pos += len;
+    }
+}
+
+/// The members of an intset, the encoding of small sets of integers.
+fn intset_elements(intset: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+    let width = u32::from_le_bytes(take(intset, 0, 4)?.try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(take(intset, 4, 4)?.try_into().unwrap()) as usize;
+    if ![2, 4, 8].contains(&width) {
+        return Err(corrupt("Invalid intset encoding"));
+    }
+    (0..len)
+        .map(|i| Ok(read_int_le(take(intset, 8 + i * width, width)?).to_string().into_bytes()))
+        .collect()
+}
+
+fn next_element<'a>(elements: &mut std::slice::Iter<'a, Vec<u8>>) -> std::io::Result<&'a [u8]> {
+    elements.next().map(Vec::as_slice).ok_or_else(|| corrupt("Truncated stream node"))
+}
+
+fn next_int(elements: &mut std::slice::Iter<'_, Vec<u8>>) -> std::io::Result<i64> {
+    std::str::from_utf8(next_element(elements)?).ok()
+        .and_then(|element| element.parse().ok())
+        .ok_or_else(|| corrupt("Invalid integer in stream node"))
+}
+
+/// Appends the live entries of a stream node to `entries`. The node's listpack starts with
+/// the master entry: the live and deleted counts, the master fields and a 0. Every entry
+/// after it is its flags, its ID as a difference to `master`, its fields (only the values
+/// when they are the master fields), and how many elements it took.
+fn stream_node_entries(master: StreamId, elements: &[Vec<u8>], entries: &mut Vec<StreamEntry>) -> std::io::Result<()> {
+    let mut elements = elements.iter();
+    let _live = next_int(&mut elements)?;
+    let _deleted = next_int(&mut elements)?;
+    let master_fields = (0..next_int(&mut elements)?)
+        .map(|_| next_element(&mut elements))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    next_int(&mut elements)?;
+
+    while elements.len() > 0 {
+        let flags = next_int(&mut elements)?;
+        let ms = master.0.wrapping_add(next_int(&mut elements)? as u64);
+        let seq = master.1.wrapping_add(next_int(&mut elements)? as u64);
+        let fields = if flags & STREAM_ITEM_SAMEFIELDS != 0 {
+            master_fields.iter()
+                .map(|field| Ok((Bytes::copy_from_slice(field), Bytes::copy_from_slice(next_element(&mut elements)?))))
+                .collect::<std::io::Result<Vec<_>>>()?
+        } else {
+            (0..next_int(&mut elements)?)
+                .map(|_| {
+                    let field = Bytes::copy_from_slice(next_element(&mut elements)?);
+                    Ok((field, Bytes::copy_from_slice(next_element(&mut elements)?)))
+                })
+                .collect::<std::io::Result<Vec<_>>>()?
+        };
+        next_int(&mut elements)?;
+        if flags & STREAM_ITEM_DELETED == 0 {
+            entries.push(StreamEntry { id: format_id((ms, seq)), fields });
+        }
+    }
+    Ok(())
+}
+
+/// Builds a list, set, hash or sorted set from its flattened elements: members, or
+/// field/value and member/score pairs.
+fn collection(value_type: u8, elements: Vec<Vec<u8>>) -> std::io::Result<ValueWrapper> {
+    let mut elements = elements.into_iter().map(Bytes::from);
+    Ok(match value_type {
+        TYPE_LIST => ValueWrapper::List { values: elements.collect::<VecDeque<_>>() },
+        TYPE_SET => ValueWrapper::Set { members: elements.collect::<HashSet<_>>() },
+        TYPE_HASH => {
+            let mut fields = HashMap::new();
+            while let (Some(field), Some(value)) = (elements.next(), elements.next()) {
+                fields.insert(field, value);
+            }
+            ValueWrapper::Hash { fields }
+        }
+        _ => {
+            let mut members = SortedSet::new();
+            while let (Some(member), Some(score)) = (elements.next(), elements.next()) {
+                let score = std::str::from_utf8(&score).ok()
+                    .and_then(|score| score.parse::<f64>().ok())
+                    .ok_or_else(|| corrupt("Invalid sorted set score"))?;
+                members.insert(member, score);
+            }
+            ValueWrapper::ZSet { members }
+        }
+    })
+}
 
 pub struct RdbParser;
 
 impl RdbParser {
-    pub fn parse(path: &Path) -> std::io::Result<Vec<(String, String, Option<u64>)>> {
+    /// Reads every entry in the file, paired with the index of the database it belongs to.
+    pub fn parse(path: &Path) -> std::io::Result<Vec<(usize, RdbEntry)>> {
         println!("Attempting to open RDB file: {:?}", path);
-        
+
         // If RDB file doesn't exist, return empty vec without error
         if !path.exists() {
             println!("RDB file does not exist, starting with empty database");
@@ -35,6 +325,8 @@ impl RdbParser {
 
         let mut pos = 9; // Skip the header
         let mut result = Vec::new();
+        // Entries before the first selector belong to database 0.
+        let mut db = 0;
 
         while pos < buffer.len() {
             #[cfg(debug_assertions)]
@@ -50,16 +342,24 @@ impl RdbParser {
                     let (_, new_pos) = Self::parse_string(&buffer, pos)?;
                     pos = new_pos;
                 }
+                0xF5 => {
+                    // A function library; functions aren't supported, skip its code
+                    let (_, new_pos) = Self::parse_string(&buffer, pos + 1)?;
+                    pos = new_pos;
+                }
                 0xFE => {
+                    // Database selector: the entries that follow belong to this database
+                    let (number, new_pos) = Self::parse_length(&buffer, pos + 1)?;
                     #[cfg(debug_assertions)]
-                    println!("Database selector found");
-                    // Database selector
-                    pos += 1;
-                    // Skip database number and hash table sizes
-                    while buffer[pos] != 0xFB && pos < buffer.len() {
-                        pos += 1;
-                    }
-                    pos += 3;
+                    println!("Database selector found: {}", number);
+                    db = number;
+                    pos = new_pos;
+                }
+                0xFB => {
+                    // Resize hint, skip the hash table and expires table sizes
+                    let (_, new_pos) = Self::parse_length(&buffer, pos + 1)?;
+                    let (_, new_pos) = Self::parse_length(&buffer, new_pos)?;
+                    pos = new_pos;
                 }
                 0xFF => {
                     #[cfg(debug_assertions)]
@@ -72,10 +372,10 @@ impl RdbParser {
                     println!("Parsing key-value pair");
                     // Key-value pair
                     match Self::parse_key_value(&buffer, pos) {
-                        Ok((key, value, expiry, new_pos)) => {
+                        Ok(((key, value, expiry), new_pos)) => {
                             #[cfg(debug_assertions)]
-                            println!("Parsed key: {} with expiry: {:?}", key, expiry);
-                            result.push((key, value, expiry));
+                            println!("Parsed key: {} with expiry: {:?}", String::from_utf8_lossy(&key), expiry);
+                            result.push((db, (key, value, expiry)));
                             pos = new_pos;
                         }
                         Err(e) => {
@@ -93,7 +393,7 @@ impl RdbParser {
         Ok(result)
     }
 
-    fn parse_key_value(buffer: &[u8], mut pos: usize) -> std::io::Result<(String, String, Option<u64>, usize)> {
+    fn parse_key_value(buffer: &[u8], mut pos: usize) -> std::io::Result<(RdbEntry, usize)> {
         let mut expiry = None;
 
         // Check for expiry
@@ -101,14 +401,24 @@ impl RdbParser {
             let expiry_bytes = if buffer[pos] == 0xFD { 4 } else { 8 };
             pos += 1;
             let mut expiry_value = 0u64;
-            for i in 0..expiry_bytes {
-                expiry_value |= (buffer[pos + i] as u64) << (8 * i);
+            for (i, byte) in take(buffer, pos, expiry_bytes)?.iter().enumerate() {
+                expiry_value |= (*byte as u64) << (8 * i);
             }
-            expiry = Some(expiry_value);
+            // 0xFD stores seconds, 0xFC milliseconds
+            expiry = Some(if expiry_bytes == 4 { expiry_value * 1000 } else { expiry_value });
             pos += expiry_bytes;
         }
 
-        // Skip value type
+        // The key's LRU idle time (0xF8) or LFU counter (0xF9), which only eviction uses
+        loop {
+            match buffer.get(pos) {
+                Some(0xF8) => pos = Self::parse_length(buffer, pos + 1)?.1,
+                Some(0xF9) => pos += 2,
+                _ => break,
+            }
+        }
+
+        let value_type = byte_at(buffer, pos).map_err(|_| corrupt("Missing value type"))?;
         pos += 1;
 
         // Parse key
@@ -116,70 +426,581 @@ impl RdbParser {
         pos = new_pos;
 
         // Parse value
-        let (value, new_pos) = Self::parse_string(buffer, pos)?;
+        let (value, new_pos) = Self::parse_value(buffer, pos, value_type)?;
         pos = new_pos;
 
-        Ok((key, value, expiry, pos))
+        Ok(((key, value, expiry), pos))
     }
 
-    fn parse_string(buffer: &[u8], mut pos: usize) -> std::io::Result<(String, usize)> {
-        let len = match buffer[pos] >> 6 {
-            0 => {
-                let len = (buffer[pos] & 0x3F) as usize;
-                pos += 1;
-                len
+    /// Reads a value of any type Redis writes, in its plain or its compact encoding. Module
+    /// types and the zipmap hashes of Redis 2.4 and before are rejected.
+    fn parse_value(buffer: &[u8], mut pos: usize, value_type: u8) -> std::io::Result<(ValueWrapper, usize)> {
+        let value = match value_type {
+            TYPE_STRING => {
+                let (value, new_pos) = Self::parse_string(buffer, pos)?;
+                pos = new_pos;
+                ValueWrapper::String { value: Bytes::from(value) }
+            }
+            TYPE_LIST | TYPE_SET | TYPE_HASH => {
+                let (len, new_pos) = Self::parse_length(buffer, pos)?;
+                pos = new_pos;
+                let per_item = if value_type == TYPE_HASH { 2 } else { 1 };
+                let mut elements = Vec::new();
+                for _ in 0..len.saturating_mul(per_item) {
+                    let (element, new_pos) = Self::parse_string(buffer, pos)?;
+                    pos = new_pos;
+                    elements.push(element);
+                }
+                collection(value_type, elements)?
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let (len, new_pos) = Self::parse_length(buffer, pos)?;
+                pos = new_pos;
+                let mut members = SortedSet::new();
+                for _ in 0..len {
+                    let (member, new_pos) = Self::parse_string(buffer, pos)?;
+                    let (score, new_pos) = if value_type == TYPE_ZSET_2 {
+                        (f64::from_bits(read_u64_le(buffer, new_pos)?), new_pos + 8)
+                    } else {
+                        Self::parse_text_double(buffer, new_pos)?
+                    };
+                    pos = new_pos;
+                    members.insert(Bytes::from(member), score);
+                }
+                ValueWrapper::ZSet { members }
+            }
+            TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+                let (nodes, new_pos) = Self::parse_length(buffer, pos)?;
+                pos = new_pos;
+                let mut values = Vec::new();
+                for _ in 0..nodes {
+                    // A quicklist 2 node is a single plain element (1) or a listpack (2)
+                    let mut container = 2;
+                    if value_type == TYPE_LIST_QUICKLIST_2 {
+                        let (node_container, new_pos) = Self::parse_length(buffer, pos)?;
+                        container = node_container;
+                        pos = new_pos;
+                    }
+                    let (node, new_pos) = Self::parse_string(buffer, pos)?;
+                    pos = new_pos;
+                    match container {
+                        1 => values.push(node),
+                        _ if value_type == TYPE_LIST_QUICKLIST => values.extend(ziplist_elements(&node)?),
+                        _ => values.extend(listpack_elements(&node)?),
+                    }
+                }
+                collection(TYPE_LIST, values)?
             }
+            TYPE_LIST_ZIPLIST | TYPE_SET_INTSET | TYPE_ZSET_ZIPLIST | TYPE_HASH_ZIPLIST
+            | TYPE_HASH_LISTPACK | TYPE_ZSET_LISTPACK | TYPE_SET_LISTPACK => {
+                let (blob, new_pos) = Self::parse_string(buffer, pos)?;
+                pos = new_pos;
+                match value_type {
+                    TYPE_LIST_ZIPLIST => collection(TYPE_LIST, ziplist_elements(&blob)?)?,
+                    TYPE_SET_INTSET => collection(TYPE_SET, intset_elements(&blob)?)?,
+                    TYPE_ZSET_ZIPLIST => collection(TYPE_ZSET, ziplist_elements(&blob)?)?,
+                    TYPE_HASH_ZIPLIST => collection(TYPE_HASH, ziplist_elements(&blob)?)?,
+                    TYPE_HASH_LISTPACK => collection(TYPE_HASH, listpack_elements(&blob)?)?,
+                    TYPE_ZSET_LISTPACK => collection(TYPE_ZSET, listpack_elements(&blob)?)?,
+                    _ => collection(TYPE_SET, listpack_elements(&blob)?)?,
+                }
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                let (stream, new_pos) = Self::parse_stream(buffer, pos, value_type)?;
+                pos = new_pos;
+                stream
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported RDB value type {}", value_type))),
+        };
+        Ok((value, pos))
+    }
+
+    /// Reads a stream: its nodes, then its length and last ID, then its consumer groups with
+    /// their pending entries and consumers. Type 19 added the first ID, the highest deleted
+    /// ID, the number of entries ever added and each group's entries-read counter, none of
+    /// which is kept here; type 21 added each consumer's active time.
+    fn parse_stream(buffer: &[u8], mut pos: usize, value_type: u8) -> std::io::Result<(ValueWrapper, usize)> {
+        let (nodes, new_pos) = Self::parse_length(buffer, pos)?;
+        pos = new_pos;
+        let mut entries = Vec::new();
+        for _ in 0..nodes {
+            let (master, new_pos) = Self::parse_string(buffer, pos)?;
+            let (node, new_pos) = Self::parse_string(buffer, new_pos)?;
+            pos = new_pos;
+            stream_node_entries(read_raw_id(&master, 0)?, &listpack_elements(&node)?, &mut entries)?;
+        }
+
+        let (_length, new_pos) = Self::parse_length(buffer, pos)?;
+        let (last_ms, new_pos) = Self::parse_length(buffer, new_pos)?;
+        let (last_seq, new_pos) = Self::parse_length(buffer, new_pos)?;
+        pos = new_pos;
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            for _ in 0..5 {
+                pos = Self::parse_length(buffer, pos)?.1;
+            }
+        }
+        let mut metadata = StreamMetadata { last_id: (last_ms as u64, last_seq as u64), ..Default::default() };
+
+        let (groups, new_pos) = Self::parse_length(buffer, pos)?;
+        pos = new_pos;
+        for _ in 0..groups {
+            let (name, new_pos) = Self::parse_string(buffer, pos)?;
+            let (ms, new_pos) = Self::parse_length(buffer, new_pos)?;
+            let (seq, new_pos) = Self::parse_length(buffer, new_pos)?;
+            pos = new_pos;
+            if value_type >= TYPE_STREAM_LISTPACKS_2 {
+                pos = Self::parse_length(buffer, pos)?.1;
+            }
+            let mut group = ConsumerGroup::new((ms as u64, seq as u64));
+
+            // The group's pending entries come first; each consumer then lists the ones it owns.
+            let (pending, new_pos) = Self::parse_length(buffer, pos)?;
+            pos = new_pos;
+            let mut unowned = BTreeMap::new();
+            for _ in 0..pending {
+                let id = read_raw_id(buffer, pos)?;
+                let delivered_at = read_u64_le(buffer, pos + 16)?;
+                let (deliveries, new_pos) = Self::parse_length(buffer, pos + 24)?;
+                pos = new_pos;
+                unowned.insert(id, (delivered_at, deliveries as u64));
+            }
+
+            let (consumers, new_pos) = Self::parse_length(buffer, pos)?;
+            pos = new_pos;
+            for _ in 0..consumers {
+                let (name, new_pos) = Self::parse_string(buffer, pos)?;
+                let consumer = Bytes::from(name);
+                let seen_at = read_u64_le(buffer, new_pos)?;
+                pos = new_pos + if value_type == TYPE_STREAM_LISTPACKS_3 { 16 } else { 8 };
+                let (owned, new_pos) = Self::parse_length(buffer, pos)?;
+                pos = new_pos;
+                for _ in 0..owned {
+                    let id = read_raw_id(buffer, pos)?;
+                    pos += 16;
+                    let (delivered_at, deliveries) = unowned.remove(&id)
+                        .ok_or_else(|| corrupt("Consumer owns an entry its group doesn't have pending"))?;
+                    group.pending.insert(id, PendingEntry { consumer: consumer.clone(), delivered_at, deliveries });
+                }
+                group.consumers.insert(consumer, Consumer { seen_at });
+            }
+            metadata.groups.insert(Bytes::from(name), group);
+        }
+
+        Ok((ValueWrapper::Stream { entries, metadata }, pos))
+    }
+
+    /// Reads a score of the original sorted set type: its text behind a one byte length, or
+    /// 253, 254 and 255 for NaN, +inf and -inf.
+    fn parse_text_double(buffer: &[u8], pos: usize) -> std::io::Result<(f64, usize)> {
+        match byte_at(buffer, pos)? {
+            253 => Ok((f64::NAN, pos + 1)),
+            254 => Ok((f64::INFINITY, pos + 1)),
+            255 => Ok((f64::NEG_INFINITY, pos + 1)),
+            len => {
+                let text = take(buffer, pos + 1, len as usize)?;
+                let score = std::str::from_utf8(text).ok()
+                    .and_then(|text| text.parse().ok())
+                    .ok_or_else(|| corrupt("Invalid sorted set score"))?;
+                Ok((score, pos + 1 + len as usize))
+            }
+        }
+    }
+
+    /// Reads a length-encoded integer, as used for string lengths, element counts, the
+    /// database selector and the resize hint.
+    fn parse_length(buffer: &[u8], pos: usize) -> std::io::Result<(usize, usize)> {
+        let truncated = || Error::new(ErrorKind::InvalidData, "Length exceeds buffer size");
+        let first = *buffer.get(pos).ok_or_else(truncated)?;
+        match first >> 6 {
+            0 => Ok(((first & 0x3F) as usize, pos + 1)),
             1 => {
-                let len = (((buffer[pos] & 0x3F) as usize) << 8) | buffer[pos + 1] as usize;
-                pos += 2;
-                len
+                let second = *buffer.get(pos + 1).ok_or_else(truncated)?;
+                Ok(((((first & 0x3F) as usize) << 8) | second as usize, pos + 2))
+            }
+            2 if first == 0x81 => {
+                let bytes = buffer.get(pos + 1..pos + 9).ok_or_else(truncated)?;
+                Ok((u64::from_be_bytes(bytes.try_into().unwrap()) as usize, pos + 9))
             }
             2 => {
-                let len = ((buffer[pos + 1] as usize) << 24)
-                    | ((buffer[pos + 2] as usize) << 16)
-                    | ((buffer[pos + 3] as usize) << 8)
-                    | (buffer[pos + 4] as usize);
-                pos += 5;
-                len
+                let bytes = buffer.get(pos + 1..pos + 5).ok_or_else(truncated)?;
+                Ok((u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize, pos + 5))
             }
+            _ => Err(Error::new(ErrorKind::InvalidData, "Invalid length encoding")),
+        }
+    }
+
+    fn parse_string(buffer: &[u8], pos: usize) -> std::io::Result<(Vec<u8>, usize)> {
+        let first = byte_at(buffer, pos)?;
+        if first >> 6 != 3 {
+            let (len, pos) = Self::parse_length(buffer, pos)?;
+            if pos + len > buffer.len() {
+                return Err(Error::new(ErrorKind::InvalidData, "String length exceeds buffer size"));
+            }
+            return Ok((buffer[pos..pos + len].to_vec(), pos + len));
+        }
+
+        // Special encoding
+        match first & 0x3F {
+            // 8, 16 and 32 bit integers
+            0 => Ok((read_int_le(take(buffer, pos + 1, 1)?).to_string().into_bytes(), pos + 2)),
+            1 => Ok((read_int_le(take(buffer, pos + 1, 2)?).to_string().into_bytes(), pos + 3)),
+            2 => Ok((read_int_le(take(buffer, pos + 1, 4)?).to_string().into_bytes(), pos + 5)),
             3 => {
-                // Special encoding
-                match buffer[pos] & 0x3F {
-                    0 => {
-                        // 8 bit integer
-                        let value = buffer[pos + 1] as i8;
-                        pos += 2;
-                        return Ok((value.to_string(), pos));
-                    }
-                    1 => {
-                        // 16 bit integer
-                        let value = i16::from_le_bytes([buffer[pos + 1], buffer[pos + 2]]);
-                        pos += 3;
-                        return Ok((value.to_string(), pos));
-                    }
-                    2 => {
-                        // 32 bit integer
-                        let value = i32::from_le_bytes([buffer[pos + 1], buffer[pos + 2], buffer[pos + 3], buffer[pos + 4]]);
-                        pos += 5;
-                        return Ok((value.to_string(), pos));
-                    }
-                    _ => {
-                        return Err(Error::new(ErrorKind::InvalidData, "Unsupported string encoding"));
-                    }
+                // LZF compressed: the compressed length, the original length, then the data
+                let (compressed_len, pos) = Self::parse_length(buffer, pos + 1)?;
+                let (len, pos) = Self::parse_length(buffer, pos)?;
+                let value = lzf_decompress(take(buffer, pos, compressed_len)?, len)?;
+                Ok((value, pos + compressed_len))
+            }
+            _ => Err(Error::new(ErrorKind::InvalidData, "Unsupported string encoding")),
+        }
+    }
+}
+
+/// Builds an RDB file the way Redis 7.2 writes one (version 11), so Redis can load it too.
+/// Values are written in their plain encodings, which every Redis reads.
+pub struct RdbWriter {
+    buffer: Vec<u8>,
+}
+
+impl RdbWriter {
+    pub fn new() -> Self {
+        RdbWriter { buffer: b"REDIS0011".to_vec() }
+    }
+
+    /// Starts database `db`; the entries written after belong to it.
+    pub fn select_db(&mut self, db: usize) {
+        self.buffer.push(0xFE);
+        write_length(&mut self.buffer, db as u64);
+    }
+
+    /// Writes `key` with its value and its expiry in unix milliseconds.
+    pub fn write_entry(&mut self, key: &[u8], value: &ValueWrapper, expiry: Option<u64>) {
+        if let Some(expiry) = expiry {
+            self.buffer.push(0xFC);
+            self.buffer.extend_from_slice(&expiry.to_le_bytes());
+        }
+        let buffer = &mut self.buffer;
+        match value {
+            ValueWrapper::String { value } => {
+                buffer.push(TYPE_STRING);
+                write_string(buffer, key);
+                write_string(buffer, value);
+            }
+            ValueWrapper::List { values } => {
+                buffer.push(TYPE_LIST);
+                write_string(buffer, key);
+                write_length(buffer, values.len() as u64);
+                values.iter().for_each(|value| write_string(buffer, value));
+            }
+            ValueWrapper::Set { members } => {
+                buffer.push(TYPE_SET);
+                write_string(buffer, key);
+                write_length(buffer, members.len() as u64);
+                members.iter().for_each(|member| write_string(buffer, member));
+            }
+            ValueWrapper::Hash { fields } => {
+                buffer.push(TYPE_HASH);
+                write_string(buffer, key);
+                write_length(buffer, fields.len() as u64);
+                for (field, value) in fields {
+                    write_string(buffer, field);
+                    write_string(buffer, value);
                 }
             }
+            ValueWrapper::ZSet { members } => {
+                buffer.push(TYPE_ZSET_2);
+                write_string(buffer, key);
+                write_length(buffer, members.len() as u64);
+                for (member, score) in members.iter() {
+                    write_string(buffer, member);
+                    buffer.extend_from_slice(&score.to_le_bytes());
+                }
+            }
+            ValueWrapper::Stream { entries, metadata } => {
+                buffer.push(TYPE_STREAM_LISTPACKS_3);
+                write_string(buffer, key);
+                write_stream(buffer, entries, metadata);
+            }
+        }
+    }
+
+    /// Ends the file and writes it to `path`. It goes to a temporary file first and is then
+    /// renamed over `path`, so a failed save never leaves half a file behind.
+    pub fn finish(mut self, path: &Path) -> std::io::Result<()> {
+        self.buffer.push(0xFF);
+        // A zero checksum tells Redis not to check it
+        self.buffer.extend_from_slice(&[0; 8]);
+        let temp = path.with_extension("rdb.tmp");
+        fs::write(&temp, &self.buffer)?;
+        fs::rename(&temp, path)
+    }
+}
+
+impl Default for RdbWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_length(buffer: &mut Vec<u8>, len: u64) {
+    match len {
+        0..=63 => buffer.push(len as u8),
+        64..=16383 => buffer.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]),
+        16384..=0xFFFF_FFFF => {
+            buffer.push(0x80);
+            buffer.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        _ => {
+            buffer.push(0x81);
+            buffer.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    write_length(buffer, value.len() as u64);
+    buffer.extend_from_slice(value);
+}
+
+fn write_raw_id(buffer: &mut Vec<u8>, (ms, seq): StreamId) {
+    buffer.extend_from_slice(&ms.to_be_bytes());
+    buffer.extend_from_slice(&seq.to_be_bytes());
+}
+
+/// Writes a stream as type 21: nodes of up to `STREAM_NODE_MAX_ENTRIES` entries, then its
+/// length, IDs and counters, then its groups. Every consumer of a group is written, whether
+/// or not it has pending entries.
+fn write_stream(buffer: &mut Vec<u8>, entries: &[StreamEntry], metadata: &StreamMetadata) {
+    let ids = entries.iter()
+        .map(|entry| Storage::parse_stream_id(&entry.id).unwrap_or_default())
+        .collect::<Vec<_>>();
+    write_length(buffer, entries.len().div_ceil(STREAM_NODE_MAX_ENTRIES) as u64);
+    for (node, node_ids) in entries.chunks(STREAM_NODE_MAX_ENTRIES).zip(ids.chunks(STREAM_NODE_MAX_ENTRIES)) {
+        let mut master = Vec::new();
+        write_raw_id(&mut master, node_ids[0]);
+        write_string(buffer, &master);
+        write_string(buffer, &stream_node(node_ids[0], node, node_ids));
+    }
+
+    write_length(buffer, entries.len() as u64);
+    let first_id = ids.first().copied().unwrap_or_default();
+    for part in [metadata.last_id.0, metadata.last_id.1, first_id.0, first_id.1] {
+        write_length(buffer, part);
+    }
+    // No highest deleted ID is kept, and the entries added can only be counted from what is left
+    write_length(buffer, 0);
+    write_length(buffer, 0);
+    write_length(buffer, entries.len() as u64);
+
+    let groups = metadata.groups.iter().collect::<BTreeMap<_, _>>();
+    write_length(buffer, groups.len() as u64);
+    for (name, group) in groups {
+        write_string(buffer, name);
+        write_length(buffer, group.last_delivered.0);
+        write_length(buffer, group.last_delivered.1);
+        write_length(buffer, INVALID_ENTRIES_READ);
+
+        write_length(buffer, group.pending.len() as u64);
+        for (id, pending) in &group.pending {
+            write_raw_id(buffer, *id);
+            buffer.extend_from_slice(&pending.delivered_at.to_le_bytes());
+            write_length(buffer, pending.deliveries);
+        }
+
+        let consumers = group.consumers.keys()
+            .chain(group.pending.values().map(|pending| &pending.consumer))
+            .collect::<BTreeSet<_>>();
+        write_length(buffer, consumers.len() as u64);
+        for consumer in consumers {
+            let seen_at = group.consumers.get(consumer).map_or(0, |consumer| consumer.seen_at);
+            write_string(buffer, consumer);
+            // Seen and active time; only the former is tracked here
+            buffer.extend_from_slice(&seen_at.to_le_bytes());
+            buffer.extend_from_slice(&seen_at.to_le_bytes());
+            let owned = group.pending.iter()
+                .filter(|(_, pending)| pending.consumer == **consumer)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            write_length(buffer, owned.len() as u64);
+            owned.into_iter().for_each(|id| write_raw_id(buffer, id));
+        }
+    }
+}
+
+/// The listpack of one stream node. Its master entry has no fields, so every entry carries
+/// its own.
+fn stream_node(master: StreamId, entries: &[StreamEntry], ids: &[StreamId]) -> Vec<u8> {
+    let mut listpack = Listpack::default();
+    for count in [entries.len() as i64, 0, 0, 0] {
+        listpack.push_int(count);
+    }
+    for (entry, (ms, seq)) in entries.iter().zip(ids) {
+        listpack.push_int(0);
+        listpack.push_int(ms.wrapping_sub(master.0) as i64);
+        listpack.push_int(seq.wrapping_sub(master.1) as i64);
+        listpack.push_int(entry.fields.len() as i64);
+        for (field, value) in &entry.fields {
+            listpack.push_string(field);
+            listpack.push_string(value);
+        }
+        listpack.push_int(entry.fields.len() as i64 * 2 + 4);
+    }
+    listpack.finish()
+}
+
+#[derive(Default)]
+struct Listpack {
+    elements: Vec<u8>,
+    count: usize,
+}
+
+impl Listpack {
+    fn push_int(&mut self, value: i64) {
+        let start = self.elements.len();
+        let bytes = value.to_le_bytes();
+        match value {
+            0..=127 => self.elements.push(value as u8),
+            -4096..=4095 => {
+                let value = value as u16 & 0x1FFF;
+                self.elements.extend_from_slice(&[0xC0 | (value >> 8) as u8, value as u8]);
+            }
+            -32768..=32767 => {
+                self.elements.push(0xF1);
+                self.elements.extend_from_slice(&bytes[..2]);
+            }
+            -8388608..=8388607 => {
+                self.elements.push(0xF2);
+                self.elements.extend_from_slice(&bytes[..3]);
+            }
+            -2147483648..=2147483647 => {
+                self.elements.push(0xF3);
+                self.elements.extend_from_slice(&bytes[..4]);
+            }
             _ => {
-                return Err(Error::new(ErrorKind::InvalidData, "Invalid string encoding"));
+                self.elements.push(0xF4);
+                self.elements.extend_from_slice(&bytes);
             }
-        };
+        }
+        self.push_backlen(start);
+    }
+
+    fn push_string(&mut self, value: &[u8]) {
+        let start = self.elements.len();
+        match value.len() {
+            len @ 0..=63 => self.elements.push(0x80 | len as u8),
+            len @ 64..=4095 => self.elements.extend_from_slice(&[0xE0 | (len >> 8) as u8, len as u8]),
+            len => {
+                self.elements.push(0xF0);
+                self.elements.extend_from_slice(&(len as u32).to_le_bytes());
+            }
+        }
+        self.elements.extend_from_slice(value);
+        self.push_backlen(start);
+    }
 
-        if pos + len > buffer.len() {
-            return Err(Error::new(ErrorKind::InvalidData, "String length exceeds buffer size"));
+    /// Appends the length of the element that starts at `start`: seven bits per byte, most
+    /// significant first, with the high bit set on all but the first byte.
+    fn push_backlen(&mut self, start: usize) {
+        let len = self.elements.len() - start;
+        let size = backlen_size(len);
+        for i in 0..size {
+            let bits = ((len >> (7 * (size - 1 - i))) & 0x7F) as u8;
+            self.elements.push(if i == 0 { bits } else { bits | 0x80 });
         }
+        self.count += 1;
+    }
 
-        let s = String::from_utf8_lossy(&buffer[pos..pos + len]).to_string();
-        pos += len;
+    fn finish(self) -> Vec<u8> {
+        let total = 6 + self.elements.len() + 1;
+        let mut listpack = Vec::with_capacity(total);
+        listpack.extend_from_slice(&(total as u32).to_le_bytes());
+        // Counts that don't fit are left for the reader to work out
+        listpack.extend_from_slice(&(self.count.min(u16::MAX as usize) as u16).to_le_bytes());
+        listpack.extend_from_slice(&self.elements);
+        listpack.push(0xFF);
+        listpack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lzf_decompress_overlapping_back_reference() {
+        // "abc" as literals, then 6 bytes copied from 3 back
+        let compressed = [0x02, b'a', b'b', b'c', 0x80, 0x02];
+        assert_eq!(lzf_decompress(&compressed, 9).unwrap(), b"abcabcabc");
+        assert!(lzf_decompress(&compressed, 8).is_err());
+    }
+
+    #[test]
+    fn test_listpack_round_trip() {
+        let mut listpack = Listpack::default();
+        let ints = [0, 127, 128, -1, -4096, 4095, 4096, -32768, 8388607, -2147483648, i64::MAX];
+        ints.iter().for_each(|value| listpack.push_int(*value));
+        let long = vec![b'x'; 5000];
+        for value in [&b""[..], b"field", &[b'y'; 64], &long] {
+            listpack.push_string(value);
+        }
+        let elements = listpack_elements(&listpack.finish()).unwrap();
+        let expected = ints.iter().map(|value| value.to_string().into_bytes())
+            .chain([b"".to_vec(), b"field".to_vec(), vec![b'y'; 64], long])
+            .collect::<Vec<_>>();
+        assert_eq!(elements, expected);
+    }
+
+    #[test]
+    fn test_ziplist_elements() {
+        let mut ziplist = vec![0; 10];
+        ziplist.extend_from_slice(&[0x00, 0x03, b'f', b'o', b'o']);
+        ziplist.extend_from_slice(&[0x05, 0xF3]); // 2
+        ziplist.extend_from_slice(&[0x02, 0xFE, 0xF6]); // -10
+        ziplist.extend_from_slice(&[0x03, 0xC0, 0x39, 0x30]); // 12345
+        ziplist.extend_from_slice(&[0x04, 0xF0, 0x00, 0x00, 0x80]); // -8388608
+        ziplist.push(0xFF);
+        let elements = ziplist_elements(&ziplist).unwrap();
+        assert_eq!(elements, [&b"foo"[..], b"2", b"-10", b"12345", b"-8388608"]);
+    }
+
+    #[test]
+    fn test_intset_elements() {
+        let mut intset = vec![4, 0, 0, 0, 2, 0, 0, 0];
+        intset.extend_from_slice(&(-7i32).to_le_bytes());
+        intset.extend_from_slice(&100000i32.to_le_bytes());
+        assert_eq!(intset_elements(&intset).unwrap(), [&b"-7"[..], b"100000"]);
+    }
+
+    #[test]
+    fn test_stream_node_with_same_fields_and_deleted_entries() {
+        let mut listpack = Listpack::default();
+        // Master entry: 1 live, 1 deleted, fields "a" and "b"
+        for value in [1, 1, 2] {
+            listpack.push_int(value);
+        }
+        listpack.push_string(b"a");
+        listpack.push_string(b"b");
+        listpack.push_int(0);
+        // 5-0, deleted
+        for value in [STREAM_ITEM_DELETED | STREAM_ITEM_SAMEFIELDS, 0, 0] {
+            listpack.push_int(value);
+        }
+        listpack.push_string(b"1");
+        listpack.push_string(b"2");
+        listpack.push_int(5);
+        // 7-1, with the master fields
+        for value in [STREAM_ITEM_SAMEFIELDS, 2, 1] {
+            listpack.push_int(value);
+        }
+        listpack.push_string(b"3");
+        listpack.push_string(b"4");
+        listpack.push_int(5);
 
-        Ok((s, pos))
+        let mut entries = Vec::new();
+        stream_node_entries((5, 0), &listpack_elements(&listpack.finish()).unwrap(), &mut entries).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "7-1");
+        assert_eq!(entries[0].fields, vec![(Bytes::from("a"), Bytes::from("3")), (Bytes::from("b"), Bytes::from("4"))]);
     }
 }
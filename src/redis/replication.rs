@@ -1,36 +1,92 @@
 use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
-use std::io::{Read, Write, Result};
-use std::thread;
+use std::io::{Error, ErrorKind, Result};
 use std::time::Duration;
 use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use bytes::Bytes;
 
-pub trait TcpStreamTrait: Read + Write + Send + 'static {
+pub trait TcpStreamTrait: AsyncRead + AsyncWrite + Unpin + Send + 'static {
     fn peer_addr(&self) -> Result<SocketAddr>;
-    fn try_clone(&self) -> Result<Box<dyn TcpStreamTrait>>;
 }
 
-impl TcpStreamTrait for std::net::TcpStream {
+impl TcpStreamTrait for tokio::net::TcpStream {
     fn peer_addr(&self) -> Result<SocketAddr> {
         self.peer_addr()
     }
+}
+
+// The replica's link to its master keeps the bytes that were buffered during the handshake.
+impl TcpStreamTrait for tokio::io::BufReader<tokio::net::TcpStream> {
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        self.get_ref().peer_addr()
+    }
+}
+
+/// Write side of a client or replica connection.
+///
+/// Writes are queued on a channel and drained to the socket by a dedicated task, so they never
+/// block and can be issued from synchronous code, e.g. while executing a command.
+#[derive(Clone)]
+pub struct Connection {
+    peer_addr: SocketAddr,
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl Connection {
+    /// Takes over the whole stream for writing. Anything the peer sends is ignored.
+    #[allow(dead_code)]
+    pub fn new<T: TcpStreamTrait>(stream: T) -> Result<Self> {
+        let peer_addr = stream.peer_addr()?;
+        let (_, writer) = tokio::io::split(stream);
+        Ok(Self::from_writer(peer_addr, writer))
+    }
+
+    pub fn from_writer<W: AsyncWrite + Unpin + Send + 'static>(peer_addr: SocketAddr, mut writer: W) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            while let Some(data) = receiver.recv().await {
+                if writer.write_all(&data).await.is_err() || writer.flush().await.is_err() {
+                    #[cfg(debug_assertions)]
+                    println!("[CONN] Error writing to {}, closing writer", peer_addr);
+                    break;
+                }
+            }
+        });
+        Connection { peer_addr, sender }
+    }
 
-    fn try_clone(&self) -> Result<Box<dyn TcpStreamTrait>> {
-        Ok(Box::new(self.try_clone()?))
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        self.sender
+            .send(data.to_vec())
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "connection closed"))
     }
 }
 
 pub struct Replica {
     pub host: String,
     pub port: String,
-    pub stream: Box<dyn TcpStreamTrait>,
+    pub stream: Connection,
     pub offset: u64,
 }
 
 pub struct ReplicationManager {
     replicas: Arc<Mutex<HashMap<String, Replica>>>,
-    command_queue: Arc<Mutex<VecDeque<String>>>,
+    command_queue: Arc<Mutex<VecDeque<Bytes>>>,
     current_offset: Arc<Mutex<u64>>,
+    /// The database the replication stream last selected; `None` when a SELECT must come first.
+    selected_db: Mutex<Option<usize>>,
+}
+
+impl Default for ReplicationManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ReplicationManager {
@@ -39,10 +95,11 @@ impl ReplicationManager {
             replicas: Arc::new(Mutex::new(HashMap::new())),
             command_queue: Arc::new(Mutex::new(VecDeque::new())),
             current_offset: Arc::new(Mutex::new(0)),
+            selected_db: Mutex::new(Some(0)),
         }
     }
 
-    pub fn add_replica(&mut self, host: String, port: String, stream: Box<dyn TcpStreamTrait>) {
+    pub fn add_replica(&self, host: String, port: String, stream: Connection) {
         let replica = Replica {
             host: host.clone(),
             port: port.clone(),
@@ -54,14 +111,31 @@ impl ReplicationManager {
         #[cfg(debug_assertions)]
         println!("[REPL] Adding new replica: {} (current replication offset: {})", key, self.get_current_offset());
         self.replicas.lock().unwrap().insert(key, replica);
+        // A new replica starts out in database 0, whatever the stream selected before.
+        let mut selected_db = self.selected_db.lock().unwrap();
+        if *selected_db != Some(0) {
+            *selected_db = None;
+        }
         #[cfg(debug_assertions)]
         println!("[REPL] Total replicas after add: {}", self.replicas.lock().unwrap().len());
     }
 
-    pub fn enqueue_for_replication(&mut self, command: &str) {
+    /// Queues a write made in database `db`, preceded by a SELECT when the stream is in another one.
+    pub fn enqueue_for_replication(&self, db: usize, command: &[u8]) {
+        let mut selected_db = self.selected_db.lock().unwrap();
+        if *selected_db != Some(db) {
+            let index = db.to_string();
+            let select = format!("*2\r\n$6\r\nSELECT\r\n${}\r\n{}\r\n", index.len(), index);
+            self.push(select.as_bytes());
+            *selected_db = Some(db);
+        }
+        self.push(command);
+    }
+
+    fn push(&self, command: &[u8]) {
         #[cfg(debug_assertions)]
-        println!("[REPL] Enqueueing command for replication: {}", command);
-        self.command_queue.lock().unwrap().push_back(command.to_string());
+        println!("[REPL] Enqueueing command for replication: {}", String::from_utf8_lossy(command));
+        self.command_queue.lock().unwrap().push_back(Bytes::copy_from_slice(command));
         
         // Update current offset immediately when command is enqueued
         let mut current_offset = self.current_offset.lock().unwrap();
@@ -70,31 +144,31 @@ impl ReplicationManager {
         println!("[REPL] Updated replication offset: {} -> {}", *current_offset - command.len() as u64, *current_offset);
     }
 
-    pub fn send_pending_commands(&mut self) -> usize {
+    pub fn send_pending_commands(&self) -> usize {
         let mut queue = self.command_queue.lock().unwrap();
         if !queue.is_empty() {
             #[cfg(debug_assertions)]
             println!("[REPL] Found {} commands in replication queue", queue.len());
             
             // Get all commands first
-            let commands: Vec<String> = queue.drain(..).collect();
+            let commands: Vec<Bytes> = queue.drain(..).collect();
             let mut sent_count = 0;
             
             // Send all commands to each replica
             for replica in self.replicas.lock().unwrap().values_mut() {
                 for command in &commands {
                     #[cfg(debug_assertions)]
-                    println!("[REPL] Sending command to replica: {}", command);
+                    println!("[REPL] Sending command to replica: {}", String::from_utf8_lossy(command));
                     
-                    match (replica.stream.write_all(command.as_bytes()), replica.stream.flush()) {
-                        (Ok(_), Ok(_)) => {
+                    match replica.stream.write(command) {
+                        Ok(_) => {
                             // Only increment sent count, don't update offset
                             // Offset will be updated when replica sends REPLCONF ACK
                             sent_count += 1;
                         }
-                        (Err(e), _) | (_, Err(e)) => {
+                        Err(_e) => {
                             #[cfg(debug_assertions)]
-                            println!("[REPL] Error sending command to replica: {}", e);
+                            println!("[REPL] Error sending command to replica: {}", _e);
                         }
                     }
                 }
@@ -106,7 +180,7 @@ impl ReplicationManager {
         }
     }
 
-    pub fn send_getack_to_replicas(&self) -> std::io::Result<()> {
+    pub fn send_getack_to_replicas(&self) -> Result<()> {
         #[cfg(debug_assertions)]
         println!("[REPL] Sending GETACK to replicas");
         
@@ -122,12 +196,12 @@ impl ReplicationManager {
             println!("[REPL] Sending GETACK to replica {}:{} (current offset: {})", replica.host, replica.port, replica.offset);
             
             let getack_command = "*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
-            match (replica.stream.write_all(getack_command.as_bytes()), replica.stream.flush()) {
-                (Ok(_), Ok(_)) => {
+            match replica.stream.write(getack_command.as_bytes()) {
+                Ok(_) => {
                     #[cfg(debug_assertions)]
                     println!("[REPL] Successfully sent GETACK to replica {}:{}", replica.host, replica.port);
                 },
-                (Err(e), _) | (_, Err(e)) => {
+                Err(e) => {
                     #[cfg(debug_assertions)]
                     println!("[REPL] Error sending GETACK to replica {}:{}: {}", replica.host, replica.port, e);
                     return Err(e);
@@ -137,7 +211,7 @@ impl ReplicationManager {
         Ok(())
     }
 
-    pub fn update_replica_offset(&mut self, replica_key: &str, offset: u64) {
+    pub fn update_replica_offset(&self, replica_key: &str, offset: u64) {
         if let Some(replica) = self.replicas.lock().unwrap().get_mut(replica_key) {
             #[cfg(debug_assertions)]
             println!("[REPL] Updating replica {} offset: {} -> {}", replica_key, replica.offset, offset);
@@ -189,27 +263,24 @@ impl ReplicationManager {
     //     self.replicas.lock().unwrap()
     // }
 
-    pub fn start_replication_sync(redis: Arc<Mutex<crate::redis::Redis>>) {
-        thread::spawn(move || {
+    pub fn start_replication_sync(redis: Arc<crate::redis::Redis>) {
+        tokio::spawn(async move {
             let mut last_getack = std::time::Instant::now();
             loop {
-                {
-                    let mut redis = redis.lock().unwrap();
-                    redis.replication.send_pending_commands();
-                    
-                    // Send GETACK every 10 seconds
-                    if last_getack.elapsed() >= Duration::from_secs(10) {
+                redis.replication.send_pending_commands();
+
+                // Send GETACK every 10 seconds
+                if last_getack.elapsed() >= Duration::from_secs(10) {
+                    #[cfg(debug_assertions)]
+                    println!("[REPL] Sending periodic GETACK");
+
+                    if let Err(_e) = redis.replication.send_getack_to_replicas() {
                         #[cfg(debug_assertions)]
-                        println!("[REPL] Sending periodic GETACK");
-                        
-                        if let Err(e) = redis.replication.send_getack_to_replicas() {
-                            #[cfg(debug_assertions)]
-                            println!("[REPL] Error sending periodic GETACK: {}", e);
-                        }
-                        last_getack = std::time::Instant::now();
+                        println!("[REPL] Error sending periodic GETACK: {}", _e);
                     }
+                    last_getack = std::time::Instant::now();
                 }
-                thread::sleep(Duration::from_millis(10));
+                tokio::time::sleep(Duration::from_millis(10)).await;
             }
         });
     }
@@ -1,161 +1,321 @@
-use dashmap::DashMap;
+use bytes::Bytes;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+use super::bitmap::{self, BitOp, FieldOp};
+use super::consumer_groups::{ClaimOptions, Claimed, ConsumerGroup, DeliveredEntry, GroupRead, PendingEntry, StreamId};
+use super::geo::{self, Origin, Query, Sort};
+use super::glob::glob_match;
+use super::hyperloglog;
+use super::keyspace::{Entry, Keyspace};
+use super::zset::{Aggregate, RangeSpec, ScoreBound, SortedSet, ZAddOptions};
 
 #[derive(Debug, Clone)]
 pub struct StreamEntry {
     pub id: String,
-    pub fields: HashMap<String, String>,
+    // in the order they were given to XADD
+    pub fields: Vec<(Bytes, Bytes)>,
+}
+
+impl StreamEntry {
+    #[allow(dead_code)]
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        self.fields.iter().find(|(name, _)| name == field).map(|(_, value)| value)
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct StreamMetadata {
-    pub last_sequences: DashMap<u64, u64>,
-    pub last_dollar_id: Option<String>,
+    /// The last ID XADD generated, as (milliseconds, sequence). Deleting or trimming entries
+    /// never lowers it, so new IDs keep growing even once the stream is empty.
+    pub last_id: (u64, u64),
+    /// Consumer groups by name.
+    pub groups: HashMap<Bytes, ConsumerGroup>,
+}
+
+/// Approximate (`~`) trimming only evicts whole nodes of this many entries, like Redis with its
+/// default `stream-node-max-entries`.
+pub const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// What XTRIM, or XADD's trimming option, keeps: at most `MaxLen` entries, or the entries whose
+/// IDs are at least `MinId`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrimThreshold {
+    MaxLen(usize),
+    MinId(u64, u64),
+}
+
+/// A stream trim. An `approximate` one evicts only whole nodes and at most `limit` entries
+/// (0 for no limit), so it may keep more than the threshold asks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamTrim {
+    pub threshold: TrimThreshold,
+    pub approximate: bool,
+    pub limit: usize,
 }
 
 #[derive(Clone)]
 pub enum ValueWrapper {
     String {
-        value: String,
-        expiration: Option<u64>,
+        value: Bytes,
     },
     Stream {
         entries: Vec<StreamEntry>,
         metadata: StreamMetadata,
     },
+    /// A deque so pushes and pops at either end are O(1) and indexing stays O(1).
     List {
-        values: Vec<String>,
+        values: VecDeque<Bytes>,
     },
+    Hash {
+        fields: HashMap<Bytes, Bytes>,
+    },
+    Set {
+        members: HashSet<Bytes>,
+    },
+    ZSet {
+        members: SortedSet,
+    },
+}
+
+/// The set algebra behind SINTER, SUNION, SDIFF and their STORE forms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// The head (left) or tail (right) of a list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// EXPIRE's NX/XX/GT/LT conditions. A key without a TTL counts as expiring never, so GT
+/// never applies to it and LT always does.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExpireOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+}
+
+/// SET's conditions and expiry options besides a relative EX/PX. `expire_at` is an absolute unix
+/// time in milliseconds from EXAT or PXAT.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SetOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub get: bool,
+    pub keep_ttl: bool,
+    pub expire_at: Option<u64>,
 }
 
 pub struct Storage {
-    data: DashMap<String, ValueWrapper>,
+    data: Keyspace<ValueWrapper>,
+    /// Absolute expiration times in unix milliseconds, for the keys in `data` that have one.
+    expires: Keyspace<u64>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Storage {
     pub fn new() -> Self {
         Storage {
-            data: DashMap::new(),
+            data: Keyspace::new(),
+            expires: Keyspace::new(),
         }
     }
 
     pub fn flushdb(&self) {
         self.data.clear();
+        self.expires.clear();
     }
 
-    pub fn set(&self, key: &str, value: &str, ttl: Option<usize>) {
-        let expiration = ttl.map(|ttl| {
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64
-                + ttl as u64
-        });
+    pub fn set(&self, key: &[u8], value: Bytes, ttl: Option<usize>) {
+        let expiration = ttl.map(|ttl| Self::get_current_time_ms() + ttl as u64);
         #[cfg(debug_assertions)]
-        println!("DEBUG: Setting key '{}' with value '{}' and expiration {:?}", key, value, expiration);
-        self.data.insert(
-            key.to_string(),
-            ValueWrapper::String {
-                value: value.to_string(),
-                expiration,
-            },
-        );
+        println!("DEBUG: Setting key {:?} with value {:?} and expiration {:?}", Bytes::copy_from_slice(key), value, expiration);
+        let key = Bytes::copy_from_slice(key);
+        if let Some(expiration) = expiration {
+            self.expires.insert(key.clone(), expiration);
+        } else {
+            self.expires.remove(&key);
+        }
+        self.data.insert(key, ValueWrapper::String { value });
     }
 
-   pub fn get(&self, key: &str) -> Option<String> {
+   pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.expire_if_due(key);
         if let Some(entry) = self.data.get(key) {
             match &*entry {
-                ValueWrapper::String { value, expiration } => {
-                    if let Some(expiration) = expiration {
-                        let now = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as u64;
-                        if now > *expiration {
-                            #[cfg(debug_assertions)]
-                            println!("DEBUG: Key '{}' has expired. Current time: {}, Expiration: {}", key, now, expiration);
-                            drop(entry); // Release the entry lock before removing the key
-                            self.data.remove(key);
-                            return None;
-                        }
-                    }
+                ValueWrapper::String { value } => {
                     #[cfg(debug_assertions)]
-                    println!("DEBUG: Retrieved key '{}' with value '{}'", key, value);
+                    println!("DEBUG: Retrieved key {:?} with value {:?}", Bytes::copy_from_slice(key), value);
                     Some(value.clone())
                 },
                 ValueWrapper::Stream { .. } => None,
                 ValueWrapper::List { .. } => None,
+                ValueWrapper::Hash { .. } => None,
+                ValueWrapper::Set { .. } => None,
+                ValueWrapper::ZSet { .. } => None,
             }
         } else {
             #[cfg(debug_assertions)]
-            println!("DEBUG: Key '{}' not found", key);
+            println!("DEBUG: Key {:?} not found", Bytes::copy_from_slice(key));
             None
         }
     }
 
-   pub fn lpush(&self, key: &str, value: &str) -> Result<i64, String> {
-        match self.data.entry(key.to_string()) {
-            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
-                match entry.get_mut() {
-                    ValueWrapper::List { values } => {
-                        values.insert(0, value.to_string());
-                        Ok(values.len() as i64)
-                    },
-                    _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
-                }
-            },
-            dashmap::mapref::entry::Entry::Vacant(entry) => {
-                entry.insert(ValueWrapper::List {
-                    values: vec![value.to_string()],
-                });
-                Ok(1)
+   /// Runs `f` on the list stored at `key`, or on an empty list if there is none.
+   fn read_list<T>(&self, key: &[u8], f: impl FnOnce(&VecDeque<Bytes>) -> T) -> Result<T, String> {
+        match self.data.get(key) {
+            Some(entry) => match entry.value() {
+                ValueWrapper::List { values } => Ok(f(values)),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
             },
+            None => Ok(f(&VecDeque::new())),
         }
     }
 
-   pub fn rpush(&self, key: &str, value: &str) -> Result<i64, String> {
-        match self.data.entry(key.to_string()) {
-            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
-                match entry.get_mut() {
-                    ValueWrapper::List { values } => {
-                        values.push(value.to_string());
-                        Ok(values.len() as i64)
-                    },
-                    _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+   /// Runs `f` on the list stored at `key`, creating it if needed. A list left empty by `f` is
+   /// removed, the same as Redis never keeps empty aggregates around.
+   fn write_list<T>(&self, key: &[u8], f: impl FnOnce(&mut VecDeque<Bytes>) -> T) -> Result<T, String> {
+        match self.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(mut entry) => {
+                let result = match entry.get_mut() {
+                    ValueWrapper::List { values } => f(values),
+                    _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                };
+                if matches!(entry.get(), ValueWrapper::List { values } if values.is_empty()) {
+                    self.expires.remove(entry.key());
+                    entry.remove();
                 }
+                Ok(result)
             },
-            dashmap::mapref::entry::Entry::Vacant(entry) => {
-                entry.insert(ValueWrapper::List {
-                    values: vec![value.to_string()],
-                });
-                Ok(1)
+            Entry::Vacant(entry) => {
+                let mut values = VecDeque::new();
+                let result = f(&mut values);
+                if !values.is_empty() {
+                    entry.insert(ValueWrapper::List { values });
+                }
+                Ok(result)
             },
         }
     }
 
-   pub fn lpop(&self, key: &str) -> Option<String> {
-        self.data.get_mut(key).and_then(|mut entry| {
-            if let ValueWrapper::List { values } = entry.value_mut() {
-                (!values.is_empty()).then(|| values.remove(0))
-            } else {
-                None
+   /// Removes `key` if it holds a list that has been emptied, the same as Redis never keeps
+   /// empty aggregates around. Must not be called while holding a reference into `data`.
+   fn remove_if_empty_list(&self, key: &[u8]) {
+        if self.data.remove_if(key, |_, value| matches!(value, ValueWrapper::List { values } if values.is_empty())).is_some() {
+            self.expires.remove(key);
+        }
+    }
+
+   /// Pushes `values` one at a time at `end` of the list at `key` and returns its new length.
+   /// With `only_if_exists` (LPUSHX and RPUSHX) a missing list stays missing.
+   pub fn push(&self, key: &[u8], end: ListEnd, values: &[Bytes], only_if_exists: bool) -> Result<i64, String> {
+        if only_if_exists && !self.data.contains_key(key) {
+            return Ok(0);
+        }
+        self.write_list(key, |list| {
+            for value in values {
+                match end {
+                    ListEnd::Left => list.push_front(value.clone()),
+                    ListEnd::Right => list.push_back(value.clone()),
+                }
             }
+            list.len() as i64
         })
     }
 
-   pub fn rpop(&self, key: &str) -> Option<String> {
-        self.data.get_mut(key).and_then(|mut entry| {
-            if let ValueWrapper::List { values } = entry.value_mut() {
-                values.pop()
-            } else {
-                None
+   /// Removes and returns up to `count` elements from `end` of the list at `key`, in the order
+   /// they were popped. `None` if there is no list.
+   pub fn pop(&self, key: &[u8], end: ListEnd, count: usize) -> Result<Option<Vec<Bytes>>, String> {
+        if !self.data.contains_key(key) {
+            return Ok(None);
+        }
+        self.write_list(key, |list| {
+            let count = count.min(list.len());
+            Some(match end {
+                ListEnd::Left => list.drain(..count).collect(),
+                ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+            })
+        })
+    }
+
+   /// Pops up to `count` elements from `end` of the first list among `keys` that exists.
+   pub fn lmpop(&self, keys: &[Bytes], end: ListEnd, count: usize) -> Result<Option<(Bytes, Vec<Bytes>)>, String> {
+        for key in keys {
+            if let Some(popped) = self.pop(key, end, count)? {
+                return Ok(Some((key.clone(), popped)));
+            }
+        }
+        Ok(None)
+    }
+
+   /// Pops an element from `from` of `source` and pushes it at `to` of `destination`. Returns
+   /// it, or `None` if `source` has no list.
+   pub fn lmove(&self, source: &[u8], destination: &[u8], from: ListEnd, to: ListEnd) -> Result<Option<Bytes>, String> {
+        // Both keys must hold lists (or nothing) before anything is changed.
+        self.read_list(destination, |_| ())?;
+        let rotate = |list: &mut VecDeque<Bytes>, into: Option<&mut VecDeque<Bytes>>| {
+            let element = match from {
+                ListEnd::Left => list.pop_front(),
+                ListEnd::Right => list.pop_back(),
+            }?;
+            let into = into.unwrap_or(list);
+            match to {
+                ListEnd::Left => into.push_front(element.clone()),
+                ListEnd::Right => into.push_back(element.clone()),
+            }
+            Some(element)
+        };
+        if source == destination {
+            // Rotating in place keeps the list, and its TTL, even with a single element.
+            return self.write_list(source, |list| rotate(list, None));
+        }
+        let Some(popped) = self.pop(source, from, 1)? else {
+            return Ok(None);
+        };
+        self.push(destination, to, &popped, false)?;
+        Ok(popped.into_iter().next())
+    }
+
+   /// Removes up to `count` occurrences of `element`: the first ones if `count` is positive, the
+   /// last ones if it's negative, and all of them if it's 0. Returns how many were removed.
+   pub fn lrem(&self, key: &[u8], count: i64, element: &[u8]) -> Result<i64, String> {
+        if !self.data.contains_key(key) {
+            return Ok(0);
+        }
+        self.write_list(key, |list| {
+            let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+            let mut removed = 0;
+            if count < 0 {
+                list.make_contiguous().reverse();
             }
+            list.retain(|value| {
+                let remove = removed < limit && value == element;
+                removed += remove as usize;
+                !remove
+            });
+            if count < 0 {
+                list.make_contiguous().reverse();
+            }
+            removed as i64
         })
     }
 
-   pub fn llen(&self, key: &str) -> i64 {
+   pub fn llen(&self, key: &[u8]) -> i64 {
         match self.data.get(key) {
             Some(entry) => {
                 if let ValueWrapper::List { values } = &*entry {
@@ -168,7 +328,7 @@ impl Storage {
         }
     }
 
-   pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Vec<String> {
+   pub fn lrange(&self, key: &[u8], start: i64, stop: i64) -> Vec<Bytes> {
         if let Some(entry) = self.data.get(key) {
             if let ValueWrapper::List { values } = &*entry {
                 let len = values.len() as i64;
@@ -179,7 +339,7 @@ impl Storage {
                 if start >= stop {
                     return vec![];
                 }
-                values[start..stop].to_vec()
+                values.range(start..stop).cloned().collect()
             } else {
                 vec![]
             }
@@ -188,7 +348,7 @@ impl Storage {
         }
     }
 
-   pub fn ltrim(&mut self, key: &str, start: i64, stop: i64) -> Result<(), String> {
+   pub fn ltrim(&self, key: &[u8], start: i64, stop: i64) -> Result<(), String> {
         if let Some(mut entry) = self.data.get_mut(key) {
             if let ValueWrapper::List { values } = entry.value_mut() {
                 let len = values.len() as i64;
@@ -197,12 +357,12 @@ impl Storage {
                     values.clear();
                 } else {
                     // First remove elements from the end
-                    if stop_idx < values.len() {
-                        values.drain(stop_idx..);
-                    }
+                    values.truncate(stop_idx);
                     // Then remove elements from the start
                     values.drain(..start_idx);
                 }
+                drop(entry);
+                self.remove_if_empty_list(key);
                 Ok(())
             } else {
                 Err("ERR value is not a list".to_string())
@@ -212,42 +372,31 @@ impl Storage {
         }
     }
 
-   pub fn lpos(&self, key: &str, element: &str, count: Option<usize>) -> Option<Vec<usize>> {
-        if let Some(entry) = self.data.get(key) {
-            if let ValueWrapper::List { values } = &*entry {
-                let mut positions = Vec::new();
-                for (i, val) in values.iter().enumerate() {
-                    if val == element {
-                        positions.push(i);
-                        if let Some(c) = count {
-                            if positions.len() >= c {
-                                break;
-                            }
-                        } else {
-                            break;  // If no count specified, only return first match
-                        }
-                    }
-                }
-                if positions.is_empty() {
-                    None  // Element not found
-                } else {
-                    Some(positions)
-                }
+   /// The indexes of the matches of `element` for LPOS. A negative `rank` searches from the tail;
+   /// either way the first `|rank| - 1` matches are skipped. `count` 0 returns every match and
+   /// `maxlen` 0 compares every element.
+   pub fn lpos(&self, key: &[u8], element: &[u8], rank: i64, count: usize, maxlen: usize) -> Result<Vec<usize>, String> {
+        self.read_list(key, |list| {
+            let len = list.len();
+            let compared = if maxlen == 0 { len } else { maxlen.min(len) };
+            let count = if count == 0 { usize::MAX } else { count };
+            let skip = (rank.unsigned_abs() - 1) as usize;
+            let matches = |index: &usize| list[*index] == element;
+            if rank > 0 {
+                (0..compared).filter(matches).skip(skip).take(count).collect()
             } else {
-                None
+                (len - compared..len).rev().filter(matches).skip(skip).take(count).collect()
             }
-        } else {
-            None  // Key doesn't exist
-        }
+        })
     }
 
-    pub fn linsert(&self, key: &str, before: bool, pivot: &str, element: &str) -> Option<usize> {
+    pub fn linsert(&self, key: &[u8], before: bool, pivot: &[u8], element: Bytes) -> Option<usize> {
         match self.data.get_mut(key) {
             Some(mut entry) => match entry.value_mut() {
                 ValueWrapper::List { values } => {
                     if let Some(pos) = values.iter().position(|x| x == pivot) {
                         let insert_pos = if before { pos } else { pos + 1 };
-                        values.insert(insert_pos, element.to_string());
+                        values.insert(insert_pos, element);
                         Some(values.len())
                     } else {
                         Some(0)
@@ -259,7 +408,7 @@ impl Storage {
         }
     }
 
-    pub fn lset(&mut self, key: &str, index: i64, element: &str) -> Result<(), String> {
+    pub fn lset(&self, key: &[u8], index: i64, element: Bytes) -> Result<(), String> {
         if let Some(mut entry) = self.data.get_mut(key) {
             if let ValueWrapper::List { values } = entry.value_mut() {
                 let len = values.len() as i64;
@@ -267,7 +416,7 @@ impl Storage {
                 if idx < 0 || idx >= len {
                     return Err("ERR index out of range".to_string());
                 }
-                values[idx as usize] = element.to_string();
+                values[idx as usize] = element;
                 Ok(())
             } else {
                 Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
@@ -277,7 +426,7 @@ impl Storage {
         }
     }
 
-    pub fn lindex(&self, key: &str, index: i64) -> Option<String> {
+    pub fn lindex(&self, key: &[u8], index: i64) -> Option<Bytes> {
         match self.data.get(key) {
             Some(entry) => match entry.value() {
                 ValueWrapper::List { values } => {
@@ -295,31 +444,819 @@ impl Storage {
         }
     }
 
-    pub fn incr(&self, key: &str) -> Result<i64, String> {
-        match self.data.entry(key.to_string()) {
-            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
-                match entry.get_mut() {
-                    ValueWrapper::String { value, .. } => {
-                        match value.parse::<i64>() {
-                            Ok(num) => {
-                                let new_num = num + 1;
-                                *value = new_num.to_string();
-                                Ok(new_num)
-                            },
-                            Err(_) => Err("ERR value is not an integer or out of range".to_string()),
-                        }
+    // String commands
+    /// The largest string SETRANGE and APPEND may produce, Redis' default proto-max-bulk-len.
+    pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+    /// The string stored at `key`, `None` if it doesn't exist.
+    pub fn read_string(&self, key: &[u8]) -> Result<Option<Bytes>, String> {
+        match self.data.get(key) {
+            Some(entry) => match entry.value() {
+                ValueWrapper::String { value } => Ok(Some(value.clone())),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Runs `f` on the string stored at `key` (`None` if there is none) and stores whatever it
+    /// leaves there. The TTL is kept; a string taken away by `f` takes its TTL with it.
+    fn write_string<T>(&self, key: &[u8], f: impl FnOnce(&mut Option<Bytes>) -> Result<T, String>) -> Result<T, String> {
+        match self.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(mut entry) => {
+                let mut string = match entry.get() {
+                    ValueWrapper::String { value } => Some(value.clone()),
+                    _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                };
+                let result = f(&mut string)?;
+                match string {
+                    Some(string) => *entry.get_mut() = ValueWrapper::String { value: string },
+                    None => {
+                        self.expires.remove(entry.key());
+                        entry.remove();
                     },
-                    _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
                 }
+                Ok(result)
             },
-            dashmap::mapref::entry::Entry::Vacant(entry) => {
-                entry.insert(ValueWrapper::String {
-                    value: "1".to_string(),
-                    expiration: None,
-                });
-                Ok(1)
+            Entry::Vacant(entry) => {
+                let mut string = None;
+                let result = f(&mut string)?;
+                if let Some(string) = string {
+                    entry.insert(ValueWrapper::String { value: string });
+                }
+                Ok(result)
+            },
+        }
+    }
+
+    /// Adds `delta` to the integer stored at `key`, which counts as 0 if missing. The TTL is kept.
+    pub fn incr_by(&self, key: &[u8], delta: i64) -> Result<i64, String> {
+        self.write_string(key, |string| {
+            let current = match string {
+                Some(value) => parse_integer(value).ok_or_else(|| "ERR value is not an integer or out of range".to_string())?,
+                None => 0,
+            };
+            let new_value = current.checked_add(delta)
+                .ok_or_else(|| "ERR increment or decrement would overflow".to_string())?;
+            *string = Some(Bytes::from(new_value.to_string()));
+            Ok(new_value)
+        })
+    }
+
+    /// Adds `increment` to the number stored at `key`, which counts as 0 if missing, and returns
+    /// it formatted the way it is stored. The TTL is kept.
+    pub fn incr_by_float(&self, key: &[u8], increment: f64) -> Result<Bytes, String> {
+        self.write_string(key, |string| {
+            let current = match string {
+                Some(value) => std::str::from_utf8(value).ok()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| "ERR value is not a valid float".to_string())?,
+                None => 0.0,
+            };
+            let new_value = current + increment;
+            if !new_value.is_finite() {
+                return Err("ERR increment would produce NaN or Infinity".to_string());
+            }
+            let new_value = Bytes::from(new_value.to_string());
+            *string = Some(new_value.clone());
+            Ok(new_value)
+        })
+    }
+
+    /// Appends `suffix` and returns the new length.
+    pub fn append(&self, key: &[u8], suffix: &[u8]) -> Result<i64, String> {
+        self.write_string(key, |string| {
+            let current = string.as_deref().unwrap_or_default();
+            if current.len() + suffix.len() > Self::MAX_STRING_LEN {
+                return Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string());
+            }
+            let mut appended = Vec::with_capacity(current.len() + suffix.len());
+            appended.extend_from_slice(current);
+            appended.extend_from_slice(suffix);
+            let len = appended.len() as i64;
+            *string = Some(Bytes::from(appended));
+            Ok(len)
+        })
+    }
+
+    pub fn strlen(&self, key: &[u8]) -> Result<i64, String> {
+        Ok(self.read_string(key)?.map_or(0, |value| value.len() as i64))
+    }
+
+    /// The bytes from `start` to `end`, both inclusive; negative offsets count from the end.
+    pub fn getrange(&self, key: &[u8], start: i64, end: i64) -> Result<Bytes, String> {
+        let value = self.read_string(key)?.unwrap_or_default();
+        let len = value.len() as i64;
+        if start < 0 && end < 0 && start > end {
+            return Ok(Bytes::new());
+        }
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+        if len == 0 || start > end {
+            return Ok(Bytes::new());
+        }
+        Ok(value.slice(start as usize..=end as usize))
+    }
+
+    /// Overwrites the string from `offset` on, padding with zero bytes if it is shorter.
+    /// Returns the new length.
+    pub fn setrange(&self, key: &[u8], offset: usize, patch: &[u8]) -> Result<i64, String> {
+        self.write_string(key, |string| {
+            let current = string.as_deref().unwrap_or_default();
+            if patch.is_empty() {
+                return Ok(current.len() as i64);
+            }
+            if offset + patch.len() > Self::MAX_STRING_LEN {
+                return Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string());
+            }
+            let mut patched = current.to_vec();
+            if patched.len() < offset + patch.len() {
+                patched.resize(offset + patch.len(), 0);
+            }
+            patched[offset..offset + patch.len()].copy_from_slice(patch);
+            let len = patched.len() as i64;
+            *string = Some(Bytes::from(patched));
+            Ok(len)
+        })
+    }
+
+    /// Sets or clears the bit at `offset`, padding the string with zero bytes if it is shorter.
+    /// Returns the old bit.
+    pub fn setbit(&self, key: &[u8], offset: usize, on: bool) -> Result<bool, String> {
+        self.write_string(key, |string| {
+            let mut data = string.as_deref().unwrap_or_default().to_vec();
+            let old = bitmap::set_bit(&mut data, offset, on);
+            *string = Some(Bytes::from(data));
+            Ok(old)
+        })
+    }
+
+    /// Stores `op` over the strings at `keys` in `destination`, replacing whatever was there, and
+    /// returns its length. An empty result deletes `destination`.
+    pub fn bitop(&self, op: BitOp, destination: &[u8], keys: &[Bytes]) -> Result<usize, String> {
+        let sources = keys.iter()
+            .map(|key| Ok(self.read_string(key)?.unwrap_or_default()))
+            .collect::<Result<Vec<_>, String>>()?;
+        let result = bitmap::bitop(op, &sources.iter().map(|source| &source[..]).collect::<Vec<_>>());
+        let len = result.len();
+        if result.is_empty() {
+            self.remove(destination);
+        } else {
+            self.insert(Bytes::copy_from_slice(destination), ValueWrapper::String { value: Bytes::from(result) }, None);
+        }
+        Ok(len)
+    }
+
+    /// Runs BITFIELD's subcommands on the string at `key`. Only GETs never create the key.
+    pub fn bitfield(&self, key: &[u8], ops: &[FieldOp]) -> Result<Vec<Option<i64>>, String> {
+        if !ops.iter().any(FieldOp::is_write) {
+            let data = self.read_string(key)?.unwrap_or_default();
+            return Ok(ops.iter().filter_map(|op| match *op {
+                FieldOp::Get { field, offset } => Some(Some(bitmap::read_field(&data, field, offset))),
+                _ => None,
+            }).collect());
+        }
+        self.write_string(key, |string| {
+            let mut data = string.as_deref().unwrap_or_default().to_vec();
+            let replies = bitmap::bitfield(&mut data, ops);
+            *string = Some(Bytes::from(data));
+            Ok(replies)
+        })
+    }
+
+    /// Adds `elements` to the HyperLogLog at `key`, creating it if needed. Returns whether it was
+    /// created or changed.
+    pub fn pfadd(&self, key: &[u8], elements: &[Bytes]) -> Result<bool, String> {
+        self.write_string(key, |string| {
+            let created = string.is_none();
+            let mut data = match string {
+                Some(value) => value.to_vec(),
+                None => hyperloglog::empty(),
+            };
+            let changed = hyperloglog::add(&mut data, elements)? || created;
+            if changed {
+                *string = Some(Bytes::from(data));
+            }
+            Ok(changed)
+        })
+    }
+
+    /// The estimated cardinality of the union of the HyperLogLogs at `keys`. Missing keys count
+    /// as empty.
+    pub fn pfcount(&self, keys: &[Bytes]) -> Result<u64, String> {
+        let mut strings = Vec::with_capacity(keys.len());
+        for key in keys {
+            strings.extend(self.read_string(key)?);
+        }
+        Ok(hyperloglog::count(&hyperloglog::union(&strings)?))
+    }
+
+    /// Stores the union of `destination` and `sources` in `destination`, which keeps its TTL. The
+    /// result is always dense.
+    pub fn pfmerge(&self, destination: &[u8], sources: &[Bytes]) -> Result<(), String> {
+        let mut strings = Vec::with_capacity(sources.len() + 1);
+        for key in sources {
+            strings.extend(self.read_string(key)?);
+        }
+        self.write_string(destination, |string| {
+            strings.extend(string.clone());
+            let registers = hyperloglog::union(&strings)?;
+            *string = Some(Bytes::from(hyperloglog::encode(&registers, false)));
+            Ok(())
+        })
+    }
+
+    /// The strings stored at `keys`; missing keys and keys of other types read as `None`.
+    pub fn mget(&self, keys: &[Bytes]) -> Vec<Option<Bytes>> {
+        keys.iter()
+            .map(|key| self.read_string(key).ok().flatten())
+            .collect()
+    }
+
+    /// Sets every pair, dropping existing TTLs. With `nx`, sets nothing at all if any of the
+    /// keys exists; returns whether the pairs were set.
+    pub fn mset(&self, pairs: &[(Bytes, Bytes)], nx: bool) -> bool {
+        if nx && pairs.iter().any(|(key, _)| self.data.contains_key(key)) {
+            return false;
+        }
+        for (key, value) in pairs {
+            self.insert(key.clone(), ValueWrapper::String { value: value.clone() }, None);
+        }
+        true
+    }
+
+    /// Sets `key` to `value`, dropping its TTL, and returns the old string.
+    pub fn getset(&self, key: &[u8], value: Bytes) -> Result<Option<Bytes>, String> {
+        let old = self.read_string(key)?;
+        self.insert(Bytes::copy_from_slice(key), ValueWrapper::String { value }, None);
+        Ok(old)
+    }
+
+    /// Deletes `key` and returns the string it held.
+    pub fn getdel(&self, key: &[u8]) -> Result<Option<Bytes>, String> {
+        self.write_string(key, |string| Ok(string.take()))
+    }
+
+    // Hash commands
+    /// Runs `f` on the hash stored at `key`. A missing key reads as an empty hash.
+    fn read_hash<T>(&self, key: &[u8], f: impl FnOnce(&HashMap<Bytes, Bytes>) -> T) -> Result<T, String> {
+        match self.data.get(key) {
+            Some(entry) => match entry.value() {
+                ValueWrapper::Hash { fields } => Ok(f(fields)),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            },
+            None => Ok(f(&HashMap::new())),
+        }
+    }
+
+    /// Runs `f` on the hash stored at `key`, creating it if needed. A hash left empty by `f` is
+    /// removed, the same as Redis never keeps empty aggregates around.
+    fn write_hash<T>(&self, key: &[u8], f: impl FnOnce(&mut HashMap<Bytes, Bytes>) -> Result<T, String>) -> Result<T, String> {
+        match self.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(mut entry) => {
+                let result = match entry.get_mut() {
+                    ValueWrapper::Hash { fields } => f(fields),
+                    _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                };
+                if matches!(entry.get(), ValueWrapper::Hash { fields } if fields.is_empty()) {
+                    self.expires.remove(entry.key());
+                    entry.remove();
+                }
+                result
+            },
+            Entry::Vacant(entry) => {
+                let mut fields = HashMap::new();
+                let result = f(&mut fields);
+                if !fields.is_empty() {
+                    entry.insert(ValueWrapper::Hash { fields });
+                }
+                result
+            },
+        }
+    }
+
+    /// Sets the given fields and returns how many of them are new.
+    pub fn hset(&self, key: &[u8], pairs: &[(Bytes, Bytes)]) -> Result<i64, String> {
+        self.write_hash(key, |fields| {
+            let mut added = 0;
+            for (field, value) in pairs {
+                if fields.insert(field.clone(), value.clone()).is_none() {
+                    added += 1;
+                }
+            }
+            Ok(added)
+        })
+    }
+
+    pub fn hsetnx(&self, key: &[u8], field: &Bytes, value: &Bytes) -> Result<bool, String> {
+        self.write_hash(key, |fields| {
+            if fields.contains_key(field) {
+                Ok(false)
+            } else {
+                fields.insert(field.clone(), value.clone());
+                Ok(true)
+            }
+        })
+    }
+
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Result<Option<Bytes>, String> {
+        self.read_hash(key, |fields| fields.get(field).cloned())
+    }
+
+    pub fn hmget(&self, key: &[u8], names: &[Bytes]) -> Result<Vec<Option<Bytes>>, String> {
+        self.read_hash(key, |fields| names.iter().map(|name| fields.get(name).cloned()).collect())
+    }
+
+    pub fn hgetall(&self, key: &[u8]) -> Result<Vec<(Bytes, Bytes)>, String> {
+        self.read_hash(key, |fields| fields.iter().map(|(f, v)| (f.clone(), v.clone())).collect())
+    }
+
+    pub fn hkeys(&self, key: &[u8]) -> Result<Vec<Bytes>, String> {
+        self.read_hash(key, |fields| fields.keys().cloned().collect())
+    }
+
+    pub fn hvals(&self, key: &[u8]) -> Result<Vec<Bytes>, String> {
+        self.read_hash(key, |fields| fields.values().cloned().collect())
+    }
+
+    pub fn hlen(&self, key: &[u8]) -> Result<i64, String> {
+        self.read_hash(key, |fields| fields.len() as i64)
+    }
+
+    pub fn hexists(&self, key: &[u8], field: &[u8]) -> Result<bool, String> {
+        self.read_hash(key, |fields| fields.contains_key(field))
+    }
+
+    pub fn hstrlen(&self, key: &[u8], field: &[u8]) -> Result<i64, String> {
+        self.read_hash(key, |fields| fields.get(field).map_or(0, |value| value.len() as i64))
+    }
+
+    /// Removes the given fields and returns how many existed.
+    pub fn hdel(&self, key: &[u8], names: &[Bytes]) -> Result<i64, String> {
+        if !self.data.contains_key(key) {
+            return Ok(0);
+        }
+        self.write_hash(key, |fields| {
+            Ok(names.iter().filter(|name| fields.remove(*name).is_some()).count() as i64)
+        })
+    }
+
+    pub fn hincrby(&self, key: &[u8], field: &Bytes, increment: i64) -> Result<i64, String> {
+        self.write_hash(key, |fields| {
+            let current = match fields.get(field) {
+                Some(value) => std::str::from_utf8(value).ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .ok_or_else(|| "ERR hash value is not an integer".to_string())?,
+                None => 0,
+            };
+            let new_value = current.checked_add(increment)
+                .ok_or_else(|| "ERR increment or decrement would overflow".to_string())?;
+            fields.insert(field.clone(), Bytes::from(new_value.to_string()));
+            Ok(new_value)
+        })
+    }
+
+    /// Returns the new value as it is stored, so the caller can reply and replicate it verbatim.
+    pub fn hincrbyfloat(&self, key: &[u8], field: &Bytes, increment: f64) -> Result<Bytes, String> {
+        self.write_hash(key, |fields| {
+            let current = match fields.get(field) {
+                Some(value) => std::str::from_utf8(value).ok()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| "ERR hash value is not a float".to_string())?,
+                None => 0.0,
+            };
+            let new_value = current + increment;
+            if !new_value.is_finite() {
+                return Err("ERR increment would produce NaN or Infinity".to_string());
+            }
+            let new_value = Bytes::from(new_value.to_string());
+            fields.insert(field.clone(), new_value.clone());
+            Ok(new_value)
+        })
+    }
+
+    /// Random fields for HRANDFIELD. A positive count returns distinct fields (at most the whole
+    /// hash), a negative one returns exactly `-count` fields that may repeat.
+    pub fn hrandfield(&self, key: &[u8], count: i64) -> Result<Vec<(Bytes, Bytes)>, String> {
+        self.read_hash(key, |fields| {
+            let all: Vec<(&Bytes, &Bytes)> = fields.iter().collect();
+            if all.is_empty() {
+                return vec![];
+            }
+            let mut rng = rand::thread_rng();
+            if count >= 0 {
+                all.choose_multiple(&mut rng, count as usize)
+                    .map(|(f, v)| ((*f).clone(), (*v).clone()))
+                    .collect()
+            } else {
+                (0..count.unsigned_abs())
+                    .map(|_| {
+                        let (f, v) = all[rng.gen_range(0..all.len())];
+                        (f.clone(), v.clone())
+                    })
+                    .collect()
+            }
+        })
+    }
+
+    // Set commands
+    /// Runs `f` on the set stored at `key`. A missing key reads as an empty set.
+    fn read_set<T>(&self, key: &[u8], f: impl FnOnce(&HashSet<Bytes>) -> T) -> Result<T, String> {
+        match self.data.get(key) {
+            Some(entry) => match entry.value() {
+                ValueWrapper::Set { members } => Ok(f(members)),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            },
+            None => Ok(f(&HashSet::new())),
+        }
+    }
+
+    /// Runs `f` on the set stored at `key`, creating it if needed. A set left empty by `f` is
+    /// removed.
+    fn write_set<T>(&self, key: &[u8], f: impl FnOnce(&mut HashSet<Bytes>) -> T) -> Result<T, String> {
+        match self.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(mut entry) => {
+                let result = match entry.get_mut() {
+                    ValueWrapper::Set { members } => f(members),
+                    _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                };
+                if matches!(entry.get(), ValueWrapper::Set { members } if members.is_empty()) {
+                    self.expires.remove(entry.key());
+                    entry.remove();
+                }
+                Ok(result)
+            },
+            Entry::Vacant(entry) => {
+                let mut members = HashSet::new();
+                let result = f(&mut members);
+                if !members.is_empty() {
+                    entry.insert(ValueWrapper::Set { members });
+                }
+                Ok(result)
+            },
+        }
+    }
+
+    /// Adds the given members and returns how many of them are new.
+    pub fn sadd(&self, key: &[u8], new_members: &[Bytes]) -> Result<i64, String> {
+        self.write_set(key, |members| {
+            new_members.iter().filter(|member| members.insert((*member).clone())).count() as i64
+        })
+    }
+
+    /// Removes the given members and returns how many of them were there.
+    pub fn srem(&self, key: &[u8], old_members: &[Bytes]) -> Result<i64, String> {
+        if !self.data.contains_key(key) {
+            return Ok(0);
+        }
+        self.write_set(key, |members| {
+            old_members.iter().filter(|member| members.remove(*member)).count() as i64
+        })
+    }
+
+    pub fn smembers(&self, key: &[u8]) -> Result<Vec<Bytes>, String> {
+        self.read_set(key, |members| members.iter().cloned().collect())
+    }
+
+    pub fn sismember(&self, key: &[u8], member: &[u8]) -> Result<bool, String> {
+        self.read_set(key, |members| members.contains(member))
+    }
+
+    pub fn smismember(&self, key: &[u8], candidates: &[Bytes]) -> Result<Vec<bool>, String> {
+        self.read_set(key, |members| candidates.iter().map(|member| members.contains(member)).collect())
+    }
+
+    pub fn scard(&self, key: &[u8]) -> Result<i64, String> {
+        self.read_set(key, |members| members.len() as i64)
+    }
+
+    /// Removes and returns up to `count` random members.
+    pub fn spop(&self, key: &[u8], count: usize) -> Result<Vec<Bytes>, String> {
+        if !self.data.contains_key(key) {
+            return Ok(vec![]);
+        }
+        self.write_set(key, |members| {
+            let picked: Vec<Bytes> = {
+                let all: Vec<&Bytes> = members.iter().collect();
+                all.choose_multiple(&mut rand::thread_rng(), count).map(|member| (*member).clone()).collect()
+            };
+            for member in &picked {
+                members.remove(member);
+            }
+            picked
+        })
+    }
+
+    /// Random members for SRANDMEMBER. A positive count returns distinct members (at most the
+    /// whole set), a negative one returns exactly `-count` members that may repeat.
+    pub fn srandmember(&self, key: &[u8], count: i64) -> Result<Vec<Bytes>, String> {
+        self.read_set(key, |members| {
+            let all: Vec<&Bytes> = members.iter().collect();
+            if all.is_empty() {
+                return vec![];
+            }
+            let mut rng = rand::thread_rng();
+            if count >= 0 {
+                all.choose_multiple(&mut rng, count as usize).map(|member| (*member).clone()).collect()
+            } else {
+                (0..count.unsigned_abs()).map(|_| all[rng.gen_range(0..all.len())].clone()).collect()
+            }
+        })
+    }
+
+    /// Moves `member` from `source` to `destination`. Returns false if it wasn't in `source`.
+    pub fn smove(&self, source: &[u8], destination: &[u8], member: &Bytes) -> Result<bool, String> {
+        // Both keys must hold sets (or nothing) before anything is changed.
+        self.read_set(destination, |_| ())?;
+        if !self.read_set(source, |members| members.contains(member))? {
+            return Ok(false);
+        }
+        if source != destination {
+            self.write_set(source, |members| members.remove(member))?;
+            self.write_set(destination, |members| members.insert(member.clone()))?;
+        }
+        Ok(true)
+    }
+
+    /// Combines the sets at `keys`; missing keys count as empty sets.
+    pub fn set_operation(&self, op: SetOp, keys: &[Bytes]) -> Result<HashSet<Bytes>, String> {
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            sets.push(self.read_set(key, |members| members.clone())?);
+        }
+        let mut sets = sets.into_iter();
+        let mut result = sets.next().unwrap_or_default();
+        for set in sets {
+            match op {
+                SetOp::Inter => result.retain(|member| set.contains(member)),
+                SetOp::Union => result.extend(set),
+                SetOp::Diff => result.retain(|member| !set.contains(member)),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Replaces whatever is at `destination` with `members`, or deletes it if there are none.
+    pub fn store_set(&self, destination: &[u8], members: HashSet<Bytes>) -> i64 {
+        let len = members.len() as i64;
+        self.expires.remove(destination);
+        if members.is_empty() {
+            self.data.remove(destination);
+        } else {
+            self.data.insert(Bytes::copy_from_slice(destination), ValueWrapper::Set { members });
+        }
+        len
+    }
+
+    // Sorted set commands
+    /// Runs `f` on the sorted set stored at `key`. A missing key reads as an empty one.
+    fn read_zset<T>(&self, key: &[u8], f: impl FnOnce(&SortedSet) -> T) -> Result<T, String> {
+        match self.data.get(key) {
+            Some(entry) => match entry.value() {
+                ValueWrapper::ZSet { members } => Ok(f(members)),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
             },
+            None => Ok(f(&SortedSet::new())),
+        }
+    }
+
+    /// Runs `f` on the sorted set stored at `key`, creating it if needed. A sorted set left
+    /// empty by `f` is removed.
+    fn write_zset<T>(&self, key: &[u8], f: impl FnOnce(&mut SortedSet) -> Result<T, String>) -> Result<T, String> {
+        match self.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(mut entry) => {
+                let result = match entry.get_mut() {
+                    ValueWrapper::ZSet { members } => f(members),
+                    _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                };
+                if matches!(entry.get(), ValueWrapper::ZSet { members } if members.is_empty()) {
+                    self.expires.remove(entry.key());
+                    entry.remove();
+                }
+                result
+            },
+            Entry::Vacant(entry) => {
+                let mut members = SortedSet::new();
+                let result = f(&mut members);
+                if !members.is_empty() {
+                    entry.insert(ValueWrapper::ZSet { members });
+                }
+                result
+            },
+        }
+    }
+
+    /// Applies ZADD without INCR. Returns how many members were added and how many existing
+    /// ones got a new score.
+    pub fn zadd(&self, key: &[u8], options: ZAddOptions, pairs: &[(f64, Bytes)]) -> Result<(i64, i64), String> {
+        self.write_zset(key, |zset| {
+            let (mut added, mut updated) = (0, 0);
+            for (score, member) in pairs {
+                match zset.score(member) {
+                    Some(current) => {
+                        if options.nx || (options.gt && *score <= current) || (options.lt && *score >= current) {
+                            continue;
+                        }
+                        if *score != current {
+                            zset.insert(member.clone(), *score);
+                            updated += 1;
+                        }
+                    },
+                    None if options.xx => {},
+                    None => {
+                        zset.insert(member.clone(), *score);
+                        added += 1;
+                    },
+                }
+            }
+            Ok((added, updated))
+        })
+    }
+
+    /// ZADD INCR and ZINCRBY. Returns the new score, or None if NX/XX/GT/LT prevented the update.
+    pub fn zadd_incr(&self, key: &[u8], options: ZAddOptions, increment: f64, member: &Bytes) -> Result<Option<f64>, String> {
+        self.write_zset(key, |zset| {
+            let current = zset.score(member);
+            if (options.nx && current.is_some()) || (options.xx && current.is_none()) {
+                return Ok(None);
+            }
+            let score = current.unwrap_or(0.0) + increment;
+            if score.is_nan() {
+                return Err("ERR resulting score is not a number (NaN)".to_string());
+            }
+            if let Some(current) = current {
+                if (options.gt && score <= current) || (options.lt && score >= current) {
+                    return Ok(None);
+                }
+            }
+            zset.insert(member.clone(), score);
+            Ok(Some(score))
+        })
+    }
+
+    pub fn zrem(&self, key: &[u8], members: &[Bytes]) -> Result<i64, String> {
+        if !self.data.contains_key(key) {
+            return Ok(0);
+        }
+        self.write_zset(key, |zset| Ok(members.iter().filter(|member| zset.remove(member)).count() as i64))
+    }
+
+    pub fn zscore(&self, key: &[u8], member: &[u8]) -> Result<Option<f64>, String> {
+        self.read_zset(key, |zset| zset.score(member))
+    }
+
+    pub fn zmscore(&self, key: &[u8], members: &[Bytes]) -> Result<Vec<Option<f64>>, String> {
+        self.read_zset(key, |zset| members.iter().map(|member| zset.score(member)).collect())
+    }
+
+    pub fn zcard(&self, key: &[u8]) -> Result<i64, String> {
+        self.read_zset(key, |zset| zset.len() as i64)
+    }
+
+    pub fn zcount(&self, key: &[u8], min: ScoreBound, max: ScoreBound) -> Result<i64, String> {
+        self.read_zset(key, |zset| zset.count(min, max) as i64)
+    }
+
+    pub fn zrange(&self, key: &[u8], spec: &RangeSpec) -> Result<Vec<(Bytes, f64)>, String> {
+        self.read_zset(key, |zset| zset.range(spec))
+    }
+
+    /// Rank and score of `member`, see `SortedSet::rank`.
+    pub fn zrank(&self, key: &[u8], member: &[u8], rev: bool) -> Result<Option<(usize, f64)>, String> {
+        self.read_zset(key, |zset| zset.rank(member, rev).zip(zset.score(member)))
+    }
+
+    pub fn zpop(&self, key: &[u8], count: usize, max: bool) -> Result<Vec<(Bytes, f64)>, String> {
+        if !self.data.contains_key(key) {
+            return Ok(vec![]);
+        }
+        self.write_zset(key, |zset| Ok(zset.pop(count, max)))
+    }
+
+    /// Combines the sorted sets at `keys` for ZUNIONSTORE/ZINTERSTORE. Plain sets take part
+    /// with a score of 1, missing keys as empty sets. Each input's scores are multiplied by its
+    /// weight (1 if not given).
+    pub fn zset_operation(&self, op: SetOp, keys: &[Bytes], weights: &[f64], aggregate: Aggregate) -> Result<SortedSet, String> {
+        let mut inputs: Vec<Vec<(Bytes, f64)>> = Vec::with_capacity(keys.len());
+        for key in keys {
+            let input = match self.data.get(&key[..]) {
+                Some(entry) => match entry.value() {
+                    ValueWrapper::ZSet { members } => members.iter().map(|(m, s)| (m.clone(), s)).collect(),
+                    ValueWrapper::Set { members } => members.iter().map(|m| (m.clone(), 1.0)).collect(),
+                    _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                },
+                None => vec![],
+            };
+            inputs.push(input);
+        }
+
+        let weighted = |i: usize, score: f64| {
+            let product = score * weights.get(i).copied().unwrap_or(1.0);
+            // 0 * inf
+            if product.is_nan() { 0.0 } else { product }
+        };
+        let mut combined: HashMap<Bytes, f64> = HashMap::new();
+        for (member, score) in inputs.first().into_iter().flatten() {
+            combined.insert(member.clone(), weighted(0, *score));
+        }
+        for (i, input) in inputs.iter().enumerate().skip(1) {
+            match op {
+                SetOp::Union => {
+                    for (member, score) in input {
+                        let score = weighted(i, *score);
+                        combined.entry(member.clone())
+                            .and_modify(|current| *current = aggregate.apply(*current, score))
+                            .or_insert(score);
+                    }
+                },
+                SetOp::Inter => {
+                    let input: HashMap<&Bytes, f64> = input.iter().map(|(m, s)| (m, *s)).collect();
+                    combined.retain(|member, current| match input.get(member) {
+                        Some(score) => {
+                            *current = aggregate.apply(*current, weighted(i, *score));
+                            true
+                        },
+                        None => false,
+                    });
+                },
+                SetOp::Diff => {
+                    for (member, _) in input {
+                        combined.remove(member);
+                    }
+                },
+            }
+        }
+
+        let mut result = SortedSet::new();
+        for (member, score) in combined {
+            result.insert(member, score);
+        }
+        Ok(result)
+    }
+
+    /// Replaces whatever is at `destination` with `members`, or deletes it if there are none.
+    pub fn store_zset(&self, destination: &[u8], members: SortedSet) -> i64 {
+        let len = members.len() as i64;
+        self.expires.remove(destination);
+        if members.is_empty() {
+            self.data.remove(destination);
+        } else {
+            self.data.insert(Bytes::copy_from_slice(destination), ValueWrapper::ZSet { members });
         }
+        len
+    }
+
+    // Geo commands
+    /// The (longitude, latitude) of each of `members` in the geo index at `key`.
+    pub fn geopos(&self, key: &[u8], members: &[Bytes]) -> Result<Vec<Option<(f64, f64)>>, String> {
+        self.read_zset(key, |zset| members.iter()
+            .map(|member| zset.score(member).map(|score| geo::decode(score as u64)))
+            .collect())
+    }
+
+    /// The members of the geo index at `key` that `query` selects, as (member, distance in
+    /// meters, score), sorted and limited as it asks.
+    pub fn geosearch(&self, key: &[u8], query: &Query) -> Result<Vec<(Bytes, f64, f64)>, String> {
+        if !self.data.contains_key(key) {
+            return Ok(vec![]);
+        }
+        self.read_zset(key, |zset| {
+            let (longitude, latitude) = match &query.origin {
+                Origin::Position(longitude, latitude) => (*longitude, *latitude),
+                Origin::Member(member) => match zset.score(member) {
+                    Some(score) => geo::decode(score as u64),
+                    None => return Err("ERR could not decode requested zset member".to_string()),
+                },
+            };
+            // With ANY the search stops as soon as it has enough, otherwise the nearest ones win.
+            let limit = match query.count {
+                Some((count, true)) => count,
+                _ => usize::MAX,
+            };
+            let mut found = Vec::new();
+            'ranges: for (start, end) in geo::score_ranges(longitude, latitude, &query.shape) {
+                for (member, score) in zset.scores_between(start as f64, end as f64) {
+                    let (lon, lat) = geo::decode(score as u64);
+                    if let Some(distance) = query.shape.distance_if_inside(longitude, latitude, lon, lat) {
+                        found.push((member.clone(), distance, score));
+                        if found.len() >= limit {
+                            break 'ranges;
+                        }
+                    }
+                }
+            }
+            match query.sort {
+                Sort::Asc => found.sort_by(|a, b| a.1.total_cmp(&b.1)),
+                Sort::Desc => found.sort_by(|a, b| b.1.total_cmp(&a.1)),
+                Sort::None => {},
+            }
+            if let Some((count, _)) = query.count {
+                found.truncate(count);
+            }
+            Ok(found)
+        })?
     }
 
     pub fn compare_stream_ids(id1: &str, id2: &str) -> std::cmp::Ordering {
@@ -339,35 +1276,26 @@ impl Storage {
             return std::cmp::Ordering::Less;
         }
 
-        let parts1: Vec<&str> = id1.split('-').collect();
-        let parts2: Vec<&str> = id2.split('-').collect();
-
-        let ms1 = parts1[0].parse::<u64>().unwrap();
-        let ms2 = parts2[0].parse::<u64>().unwrap();
+        // An ID without a sequence is at sequence 0, and one that doesn't parse sorts first
+        let id1 = Self::parse_stream_id(id1).unwrap_or_default();
+        let id2 = Self::parse_stream_id(id2).unwrap_or_default();
+        id1.cmp(&id2)
+    }
 
-        if ms1 != ms2 {
-            ms1.cmp(&ms2)
+    pub fn get_next_sequence(metadata: &StreamMetadata, time_part: u64) -> u64 {
+        let (last_time, last_sequence) = metadata.last_id;
+        if time_part == last_time {
+            last_sequence.saturating_add(1)
+        } else if time_part == 0 {
+            1
         } else {
-            let seq1 = parts1[1].parse::<u64>().unwrap();
-            let seq2 = parts2[1].parse::<u64>().unwrap();
-            seq1.cmp(&seq2)
+            0
         }
     }
 
-    pub fn get_next_sequence(metadata: &mut StreamMetadata, time_part: u64) -> u64 {
-        let next_seq = match metadata.last_sequences.get(&time_part) {
-            Some(seq) => *seq + 1,
-            None => if time_part == 0 { 1 } else { 0 }
-        };
-        metadata.last_sequences.insert(time_part, next_seq);
-        next_seq
-    }
-
-    pub fn validate_new_id(&self, entries: &[StreamEntry], new_id: &str) -> Result<(), Cow<'static, str>> {
-        if let Some(last_entry) = entries.last() {
-            if Self::compare_stream_ids(new_id, &last_entry.id) != std::cmp::Ordering::Greater {
-                return Err("ERR The ID specified in XADD is equal or smaller than the target stream top item".into());
-            }
+    pub fn validate_new_id(metadata: &StreamMetadata, new_id: (u64, u64)) -> Result<(), Cow<'static, str>> {
+        if new_id <= metadata.last_id {
+            return Err("ERR The ID specified in XADD is equal or smaller than the target stream top item".into());
         }
         Ok(())
     }
@@ -379,7 +1307,7 @@ impl Storage {
             .as_millis() as u64
     }
 
-    pub fn xadd(&self, key: &str, id: &str, fields: HashMap<String, String>) -> Result<String, Cow<'static, str>> {
+    pub fn xadd(&self, key: &[u8], id: &str, fields: Vec<(Bytes, Bytes)>) -> Result<String, Cow<'static, str>> {
         let (time_part, sequence) = if id == "*" {
             (Self::get_current_time_ms(), None)
         } else {
@@ -396,66 +1324,333 @@ impl Storage {
             }
         }
 
-        match self.data.entry(key.to_string()) {
-            dashmap::mapref::entry::Entry::Occupied(mut occupied) => {
-                match occupied.get_mut() {
-                    ValueWrapper::Stream { entries, metadata } => {
-                        let new_sequence = sequence.unwrap_or_else(|| Self::get_next_sequence(metadata, time_part));
-                        let new_id = format!("{}-{}", time_part, new_sequence);
-                        
-                        if let Err(e) = self.validate_new_id(entries, &new_id) {
-                            return Err(e);
-                        }
+        let mut stream = self.data.entry(Bytes::copy_from_slice(key)).or_insert_with(|| ValueWrapper::Stream {
+            entries: Vec::new(),
+            metadata: StreamMetadata::default(),
+        });
+        match stream.value_mut() {
+            ValueWrapper::Stream { entries, metadata } => {
+                // A clock that went backwards mustn't make `*` fail, keep counting from the last ID
+                let time_part = if id == "*" { time_part.max(metadata.last_id.0) } else { time_part };
+                let new_sequence = sequence.unwrap_or_else(|| Self::get_next_sequence(metadata, time_part));
+                Self::validate_new_id(metadata, (time_part, new_sequence))?;
+                metadata.last_id = (time_part, new_sequence);
 
-                        let entry = StreamEntry {
-                            id: new_id.clone(),
-                            fields,
-                        };
-                        entries.push(entry);
-                        Ok(new_id)
-                    },
-                    _ => Err("ERR WRONGTYPE Operation against a key holding the wrong kind of value".into()),
-                }
-            },
-            dashmap::mapref::entry::Entry::Vacant(vacant) => {
-                let mut metadata = StreamMetadata::default();
-                let new_sequence = sequence.unwrap_or_else(|| Self::get_next_sequence(&mut metadata, time_part));
                 let new_id = format!("{}-{}", time_part, new_sequence);
-
-                let entry = StreamEntry {
+                entries.push(StreamEntry {
                     id: new_id.clone(),
                     fields,
-                };
-                vacant.insert(ValueWrapper::Stream { 
-                    entries: vec![entry],
-                    metadata,
                 });
                 Ok(new_id)
             },
+            _ => Err("ERR WRONGTYPE Operation against a key holding the wrong kind of value".into()),
         }
     }
 
-    pub fn xrange(&self, key: &str, start: &str, end: &str) -> Result<Vec<StreamEntry>, Cow<'static, str>> {
+    /// The entries from `start` to `end`, at most `count` of them, the last ones first if `rev`.
+    pub fn xrange(&self, key: &[u8], start: StreamId, end: StreamId, count: Option<usize>, rev: bool) -> Result<Vec<StreamEntry>, String> {
+        let found = self.read_stream(key, |entries, _| {
+            let from = entries.partition_point(|entry| stream_id(entry) < start);
+            let to = entries.partition_point(|entry| stream_id(entry) <= end).max(from);
+            let range = entries[from..to].iter();
+            let count = count.unwrap_or(usize::MAX);
+            if rev {
+                range.rev().take(count).cloned().collect()
+            } else {
+                range.take(count).cloned().collect()
+            }
+        })?;
+        Ok(found.unwrap_or_default())
+    }
+
+    fn read_stream<T>(&self, key: &[u8], f: impl FnOnce(&[StreamEntry], &StreamMetadata) -> T) -> Result<Option<T>, String> {
         match self.data.get(key) {
             Some(entry) => match entry.value() {
-                ValueWrapper::Stream { entries, .. } => {
-                    let filtered_entries: Vec<StreamEntry> = entries
-                        .iter()
-                        .filter(|entry| {
-                            Self::compare_stream_ids(&entry.id, start) != std::cmp::Ordering::Less &&
-                            Self::compare_stream_ids(&entry.id, end) != std::cmp::Ordering::Greater
-                        })
-                        .cloned()
+                ValueWrapper::Stream { entries, metadata } => Ok(Some(f(entries, metadata))),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Runs `f` on the stream stored at `key`, or returns None if there is none. Unlike the other
+    /// aggregates a stream stays around once it's empty, it still holds its last ID and groups.
+    fn write_stream<T>(&self, key: &[u8], f: impl FnOnce(&mut Vec<StreamEntry>, &mut StreamMetadata) -> T) -> Result<Option<T>, String> {
+        match self.data.get_mut(key) {
+            Some(mut entry) => match entry.value_mut() {
+                ValueWrapper::Stream { entries, metadata } => Ok(Some(f(entries, metadata))),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Runs `f` on a consumer group of the stream at `key`. None if there's no such stream or group.
+    fn write_group<T>(&self, key: &[u8], group: &[u8], f: impl FnOnce(&[StreamEntry], &mut ConsumerGroup) -> T) -> Result<Option<T>, String> {
+        let result = self.write_stream(key, |entries, metadata| {
+            metadata.groups.get_mut(group).map(|group| f(entries, group))
+        })?;
+        Ok(result.flatten())
+    }
+
+    pub fn xlen(&self, key: &[u8]) -> Result<i64, String> {
+        match self.data.get(key) {
+            Some(entry) => match entry.value() {
+                ValueWrapper::Stream { entries, .. } => Ok(entries.len() as i64),
+                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// Deletes the entries with the given IDs. Returns how many existed.
+    pub fn xdel(&self, key: &[u8], ids: &[(u64, u64)]) -> Result<i64, String> {
+        let deleted = self.write_stream(key, |entries, _| {
+            let mut deleted = 0;
+            for id in ids {
+                if let Ok(index) = entries.binary_search_by_key(id, stream_id) {
+                    entries.remove(index);
+                    deleted += 1;
+                }
+            }
+            deleted
+        })?;
+        Ok(deleted.unwrap_or(0))
+    }
+
+    /// Evicts the oldest entries as `trim` asks. Returns how many went.
+    pub fn xtrim(&self, key: &[u8], trim: &StreamTrim) -> Result<i64, String> {
+        let trimmed = self.write_stream(key, |entries, _| {
+            let excess = match trim.threshold {
+                TrimThreshold::MaxLen(maxlen) => entries.len().saturating_sub(maxlen),
+                TrimThreshold::MinId(ms, seq) => entries.partition_point(|entry| stream_id(entry) < (ms, seq)),
+            };
+            let evicted = if trim.approximate {
+                let limit = if trim.limit == 0 { usize::MAX } else { trim.limit };
+                excess.min(limit) / STREAM_NODE_MAX_ENTRIES * STREAM_NODE_MAX_ENTRIES
+            } else {
+                excess
+            };
+            entries.drain(..evicted);
+            evicted as i64
+        })?;
+        Ok(trimmed.unwrap_or(0))
+    }
+
+    // Consumer groups
+    /// XGROUP CREATE. `id` None is `$`, the stream's last ID. Returns the ID the group starts at.
+    pub fn xgroup_create(&self, key: &[u8], group: &Bytes, id: Option<StreamId>, mkstream: bool) -> Result<StreamId, String> {
+        if mkstream {
+            self.data.entry(Bytes::copy_from_slice(key)).or_insert_with(|| ValueWrapper::Stream {
+                entries: Vec::new(),
+                metadata: StreamMetadata::default(),
+            });
+        }
+        self.write_stream(key, |_, metadata| {
+            if metadata.groups.contains_key(group) {
+                return Err("BUSYGROUP Consumer Group name already exists".to_string());
+            }
+            let start = id.unwrap_or(metadata.last_id);
+            metadata.groups.insert(group.clone(), ConsumerGroup::new(start));
+            Ok(start)
+        })?.unwrap_or_else(|| Err(XGROUP_REQUIRES_KEY.to_string()))
+    }
+
+    /// XGROUP SETID. Returns the ID the group now starts after, or None if there's no such group.
+    pub fn xgroup_setid(&self, key: &[u8], group: &[u8], id: Option<StreamId>) -> Result<Option<StreamId>, String> {
+        self.write_stream(key, |_, metadata| {
+            let last_id = metadata.last_id;
+            metadata.groups.get_mut(group).map(|group| {
+                group.last_delivered = id.unwrap_or(last_id);
+                group.last_delivered
+            })
+        })?.ok_or_else(|| XGROUP_REQUIRES_KEY.to_string())
+    }
+
+    pub fn xgroup_destroy(&self, key: &[u8], group: &[u8]) -> Result<bool, String> {
+        self.write_stream(key, |_, metadata| metadata.groups.remove(group).is_some())?
+            .ok_or_else(|| XGROUP_REQUIRES_KEY.to_string())
+    }
+
+    /// XGROUP CREATECONSUMER. Returns whether the consumer is new, or None if there's no such group.
+    pub fn xgroup_createconsumer(&self, key: &[u8], group: &[u8], consumer: &Bytes) -> Result<Option<bool>, String> {
+        let now = Self::get_current_time_ms();
+        self.write_stream(key, |_, metadata| {
+            metadata.groups.get_mut(group).map(|group| {
+                let created = !group.consumers.contains_key(consumer);
+                if created {
+                    group.touch_consumer(consumer, now);
+                }
+                created
+            })
+        })?.ok_or_else(|| XGROUP_REQUIRES_KEY.to_string())
+    }
+
+    /// XGROUP DELCONSUMER. Returns how many entries the consumer had pending, or None if there's
+    /// no such group.
+    pub fn xgroup_delconsumer(&self, key: &[u8], group: &[u8], consumer: &[u8]) -> Result<Option<usize>, String> {
+        self.write_stream(key, |_, metadata| {
+            metadata.groups.get_mut(group).map(|group| group.delete_consumer(consumer))
+        })?.ok_or_else(|| XGROUP_REQUIRES_KEY.to_string())
+    }
+
+    pub fn has_group(&self, key: &[u8], group: &[u8]) -> Result<bool, String> {
+        Ok(self.read_stream(key, |_, metadata| metadata.groups.contains_key(group))? == Some(true))
+    }
+
+    /// XREADGROUP on one stream. `after` None is `>`: entries the group hasn't delivered yet,
+    /// which become pending for `consumer` unless `noack`. Otherwise it's the consumer's own
+    /// pending entries after that ID, each one still in the stream counting as delivered again.
+    /// None if there's no such stream or group.
+    pub fn xreadgroup(&self, key: &[u8], group: &[u8], consumer: &Bytes, after: Option<StreamId>, count: Option<usize>, noack: bool) -> Result<Option<GroupRead>, String> {
+        let now = Self::get_current_time_ms();
+        let limit = count.unwrap_or(usize::MAX);
+        self.write_group(key, group, |entries, group| {
+            let consumer_created = group.touch_consumer(consumer, now);
+            let mut redelivered = Vec::new();
+            let read: Vec<DeliveredEntry> = match after {
+                None => {
+                    let start = entries.partition_point(|entry| stream_id(entry) <= group.last_delivered);
+                    let delivered: Vec<DeliveredEntry> = entries[start..].iter()
+                        .take(limit)
+                        .map(|entry| (stream_id(entry), Some(entry.fields.clone())))
                         .collect();
-                    Ok(filtered_entries)
+                    for (id, _) in &delivered {
+                        group.last_delivered = *id;
+                        if !noack {
+                            group.pending.insert(*id, PendingEntry { consumer: consumer.clone(), delivered_at: now, deliveries: 1 });
+                        }
+                    }
+                    delivered
                 },
-                _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".into()),
-            },
-            None => Ok(Vec::new()),
+                Some(after) => {
+                    let ids: Vec<StreamId> = group.pending.range((std::ops::Bound::Excluded(after), std::ops::Bound::Unbounded))
+                        .filter(|(_, pending)| pending.consumer == consumer)
+                        .take(limit)
+                        .map(|(id, _)| *id)
+                        .collect();
+                    ids.into_iter()
+                        .map(|id| {
+                            let fields = entries.binary_search_by_key(&id, stream_id).ok().map(|index| entries[index].fields.clone());
+                            // Delivered again, like Redis does for a re-read: it counts towards
+                            // the delivery count and resets the idle time
+                            if let (Some(_), Some(pending)) = (&fields, group.pending.get_mut(&id)) {
+                                pending.delivered_at = now;
+                                pending.deliveries += 1;
+                                redelivered.push((id, pending.clone()));
+                            }
+                            (id, fields)
+                        })
+                        .collect()
+                },
+            };
+            GroupRead { entries: read, consumer_created, delivered_at: now, redelivered, last_delivered: group.last_delivered }
+        })
+    }
+
+    /// Acknowledges `ids` in `group`. Returns how many were pending.
+    pub fn xack(&self, key: &[u8], group: &[u8], ids: &[StreamId]) -> Result<i64, String> {
+        Ok(self.write_group(key, group, |_, group| group.ack(ids))?.unwrap_or(0))
+    }
+
+    /// XPENDING's summary: how many entries are pending, the lowest and highest of their IDs,
+    /// and how many each consumer has. None if there's no such stream or group.
+    #[allow(clippy::type_complexity)]
+    pub fn xpending_summary(&self, key: &[u8], group: &[u8]) -> Result<Option<(usize, Option<(StreamId, StreamId)>, Vec<(Bytes, usize)>)>, String> {
+        let summary = self.read_stream(key, |_, metadata| {
+            metadata.groups.get(group).map(|group| {
+                let bounds = group.pending.keys().next().zip(group.pending.keys().next_back())
+                    .map(|(first, last)| (*first, *last));
+                (group.pending.len(), bounds, group.pending_by_consumer())
+            })
+        })?;
+        Ok(summary.flatten())
+    }
+
+    /// XPENDING's extended form: up to `count` pending entries between `start` and `end`,
+    /// optionally only one consumer's and only those idle at least `min_idle` milliseconds.
+    #[allow(clippy::too_many_arguments)]
+    pub fn xpending(&self, key: &[u8], group: &[u8], start: StreamId, end: StreamId, count: usize, consumer: Option<&[u8]>, min_idle: Option<u64>) -> Result<Option<Vec<(StreamId, PendingEntry)>>, String> {
+        let now = Self::get_current_time_ms();
+        if start > end {
+            return Ok(self.has_group(key, group)?.then(Vec::new));
         }
+        let pending = self.read_stream(key, |_, metadata| {
+            metadata.groups.get(group).map(|group| {
+                group.pending.range(start..=end)
+                    .filter(|(_, pending)| consumer.is_none_or(|consumer| pending.consumer == consumer))
+                    .filter(|(_, pending)| min_idle.is_none_or(|min_idle| now.saturating_sub(pending.delivered_at) >= min_idle))
+                    .take(count)
+                    .map(|(id, pending)| (*id, pending.clone()))
+                    .collect()
+            })
+        })?;
+        Ok(pending.flatten())
     }
 
-    pub fn get_stream_entries(&self, stream_key: &str, ms: u64, seq: u64, count: Option<usize>) -> Vec<StreamEntry> {
+    /// XCLAIM: hands the pending `ids` idle at least `min_idle` milliseconds to `consumer`.
+    /// None if there's no such stream or group.
+    pub fn xclaim(&self, key: &[u8], group: &[u8], consumer: &Bytes, min_idle: u64, ids: &[StreamId], options: &ClaimOptions) -> Result<Option<Claimed>, String> {
+        let now = Self::get_current_time_ms();
+        let delivered_at = options.time.or_else(|| options.idle.map(|idle| now.saturating_sub(idle))).unwrap_or(now);
+        self.write_group(key, group, |entries, group| {
+            let mut claimed = Claimed::default();
+            if let Some(last_id) = options.last_id {
+                group.last_delivered = group.last_delivered.max(last_id);
+            }
+            for id in ids {
+                let entry = entries.binary_search_by_key(id, stream_id).ok().map(|index| &entries[index]);
+                match (entry, group.pending.get(id)) {
+                    (None, Some(_)) => {
+                        group.pending.remove(id);
+                        claimed.deleted.push(*id);
+                        continue;
+                    },
+                    (None, None) => continue,
+                    (Some(_), None) if !options.force => continue,
+                    (Some(_), Some(pending)) if now.saturating_sub(pending.delivered_at) < min_idle => continue,
+                    _ => (),
+                }
+                let pending = group.claim(*id, consumer, delivered_at, options, now);
+                let fields = entry.filter(|_| !options.justid).map(|entry| entry.fields.clone());
+                claimed.entries.push((*id, fields, pending));
+            }
+            claimed.last_delivered = group.last_delivered;
+            claimed
+        })
+    }
+
+    /// XAUTOCLAIM: goes through the PEL from `start` and claims up to `count` entries idle at
+    /// least `min_idle` milliseconds, looking at no more than ten times that many.
+    #[allow(clippy::too_many_arguments)]
+    pub fn xautoclaim(&self, key: &[u8], group: &[u8], consumer: &Bytes, min_idle: u64, start: StreamId, count: usize, justid: bool) -> Result<Option<Claimed>, String> {
+        let now = Self::get_current_time_ms();
+        let options = ClaimOptions { justid, ..Default::default() };
+        self.write_group(key, group, |entries, group| {
+            let mut claimed = Claimed::default();
+            let mut attempts = count.saturating_mul(10);
+            let mut ids = group.pending.range(start..).map(|(id, _)| *id).collect::<Vec<_>>().into_iter().peekable();
+            while attempts > 0 && claimed.entries.len() < count {
+                let Some(id) = ids.next() else { break };
+                attempts -= 1;
+                let Ok(index) = entries.binary_search_by_key(&id, stream_id) else {
+                    group.pending.remove(&id);
+                    claimed.deleted.push(id);
+                    continue;
+                };
+                if now.saturating_sub(group.pending[&id].delivered_at) < min_idle {
+                    continue;
+                }
+                let pending = group.claim(id, consumer, now, &options, now);
+                claimed.entries.push((id, (!justid).then(|| entries[index].fields.clone()), pending));
+            }
+            claimed.next = ids.peek().copied().unwrap_or((0, 0));
+            claimed.last_delivered = group.last_delivered;
+            claimed
+        })
+    }
+
+    pub fn get_stream_entries(&self, stream_key: &[u8], ms: u64, seq: u64, count: Option<usize>) -> Vec<StreamEntry> {
         match self.data.get(stream_key) {
             Some(entry) => match entry.value() {
                 ValueWrapper::Stream { entries, .. } => {
@@ -481,50 +1676,196 @@ impl Storage {
     }
 
     pub fn parse_stream_id(id: &str) -> Result<(u64, u64), String> {
-        let parts: Vec<&str> = id.split('-').collect();
-        if parts.len() != 2 {
-            return Err("ERR Invalid stream ID format".to_string());
+        if id == "$" {
+            return Ok((u64::MAX, 0));
         }
-        
-        let ms = parts[0].parse::<u64>()
+        let (ms, seq) = match id.split_once('-') {
+            Some((ms, seq)) => (ms, Some(seq)),
+            None => (id, None),
+        };
+
+        let ms = ms.parse::<u64>()
             .map_err(|_| "ERR Invalid stream ID milliseconds")?;
-        let seq = parts[1].parse::<u64>()
-            .map_err(|_| "ERR Invalid stream ID sequence number")?;
-            
+        let seq = match seq {
+            Some(seq) => seq.parse::<u64>()
+                .map_err(|_| "ERR Invalid stream ID sequence number")?,
+            None => 0,
+        };
+
         Ok((ms, seq))
     }
 
-    pub fn keys(&self, pattern: &str) -> Vec<String> {
+    // Expiration
+    /// Deletes `key` if its TTL has run out. Returns true if it did.
+    pub fn expire_if_due(&self, key: &[u8]) -> bool {
+        let now = Self::get_current_time_ms();
+        match self.expires.remove_if(key, |_, expiration| now > *expiration) {
+            Some((_, _expiration)) => {
+                #[cfg(debug_assertions)]
+                println!("DEBUG: Key {:?} has expired. Current time: {}, Expiration: {}", Bytes::copy_from_slice(key), now, _expiration);
+                self.data.remove(key);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// True if `key` has a TTL that has run out but hasn't been removed yet.
+    fn is_expired(&self, key: &[u8]) -> bool {
+        self.expires.get(key).is_some_and(|expiration| Self::get_current_time_ms() > *expiration)
+    }
+
+    /// Looks at up to `count` keys with a TTL, picked from a random place in the keyspace.
+    /// Returns the ones that have expired, and how many keys were looked at. Nothing is
+    /// removed; the caller does that under the key locks.
+    pub fn sample_expired(&self, count: usize) -> (Vec<Bytes>, usize) {
+        if self.expires.is_empty() {
+            return (vec![], 0);
+        }
+        let sample = self.expires.sample(count);
+        let sampled = sample.len();
+        let expired = sample.into_iter()
+            .filter(|key| self.is_expired(key))
+            .collect();
+        (expired, sampled)
+    }
+
+    /// Sets the expiration of an existing key to `when` (unix milliseconds), subject to
+    /// `options`. A time that has already passed deletes the key. Returns false if the key
+    /// doesn't exist or the condition didn't hold.
+    pub fn expire_at(&self, key: &[u8], when: i64, options: ExpireOptions) -> bool {
+        if !self.data.contains_key(key) {
+            return false;
+        }
+        let applies = match self.expires.get(key).map(|expiration| *expiration as i64) {
+            Some(current) => !options.nx && (!options.gt || when > current) && (!options.lt || when < current),
+            None => !options.xx && !options.gt,
+        };
+        if !applies {
+            return false;
+        }
+        if when <= Self::get_current_time_ms() as i64 {
+            self.expires.remove(key);
+            self.data.remove(key);
+        } else {
+            self.expires.insert(Bytes::copy_from_slice(key), when as u64);
+        }
+        true
+    }
+
+    /// The expiration of `key` in unix milliseconds, -1 if it has none and -2 if it doesn't exist.
+    pub fn expire_time(&self, key: &[u8]) -> i64 {
+        if !self.data.contains_key(key) {
+            return -2;
+        }
+        self.expires.get(key).map_or(-1, |expiration| *expiration as i64)
+    }
+
+    /// Removes the TTL of `key`. Returns false if it doesn't exist or has no TTL.
+    pub fn persist(&self, key: &[u8]) -> bool {
+        self.data.contains_key(key) && self.expires.remove(key).is_some()
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.data.contains_key(key)
+    }
+
+    /// A copy of the value of `key`, along with its expiration.
+    pub fn clone_value(&self, key: &[u8]) -> Option<(ValueWrapper, Option<u64>)> {
+        let value = self.data.get(key)?.value().clone();
+        Some((value, self.expires.get(key).map(|expiration| *expiration)))
+    }
+
+    /// Takes `key` out of the database, along with its expiration.
+    pub fn remove(&self, key: &[u8]) -> Option<(ValueWrapper, Option<u64>)> {
+        let (_, value) = self.data.remove(key)?;
+        let expiration = self.expires.remove(key).map(|(_, expiration)| expiration);
+        Some((value, expiration))
+    }
+
+    /// Stores `value` under `key` with an absolute expiration, replacing whatever was there.
+    pub fn insert(&self, key: Bytes, value: ValueWrapper, expiration: Option<u64>) {
+        match expiration {
+            Some(expiration) => {
+                self.expires.insert(key.clone(), expiration);
+            },
+            None => {
+                self.expires.remove(&key);
+            },
+        }
+        self.data.insert(key, value);
+    }
+
+    /// Calls `f` with every key that hasn't expired, its value and its expiration.
+    pub fn for_each_entry(&self, mut f: impl FnMut(&Bytes, &ValueWrapper, Option<u64>)) {
+        for entry in self.data.iter() {
+            if !self.is_expired(entry.key()) {
+                f(entry.key(), entry.value(), self.expires.get(entry.key()).map(|expiration| *expiration));
+            }
+        }
+    }
+
+    /// How many keys there are, not counting expired ones that haven't been removed yet.
+    pub fn dbsize(&self) -> usize {
+        let now = Self::get_current_time_ms();
+        let expired = self.expires.iter().filter(|expiration| now > *expiration.value()).count();
+        self.data.len().saturating_sub(expired)
+    }
+
+    /// A key picked uniformly at random, skipping expired ones.
+    pub fn random_key(&self) -> Option<Bytes> {
+        self.data.iter()
+            .filter(|entry| !self.is_expired(entry.key()))
+            .map(|entry| entry.key().clone())
+            .choose(&mut rand::thread_rng())
+    }
+
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Bytes> {
         let mut keys = Vec::new();
         for entry in self.data.iter() {
             let key = entry.key();
-            if pattern == "*" || key == pattern {
+            if glob_match(pattern, key) && !self.is_expired(key) {
                 keys.push(key.clone());
             }
         }
         keys
     }
 
-    pub fn get_type(&self, key: &str) -> Cow<'static, str> {
+    /// One SCAN step. Keys are visited in hash order and the cursor is the hash to continue
+    /// from, so inserts and deletes don't move the keys that are still ahead of it: a key
+    /// present for the whole iteration is returned at least once. Keys sharing a hash are
+    /// returned together. `count` is the amount of work, MATCH and TYPE filter afterwards, so a
+    /// step can come back empty with a non-zero cursor. Returns the next cursor, 0 once done.
+    pub fn scan(&self, cursor: u64, count: usize, pattern: Option<&[u8]>, type_name: Option<&str>) -> (u64, Vec<Bytes>) {
+        let (next, keys) = self.data.scan(cursor, count);
+        let keys = keys.into_iter()
+            .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern, key)))
+            .filter(|key| !self.is_expired(key))
+            .filter(|key| type_name.is_none_or(|type_name| self.get_type(key).eq_ignore_ascii_case(type_name)))
+            .collect();
+        (next, keys)
+    }
+
+    pub fn get_type(&self, key: &[u8]) -> Cow<'static, str> {
         match self.data.get(key) {
             Some(entry) => match entry.value() {
                 ValueWrapper::String { .. } => "string".into(),
                 ValueWrapper::Stream { .. } => "stream".into(),
                 ValueWrapper::List { .. } => "list".into(),
+                ValueWrapper::Hash { .. } => "hash".into(),
+                ValueWrapper::Set { .. } => "set".into(),
+                ValueWrapper::ZSet { .. } => "zset".into(),
             },
             None => "none".into(),
         }
     }
 
-    pub fn get_last_stream_id(&self, stream_key: &str) -> Option<String> {
+    pub fn get_last_stream_id(&self, stream_key: &[u8]) -> Option<String> {
         match self.data.get(stream_key) {
             Some(entry) => match entry.value() {
-                ValueWrapper::Stream { entries, metadata } => {
-                    if entries.is_empty() {
-                        metadata.last_dollar_id.clone()
-                    } else {
-                        Some(entries.last()?.id.clone())
-                    }
+                ValueWrapper::Stream { metadata, .. } => {
+                    let (ms, seq) = metadata.last_id;
+                    Some(format!("{}-{}", ms, seq))
                 },
                 _ => None,
             },
@@ -540,3 +1881,27 @@ impl Storage {
         (start_idx, stop_idx)
     }
 }
+
+/// XGROUP's reply when the stream isn't there.
+const XGROUP_REQUIRES_KEY: &str = "ERR The XGROUP subcommand requires the key to exist. \
+    Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+/// An entry's ID as (milliseconds, sequence), the order entries are kept in.
+fn stream_id(entry: &StreamEntry) -> (u64, u64) {
+    Storage::parse_stream_id(&entry.id).unwrap_or_default()
+}
+
+/// Reads a stored string as an integer the way Redis does: optional minus sign, no plus sign,
+/// no leading zeros or whitespace.
+fn parse_integer(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    let canonical = match digits {
+        [b'0'] => value.len() == 1,
+        [first, ..] => (b'1'..=b'9').contains(first),
+        [] => false,
+    };
+    if !canonical {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
@@ -1,10 +1,7 @@
-use std::net::TcpStream;
-use std::io::{Read, Write};
-use std::thread;
-use std::time::Duration;
-use std::borrow::Cow;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use crate::redis::Redis;
 
 // Helper function to generate a replica ID
@@ -14,79 +11,54 @@ pub fn gen_replid() -> &'static str {
 }
 
 #[inline]
-pub fn connect_to_server(host: &str, port: &str) -> std::io::Result<TcpStream> {
+pub async fn connect_to_server(host: &str, port: &str) -> std::io::Result<BufReader<TcpStream>> {
     let replicaof_addr = format!("{}:{}", host, port);
-    TcpStream::connect(replicaof_addr)
+    Ok(BufReader::new(TcpStream::connect(replicaof_addr).await?))
 }
 
 #[inline]
-pub fn send_command(stream: &mut std::net::TcpStream, command: &str) -> std::io::Result<usize> {
-    stream.write(command.as_bytes())
+pub async fn send_command(stream: &mut BufReader<TcpStream>, command: &str) -> std::io::Result<()> {
+    stream.write_all(command.as_bytes()).await?;
+    stream.flush().await
 }
 
+/// Reads a single CRLF terminated reply line, e.g. `+PONG\r\n`.
 #[inline]
-pub fn read_response<'a>(stream: &mut TcpStream, buffer: &'a mut [u8; 512]) -> std::io::Result<Cow<'a, str>> {
-    let bytes_read = stream.read(buffer)?;
-    Ok(String::from_utf8_lossy(&buffer[0..bytes_read])) // Zero-copy conversion using Cow
+pub async fn read_response(stream: &mut BufReader<TcpStream>) -> std::io::Result<String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line)
 }
 
-#[inline]
-pub fn read_until_end_of_rdb(stream: &mut TcpStream, buffer: &mut [u8; 512], redis: Arc<Mutex<Redis>>) {
-    let mut count = 0;
-    'LOOP_PEEK: while let Ok(peek_size) = stream.peek(buffer) {
-        thread::sleep(Duration::from_millis(200));
-        #[cfg(debug_assertions)]
-        println!("peek_size: {}", peek_size);
-        
-        // Zero-copy string conversion for debug output
+/// Consumes the `+FULLRESYNC` line and the `$<len>\r\n<rdb>` payload that follows it.
+/// Anything the master sends after the RDB stays buffered in `stream` for the event loop.
+pub async fn read_until_end_of_rdb(stream: &mut BufReader<TcpStream>, redis: Arc<Redis>) -> std::io::Result<()> {
+    let length = loop {
+        let line = read_response(stream).await?;
         #[cfg(debug_assertions)]
-        println!("peeked: {}", String::from_utf8_lossy(&buffer[0..peek_size]));
-        
-        if count > 10 {
-            #[cfg(debug_assertions)]
-            println!("count exceeded");
-            break;
-        }
-        if peek_size == 0 {
-            count += 1;
-            continue;
-        }
+        println!("read: {}", line.trim_end());
 
-        // find the pattern '$xx\r\n' where xx is numeric in the buffer
-        let mut found = false;
-        for i in 0..peek_size {
-            if buffer[i] == b'$' {
-                let mut length = 0;
-                let mut j = i + 1;
-                while j < peek_size {
-                    if buffer[j] == b'\r' {
-                        found = true;
-                        break;
-                    }
-                    length = length * 10 + (buffer[j] - b'0') as usize;
-                    j += 1;
-                }
-                if found {
-                    #[cfg(debug_assertions)]
-                    println!("found RDB length: {}", length);
-                    
-                    // Pre-allocate buffer with exact size needed
-                    let mut rdb_buffer = vec![0; i + length + 5];
-                    if let Ok(_) = stream.read_exact(&mut rdb_buffer) {
-                        // Zero-copy string conversion for debug output
-                        #[cfg(debug_assertions)]
-                        println!("rdb_file: {}", String::from_utf8_lossy(&rdb_buffer));
-                        
-                        // After RDB transfer, reset the counter to start fresh
-                        if let Ok(redis) = redis.lock() {
-                            redis.bytes_processed.store(0, Ordering::SeqCst);
-                            #[cfg(debug_assertions)]
-                            println!("[RDB] Reset bytes_processed to 0");
-                        }
-                    }
-                    break 'LOOP_PEEK;
-                }
-            }
+        if let Some(length) = line.strip_prefix('$') {
+            break length.trim_end().parse::<usize>()
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid RDB length"))?;
         }
-    }
+    };
+
+    #[cfg(debug_assertions)]
+    println!("found RDB length: {}", length);
+
+    // The RDB payload is not followed by CRLF
+    let mut rdb_buffer = vec![0; length];
+    stream.read_exact(&mut rdb_buffer).await?;
+
+    #[cfg(debug_assertions)]
+    println!("rdb_file: {}", String::from_utf8_lossy(&rdb_buffer));
+
+    // After RDB transfer, reset the counter to start fresh
+    redis.bytes_processed.store(0, Ordering::SeqCst);
+    #[cfg(debug_assertions)]
+    println!("[RDB] Reset bytes_processed to 0");
+    Ok(())
 }
@@ -1,31 +1,34 @@
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use std::thread;
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
 
+use crate::redis::blocking;
 use crate::redis::Redis;
 use crate::redis::storage::{StreamEntry, Storage};
 
 pub struct XReadRequest {
-    pub keys: Vec<String>,
+    /// The database of the client that sent the XREAD.
+    pub db: usize,
+    pub keys: Vec<Bytes>,
     pub ids: Vec<String>,
     pub block: Option<u64>,
     pub count: Option<usize>,
 }
 
 pub struct XReadHandler {
-    redis: Arc<Mutex<Redis>>,
+    redis: Arc<Redis>,
     request: XReadRequest,
 }
 
 impl XReadHandler {
-    pub fn new(redis: Arc<Mutex<Redis>>, request: XReadRequest) -> Self {
+    pub fn new(redis: Arc<Redis>, request: XReadRequest) -> Self {
         XReadHandler {
             redis,
             request,
         }
     }
 
-    pub fn run_loop(&mut self) -> Result<Vec<(String, Vec<StreamEntry>)>, String> {
+    pub async fn run_loop(&mut self) -> Result<Vec<(Bytes, Vec<StreamEntry>)>, String> {
         #[cfg(debug_assertions)]
         println!("\n[XReadHandler::run_loop] Starting with block={:?}, count={:?}", self.request.block, self.request.count);
 
@@ -34,45 +37,29 @@ impl XReadHandler {
         for (i, (key, id)) in self.request.keys.iter().zip(&self.request.ids).enumerate() {
             if id == "$" {
                 // For $ ID, use the last ID in the stream or "0-0" if stream is empty
-                let redis = self.redis.lock().unwrap();
-                concrete_ids[i] = redis.storage.get_last_stream_id(key)
+                concrete_ids[i] = self.redis.db(self.request.db).get_last_stream_id(key)
                     .unwrap_or_else(|| "0-0".to_string());
                 #[cfg(debug_assertions)]
-                println!("[XReadHandler::run_loop] key={}, id={}, concrete_id={}", key, id, concrete_ids[i]);
+                println!("[XReadHandler::run_loop] key={:?}, id={}, concrete_id={}", key, id, concrete_ids[i]);
             }
         }
 
         if let Some(block_ms) = self.request.block {
-            let start_time = Instant::now();
-            
             // block_ms == 0 means block indefinitely
-            let block_duration = if block_ms == 0 {
-                None // No timeout
-            } else {
-                Some(Duration::from_millis(block_ms))
-            };
-            
-            // Keep checking until timeout
-            loop {
-                // Check for timeout first
-                if let Some(timeout) = block_duration {
-                    let elapsed = start_time.elapsed();
-                    if elapsed >= timeout {
-                        #[cfg(debug_assertions)]
-                        println!("[XReadHandler::run_loop] Block timeout reached after {:?}", elapsed);
-                        return Ok(vec![]); // Return empty vec ONLY on timeout
-                    }
-                }
-
-                // Try reading data
-                let results = self.try_read(&concrete_ids)?;
-                if !results.is_empty() {
-                    return Ok(results); // Return non-empty results immediately
+            let timeout = (block_ms > 0).then(|| Duration::from_millis(block_ms));
+            // Woken by XADD in the order the readers blocked, see `blocking`
+            let results = blocking::wait_until(&self.redis, self.request.db, &self.request.keys, timeout, || {
+                match self.try_read(&concrete_ids) {
+                    Ok(results) if results.is_empty() => None,
+                    other => Some(other),
                 }
-
-                // Sleep for a short duration to avoid busy waiting
-                thread::sleep(Duration::from_millis(10));
+            }).await;
+            #[cfg(debug_assertions)]
+            if results.is_none() {
+                println!("[XReadHandler::run_loop] Block timeout reached after {:?}", timeout);
             }
+            // Empty ONLY on timeout
+            results.unwrap_or(Ok(vec![]))
         } else {
             // For non-blocking mode, just try once
             let results = self.try_read(&concrete_ids)?;
@@ -82,10 +69,8 @@ impl XReadHandler {
         }
     }
 
-    fn try_read(&self, ids: &[String]) -> Result<Vec<(String, Vec<StreamEntry>)>, String> {
+    fn try_read(&self, ids: &[String]) -> Result<Vec<(Bytes, Vec<StreamEntry>)>, String> {
         let mut results = Vec::new();
-        let redis = self.redis.lock().unwrap();
-
         for (i, stream_key) in self.request.keys.iter().enumerate() {
             let (ms, seq) = match Storage::parse_stream_id(&ids[i]) {
                 Ok((ms, seq)) => (ms, seq),
@@ -93,7 +78,7 @@ impl XReadHandler {
             };
 
             // Get entries that arrived after the specified ID
-            let entries = redis.storage.get_stream_entries(
+            let entries = self.redis.db(self.request.db).get_stream_entries(
                 stream_key,
                 ms,
                 seq,
@@ -107,8 +92,6 @@ impl XReadHandler {
             }
         }
 
-        drop(redis);
-
         #[cfg(debug_assertions)]
         if !results.is_empty() {
             println!("[XRead] Found {} entries in {} streams", 
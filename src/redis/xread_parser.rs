@@ -1,16 +1,22 @@
+use bytes::Bytes;
+
 #[derive(Debug)]
 pub struct XReadParams {
-    pub keys: Vec<String>,
+    pub keys: Vec<Bytes>,
     pub ids: Vec<String>,
     pub block: Option<u64>,
     pub count: Option<usize>,
 }
 
+fn parse_number<T: std::str::FromStr>(param: &[u8]) -> Option<T> {
+    std::str::from_utf8(param).ok()?.parse().ok()
+}
+
 fn is_stream_id(s: &str) -> bool {
     s == "0" || s == "$" || s.contains('-') || s.parse::<u64>().is_ok()
 }
 
-pub fn parse_xread(params: &[String]) -> Result<XReadParams, String> {
+pub fn parse_xread(params: &[Bytes]) -> Result<XReadParams, String> {
     #[cfg(debug_assertions)]
     println!("\n[XReadParser::parse_xread] Starting with params: {:?}", params);
 
@@ -20,7 +26,7 @@ pub fn parse_xread(params: &[String]) -> Result<XReadParams, String> {
 
     // First find the STREAMS keyword
     for (i, param) in params.iter().enumerate() {
-        if param.eq_ignore_ascii_case("STREAMS") {
+        if param.eq_ignore_ascii_case(b"STREAMS") {
             streams_index = Some(i);
             break;
         }
@@ -37,10 +43,10 @@ pub fn parse_xread(params: &[String]) -> Result<XReadParams, String> {
     // Process parameters before STREAMS
     let mut i = 0;
     while i < streams_pos {
-        match params[i].to_ascii_uppercase().as_str() {
-            "BLOCK" => {
+        match params[i].to_ascii_uppercase().as_slice() {
+            b"BLOCK" => {
                 if i + 1 < streams_pos {
-                    if let Ok(block_value) = params[i + 1].parse::<u64>() {
+                    if let Some(block_value) = parse_number::<u64>(&params[i + 1]) {
                         block = Some(block_value);
                         i += 2;
                     } else {
@@ -50,9 +56,9 @@ pub fn parse_xread(params: &[String]) -> Result<XReadParams, String> {
                     return Err("-ERR syntax error in BLOCK parameter".to_string());
                 }
             },
-            "COUNT" => {
+            b"COUNT" => {
                 if i + 1 < streams_pos {
-                    if let Ok(count_value) = params[i + 1].parse::<usize>() {
+                    if let Some(count_value) = parse_number::<usize>(&params[i + 1]) {
                         count = Some(count_value);
                         i += 2;
                     } else {
@@ -79,13 +85,15 @@ pub fn parse_xread(params: &[String]) -> Result<XReadParams, String> {
 
     // Split remaining parameters into stream names and IDs
     let num_params = remaining.len();
-    if num_params % 2 != 0 {
+    if !num_params.is_multiple_of(2) {
         return Err("-ERR wrong number of arguments for 'xread' command".to_string());
     }
 
     let mid = num_params / 2;
     let stream_names = remaining[..mid].to_vec();
-    let stream_ids = remaining[mid..].to_vec();
+    let stream_ids: Vec<String> = remaining[mid..].iter()
+        .map(|id| String::from_utf8_lossy(id).into_owned())
+        .collect();
 
     #[cfg(debug_assertions)]
     println!("[XReadParser::parse_xread] Split into:");
@@ -0,0 +1,386 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use bytes::Bytes;
+
+/// A score with a total order, so it can key the index. NaN never gets this far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// The value behind a ZSet key: a member -> score map plus an index ordered by
+/// (score, member), which is the order every range command walks in.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    index: BTreeSet<(Score, Bytes)>,
+}
+
+/// One end of a BYSCORE range: `1.5` is inclusive, `(1.5` exclusive; `-inf`/`+inf` are plain scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+/// One end of a BYLEX range: `[a` is inclusive, `(a` exclusive, `-` and `+` are the open ends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeBy {
+    /// Ranks as given, negative ones counting from the end.
+    Rank(i64, i64),
+    /// Always (min, max), also for REV.
+    Score(ScoreBound, ScoreBound),
+    /// Always (min, max), also for REV.
+    Lex(LexBound, LexBound),
+}
+
+/// What ZRANGE and ZRANGESTORE select.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeSpec {
+    pub by: RangeBy,
+    pub rev: bool,
+    /// LIMIT offset count; a negative count means "all the rest".
+    pub limit: Option<(i64, i64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    pub fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN; Redis settles on 0 there.
+            Aggregate::Sum => {
+                let sum = a + b;
+                if sum.is_nan() { 0.0 } else { sum }
+            },
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// ZADD flags. NX/XX and GT/LT/NX combinations are rejected while parsing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ZAddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+    pub incr: bool,
+}
+
+/// Parses a score the way Redis does: any float including `inf`, `+inf` and `-inf`, but not NaN.
+pub fn parse_score(raw: &[u8]) -> Option<f64> {
+    std::str::from_utf8(raw).ok()?
+        .parse::<f64>().ok()
+        .filter(|score| !score.is_nan())
+}
+
+/// Formats a double the way Redis replies with a score, C's `%.17g`: 17 significant digits without
+/// trailing zeros, in exponent form below 1e-4 or from 1e17 up. Infinities are `inf`/`-inf`.
+pub fn format_double(value: f64) -> Bytes {
+    if value.is_infinite() {
+        return Bytes::from_static(if value > 0.0 { b"inf" } else { b"-inf" });
+    }
+    // Rounded to 17 digits first, since rounding can carry into the exponent
+    let scientific = format!("{:.16e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let trim = |digits: &str| match digits.contains('.') {
+        true => digits.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => digits.to_string(),
+    };
+    let formatted = if !(-4..17).contains(&exponent) {
+        format!("{}e{}{:02}", trim(mantissa), if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        trim(&format!("{:.*}", (16 - exponent) as usize, value))
+    };
+    Bytes::from(formatted)
+}
+
+impl ScoreBound {
+    pub fn parse(raw: &[u8]) -> Option<ScoreBound> {
+        match raw.strip_prefix(b"(") {
+            Some(rest) => parse_score(rest).map(ScoreBound::Exclusive),
+            None => parse_score(raw).map(ScoreBound::Inclusive),
+        }
+    }
+
+    fn above_min(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(min) => score >= min,
+            ScoreBound::Exclusive(min) => score > min,
+        }
+    }
+
+    fn below_max(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+impl LexBound {
+    pub fn parse(raw: &[u8]) -> Option<LexBound> {
+        match raw {
+            b"-" => Some(LexBound::Min),
+            b"+" => Some(LexBound::Max),
+            [b'[', rest @ ..] => Some(LexBound::Inclusive(Bytes::copy_from_slice(rest))),
+            [b'(', rest @ ..] => Some(LexBound::Exclusive(Bytes::copy_from_slice(rest))),
+            _ => None,
+        }
+    }
+
+    fn above_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => member >= &min[..],
+            LexBound::Exclusive(min) => member > &min[..],
+        }
+    }
+
+    fn below_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+        }
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds `member` or moves it to `score`. Returns true if it is new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        // -0.0 and 0.0 are the same score to clients, keep them the same in the index too.
+        let score = score + 0.0;
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.index.remove(&(Score(old), member.clone()));
+                self.index.insert((Score(score), member));
+                false
+            },
+            None => {
+                self.index.insert((Score(score), member));
+                true
+            },
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.index.remove(&(Score(score), member));
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// 0-based position of `member`, from the lowest score or, with `rev`, the highest.
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let below = self.index.range(..(Score(score), Bytes::copy_from_slice(member))).count();
+        Some(if rev { self.len() - 1 - below } else { below })
+    }
+
+    /// Members with `min <= score < max`, lowest score first, found through the index.
+    pub fn scores_between(&self, min: f64, max: f64) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.index.range((Score(min), Bytes::new())..)
+            .take_while(move |(score, _)| score.0 < max)
+            .map(|(score, member)| (member, score.0))
+    }
+
+    /// Members with their scores, lowest score first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.index.iter().map(|(score, member)| (member, score.0))
+    }
+
+    pub fn count(&self, min: ScoreBound, max: ScoreBound) -> usize {
+        self.iter()
+            .skip_while(|(_, score)| !min.above_min(*score))
+            .take_while(|(_, score)| max.below_max(*score))
+            .count()
+    }
+
+    pub fn range(&self, spec: &RangeSpec) -> Vec<(Bytes, f64)> {
+        let selected: Vec<(&Bytes, f64)> = match &spec.by {
+            RangeBy::Rank(start, stop) => {
+                let len = self.len() as i64;
+                let start = if *start < 0 { (len + start).max(0) } else { *start };
+                let stop = if *stop < 0 { len + stop } else { (*stop).min(len - 1) };
+                if start > stop || start >= len {
+                    return vec![];
+                }
+                let (skip, take) = (start as usize, (stop - start + 1) as usize);
+                if spec.rev {
+                    self.iter().rev().skip(skip).take(take).collect()
+                } else {
+                    self.iter().skip(skip).take(take).collect()
+                }
+            },
+            RangeBy::Score(min, max) => {
+                let in_range = self.iter()
+                    .skip_while(|(_, score)| !min.above_min(*score))
+                    .take_while(|(_, score)| max.below_max(*score));
+                let mut selected: Vec<_> = in_range.collect();
+                if spec.rev {
+                    selected.reverse();
+                }
+                selected
+            },
+            RangeBy::Lex(min, max) => {
+                let mut selected: Vec<_> = self.iter()
+                    .filter(|(member, _)| min.above_min(member) && max.below_max(member))
+                    .collect();
+                if spec.rev {
+                    selected.reverse();
+                }
+                selected
+            },
+        };
+
+        let selected = selected.into_iter().map(|(member, score)| (member.clone(), score));
+        match spec.limit {
+            Some((offset, _)) if offset < 0 => vec![],
+            Some((offset, count)) if count >= 0 => selected.skip(offset as usize).take(count as usize).collect(),
+            Some((offset, _)) => selected.skip(offset as usize).collect(),
+            None => selected.collect(),
+        }
+    }
+
+    /// Removes and returns up to `count` members with the lowest (or, with `max`, highest) scores.
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<(Bytes, f64)> {
+        let mut popped = Vec::new();
+        while popped.len() < count {
+            let next = if max { self.index.pop_last() } else { self.index.pop_first() };
+            match next {
+                Some((score, member)) => {
+                    self.scores.remove(&member);
+                    popped.push((member, score.0));
+                },
+                None => break,
+            }
+        }
+        popped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zset(items: &[(&'static str, f64)]) -> SortedSet {
+        let mut zset = SortedSet::new();
+        for (member, score) in items {
+            zset.insert(Bytes::from_static(member.as_bytes()), *score);
+        }
+        zset
+    }
+
+    fn members(range: Vec<(Bytes, f64)>) -> Vec<Bytes> {
+        range.into_iter().map(|(member, _)| member).collect()
+    }
+
+    #[test]
+    fn test_format_double_like_printf() {
+        for (value, formatted) in [
+            (10.0, "10"), (-2.5, "-2.5"), (0.1 + 0.2, "0.30000000000000004"), (1e300, "1.0000000000000001e+300"),
+            (1e17, "1e+17"), (1e16, "10000000000000000"), (0.0001, "0.0001"), (0.00001, "1.0000000000000001e-05"),
+            (3479099956230698.0, "3479099956230698"), (f64::INFINITY, "inf"), (f64::NEG_INFINITY, "-inf"),
+        ] {
+            assert_eq!(format_double(value), formatted);
+        }
+    }
+
+    #[test]
+    fn test_order_rank_and_update() {
+        let mut set = zset(&[("c", 3.0), ("a", 1.0), ("b", 1.0)]);
+        assert_eq!(members(set.range(&RangeSpec { by: RangeBy::Rank(0, -1), rev: false, limit: None })),
+            vec!["a", "b", "c"]);
+        assert_eq!(set.rank(b"b", false), Some(1));
+        assert_eq!(set.rank(b"b", true), Some(1));
+        assert_eq!(set.rank(b"c", true), Some(0));
+
+        assert!(!set.insert(Bytes::from_static(b"c"), 0.5));
+        assert_eq!(set.rank(b"c", false), Some(0));
+        assert_eq!(set.len(), 3);
+        assert!(set.remove(b"c"));
+        assert!(!set.remove(b"c"));
+        assert_eq!(set.rank(b"c", false), None);
+    }
+
+    #[test]
+    fn test_score_and_lex_ranges() {
+        let set = zset(&[("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", f64::INFINITY)]);
+        let by_score = |min: &[u8], max: &[u8], rev| RangeSpec {
+            by: RangeBy::Score(ScoreBound::parse(min).unwrap(), ScoreBound::parse(max).unwrap()),
+            rev,
+            limit: None,
+        };
+        assert_eq!(members(set.range(&by_score(b"(1", b"3", false))), vec!["b", "c"]);
+        assert_eq!(members(set.range(&by_score(b"-inf", b"+inf", true))), vec!["d", "c", "b", "a"]);
+        assert_eq!(set.count(ScoreBound::Inclusive(2.0), ScoreBound::Exclusive(f64::INFINITY)), 2);
+
+        let lex = zset(&[("apple", 0.0), ("banana", 0.0), ("cherry", 0.0)]);
+        let spec = RangeSpec {
+            by: RangeBy::Lex(LexBound::parse(b"[b").unwrap(), LexBound::parse(b"+").unwrap()),
+            rev: false,
+            limit: Some((1, 5)),
+        };
+        assert_eq!(members(lex.range(&spec)), vec!["cherry"]);
+        assert_eq!(LexBound::parse(b"b"), None);
+    }
+
+    #[test]
+    fn test_pop() {
+        let mut set = zset(&[("a", 1.0), ("b", 2.0), ("c", 3.0)]);
+        assert_eq!(members(set.pop(2, true)), vec!["c", "b"]);
+        assert_eq!(members(set.pop(5, false)), vec!["a"]);
+        assert!(set.is_empty());
+    }
+}
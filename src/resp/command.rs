@@ -1,25 +1,32 @@
+use bytes::Bytes;
+use crate::redis::RedisCommand;
+
+/// A complete request as it came off the wire: the arguments, and the raw bytes they were
+/// parsed from (needed to propagate write commands to replicas verbatim).
+#[derive(Debug)]
 pub struct Command {
-    pub buffer_start: usize,
-    pub buffer_end: usize,
-    pub command: String,
-    pub data: Vec<String>,
-    pub num_params: u8
+    pub raw: Bytes,
+    pub args: Vec<Bytes>,
 }
 
 impl Command {
-    pub const EMPTY_STR: &'static str = "";
+    /// Number of bytes the request took on the wire.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
 
-    pub fn new(num_params:u8, buffer_start: usize) -> Self {
-        Command {
-            command: String::new(),
-            data: Vec::with_capacity(num_params as usize),
-            buffer_start,
-            buffer_end: 0,
-            num_params
-        }
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
     }
 
-    pub fn push_param(&mut self, param: String) {
-        self.data.push(param);
+    pub fn into_redis_command(self) -> RedisCommand {
+        let name = String::from_utf8_lossy(&self.args[0]).into_owned();
+        match RedisCommand::data(name.clone(), &self.args[1..], self.raw) {
+            Some(command) => command,
+            None => RedisCommand::Error {
+                message: format!("ERR wrong number of arguments for '{}' command", name.to_ascii_lowercase()),
+            },
+        }
     }
 }
@@ -1,242 +1,214 @@
-use std::borrow::Cow;
+use std::fmt;
+use bytes::{Buf, BytesMut};
 use super::state::RespState;
 use super::command::Command;
-use crate::redis::RedisCommand;
-use crate::process_command;
 
+/// Malformed input. The connection can't be resynchronized after one of these,
+/// so the client gets the error and is disconnected.
+#[derive(Debug, PartialEq)]
+pub struct ProtocolError(pub String);
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERR Protocol error: {}", self.0)
+    }
+}
+
+/// Incremental RESP state machine. Bytes are appended as they arrive and every complete
+/// request is cut off the front of the buffer; a partial request stays buffered (together
+/// with the progress made on it) until the rest shows up.
 pub struct Context {
-    pub buffer: Vec<u8>,
-    pub read_len: usize,
-    pub current_pos: usize,
-    pub resp_state: RespState,
-    pub data_length: usize,
-    pub current_command: Option<Command>,
-    pub command_index: u8,
-    pub commands: Vec<RedisCommand>,
-    pub error_reason: Cow<'static, str>,
+    buffer: BytesMut,
+    // how far into the current request we have parsed
+    current_pos: usize,
+    resp_state: RespState,
+    params_left: usize,
+    // argument ranges within the current request
+    params: Vec<(usize, usize)>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Context {
-    pub const NO_ERROR: &'static str = "NO_ERROR";
-    pub const PARSE_ERROR: &'static str = "PARSE_ERROR";
-    pub const STATE_ERROR: &'static str = "STATE_ERROR";
-
-    // const EMPTY_STR: &'static str = "";
-
-    pub fn char_asterisk(&mut self) {
-        // parse the array length, move pointer to the end of the array length, change state to Idle
-        match self.resp_state {
-            RespState::Idle => {
-                // expect the next character to be numeric, if not, throw an error.
-                // after that just fast forward to the end of the array, or the end of carriage return + endline.
-                self.current_pos += 1;
-                let startpos = self.current_pos;
-                while self.buffer[self.current_pos].is_ascii_digit() {
-                    self.current_pos += 1;
-                }
-                // parse length of the array.
-                // the length of the array is the number of params in current command.
-                let num_params: u8 = std::str::from_utf8(&self.buffer[startpos..self.current_pos]).unwrap().parse().unwrap();
-                // for every *n, create a new command object.
-                // number of params is n-1 because the first param is the command itself.
-                let mut current_command = Command::new(num_params-1, startpos-1);
-                // check for end of buffer
-                if self.current_pos == self.read_len {
-                    self.resp_state = RespState::End;
-                    current_command.buffer_end = self.current_pos;
-                    self.current_command = Some(current_command);
-                    return;
-                }
-                // when we first encounter a non-numeric character, we should expect a carriage return
-                // otherwise assume it's the end of operation (because whatever character there might be leftover
-                // from previous stream operation)
-                if self.buffer[self.current_pos] == b'\r' {
-                    if self.buffer[self.current_pos + 1] == b'\n' {
-                        self.current_pos += 2;
-                        self.resp_state = RespState::Idle;
-                        current_command.buffer_end = self.current_pos;
-                        self.current_command = Some(current_command);
-                        return;
-                    } else {
-                        self.error_reason = Cow::Borrowed(Context::PARSE_ERROR);
-                        return;
-                    }
-                } else {
-                    self.resp_state = RespState::End;
-                    current_command.buffer_end = self.current_pos;
-                    self.current_command = Some(current_command);
-                    return;
-                }
-            },
-            _ => self.error_reason = Cow::Borrowed(Context::STATE_ERROR),
+    // Same limits as Redis
+    pub const MAX_INLINE_LEN: usize = 64 * 1024;
+    pub const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+    pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+    pub fn new() -> Self {
+        Context {
+            buffer: BytesMut::new(),
+            current_pos: 0,
+            resp_state: RespState::Idle,
+            params_left: 0,
+            params: Vec::new(),
         }
     }
 
-    pub fn char_dollar(&mut self) {
-        match self.resp_state {
-            RespState::Idle => {
-                // expect the next character to be numeric, if not, throw an error.
-                // after that expect the next 2 characters to be carriage return + endline,
-                // then parse the bulk data for the length of the data,
-                // create command object, and move pointer at the end of the data
-                self.current_pos += 1;
-                let startpos = self.current_pos;
-                while self.buffer[self.current_pos].is_ascii_digit() {
-                    self.current_pos += 1;
-                }
-                // parse length of the data
-                let length = std::str::from_utf8(&self.buffer[startpos..self.current_pos]).unwrap().parse().unwrap();
-                self.data_length = length;
-
-                // when we first encounter a non-numeric character, we should expect a carriage return
-                if self.buffer[self.current_pos] == b'\r' && self.buffer[self.current_pos + 1] == b'\n' {
-                    self.current_pos += 2;
-                    self.resp_state = RespState::BulkData;
-                } else {
-                    self.resp_state = RespState::Error;
-                    self.error_reason = Cow::Owned(format!("parse error at position {}", self.current_pos));
-                    return;
-                }
-
-                // parse bulk data
-                match self.resp_state {
-                    RespState::BulkData { .. } => {
-                        let mut endpos = self.current_pos + self.data_length;
-                        if endpos >= self.read_len {
-                            endpos = self.read_len - 1;
-                        }
-                        let data = std::str::from_utf8(&self.buffer[self.current_pos..endpos]).unwrap().to_string();
-
-                        { // enclosing the block to limit the scope of mutable borrow
-                            let command = match self.current_command {
-                                Some(ref mut command) => {
-                                    command
-                                },
-                                None => {
-                                    panic!("Current command not initialized when parsing bulk data");
-                                }
-                            };
-                            if command.command.is_empty() {
-                                command.command = data;
-                            } else {
-                                command.push_param(data);
-                            }
-                        }
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// True when bytes of an unfinished request are buffered.
+    #[allow(dead_code)]
+    pub fn has_partial(&self) -> bool {
+        !self.buffer.is_empty()
+    }
 
-                        // after this, expect the next to be carriage return + endline and then move the pointer.
-                        // if not, it's end of operation.
-                        if self.buffer[endpos] == b'\r' && self.buffer[endpos + 1] == b'\n' {
-                            self.current_pos = endpos + 2;
-                            self.resp_state = RespState::Idle;
-                        } else {
-                            self.resp_state = RespState::End;
+    /// Returns the next complete request, `Ok(None)` if more bytes are needed.
+    pub fn next_command(&mut self) -> Result<Option<Command>, ProtocolError> {
+        loop {
+            match self.resp_state {
+                RespState::Idle => {
+                    if self.buffer.is_empty() {
+                        return Ok(None);
+                    }
+                    if self.buffer[0] == b'*' {
+                        if !self.char_asterisk()? {
+                            return Ok(None);
                         }
-                        match self.current_command {
-                            Some(ref mut command) => {
-                                command.buffer_end = self.current_pos;
-                            },
-                            None => {
-                            }
-                        };
-
-                        // check if we have read all params.
-                        // if we have, convert to RedisCommand and push to vector.
-
-                        if let Some(command) = &self.current_command {
-                            if command.data.iter().filter(|&x| x != &Command::EMPTY_STR).count() == command.num_params as usize {
-                                // TODO encapsulate into inline function or macro
-                                match &self.current_command {
-                                    Some(command) => {
-                                        let command_ = command.command.to_string();
-                                        let params: Vec<String> = command.data.iter().map(|s| s.to_string()).collect();
-                                        let original_resp = std::str::from_utf8(&self.buffer[command.buffer_start..command.buffer_end]).unwrap().to_string();
-                                        match RedisCommand::data(command_, &params, original_resp) {
-                                            Some(redis_command) => {
-                                                self.commands.push(redis_command);
-                                                self.current_command = None;
-                                                self.command_index += 1;
-                                            },
-                                            None => {
-                                                // None we just continue...
-                                                // it means the command is not complete, maybe missing parameter.
-                                            }
-                                        }
-                                    },
-                                    None => {
-                                        // $self.error_reason = Context::STATE_ERROR.to_string();
-                                    }
-                                }
-                            }
+                    } else {
+                        match self.handle_data_non_resp()? {
+                            Some(Some(command)) => return Ok(Some(command)),
+                            // blank line, look at what comes after it
+                            Some(None) => continue,
+                            None => return Ok(None),
                         }
-                    },
-                    _ => {
-                        self.error_reason = Cow::Borrowed(Context::STATE_ERROR);
-                        return;
                     }
-                }
-            },
-            _ => self.error_reason = Cow::Borrowed(Context::STATE_ERROR),
+                },
+                RespState::BulkDef => {
+                    if !self.char_dollar()? {
+                        return Ok(None);
+                    }
+                },
+                RespState::BulkData(length) => {
+                    if !self.bulk_data(length)? {
+                        return Ok(None);
+                    }
+                    if self.params_left == 0 {
+                        return Ok(Some(self.take_command()));
+                    }
+                },
+            }
         }
     }
 
-    pub fn char_carriage_return(&mut self) {
-        match self.resp_state {
-            RespState::Idle => {
-                // expect the next character to be endline, if not, throw an error.
-                // after that, move the pointer to the end of the endline, and change state to Idle
-                if self.buffer[self.current_pos + 1] == b'\n' {
-                    self.current_pos += 1;
-                    self.resp_state = RespState::Idle;
-                } else {
-                    self.error_reason = Cow::Borrowed(Context::PARSE_ERROR);
-                }
-            },
-            _ => self.error_reason = Cow::Borrowed(Context::STATE_ERROR),
-        }
+    /// Position of the CRLF terminating the line that starts at `from`.
+    fn find_line_end(&self, from: usize) -> Option<usize> {
+        self.buffer[from..].windows(2).position(|w| w == b"\r\n").map(|i| from + i)
     }
 
-    pub fn handle_data_non_resp(&mut self) {
-        // we should parse "SET key00 val00" as a single command,
-        // resulting in command = "SET", data = "key00 val00".
-        // we iterate the buffer and split the command and data by the first space character.
-        // first we should validate that the string is not in RESP format.
-        // we do this by validating that the first character is not an asterisk or dollar sign,
-        // and pointer is at pos 0.
-
-        // we don't now how many params we have, so we set it to 0.
-        let mut command  = Command::new(0, 0);
-
-        if self.current_pos == 0 && (self.buffer[self.current_pos] != b'*' && self.buffer[self.current_pos] != b'$') {
-            let mut startpos = self.current_pos;
-            // let mut first_space = false;
-            loop {
-                if self.current_pos == self.read_len ||
-                    self.buffer[self.current_pos] == b' ' ||
-                    self.buffer[self.current_pos] == b'\r' ||
-                    self.buffer[self.current_pos] == b'\n' {
-                    let data = std::str::from_utf8(&self.buffer[startpos..self.current_pos]).unwrap().to_string();
-                    // first_space = true;
-                    if command.command.is_empty() {
-                        command.command = data;
-                    } else {
-                        command.push_param(data);
-                    }
+    fn parse_length(&self, start: usize, end: usize) -> Option<i64> {
+        std::str::from_utf8(&self.buffer[start..end]).ok()?.parse().ok()
+    }
 
-                    if self.current_pos >= self.read_len {
-                        break;
-                    }
-                    startpos = self.current_pos + 1;
-                }
+    /// `*<n>\r\n` opens a request of n bulk strings. Returns false if the line is incomplete.
+    fn char_asterisk(&mut self) -> Result<bool, ProtocolError> {
+        let Some(end) = self.find_line_end(1) else {
+            if self.buffer.len() > Self::MAX_INLINE_LEN {
+                return Err(ProtocolError("too big mbulk count string".to_string()));
+            }
+            return Ok(false);
+        };
+        let num_params = match self.parse_length(1, end) {
+            Some(n) if n <= Self::MAX_MULTIBULK_LEN as i64 => n,
+            _ => return Err(ProtocolError("invalid multibulk length".to_string())),
+        };
+
+        if num_params <= 0 {
+            // empty and null arrays are skipped, like Redis does
+            self.buffer.advance(end + 2);
+            return Ok(true);
+        }
 
-                self.current_pos += 1;
+        self.current_pos = end + 2;
+        self.params_left = num_params as usize;
+        self.params = Vec::with_capacity(self.params_left);
+        self.resp_state = RespState::BulkDef;
+        Ok(true)
+    }
+
+    /// `$<len>\r\n` announces the next argument. Returns false if the line is incomplete.
+    fn char_dollar(&mut self) -> Result<bool, ProtocolError> {
+        if self.current_pos >= self.buffer.len() {
+            return Ok(false);
+        }
+        if self.buffer[self.current_pos] != b'$' {
+            return Err(ProtocolError(format!("expected '$', got '{}'", self.buffer[self.current_pos] as char)));
+        }
+        let Some(end) = self.find_line_end(self.current_pos + 1) else {
+            if self.buffer.len() - self.current_pos > Self::MAX_INLINE_LEN {
+                return Err(ProtocolError("too big bulk count string".to_string()));
             }
+            return Ok(false);
+        };
+        let length = match self.parse_length(self.current_pos + 1, end) {
+            Some(n) if (0..=Self::MAX_BULK_LEN as i64).contains(&n) => n as usize,
+            _ => return Err(ProtocolError("invalid bulk length".to_string())),
+        };
+
+        self.current_pos = end + 2;
+        self.resp_state = RespState::BulkData(length);
+        Ok(true)
+    }
 
-            command.buffer_end = self.current_pos;
-            self.current_command = Some(command);
+    /// The payload of a bulk string; it may contain any bytes, CRLF included.
+    fn bulk_data(&mut self, length: usize) -> Result<bool, ProtocolError> {
+        let end = self.current_pos + length;
+        if self.buffer.len() < end + 2 {
+            self.buffer.reserve(end + 2 - self.buffer.len());
+            return Ok(false);
+        }
+        if &self.buffer[end..end + 2] != b"\r\n" {
+            return Err(ProtocolError("bulk data not terminated by CRLF".to_string()));
+        }
+
+        self.params.push((self.current_pos, end));
+        self.params_left -= 1;
+        self.current_pos = end + 2;
+        self.resp_state = RespState::BulkDef;
+        Ok(true)
+    }
+
+    fn take_command(&mut self) -> Command {
+        let raw = self.buffer.split_to(self.current_pos).freeze();
+        let args = self.params.drain(..).map(|(start, end)| raw.slice(start..end)).collect();
+        self.current_pos = 0;
+        self.resp_state = RespState::Idle;
+        Command { raw, args }
+    }
+
+    /// Inline requests such as `PING\r\n` or `SET key00 val00\r\n`, split on whitespace.
+    /// Returns None if the line is incomplete, Some(None) for a blank line.
+    fn handle_data_non_resp(&mut self) -> Result<Option<Option<Command>>, ProtocolError> {
+        let Some(end) = self.buffer.iter().position(|&b| b == b'\n') else {
+            if self.buffer.len() > Self::MAX_INLINE_LEN {
+                return Err(ProtocolError("too big inline request".to_string()));
+            }
+            return Ok(None);
+        };
+
+        let raw = self.buffer.split_to(end + 1).freeze();
+        let mut args = Vec::new();
+        let mut start = None;
+        for (i, byte) in raw.iter().enumerate() {
+            match (byte.is_ascii_whitespace(), start) {
+                (false, None) => start = Some(i),
+                (true, Some(s)) => {
+                    args.push(raw.slice(s..i));
+                    start = None;
+                },
+                _ => {},
+            }
+        }
 
-            process_command!(self);
-        } else {
-            self.error_reason = Cow::Borrowed(Context::PARSE_ERROR);
+        if args.is_empty() {
+            return Ok(Some(None));
         }
+        Ok(Some(Some(Command { raw, args })))
     }
 }
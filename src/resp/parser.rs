@@ -1,55 +1,32 @@
-use std::borrow::Cow;
-use super::context::Context;
-use super::state::RespState;
+use super::context::{Context, ProtocolError};
 use crate::redis::RedisCommand;
-use crate::process_command;
 
-// parse RESP using a simple state machine algorithm with zero-copy optimizations
-pub fn parse_resp(buffer: &[u8], len: usize) -> Vec<RedisCommand> {
-    let mut context = Context {
-        buffer: buffer.to_vec(),
-        read_len: len,
-        current_pos: 0,
-        resp_state: RespState::Idle,
-        data_length: 0,
-        current_command: None,
-        command_index: 0,
-        commands: Vec::new(),
-        error_reason: Cow::Borrowed(Context::NO_ERROR),
-    };
+/// Parses a buffer holding complete requests. A trailing inline request doesn't need its
+/// newline. Connections use `Context` directly, which also copes with requests split across reads.
+#[allow(dead_code)]
+pub fn parse_resp(buffer: &[u8], len: usize) -> Result<Vec<RedisCommand>, ProtocolError> {
+    let mut context = Context::new();
+    context.extend(&buffer[..len]);
 
-    while context.current_pos < context.read_len {
-        if context.error_reason != Context::NO_ERROR || matches!(context.resp_state, RespState::End) {
-            break;
-        }
-        // end if length > buffer length or end of string length
-        if context.current_pos >= context.read_len {
-            break;
-        }
-
-        match context.buffer[context.current_pos] {
-            b'*' => context.char_asterisk(),
-            b'$' => context.char_dollar(),
-            b'\r' => context.char_carriage_return(),
-            _ => {
-                context.handle_data_non_resp();
-                break;
-            },
-        }
+    let mut commands = Vec::new();
+    while let Some(command) = context.next_command()? {
+        commands.push(command.into_redis_command());
     }
 
-    if context.error_reason != Context::NO_ERROR {
-        panic!("Error parsing RESP: {}", context.error_reason);
+    if context.has_partial() && buffer[0] != b'*' {
+        context.extend(b"\r\n");
+        if let Some(command) = context.next_command()? {
+            commands.push(command.into_redis_command());
+        }
     }
 
-    process_command!(context);
-
-    context.commands
+    Ok(commands)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::context::Context;
 
     macro_rules! commands_len  {
         ($commands:expr) => {
@@ -64,7 +41,7 @@ mod tests {
     #[test]
     fn test_resp_command_pair() {
         let buffer = b"*2\r\n$4\r\nECHO\r\n$4\r\nHOLA\r\n";
-        let commands = parse_resp(buffer,24);
+        let commands = parse_resp(buffer,24).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match &commands[0] {
             RedisCommand::Echo { data } => assert_eq!(data, "HOLA"), 
@@ -75,7 +52,7 @@ mod tests {
     #[test]
     fn test_resp_command_single() {
         let buffer = b"*1\r\n$4\r\nping\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match commands[0] {
             RedisCommand::Ping => (),
@@ -86,10 +63,10 @@ mod tests {
     #[test]
     fn test_resp_set_command() {
         let buffer = b"*3\r\n$3\r\nSET\r\n$5\r\nkey01\r\n$5\r\nval01\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => { 
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => { 
                 assert_eq!(key, "key01");
                 assert_eq!(value, "val01");
                 assert_eq!(*ttl, None);
@@ -102,10 +79,10 @@ mod tests {
     #[test]
     fn test_resp_set_ttl_and_echo() {
         let buffer = b"*5\r\n$3\r\nSET\r\n$5\r\nkey01\r\n$5\r\nval01\r\n$2\r\nex\r\n$2\r\n60\r\n*2\r\n$4\r\nECHO\r\n$5\r\nHELLO\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 2);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key01");
                 assert_eq!(value, "val01");
                 assert_eq!(*ttl, Some(60000));
@@ -122,10 +99,10 @@ mod tests {
     #[test]
     fn test_resp_five_commands_various_types_with_invalid() {
         let buffer = b"*3\r\n$3\r\nSET\r\n$5\r\nkey01\r\n$5\r\nval01\r\n*2\r\n$3\r\nGET\r\n$5\r\nkey01\r\n*2\r\n$4\r\nECHO\r\n$5\r\nHELLO\r\n*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nFAKE\r\n$5\r\nPARAM\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 5);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key01");
                 assert_eq!(value, "val01");
                 assert_eq!(*ttl, None);
@@ -156,10 +133,10 @@ mod tests {
     #[test]
     fn test_resp_six_commands_various_types_all_valid() {
         let buffer = b"*3\r\n$3\r\nSET\r\n$5\r\nkey01\r\n$5\r\nval01\r\n*2\r\n$3\r\nGET\r\n$5\r\nkey01\r\n*2\r\n$4\r\nECHO\r\n$5\r\nHELLO\r\n*1\r\n$4\r\nPING\r\n*5\r\n$3\r\nSET\r\n$5\r\nkey02\r\n$5\r\nval02\r\n$2\r\nEX\r\n$2\r\n60\r\n*2\r\n$3\r\nGET\r\n$5\r\nkey02\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 6);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key01");
                 assert_eq!(value, "val01");
                 assert_eq!(*ttl, None);
@@ -180,7 +157,7 @@ mod tests {
             _ => panic!("Invalid command"),
         }
         match &commands[4] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key02");
                 assert_eq!(value, "val02");
                 assert_eq!(*ttl, Some(60000));
@@ -197,7 +174,7 @@ mod tests {
     #[test]
     fn test_non_resp_single() {
         let buffer = b"PING\r\n";
-        let commands = parse_resp(buffer,6);
+        let commands = parse_resp(buffer,6).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match commands[0] {
             RedisCommand::Ping => (),
@@ -208,7 +185,7 @@ mod tests {
     #[test]
     fn test_non_resp_single_no_ending_whitespace() {
         let buffer = b"PING";
-        let commands = parse_resp(buffer,4);
+        let commands = parse_resp(buffer,4).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match commands[0] {
             RedisCommand::Ping => (),
@@ -219,10 +196,10 @@ mod tests {
     #[test]
     fn test_non_resp_pair() {
         let buffer = b"SET key00 val00\r\n";
-        let commands = parse_resp(buffer,17);
+        let commands = parse_resp(buffer,17).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key00");
                 assert_eq!(value, "val00");
                 assert_eq!(*ttl, None);
@@ -235,10 +212,10 @@ mod tests {
     #[test]
     fn test_resp_set_px() {
         let buffer = b"*5\r\n$3\r\nSET\r\n$5\r\nkey01\r\n$5\r\nval01\r\n$2\r\nPX\r\n$4\r\n1500\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key01");
                 assert_eq!(value, "val01");
                 assert_eq!(*ttl, Some(1500));
@@ -251,10 +228,10 @@ mod tests {
     #[test]
     fn test_resp_set_ex() {
         let buffer = b"*5\r\n$3\r\nSET\r\n$5\r\nkey02\r\n$5\r\nval02\r\n$2\r\nEX\r\n$2\r\n60\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match &commands[0] {
-            RedisCommand::Set { key, value, ttl, original_resp } => {
+            RedisCommand::Set { key, value, ttl, original_resp, .. } => {
                 assert_eq!(key, "key02");
                 assert_eq!(value, "val02");
                 assert_eq!(*ttl, Some(60000));
@@ -267,10 +244,10 @@ mod tests {
     #[test]
     fn test_resp_flushdb() {
         let buffer = b"*1\r\n$7\r\nFLUSHDB\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match commands[0] {
-            RedisCommand::FlushDB => (),
+            RedisCommand::FlushDB { .. } => (),
             _ => panic!("Invalid command"),
         }
     }
@@ -278,11 +255,118 @@ mod tests {
     #[test]
     fn test_non_resp_flushdb() {
         let buffer = b"FLUSHDB\r\n";
-        let commands = parse_resp(buffer, buffer.len());
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
         assert_eq!(commands_len!(commands), 1);
         match commands[0] {
-            RedisCommand::FlushDB => (),
+            RedisCommand::FlushDB { .. } => (),
+            _ => panic!("Invalid command"),
+        }
+    }
+
+    #[test]
+    fn test_resp_frame_split_across_reads() {
+        let frame = b"*3\r\n$3\r\nSET\r\n$5\r\nkey01\r\n$5\r\nval01\r\n";
+        let mut context = Context::new();
+        // feed one byte at a time, nothing comes out until the last one
+        for (i, byte) in frame.iter().enumerate() {
+            context.extend(&[*byte]);
+            let command = context.next_command().unwrap();
+            if i + 1 < frame.len() {
+                assert!(command.is_none(), "complete command after {} bytes", i + 1);
+            } else {
+                let command = command.unwrap();
+                assert_eq!(command.len(), frame.len());
+                match command.into_redis_command() {
+                    RedisCommand::Set { key, value, .. } => {
+                        assert_eq!(key, "key01");
+                        assert_eq!(value, "val01");
+                    },
+                    _ => panic!("Invalid command"),
+                }
+            }
+        }
+        assert!(!context.has_partial());
+    }
+
+    #[test]
+    fn test_resp_pipelined_with_trailing_partial() {
+        let mut context = Context::new();
+        context.extend(b"*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nECHO\r\n$5\r\nHEL");
+        assert!(matches!(context.next_command().unwrap().unwrap().into_redis_command(), RedisCommand::Ping));
+        assert!(context.next_command().unwrap().is_none());
+        assert!(context.has_partial());
+
+        context.extend(b"LO\r\n");
+        match context.next_command().unwrap().unwrap().into_redis_command() {
+            RedisCommand::Echo { data } => assert_eq!(data, "HELLO"),
+            _ => panic!("Invalid command"),
+        }
+    }
+
+    #[test]
+    fn test_resp_binary_bulk_data() {
+        let mut context = Context::new();
+        context.extend(b"*2\r\n$4\r\nECHO\r\n$6\r\na\r\n\0\xffb\r\n");
+        let command = context.next_command().unwrap().unwrap();
+        assert_eq!(&command.args[1][..], b"a\r\n\0\xffb");
+    }
+
+    #[test]
+    fn test_resp_more_than_255_arguments() {
+        let mut buffer = format!("*{}\r\n$5\r\nRPUSH\r\n$4\r\nlist\r\n", 302).into_bytes();
+        for i in 0..300 {
+            let value = i.to_string();
+            buffer.extend_from_slice(format!("${}\r\n{}\r\n", value.len(), value).as_bytes());
+        }
+        let mut context = Context::new();
+        context.extend(&buffer);
+        let command = context.next_command().unwrap().unwrap();
+        assert_eq!(command.args.len(), 302);
+        assert_eq!(&command.args[301][..], b"299");
+    }
+
+    #[test]
+    fn test_resp_large_bulk_length() {
+        let value = "x".repeat(100_000);
+        let buffer = format!("*2\r\n$4\r\nECHO\r\n${}\r\n{}\r\n", value.len(), value);
+        let commands = parse_resp(buffer.as_bytes(), buffer.len()).unwrap();
+        match &commands[0] {
+            RedisCommand::Echo { data } => assert_eq!(data.len(), 100_000),
+            _ => panic!("Invalid command"),
+        }
+    }
+
+    #[test]
+    fn test_resp_wrong_number_of_arguments() {
+        let buffer = b"*1\r\n$3\r\nGET\r\n";
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
+        match &commands[0] {
+            RedisCommand::Error { message } => assert_eq!(message, "ERR wrong number of arguments for 'get' command"),
             _ => panic!("Invalid command"),
         }
     }
+
+    #[test]
+    fn test_resp_protocol_errors() {
+        let cases: [&[u8]; 4] = [
+            b"*abc\r\n",
+            b"*1\r\n+PING\r\n",
+            b"*1\r\n$-5\r\n",
+            b"*1\r\n$4\r\nPINGXX",
+        ];
+        for case in cases {
+            let mut context = Context::new();
+            context.extend(case);
+            let error = context.next_command().unwrap_err();
+            assert!(error.to_string().starts_with("ERR Protocol error"), "{:?} -> {}", case, error);
+        }
+    }
+
+    #[test]
+    fn test_resp_empty_array_and_blank_lines_are_skipped() {
+        let buffer = b"*0\r\n\r\n*1\r\n$4\r\nPING\r\n";
+        let commands = parse_resp(buffer, buffer.len()).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert!(matches!(commands[0], RedisCommand::Ping));
+    }
 }
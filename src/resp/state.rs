@@ -1,8 +1,8 @@
 pub enum RespState {
+    /// Between requests, the next byte starts a new one.
     Idle,
-    // ArrayDef,
-    // BulkDef,
-    BulkData,
-    End,
-    Error,
+    /// Inside a `*<n>` array, expecting the `$<len>` header of the next argument.
+    BulkDef,
+    /// Expecting `len` bytes of bulk data followed by CRLF.
+    BulkData(usize),
 }
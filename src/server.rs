@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+use crate::client_handler::ClientHandler;
+use crate::redis::Redis;
+use crate::redis::replication::TcpStreamTrait;
+
+/// Accepts client connections and serves each one on its own `ClientHandler` task,
+/// so many clients can work against the same dataset side by side.
+///
+/// The number of live handlers is bounded by `maxclients`. Handlers whose client went away
+/// are reaped before every accept, so a long-running server doesn't pile up
+/// finished tasks.
+pub struct Server {
+    redis: Arc<Redis>,
+    maxclients: usize,
+    clients: Vec<(ClientHandler, JoinHandle<()>)>,
+}
+
+impl Server {
+    pub const MAX_CLIENTS_ERROR: &'static str = "-ERR max number of clients reached\r\n";
+
+    pub fn new(redis: Arc<Redis>) -> Self {
+        let maxclients = redis.config().maxclients;
+        Server {
+            redis,
+            maxclients,
+            clients: Vec::new(),
+        }
+    }
+
+    /// Starts a handler for a freshly accepted connection.
+    /// Returns false when the server is full; the client gets an error and the connection is dropped.
+    pub async fn accept<T: TcpStreamTrait + 'static>(&mut self, mut client: T) -> bool {
+        self.reap();
+
+        if self.clients.len() >= self.maxclients {
+            #[cfg(debug_assertions)]
+            println!("[SERVER] Rejecting connection, {} clients connected", self.clients.len());
+            let _ = client.write_all(Self::MAX_CLIENTS_ERROR.as_bytes()).await;
+            let _ = client.flush().await;
+            let _ = client.shutdown().await;
+            return false;
+        }
+
+        let mut handler = ClientHandler::new(client, Arc::clone(&self.redis));
+        let handle = handler.start();
+        self.clients.push((handler, handle));
+        true
+    }
+
+    /// Drops the handler tasks of clients that disconnected. Returns how many were reaped.
+    pub fn reap(&mut self) -> usize {
+        let before = self.clients.len();
+        let mut alive = Vec::with_capacity(before);
+        for (handler, handle) in self.clients.drain(..) {
+            if !handle.is_finished() {
+                alive.push((handler, handle));
+            }
+        }
+        self.clients = alive;
+        before - self.clients.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn connected_clients(&mut self) -> usize {
+        self.reap();
+        self.clients.len()
+    }
+
+    /// Asks every handler to stop. Handlers exit after their current read returns.
+    #[allow(dead_code)]
+    pub fn shutdown(&mut self) {
+        for (handler, _) in &self.clients {
+            handler.shutdown();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_setbit_getbit_and_bitcount() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    // Daily active users: one bit per user id.
+    assert_reply(&mut client, &["SETBIT", "active", "7", "1"], ":0\r\n");
+    assert_reply(&mut client, &["SETBIT", "active", "7", "1"], ":1\r\n");
+    assert_reply(&mut client, &["SETBIT", "active", "100", "1"], ":0\r\n");
+    assert_reply(&mut client, &["GETBIT", "active", "7"], ":1\r\n");
+    assert_reply(&mut client, &["GETBIT", "active", "8"], ":0\r\n");
+    assert_reply(&mut client, &["GETBIT", "active", "999999"], ":0\r\n");
+    assert_reply(&mut client, &["GETBIT", "missing", "0"], ":0\r\n");
+    assert_reply(&mut client, &["STRLEN", "active"], ":13\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "active"], ":2\r\n");
+    assert_reply(&mut client, &["SETBIT", "active", "7", "0"], ":1\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "active"], ":1\r\n");
+
+    assert_reply(&mut client, &["SET", "s", "foobar"], "+OK\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s"], ":26\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s", "1", "1"], ":6\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s", "-2", "-1", "BYTE"], ":7\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s", "5", "30", "BIT"], ":17\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "missing"], ":0\r\n");
+
+    assert_reply(&mut client, &["SETBIT", "s", "4294967296", "1"], "-ERR bit offset is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["SETBIT", "s", "-1", "1"], "-ERR bit offset is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["SETBIT", "s", "0", "2"], "-ERR bit is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s", "1"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s", "0", "1", "BITS"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "s", "a", "1"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["LPUSH", "list", "a"], ":1\r\n");
+    assert_reply(&mut client, &["SETBIT", "list", "0", "1"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "list"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bitpos() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    redis.db(0).set(b"k", vec![0xFF, 0xF0, 0x00].into(), None);
+    assert_reply(&mut client, &["BITPOS", "k", "0"], ":12\r\n");
+    assert_reply(&mut client, &["BITPOS", "k", "1", "1"], ":8\r\n");
+    assert_reply(&mut client, &["BITPOS", "k", "1", "2"], ":-1\r\n");
+    assert_reply(&mut client, &["BITPOS", "k", "0", "10", "20", "BIT"], ":12\r\n");
+    assert_reply(&mut client, &["BITPOS", "k", "1", "-1"], ":-1\r\n");
+
+    redis.db(0).set(b"ones", vec![0xFF, 0xFF].into(), None);
+    // Without an end the string counts as padded with zeros.
+    assert_reply(&mut client, &["BITPOS", "ones", "0"], ":16\r\n");
+    assert_reply(&mut client, &["BITPOS", "ones", "0", "0", "-1"], ":-1\r\n");
+
+    assert_reply(&mut client, &["BITPOS", "missing", "1"], ":-1\r\n");
+    assert_reply(&mut client, &["BITPOS", "missing", "0"], ":0\r\n");
+    assert_reply(&mut client, &["BITPOS", "k", "2"], "-ERR The bit argument must be 1 or 0.\r\n");
+    assert_reply(&mut client, &["BITPOS", "k", "1", "0", "1", "WORD"], "-ERR syntax error\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bitop() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    redis.db(0).set(b"a", vec![0b1100_1100, 0xFF].into(), None);
+    redis.db(0).set(b"b", vec![0b1010_1010].into(), None);
+    assert_reply(&mut client, &["BITOP", "AND", "dest", "a", "b"], ":2\r\n");
+    assert_eq!(redis.db(0).get(b"dest").unwrap(), vec![0b1000_1000, 0x00]);
+    assert_reply(&mut client, &["BITOP", "or", "dest", "a", "b"], ":2\r\n");
+    assert_eq!(redis.db(0).get(b"dest").unwrap(), vec![0b1110_1110, 0xFF]);
+    assert_reply(&mut client, &["BITOP", "XOR", "dest", "a", "b", "missing"], ":2\r\n");
+    assert_eq!(redis.db(0).get(b"dest").unwrap(), vec![0b0110_0110, 0xFF]);
+    assert_reply(&mut client, &["BITOP", "NOT", "dest", "b"], ":1\r\n");
+    assert_eq!(redis.db(0).get(b"dest").unwrap(), vec![0b0101_0101]);
+
+    // The result replaces the destination and its TTL; an empty one deletes it.
+    assert_reply(&mut client, &["EXPIRE", "dest", "100"], ":1\r\n");
+    assert_reply(&mut client, &["BITOP", "AND", "dest", "a"], ":2\r\n");
+    assert_reply(&mut client, &["TTL", "dest"], ":-1\r\n");
+    assert_reply(&mut client, &["BITOP", "OR", "dest", "missing"], ":0\r\n");
+    assert_reply(&mut client, &["EXISTS", "dest"], ":0\r\n");
+
+    assert_reply(&mut client, &["BITOP", "NOT", "dest", "a", "b"], "-ERR BITOP NOT must be called with a single source key.\r\n");
+    assert_reply(&mut client, &["BITOP", "NAND", "dest", "a"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["SADD", "set", "m"], ":1\r\n");
+    assert_reply(&mut client, &["BITOP", "OR", "dest", "a", "set"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bitfield() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["BITFIELD", "f", "GET", "u8", "0"], "*1\r\n:0\r\n");
+    assert_reply(&mut client, &["EXISTS", "f"], ":0\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "SET", "i8", "#1", "-100", "GET", "i8", "8", "GET", "u4", "8"], "*3\r\n:0\r\n:-100\r\n:9\r\n");
+    assert_reply(&mut client, &["STRLEN", "f"], ":2\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "INCRBY", "i8", "#1", "-100"], "*1\r\n:56\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "OVERFLOW", "SAT", "INCRBY", "i8", "#1", "100", "INCRBY", "u2", "0", "9"], "*2\r\n:127\r\n:3\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "OVERFLOW", "FAIL", "INCRBY", "i8", "#1", "1", "SET", "u2", "0", "4", "INCRBY", "u2", "0", "-3"], "*3\r\n$-1\r\n$-1\r\n:0\r\n");
+    assert_reply(&mut client, &["BITFIELD_RO", "f", "GET", "i8", "#1", "GET", "u2", "0"], "*2\r\n:127\r\n:0\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f"], "*0\r\n");
+
+    // Per-user feature flags, four bits each.
+    assert_reply(&mut client, &["BITFIELD", "flags", "SET", "u4", "#1000", "5"], "*1\r\n:0\r\n");
+    assert_reply(&mut client, &["STRLEN", "flags"], ":501\r\n");
+
+    assert_reply(&mut client, &["BITFIELD", "f", "GET", "u64", "0"], "-ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "GET", "i8", "-1"], "-ERR bit offset is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "OVERFLOW", "CLAMP"], "-ERR Invalid OVERFLOW type specified\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "SET", "i8", "0"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["BITFIELD", "f", "SET", "i8", "0", "x"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["BITFIELD_RO", "f", "SET", "i8", "0", "1"], "-ERR BITFIELD_RO only supports the GET subcommand\r\n");
+    assert_reply(&mut client, &["BITFIELD_RO", "f", "GET", "i8", "#1"], "*1\r\n:127\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bitmap_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SETBIT", "a", "3", "1"], ":0\r\n");
+    assert_reply(&mut client, &["GETBIT", "a", "3"], ":1\r\n");
+    assert_reply(&mut client, &["BITCOUNT", "a"], ":1\r\n");
+    assert_reply(&mut client, &["BITOP", "NOT", "b", "a"], ":1\r\n");
+    assert_reply(&mut client, &["BITFIELD", "a", "GET", "u8", "0"], "*1\r\n:16\r\n");
+    assert_reply(&mut client, &["BITFIELD", "a", "INCRBY", "u8", "0", "1"], "*1\r\n:17\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["SETBIT", "a", "3", "1"]);
+    expected.extend(encode(&["BITOP", "NOT", "b", "a"]));
+    expected.extend(encode(&["BITFIELD", "a", "INCRBY", "u8", "0", "1"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
@@ -0,0 +1,225 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use redis_starter_rust::client_handler::ClientHandler;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_next_reply, assert_reply, encode, send, start_client, wait_until_blocked};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_pops_serve_existing_elements_right_away() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "list", "a", "b"], ":2\r\n");
+    assert_reply(&mut client, &["BLPOP", "empty", "list", "0"], "*2\r\n$4\r\nlist\r\n$1\r\na\r\n");
+    assert_reply(&mut client, &["BRPOP", "list", "0"], "*2\r\n$4\r\nlist\r\n$1\r\nb\r\n");
+    assert_reply(&mut client, &["EXISTS", "list"], ":0\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"list"), 0);
+
+    let start = Instant::now();
+    assert_reply(&mut client, &["BLPOP", "list", "0.2"], "*-1\r\n");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_reply(&mut client, &["BLMOVE", "list", "other", "LEFT", "LEFT", "0.05"], "$-1\r\n");
+    assert_reply(&mut client, &["BLMPOP", "0.05", "1", "list", "LEFT"], "*-1\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"list"), 0);
+
+    assert_reply(&mut client, &["BLPOP", "list", "-1"], "-ERR timeout is negative\r\n");
+    assert_reply(&mut client, &["BLPOP", "list", "soon"], "-ERR timeout is not a float or out of range\r\n");
+    assert_reply(&mut client, &["BLPOP", "list", "inf"], "-ERR timeout is not a float or out of range\r\n");
+    assert_reply(&mut client, &["BLMOVE", "list", "other", "LEFT", "UP", "0"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["BLMPOP", "0", "0", "list", "LEFT"], "-ERR numkeys should be greater than 0\r\n");
+    assert_reply(&mut client, &["SET", "string", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["BLPOP", "string", "0"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_push_wakes_blocked_client() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut worker = start_client(&redis);
+    let mut producer = start_client(&redis);
+
+    send(&mut worker, &["BLPOP", "jobs", "other", "0"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    assert_reply(&mut producer, &["RPUSH", "other", "job"], ":1\r\n");
+    assert_next_reply(&worker, "*2\r\n$5\r\nother\r\n$3\r\njob\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"jobs"), 0);
+    assert_reply(&mut producer, &["EXISTS", "other"], ":0\r\n");
+
+    // The worker goes on with whatever it sent in the meantime.
+    send(&mut worker, &["BRPOP", "jobs", "5"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    worker.write_all(&encode(&["PING"])).unwrap();
+    assert_reply(&mut producer, &["LPUSH", "jobs", "1", "2"], ":2\r\n");
+    assert_next_reply(&worker, "*2\r\n$4\r\njobs\r\n$1\r\n1\r\n");
+    assert_next_reply(&worker, "+PONG\r\n");
+    assert_reply(&mut producer, &["LRANGE", "jobs", "0", "-1"], "*1\r\n$1\r\n2\r\n");
+    worker.shutdown();
+    producer.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocked_clients_are_served_in_order() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut workers: Vec<MockTcpStream> = (0..3).map(|_| start_client(&redis)).collect();
+    let mut producer = start_client(&redis);
+
+    for (i, worker) in workers.iter_mut().enumerate() {
+        send(worker, &["BLPOP", "queue", "0"]);
+        wait_until_blocked(&redis, "queue", i + 1);
+    }
+    // One push of three elements hands one to each, first come first served.
+    assert_reply(&mut producer, &["RPUSH", "queue", "a", "b", "c"], ":3\r\n");
+    for (worker, element) in workers.iter().zip(["a", "b", "c"]) {
+        assert_next_reply(worker, &format!("*2\r\n$5\r\nqueue\r\n$1\r\n{}\r\n", element));
+    }
+
+    for (i, worker) in workers.iter_mut().enumerate() {
+        send(worker, &["BLPOP", "queue", "0"]);
+        wait_until_blocked(&redis, "queue", i + 1);
+    }
+    for (i, element) in ["d", "e", "f"].into_iter().enumerate() {
+        assert_reply(&mut producer, &["RPUSH", "queue", element], ":1\r\n");
+        assert_next_reply(&workers[i], &format!("*2\r\n$5\r\nqueue\r\n$1\r\n{}\r\n", element));
+        wait_until_blocked(&redis, "queue", 2 - i);
+    }
+    for worker in workers.iter().chain([&producer]) {
+        worker.shutdown();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blmove_and_blmpop_wait_for_pushes() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut worker = start_client(&redis);
+    let mut producer = start_client(&redis);
+
+    send(&mut worker, &["BLMOVE", "jobs", "processing", "RIGHT", "LEFT", "1.5"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    assert_reply(&mut producer, &["RPUSH", "jobs", "1", "2"], ":2\r\n");
+    assert_next_reply(&worker, "$1\r\n2\r\n");
+    assert_reply(&mut producer, &["LRANGE", "processing", "0", "-1"], "*1\r\n$1\r\n2\r\n");
+
+    send(&mut worker, &["BRPOPLPUSH", "empty", "processing", "0"]);
+    wait_until_blocked(&redis, "empty", 1);
+    // A move into a key wakes whoever waits on it, too.
+    assert_reply(&mut producer, &["LMOVE", "jobs", "empty", "LEFT", "LEFT"], "$1\r\n1\r\n");
+    assert_next_reply(&worker, "$1\r\n1\r\n");
+    assert_reply(&mut producer, &["LRANGE", "processing", "0", "-1"], "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+
+    send(&mut worker, &["BLMPOP", "0", "2", "a", "b", "RIGHT", "COUNT", "2"]);
+    wait_until_blocked(&redis, "b", 1);
+    assert_reply(&mut producer, &["RPUSH", "b", "x", "y", "z"], ":3\r\n");
+    assert_next_reply(&worker, "*2\r\n$1\r\nb\r\n*2\r\n$1\r\nz\r\n$1\r\ny\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"a"), 0);
+    worker.shutdown();
+    producer.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_pops_do_not_block_inside_multi() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["MULTI"], "+OK\r\n");
+    assert_reply(&mut client, &["BLPOP", "list", "0"], "+QUEUED\r\n");
+    assert_reply(&mut client, &["RPUSH", "list", "a"], "+QUEUED\r\n");
+    assert_reply(&mut client, &["BRPOP", "list", "0"], "+QUEUED\r\n");
+    assert_reply(&mut client, &["BLMOVE", "list", "other", "LEFT", "LEFT", "0"], "+QUEUED\r\n");
+    assert_reply(&mut client, &["EXEC"], "*4\r\n*-1\r\n:1\r\n*2\r\n$4\r\nlist\r\n$1\r\na\r\n$-1\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_disconnected_client_stops_waiting() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut gone = start_client(&redis);
+    let mut worker = start_client(&redis);
+    let mut producer = start_client(&redis);
+
+    send(&mut gone, &["BLPOP", "jobs", "0"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    send(&mut worker, &["BLPOP", "jobs", "0"]);
+    wait_until_blocked(&redis, "jobs", 2);
+    gone.shutdown();
+    wait_until_blocked(&redis, "jobs", 1);
+
+    assert_reply(&mut producer, &["RPUSH", "jobs", "job"], ":1\r\n");
+    assert_next_reply(&worker, "*2\r\n$4\r\njobs\r\n$3\r\njob\r\n");
+    worker.shutdown();
+    producer.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_served_blocking_pops_are_replicated_as_pops() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut worker = start_client(&redis);
+    let mut producer = start_client(&redis);
+
+    assert_reply(&mut worker, &["BLPOP", "jobs", "0.01"], "*-1\r\n");
+    send(&mut worker, &["BRPOP", "jobs", "0"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    assert_reply(&mut producer, &["RPUSH", "jobs", "1", "2", "3"], ":3\r\n");
+    assert_next_reply(&worker, "*2\r\n$4\r\njobs\r\n$1\r\n3\r\n");
+    assert_reply(&mut worker, &["BLMOVE", "jobs", "done", "LEFT", "RIGHT", "0"], "$1\r\n1\r\n");
+    assert_reply(&mut worker, &["BLMPOP", "0", "1", "jobs", "LEFT", "COUNT", "5"], "*2\r\n$4\r\njobs\r\n*1\r\n$1\r\n2\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["RPUSH", "jobs", "1", "2", "3"]);
+    expected.extend(encode(&["RPOP", "jobs"]));
+    expected.extend(encode(&["LMOVE", "jobs", "done", "LEFT", "RIGHT"]));
+    expected.extend(encode(&["LPOP", "jobs", "1"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    worker.shutdown();
+    producer.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replicated_push_wakes_clients_on_replica() {
+    let config = RedisConfig {
+        replicaof_host: Some("localhost".to_string()),
+        replicaof_port: Some("6379".to_string()),
+        ..RedisConfig::default()
+    };
+    let redis = Arc::new(Redis::new(config));
+    let (mut master, master_link) = MockTcpStream::new_pair();
+    let mut handler = ClientHandler::new_redis_handler(master_link, Arc::clone(&redis));
+    let _handle = handler.start();
+    let mut worker = start_client(&redis);
+
+    send(&mut worker, &["BLPOP", "jobs", "0"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    master.write_all(&encode(&["RPUSH", "jobs", "job"])).unwrap();
+    assert_next_reply(&worker, "*2\r\n$4\r\njobs\r\n$3\r\njob\r\n");
+    assert_eq!(redis.db(0).llen(b"jobs"), 0);
+    worker.shutdown();
+    master.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_swapdb_wakes_clients_blocked_in_either_database() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut worker = start_client(&redis);
+    let mut producer = start_client(&redis);
+
+    send(&mut worker, &["BLPOP", "jobs", "0"]);
+    wait_until_blocked(&redis, "jobs", 1);
+    assert_reply(&mut producer, &["SELECT", "1"], "+OK\r\n");
+    assert_reply(&mut producer, &["RPUSH", "jobs", "job"], ":1\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"jobs"), 1);
+    // The worker's database 0 now holds the list that was pushed in database 1
+    assert_reply(&mut producer, &["SWAPDB", "0", "1"], "+OK\r\n");
+    assert_next_reply(&worker, "*2\r\n$4\r\njobs\r\n$3\r\njob\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"jobs"), 0);
+    worker.shutdown();
+    producer.shutdown();
+}
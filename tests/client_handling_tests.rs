@@ -1,7 +1,8 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::io::{Write, Read};
 use redis_starter_rust::redis::{Redis, RedisConfig};
 use redis_starter_rust::client_handler::ClientHandler;
+use redis_starter_rust::server::Server;
 mod utils;
 use utils::mock_tcp_stream::MockTcpStream;
 
@@ -28,8 +29,8 @@ fn wait_for_response(stream: &mut MockTcpStream, expected: &str, timeout_ms: u64
     read_response(stream)
 }
 
-#[test]
-fn test_concurrent_set_get() -> std::io::Result<()> {
+#[tokio::test(flavor = "multi_thread")]
+async fn test_concurrent_set_get() -> std::io::Result<()> {
     #[cfg(debug_assertions)]
     println!("[TEST] Starting test_concurrent_set_get");
     let config = RedisConfig {
@@ -39,8 +40,9 @@ fn test_concurrent_set_get() -> std::io::Result<()> {
         replicaof_port: None,
         dir: "./".to_string(),
         dbfilename: "dump.rdb".to_string(),
+        ..RedisConfig::default()
     };
-    let redis = Arc::new(Mutex::new(Redis::new(config)));
+    let redis = Arc::new(Redis::new(config));
 
     // Create client-server pairs
     let (mut client1, server1) = MockTcpStream::new_pair();
@@ -112,9 +114,175 @@ fn test_concurrent_set_get() -> std::io::Result<()> {
     client3.shutdown();
     
     // Wait for threads to finish
-    let _ = handle1.join();
-    let _ = handle2.join();
-    let _ = handle3.join();
+    let _ = handle1.await;
+    let _ = handle2.await;
+    let _ = handle3.await;
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_serves_clients_concurrently() -> std::io::Result<()> {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut server = Server::new(redis.clone());
+
+    let (mut client1, server1) = MockTcpStream::new_pair();
+    let (mut client2, server2) = MockTcpStream::new_pair();
+    assert!(server.accept(server1).await);
+    assert!(server.accept(server2).await);
+
+    // The second client is answered while the first one is still connected
+    send_command(&mut client2, "*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")?;
+    assert!(client2.wait_for_pattern("+OK\r\n", 1000));
+
+    send_command(&mut client1, "*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n")?;
+    assert!(client1.wait_for_pattern("$3\r\nbar\r\n", 1000));
+    assert_eq!(server.connected_clients(), 2);
+
+    server.shutdown();
+    client1.shutdown();
+    client2.shutdown();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_rejects_clients_over_maxclients() {
+    let config = RedisConfig {
+        maxclients: 1,
+        ..RedisConfig::default()
+    };
+    let redis = Arc::new(Redis::new(config));
+    let mut server = Server::new(redis.clone());
+
+    let (client1, server1) = MockTcpStream::new_pair();
+    let (client2, server2) = MockTcpStream::new_pair();
+    assert!(server.accept(server1).await);
+    assert!(!server.accept(server2).await);
+    assert!(client2.wait_for_pattern("-ERR max number of clients reached\r\n", 1000));
+
+    client1.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_reaps_disconnected_clients() {
+    let config = RedisConfig {
+        maxclients: 1,
+        ..RedisConfig::default()
+    };
+    let redis = Arc::new(Redis::new(config));
+    let mut server = Server::new(redis.clone());
+
+    let (client1, server1) = MockTcpStream::new_pair();
+    assert!(server.accept(server1).await);
+
+    // Once the first client disconnects its handler task finishes and frees the slot
+    client1.shutdown();
+    let start = std::time::Instant::now();
+    while server.connected_clients() > 0 && start.elapsed() < std::time::Duration::from_secs(2) {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert_eq!(server.connected_clients(), 0);
+
+    let (mut client2, server2) = MockTcpStream::new_pair();
+    assert!(server.accept(server2).await);
+    send_command(&mut client2, "*1\r\n$4\r\nPING\r\n").unwrap();
+    assert!(client2.wait_for_pattern("+PONG\r\n", 1000));
+    client2.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocked_client_does_not_stall_others() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut server = Server::new(redis.clone());
+
+    let (mut client1, server1) = MockTcpStream::new_pair();
+    let (mut client2, server2) = MockTcpStream::new_pair();
+    assert!(server.accept(server1).await);
+    assert!(server.accept(server2).await);
+
+    // client1 blocks for a full second waiting on an empty stream
+    send_command(&mut client1, "*6\r\n$5\r\nXREAD\r\n$5\r\nBLOCK\r\n$4\r\n1000\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let start = std::time::Instant::now();
+    send_command(&mut client2, "*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n").unwrap();
+    assert!(client2.wait_for_pattern("+OK\r\n", 1000));
+    assert!(start.elapsed() < std::time::Duration::from_millis(500), "SET waited for the blocked XREAD");
+
+    client1.shutdown();
+    client2.shutdown();
+}
+
+#[test]
+fn test_concurrent_incr_on_one_key_loses_no_updates() {
+    use redis_starter_rust::redis::RedisCommand;
+
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let workers: Vec<_> = (0..8).map(|_| {
+        let redis = redis.clone();
+        std::thread::spawn(move || {
+            let incr = RedisCommand::Incr { key: "counter".into(), delta: 1, original_resp: Default::default() };
+            for _ in 0..500 {
+                redis.execute_command(&mut 0, &incr, None);
+            }
+        })
+    }).collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    assert_eq!(redis.get(b"counter").unwrap(), "4000");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_request_split_across_writes() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let (mut client, server) = MockTcpStream::new_pair();
+    let mut handler = ClientHandler::new(server, redis.clone());
+    let _handle = handler.start();
+
+    send_command(&mut client, "*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nb").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    send_command(&mut client, "ar\r\n*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n").unwrap();
+
+    assert!(client.wait_for_pattern("+OK\r\n$3\r\nbar\r\n", 1000));
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_protocol_error_closes_connection() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let (mut client, server) = MockTcpStream::new_pair();
+    let mut handler = ClientHandler::new(server, redis.clone());
+    let handle = handler.start();
+
+    send_command(&mut client, "*1\r\n$4\r\nPING\r\n*x\r\n*1\r\n$4\r\nPING\r\n").unwrap();
+    assert!(client.wait_for_pattern("+PONG\r\n-ERR Protocol error", 1000));
+
+    let _ = tokio::time::timeout(std::time::Duration::from_secs(1), handle).await
+        .expect("handler should hang up after a protocol error");
+    // nothing after the bad request is executed
+    assert_eq!(client.read_data.lock().unwrap().windows(7).filter(|w| w == b"+PONG\r\n").count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_binary_keys_and_values_round_trip() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let (mut client, server) = MockTcpStream::new_pair();
+    let mut handler = ClientHandler::new(server, redis.clone());
+    let _handle = handler.start();
+
+    let value: &[u8] = b"\x00\xff\xfe\r\n\x89PNG";
+    let mut request = b"*3\r\n$3\r\nSET\r\n$3\r\nk\x00\xff\r\n$9\r\n".to_vec();
+    request.extend_from_slice(value);
+    request.extend_from_slice(b"\r\n*2\r\n$3\r\nGET\r\n$3\r\nk\x00\xff\r\n");
+    client.write_all(&request).unwrap();
+
+    assert!(client.wait_for_pattern("PNG\r\n", 1000));
+    let mut expected = b"+OK\r\n$9\r\n".to_vec();
+    expected.extend_from_slice(value);
+    expected.extend_from_slice(b"\r\n");
+    assert_eq!(*client.read_data.lock().unwrap(), expected);
+    assert_eq!(redis.get(b"k\x00\xff").unwrap(), value);
+    client.shutdown();
+}
@@ -0,0 +1,343 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_next_reply, assert_reply, encode, read_reply, send, start_client, wait_until_blocked};
+
+/// An entry with a single field as XREADGROUP and XCLAIM reply with it.
+fn entry(id: &str, field: &str, value: &str) -> String {
+    format!("*2\r\n${}\r\n{}\r\n*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", id.len(), id, field.len(), field, value.len(), value)
+}
+
+/// A reply array of `items`.
+fn array(items: &[String]) -> String {
+    format!("*{}\r\n{}", items.len(), items.concat())
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+/// XPENDING's extended form: each entry as "id consumer deliveries", and how long each has been
+/// idle in milliseconds.
+fn pending(client: &mut MockTcpStream, args: &[&str]) -> (Vec<String>, Vec<u64>) {
+    send(client, args);
+    let reply = String::from_utf8(read_reply(client, 1000).expect("XPENDING never replied")).unwrap();
+    let lines: Vec<&str> = reply.split("\r\n").collect();
+    let count: usize = lines[0][1..].parse().unwrap();
+    // Each entry is *4, the ID and consumer as bulk strings, then idle time and deliveries
+    (0..count)
+        .map(|i| {
+            let entry = &lines[1 + i * 7..];
+            (format!("{} {} {}", entry[2], entry[4], &entry[6][1..]), entry[5][1..].parse::<u64>().unwrap())
+        })
+        .unzip()
+}
+
+/// A stream `s` with entries 1-0, 2-0 and 3-0, each with field f set to a, b and c.
+fn stream_client(redis: &Arc<Redis>) -> MockTcpStream {
+    let mut client = start_client(redis);
+    for (id, value) in [("1-0", "a"), ("2-0", "b"), ("3-0", "c")] {
+        assert_reply(&mut client, &["XADD", "s", id, "f", value], &bulk(id));
+    }
+    client
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xgroup_subcommands() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = stream_client(&redis);
+
+    assert_reply(&mut client, &["XGROUP", "CREATE", "missing", "g", "$"],
+        "-ERR The XGROUP subcommand requires the key to exist. \
+        Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "fresh", "g", "$", "MKSTREAM"], "+OK\r\n");
+    assert_reply(&mut client, &["TYPE", "fresh"], "$6\r\nstream\r\n");
+    assert_reply(&mut client, &["XLEN", "fresh"], ":0\r\n");
+
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "$"], "+OK\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "0"], "-BUSYGROUP Consumer Group name already exists\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "h", "nope"], "-ERR Invalid stream ID specified as stream command argument\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "h", "0", "NOW"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s"], "-ERR wrong number of arguments for 'xgroup|create' command\r\n");
+    assert_reply(&mut client, &["XGROUP", "FROB", "s", "g"], "-ERR unknown subcommand 'FROB'. Try XGROUP HELP.\r\n");
+
+    // Created at `$`, the group has nothing to deliver until it's moved back
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"], "*-1\r\n");
+    assert_reply(&mut client, &["XGROUP", "SETID", "s", "g", "2"], "+OK\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("3-0", "f", "c")])])]));
+    assert_reply(&mut client, &["XGROUP", "SETID", "s", "nope", "0"], "-NOGROUP No such consumer group 'nope' for key name 's'\r\n");
+
+    assert_reply(&mut client, &["XGROUP", "CREATECONSUMER", "s", "g", "bob"], ":1\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATECONSUMER", "s", "g", "bob"], ":0\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATECONSUMER", "s", "nope", "bob"], "-NOGROUP No such consumer group 'nope' for key name 's'\r\n");
+    // Deleting a consumer drops what it had pending
+    assert_reply(&mut client, &["XGROUP", "DELCONSUMER", "s", "g", "alice"], ":1\r\n");
+    assert_reply(&mut client, &["XGROUP", "DELCONSUMER", "s", "g", "bob"], ":0\r\n");
+    assert_reply(&mut client, &["XPENDING", "s", "g"], "*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n");
+
+    assert_reply(&mut client, &["XGROUP", "DESTROY", "s", "g"], ":1\r\n");
+    assert_reply(&mut client, &["XGROUP", "DESTROY", "s", "g"], ":0\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        "-NOGROUP No such key 's' or consumer group 'g' in XREADGROUP with GROUP option\r\n");
+
+    assert_reply(&mut client, &["SET", "str", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "str", "g", "$"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xreadgroup_new_and_history_reads() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = stream_client(&redis);
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "0"], "+OK\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "other", "0"], "+OK\r\n");
+
+    // Each entry goes to one consumer of a group, and to every group
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("1-0", "f", "a"), entry("2-0", "f", "b")])])]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("3-0", "f", "c")])])]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"], "*-1\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "other", "carol", "NOACK", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("1-0", "f", "a"), entry("2-0", "f", "b"), entry("3-0", "f", "c")])])]));
+    assert_reply(&mut client, &["XPENDING", "s", "other"], "*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n");
+
+    // History reads see only the consumer's own pending entries, deleted ones without fields
+    assert_reply(&mut client, &["XDEL", "s", "1-0"], ":1\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"],
+        &array(&[array(&[bulk("s"), array(&["*2\r\n$3\r\n1-0\r\n*-1\r\n".to_string(), entry("2-0", "f", "b")])])]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "1", "STREAMS", "s", "1-0"],
+        &array(&[array(&[bulk("s"), array(&[entry("2-0", "f", "b")])])]));
+    // Each re-read counts as a delivery and resets the idle time, a deleted entry's is left alone
+    let (entries, idle) = pending(&mut client, &["XPENDING", "s", "g", "-", "+", "10", "alice"]);
+    assert_eq!(entries, ["1-0 alice 1", "2-0 alice 3"]);
+    assert!(idle[1] < 1000, "{:?}", idle);
+    assert_reply(&mut client, &["XACK", "s", "g", "1-0", "2-0", "9-0"], ":2\r\n");
+    assert_reply(&mut client, &["XACK", "s", "g", "1-0"], ":0\r\n");
+    assert_reply(&mut client, &["XACK", "missing", "g", "1-0"], ":0\r\n");
+    // An empty history still answers for the stream
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"], "*1\r\n*2\r\n$1\r\ns\r\n*0\r\n");
+
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "t", ">"],
+        "-ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "$"],
+        "-ERR Invalid stream ID specified as stream command argument\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "BLOCK", "-1", "STREAMS", "s", ">"], "-ERR timeout is negative\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "EVERYTHING", "STREAMS", "s", ">"], "-ERR syntax error\r\n");
+    // The group must exist on every stream before anything is delivered
+    assert_reply(&mut client, &["XADD", "s", "4-0", "f", "d"], "$3\r\n4-0\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "missing", ">", ">"],
+        "-NOGROUP No such key 'missing' or consumer group 'g' in XREADGROUP with GROUP option\r\n");
+    assert_reply(&mut client, &["XPENDING", "s", "g"], "*4\r\n:1\r\n$3\r\n3-0\r\n$3\r\n3-0\r\n*1\r\n*2\r\n$3\r\nbob\r\n$1\r\n1\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xpending_forms() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = stream_client(&redis);
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "0"], "+OK\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("1-0", "f", "a"), entry("2-0", "f", "b")])])]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("3-0", "f", "c")])])]));
+
+    assert_reply(&mut client, &["XPENDING", "s", "g"],
+        "*4\r\n:3\r\n$3\r\n1-0\r\n$3\r\n3-0\r\n*2\r\n*2\r\n$5\r\nalice\r\n$1\r\n2\r\n*2\r\n$3\r\nbob\r\n$1\r\n1\r\n");
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "-", "+", "10"]).0, ["1-0 alice 1", "2-0 alice 1", "3-0 bob 1"]);
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "-", "+", "10", "bob"]).0, ["3-0 bob 1"]);
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "2", "+", "1"]).0, ["2-0 alice 1"]);
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "-", "1", "10"]).0, ["1-0 alice 1"]);
+    assert_reply(&mut client, &["XPENDING", "s", "g", "3", "1", "10"], "*0\r\n");
+    // Make 1-0 look delivered a minute ago
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "alice", "0", "1-0", "IDLE", "60000", "JUSTID"], "*1\r\n$3\r\n1-0\r\n");
+    let (entries, idle) = pending(&mut client, &["XPENDING", "s", "g", "IDLE", "30000", "-", "+", "10"]);
+    assert_eq!(entries, ["1-0 alice 1"]);
+    assert!(idle[0] >= 60000);
+    assert_reply(&mut client, &["XPENDING", "s", "g", "IDLE", "30000", "-", "+", "10", "bob"], "*0\r\n");
+
+    assert_reply(&mut client, &["XPENDING", "s", "nope"], "-NOGROUP No such key 's' or consumer group 'nope'\r\n");
+    assert_reply(&mut client, &["XPENDING", "missing", "g", "-", "+", "10"], "-NOGROUP No such key 'missing' or consumer group 'g'\r\n");
+    assert_reply(&mut client, &["XPENDING", "s", "g", "-", "+"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["XPENDING", "s", "g", "-", "+", "many"], "-ERR value is not an integer or out of range\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xclaim() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = stream_client(&redis);
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "0"], "+OK\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("1-0", "f", "a"), entry("2-0", "f", "b")])])]));
+
+    // Just delivered, so not idle long enough
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "bob", "60000", "1-0"], "*0\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "bob", "0", "1-0", "2-0"],
+        &array(&[entry("1-0", "f", "a"), entry("2-0", "f", "b")]));
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "-", "+", "10", "bob"]).0, ["1-0 bob 2", "2-0 bob 2"]);
+
+    // Not pending: only FORCE claims it. RETRYCOUNT sets the delivery count outright.
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "bob", "0", "3-0"], "*0\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "bob", "0", "3-0", "FORCE", "RETRYCOUNT", "7", "JUSTID"], "*1\r\n$3\r\n3-0\r\n");
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "3", "3", "1"]).0, ["3-0 bob 7"]);
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "carol", "0", "3-0", "TIME", "1000", "JUSTID"], "*1\r\n$3\r\n3-0\r\n");
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "3", "3", "1", "carol"]).0, ["3-0 carol 7"]);
+
+    // A pending entry deleted from the stream is dropped instead
+    assert_reply(&mut client, &["XDEL", "s", "2-0"], ":1\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "alice", "0", "2-0"], "*0\r\n");
+    assert_reply(&mut client, &["XPENDING", "s", "g"],
+        "*4\r\n:2\r\n$3\r\n1-0\r\n$3\r\n3-0\r\n*2\r\n*2\r\n$3\r\nbob\r\n$1\r\n1\r\n*2\r\n$5\r\ncarol\r\n$1\r\n1\r\n");
+
+    // LASTID only moves the group forward
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "alice", "0", "9-0", "LASTID", "3-0"], "*0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "4-0", "f", "d"], "$3\r\n4-0\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("4-0", "f", "d")])])]));
+
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "alice", "soon", "1-0"], "-ERR Invalid min-idle-time argument for XCLAIM\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "alice", "0", "1-0", "IDLE", "x"], "-ERR Invalid IDLE option argument for XCLAIM\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "alice", "0", "1-0", "SOON"], "-ERR Unrecognized XCLAIM option 'SOON'\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "nope", "alice", "0", "1-0"], "-NOGROUP No such key 's' or consumer group 'nope'\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xautoclaim() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = stream_client(&redis);
+    assert_reply(&mut client, &["XADD", "s", "4-0", "f", "d"], "$3\r\n4-0\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "0"], "+OK\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"], &array(&[array(&[bulk("s"), array(&[
+        entry("1-0", "f", "a"), entry("2-0", "f", "b"), entry("3-0", "f", "c"), entry("4-0", "f", "d"),
+    ])])]));
+    assert_reply(&mut client, &["XDEL", "s", "2-0"], ":1\r\n");
+
+    assert_reply(&mut client, &["XAUTOCLAIM", "s", "g", "bob", "60000", "0"], "*3\r\n$3\r\n0-0\r\n*0\r\n*1\r\n$3\r\n2-0\r\n");
+    assert_reply(&mut client, &["XAUTOCLAIM", "s", "g", "bob", "0", "-", "COUNT", "1"],
+        &array(&[bulk("3-0"), array(&[entry("1-0", "f", "a")]), array(&[])]));
+    assert_reply(&mut client, &["XAUTOCLAIM", "s", "g", "bob", "0", "3-0", "COUNT", "1", "JUSTID"],
+        &array(&[bulk("4-0"), array(&[bulk("3-0")]), array(&[])]));
+    assert_reply(&mut client, &["XAUTOCLAIM", "s", "g", "bob", "0", "4", "JUSTID"],
+        &array(&[bulk("0-0"), array(&[bulk("4-0")]), array(&[])]));
+    assert_reply(&mut client, &["XPENDING", "s", "g"], "*4\r\n:3\r\n$3\r\n1-0\r\n$3\r\n4-0\r\n*1\r\n*2\r\n$3\r\nbob\r\n$1\r\n3\r\n");
+    // JUSTID doesn't count as a delivery, a full claim does
+    assert_eq!(pending(&mut client, &["XPENDING", "s", "g", "-", "+", "10"]).0, ["1-0 bob 2", "3-0 bob 1", "4-0 bob 1"]);
+
+    assert_reply(&mut client, &["XAUTOCLAIM", "s", "g", "bob", "0", "0", "COUNT", "0"], "-ERR COUNT must be > 0\r\n");
+    assert_reply(&mut client, &["XAUTOCLAIM", "missing", "g", "bob", "0", "0"], "-NOGROUP No such key 'missing' or consumer group 'g'\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xreadgroup_block_is_woken_by_xadd() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut producer = start_client(&redis);
+    let mut alice = start_client(&redis);
+    let mut bob = start_client(&redis);
+    let mut carol = start_client(&redis);
+    assert_reply(&mut producer, &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"], "+OK\r\n");
+    assert_reply(&mut producer, &["XGROUP", "CREATE", "s", "other", "$"], "+OK\r\n");
+
+    let start = Instant::now();
+    assert_reply(&mut alice, &["XREADGROUP", "GROUP", "g", "alice", "BLOCK", "100", "STREAMS", "s", ">"], "*-1\r\n");
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // Both groups get the entry, but only one consumer of each group: the one that blocked first
+    send(&mut alice, &["XREADGROUP", "GROUP", "g", "alice", "BLOCK", "0", "STREAMS", "s", ">"]);
+    wait_until_blocked(&redis, "s", 1);
+    send(&mut bob, &["XREADGROUP", "GROUP", "g", "bob", "BLOCK", "300", "STREAMS", "s", ">"]);
+    wait_until_blocked(&redis, "s", 2);
+    send(&mut carol, &["XREADGROUP", "GROUP", "other", "carol", "BLOCK", "0", "STREAMS", "s", ">"]);
+    wait_until_blocked(&redis, "s", 3);
+    assert_reply(&mut producer, &["XADD", "s", "1-0", "f", "a"], "$3\r\n1-0\r\n");
+    let delivered = array(&[array(&[bulk("s"), array(&[entry("1-0", "f", "a")])])]);
+    assert_next_reply(&alice, &delivered);
+    assert_next_reply(&carol, &delivered);
+    assert_next_reply(&bob, "*-1\r\n");
+
+    // A history read never blocks
+    assert_reply(&mut bob, &["XREADGROUP", "GROUP", "g", "bob", "BLOCK", "0", "STREAMS", "s", "0"], "*1\r\n*2\r\n$1\r\ns\r\n*0\r\n");
+
+    // Destroying the group answers its blocked readers
+    send(&mut bob, &["XREADGROUP", "GROUP", "g", "bob", "BLOCK", "0", "STREAMS", "s", ">"]);
+    wait_until_blocked(&redis, "s", 1);
+    assert_reply(&mut producer, &["XGROUP", "DESTROY", "s", "g"], ":1\r\n");
+    assert_next_reply(&bob, "-NOGROUP No such key 's' or consumer group 'g' in XREADGROUP with GROUP option\r\n");
+    assert_eq!(redis.blocked.waiting(0, b"s"), 0);
+
+    // Inside MULTI it doesn't wait
+    assert_reply(&mut carol, &["MULTI"], "+OK\r\n");
+    assert_reply(&mut carol, &["XREADGROUP", "GROUP", "other", "carol", "BLOCK", "0", "STREAMS", "s", ">"], "+QUEUED\r\n");
+    assert_reply(&mut carol, &["EXEC"], "*1\r\n*-1\r\n");
+    for client in [producer, alice, bob, carol] {
+        client.shutdown();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_consumer_group_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = stream_client(&redis);
+
+    assert_reply(&mut client, &["XGROUP", "CREATE", "s", "g", "$"], "+OK\r\n");
+    assert_reply(&mut client, &["XGROUP", "SETID", "s", "g", "1-0"], "+OK\r\n");
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "1", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("2-0", "f", "b")])])]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "NOACK", "STREAMS", "s", ">"],
+        &array(&[array(&[bulk("s"), array(&[entry("3-0", "f", "c")])])]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"], "*-1\r\n");
+    assert_reply(&mut client, &["XCLAIM", "s", "g", "bob", "0", "2-0", "TIME", "1000"], &array(&[entry("2-0", "f", "b")]));
+    assert_reply(&mut client, &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", "0"],
+        &array(&[array(&[bulk("s"), array(&[entry("2-0", "f", "b")])])]));
+    assert_reply(&mut client, &["XDEL", "s", "2-0"], ":1\r\n");
+    assert_reply(&mut client, &["XAUTOCLAIM", "s", "g", "bob", "0", "0"], "*3\r\n$3\r\n0-0\r\n*0\r\n*1\r\n$3\r\n2-0\r\n");
+    assert_reply(&mut client, &["XACK", "s", "g", "2-0"], ":0\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATECONSUMER", "s", "g", "carol"], ":1\r\n");
+    assert_reply(&mut client, &["XGROUP", "DELCONSUMER", "s", "g", "carol"], ":0\r\n");
+    assert_reply(&mut client, &["XGROUP", "DESTROY", "s", "g"], ":1\r\n");
+    redis.replication.send_pending_commands();
+
+    let sent = |args: &[&str]| encode(args);
+    let mut expected = sent(&["XADD", "s", "1-0", "f", "a"]);
+    expected.extend(sent(&["XADD", "s", "2-0", "f", "b"]));
+    expected.extend(sent(&["XADD", "s", "3-0", "f", "c"]));
+    expected.extend(sent(&["XGROUP", "CREATE", "s", "g", "3-0"]));
+    expected.extend(sent(&["XGROUP", "SETID", "s", "g", "1-0"]));
+    expected.extend(sent(&["XGROUP", "CREATECONSUMER", "s", "g", "alice"]));
+    let prefix = String::from_utf8(expected.clone()).unwrap();
+    assert!(replica_server.wait_for_pattern(&prefix, 1000));
+    let replicated = String::from_utf8(replica_server.read_data.lock().unwrap().clone()).unwrap();
+    let rest = replicated.strip_prefix(&prefix).unwrap();
+
+    // The delivery is an XCLAIM that sets the pending entry up as it is on the master
+    let claim = String::from_utf8(sent(&["XCLAIM", "s", "g", "alice", "0", "2-0", "TIME"])).unwrap().replacen("*7", "*14", 1);
+    assert!(rest.starts_with(&claim), "{:?}", rest);
+    assert!(rest.contains(&String::from_utf8(sent(&["RETRYCOUNT", "1", "FORCE", "JUSTID", "LASTID", "2-0"])[4..].to_vec()).unwrap()));
+    let rest = &rest[rest.find(&String::from_utf8(sent(&["XGROUP", "SETID", "s", "g", "3-0"])).unwrap()).unwrap()..];
+    let mut expected = sent(&["XGROUP", "SETID", "s", "g", "3-0"]);
+    expected.extend(sent(&["XCLAIM", "s", "g", "bob", "0", "2-0", "TIME", "1000", "RETRYCOUNT", "2", "FORCE", "JUSTID", "LASTID", "3-0"]));
+    // The history read delivered 2-0 again, at a time only the master knows
+    let redelivery = String::from_utf8(sent(&["XCLAIM", "s", "g", "bob", "0", "2-0", "TIME"])).unwrap().replacen("*7", "*14", 1);
+    expected.extend(redelivery.as_bytes());
+    let rest = rest.strip_prefix(String::from_utf8(expected).unwrap().as_str()).unwrap_or_else(|| panic!("{:?}", rest));
+    let retry = String::from_utf8(sent(&["RETRYCOUNT", "3", "FORCE", "JUSTID", "LASTID", "3-0"])[4..].to_vec()).unwrap();
+    let rest = &rest[rest.find(&retry).unwrap() + retry.len()..];
+    let mut expected = sent(&["XDEL", "s", "2-0"]);
+    expected.extend(sent(&["XACK", "s", "g", "2-0"]));
+    expected.extend(sent(&["XGROUP", "CREATECONSUMER", "s", "g", "carol"]));
+    expected.extend(sent(&["XGROUP", "DELCONSUMER", "s", "g", "carol"]));
+    expected.extend(sent(&["XGROUP", "DESTROY", "s", "g"]));
+    assert_eq!(rest, String::from_utf8(expected).unwrap());
+    client.shutdown();
+}
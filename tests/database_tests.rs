@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::consumer_groups::ConsumerGroup;
+use redis_starter_rust::redis::storage::ValueWrapper;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, read_reply, send, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_select_keeps_databases_apart() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+    let mut other = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "k", "zero"], "+OK\r\n");
+    assert_reply(&mut client, &["SELECT", "3"], "+OK\r\n");
+    assert_reply(&mut client, &["TYPE", "k"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["SET", "k", "three"], "+OK\r\n");
+    assert_reply(&mut client, &["GET", "k"], "$5\r\nthree\r\n");
+
+    // The selection belongs to the connection.
+    assert_reply(&mut other, &["GET", "k"], "$4\r\nzero\r\n");
+    assert_eq!(redis.db(3).get(b"k").unwrap(), "three");
+
+    assert_reply(&mut client, &["SELECT", "16"], "-ERR DB index is out of range\r\n");
+    assert_reply(&mut client, &["SELECT", "-1"], "-ERR DB index is out of range\r\n");
+    assert_reply(&mut client, &["SELECT", "one"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["GET", "k"], "$5\r\nthree\r\n");
+    assert_reply(&mut client, &["CONFIG", "GET", "databases"], "$2\r\n16\r\n");
+
+    // SELECT inside MULTI applies to the commands queued after it.
+    assert_reply(&mut client, &["MULTI"], "+OK\r\n");
+    assert_reply(&mut client, &["SELECT", "0"], "+QUEUED\r\n");
+    assert_reply(&mut client, &["GET", "k"], "+QUEUED\r\n");
+    assert_reply(&mut client, &["EXEC"], "*2\r\n+OK\r\n$4\r\nzero\r\n");
+    assert_reply(&mut client, &["GET", "k"], "$4\r\nzero\r\n");
+    client.shutdown();
+    other.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_move_swapdb_and_flush() {
+    let redis = Arc::new(Redis::new(RedisConfig { databases: 4, ..RedisConfig::default() }));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "list", "a"], ":1\r\n");
+    assert_reply(&mut client, &["RPUSH", "list", "b"], ":2\r\n");
+    assert_reply(&mut client, &["EXPIRE", "list", "100"], ":1\r\n");
+    assert_reply(&mut client, &["MOVE", "list", "2"], ":1\r\n");
+    assert_reply(&mut client, &["TYPE", "list"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["MOVE", "list", "2"], ":0\r\n");
+    assert_reply(&mut client, &["MOVE", "list", "0"], "-ERR source and destination objects are the same\r\n");
+    assert_reply(&mut client, &["MOVE", "list", "4"], "-ERR DB index is out of range\r\n");
+
+    // An existing key in the destination blocks the move.
+    assert_reply(&mut client, &["SET", "s", "mine"], "+OK\r\n");
+    redis.db(2).set(b"s", "theirs".into(), None);
+    assert_reply(&mut client, &["MOVE", "s", "2"], ":0\r\n");
+    assert_reply(&mut client, &["GET", "s"], "$4\r\nmine\r\n");
+
+    assert_reply(&mut client, &["SELECT", "2"], "+OK\r\n");
+    assert_reply(&mut client, &["LRANGE", "list", "0", "-1"], "*2\r\n$1\r\na\r\n$1\r\nb\r\n");
+    // The TTL moves along with the key.
+    assert!(redis.db(2).expire_time(b"list") > 0);
+
+    // The connection stays on index 2, which now holds what was database 0.
+    assert_reply(&mut client, &["SWAPDB", "0", "2"], "+OK\r\n");
+    assert_reply(&mut client, &["GET", "s"], "$4\r\nmine\r\n");
+    assert_reply(&mut client, &["TYPE", "list"], "$4\r\nnone\r\n");
+    assert_eq!(redis.db(0).get(b"s").unwrap(), "theirs");
+    assert_reply(&mut client, &["SWAPDB", "0", "9"], "-ERR DB index is out of range\r\n");
+    assert_reply(&mut client, &["SWAPDB", "x", "1"], "-ERR invalid first DB index\r\n");
+    assert_reply(&mut client, &["SWAPDB", "1", "y"], "-ERR invalid second DB index\r\n");
+
+    assert_reply(&mut client, &["FLUSHDB"], "+OK\r\n");
+    assert_reply(&mut client, &["GET", "s"], "$-1\r\n");
+    assert_eq!(redis.db(0).keys(b"*").len(), 2);
+    assert_reply(&mut client, &["FLUSHALL"], "+OK\r\n");
+    assert!(redis.db(0).keys(b"*").is_empty());
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replication_stream_selects_databases() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "a", "m"], ":1\r\n");
+    assert_reply(&mut client, &["SELECT", "5"], "+OK\r\n");
+    assert_reply(&mut client, &["SADD", "b", "m"], ":1\r\n");
+    assert_reply(&mut client, &["SADD", "c", "m"], ":1\r\n");
+    assert_reply(&mut client, &["MOVE", "c", "0"], ":1\r\n");
+    assert_reply(&mut client, &["SELECT", "0"], "+OK\r\n");
+    assert_reply(&mut client, &["SREM", "c", "m"], ":1\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["SADD", "a", "m"]);
+    expected.extend(encode(&["SELECT", "5"]));
+    expected.extend(encode(&["SADD", "b", "m"]));
+    expected.extend(encode(&["SADD", "c", "m"]));
+    expected.extend(encode(&["MOVE", "c", "0"]));
+    expected.extend(encode(&["SELECT", "0"]));
+    expected.extend(encode(&["SREM", "c", "m"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    assert_eq!(redis.replication.get_current_offset(), expected.len() as u64);
+    client.shutdown();
+}
+
+fn rdb_string(out: &mut Vec<u8>, s: &str) {
+    out.push(s.len() as u8);
+    out.extend_from_slice(s.as_bytes());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rdb_loads_into_selected_databases() {
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.push(0xFA);
+    rdb_string(&mut rdb, "redis-ver");
+    rdb_string(&mut rdb, "7.2.0");
+    rdb.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x01, 0x00, 0x00]);
+    rdb_string(&mut rdb, "a");
+    rdb_string(&mut rdb, "zero");
+    rdb.extend_from_slice(&[0xFE, 0x07, 0xFB, 0x02, 0x01]);
+    rdb.push(0xFC);
+    rdb.extend_from_slice(&4_000_000_000_000u64.to_le_bytes());
+    rdb.push(0x00);
+    rdb_string(&mut rdb, "b");
+    rdb_string(&mut rdb, "seven");
+    rdb.push(0x00);
+    rdb_string(&mut rdb, "a");
+    rdb_string(&mut rdb, "also seven");
+    rdb.push(0xFF);
+    rdb.extend_from_slice(&[0; 8]);
+
+    let dir = std::env::temp_dir().join(format!("rdb-databases-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dump.rdb"), &rdb).unwrap();
+    let config = RedisConfig { dir: dir.to_string_lossy().into_owned(), ..RedisConfig::default() };
+
+    let redis = Redis::new(config.clone());
+    redis.parse_rdb_file().unwrap();
+    assert_eq!(redis.db(0).get(b"a").unwrap(), "zero");
+    assert_eq!(redis.db(7).get(b"a").unwrap(), "also seven");
+    assert_eq!(redis.db(7).get(b"b").unwrap(), "seven");
+    assert_eq!(redis.db(7).expire_time(b"b"), 4_000_000_000_000);
+    assert!(redis.db(0).keys(b"b").is_empty());
+
+    // A file using more databases than configured is rejected.
+    let redis = Redis::new(RedisConfig { databases: 4, ..config });
+    assert!(redis.parse_rdb_file().is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rdb_loads_compact_encodings() {
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.extend_from_slice(&[0xFE, 0x00]);
+    // A hash as a listpack: "f" and the integer 5
+    let listpack = [12, 0, 0, 0, 2, 0, 0x81, b'f', 2, 0x05, 1, 0xFF];
+    rdb.push(16);
+    rdb_string(&mut rdb, "h");
+    rdb.push(listpack.len() as u8);
+    rdb.extend_from_slice(&listpack);
+    // A set as an intset of 16 bit integers
+    let intset = [2, 0, 0, 0, 2, 0, 0, 0, 0xFF, 0xFF, 7, 0];
+    rdb.push(11);
+    rdb_string(&mut rdb, "i");
+    rdb.push(intset.len() as u8);
+    rdb.extend_from_slice(&intset);
+    // An LZF compressed string, with the LRU idle time Redis writes before some keys
+    rdb.extend_from_slice(&[0xF8, 0x05, 0x00]);
+    rdb_string(&mut rdb, "z");
+    rdb.extend_from_slice(&[0xC3, 6, 9, 0x02, b'a', b'b', b'c', 0x80, 0x02]);
+    rdb.push(0xFF);
+    rdb.extend_from_slice(&[0; 8]);
+
+    let dir = std::env::temp_dir().join(format!("rdb-encodings-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dump.rdb"), &rdb).unwrap();
+    let redis = Arc::new(Redis::new(RedisConfig { dir: dir.to_string_lossy().into_owned(), ..RedisConfig::default() }));
+    redis.parse_rdb_file().unwrap();
+
+    let mut client = start_client(&redis);
+    assert_reply(&mut client, &["HGET", "h", "f"], "$1\r\n5\r\n");
+    assert_reply(&mut client, &["SMISMEMBER", "i", "-1", "7", "8"], "*3\r\n:1\r\n:1\r\n:0\r\n");
+    assert_reply(&mut client, &["GET", "z"], "$9\r\nabcabcabc\r\n");
+
+    // A module value still can't be read.
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.push(7);
+    rdb_string(&mut rdb, "m");
+    rdb.push(0xFF);
+    std::fs::write(dir.join("dump.rdb"), &rdb).unwrap();
+    let error = Redis::new(RedisConfig { dir: dir.to_string_lossy().into_owned(), ..RedisConfig::default() })
+        .parse_rdb_file().unwrap_err();
+    assert_eq!(error.to_string(), "Unsupported RDB value type 7");
+    client.shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_save_and_load_every_type() {
+    let dir = std::env::temp_dir().join(format!("rdb-save-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = RedisConfig { dir: dir.to_string_lossy().into_owned(), ..RedisConfig::default() };
+    let redis = Arc::new(Redis::new(config.clone()));
+    let mut client = start_client(&redis);
+    let long = "x".repeat(20000);
+    assert_reply(&mut client, &["SET", "string", &long], "+OK\r\n");
+    assert_reply(&mut client, &["SET", "expiring", "v", "PXAT", "4000000000000"], "+OK\r\n");
+    assert_reply(&mut client, &["RPUSH", "list", "a", "b", "a"], ":3\r\n");
+    assert_reply(&mut client, &["SADD", "set", "x", "y"], ":2\r\n");
+    assert_reply(&mut client, &["HSET", "hash", "f", "1", "g", "2"], ":2\r\n");
+    assert_reply(&mut client, &["ZADD", "zset", "1.5", "a", "-inf", "b"], ":2\r\n");
+    // Enough entries for more than one node, with sequence numbers below the first entry's
+    for i in 1..=150 {
+        let id = format!("{}-{}", 1000 + i, 150 - i);
+        let value = i.to_string();
+        let fields: Vec<&str> = if i % 2 == 0 { vec!["a", &value] } else { vec!["a", &value, "b", "more"] };
+        let mut args = vec!["XADD", "stream", &id];
+        args.extend(fields);
+        assert_reply(&mut client, &args, &format!("${}\r\n{}\r\n", id.len(), id));
+    }
+    assert_reply(&mut client, &["XDEL", "stream", "1100-50"], ":1\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "stream", "g", "0"], "+OK\r\n");
+    assert_reply(&mut client, &["XGROUP", "CREATE", "stream", "h", "$"], "+OK\r\n");
+    send(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "stream", ">"]);
+    read_reply(&client, 1000).unwrap();
+    send(&mut client, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "stream", "0"]);
+    read_reply(&client, 1000).unwrap();
+    assert_reply(&mut client, &["XGROUP", "CREATECONSUMER", "stream", "g", "bob"], ":1\r\n");
+    assert_reply(&mut client, &["SELECT", "3"], "+OK\r\n");
+    assert_reply(&mut client, &["SET", "other", "db"], "+OK\r\n");
+    assert_reply(&mut client, &["SAVE"], "+OK\r\n");
+    client.shutdown();
+
+    let loaded = Arc::new(Redis::new(config));
+    loaded.parse_rdb_file().unwrap();
+    let mut client = start_client(&loaded);
+    assert_reply(&mut client, &["GET", "string"], &format!("${}\r\n{}\r\n", long.len(), long));
+    assert_reply(&mut client, &["PEXPIRETIME", "expiring"], ":4000000000000\r\n");
+    assert_reply(&mut client, &["LRANGE", "list", "0", "-1"], "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\na\r\n");
+    assert_reply(&mut client, &["SMISMEMBER", "set", "x", "y", "z"], "*3\r\n:1\r\n:1\r\n:0\r\n");
+    assert_reply(&mut client, &["HGET", "hash", "g"], "$1\r\n2\r\n");
+    assert_reply(&mut client, &["ZRANGE", "zset", "0", "-1", "WITHSCORES"], "*4\r\n$1\r\nb\r\n$4\r\n-inf\r\n$1\r\na\r\n$3\r\n1.5\r\n");
+    assert_reply(&mut client, &["SELECT", "3"], "+OK\r\n");
+    assert_reply(&mut client, &["GET", "other"], "$2\r\ndb\r\n");
+    client.shutdown();
+
+    let stream = |redis: &Redis| match redis.db(0).clone_value(b"stream") {
+        Some((ValueWrapper::Stream { entries, metadata }, _)) => (entries, metadata),
+        _ => panic!("not a stream"),
+    };
+    let (entries, metadata) = stream(&redis);
+    let (loaded_entries, loaded_metadata) = stream(&loaded);
+    assert_eq!(loaded_entries.len(), 149);
+    assert!(entries.iter().zip(&loaded_entries).all(|(entry, loaded)| entry.id == loaded.id && entry.fields == loaded.fields));
+    assert_eq!(loaded_metadata.last_id, metadata.last_id);
+    assert_eq!(loaded_metadata.groups.len(), 2);
+    for (name, group) in &metadata.groups {
+        let loaded = &loaded_metadata.groups[name];
+        assert_eq!(loaded.last_delivered, group.last_delivered);
+        assert_eq!(loaded.pending, group.pending);
+        let seen = |group: &ConsumerGroup| group.consumers.iter().map(|(name, consumer)| (name.clone(), consumer.seen_at)).collect::<BTreeMap<_, _>>();
+        assert_eq!(seen(loaded), seen(group));
+    }
+    // Both reads delivered the first two entries
+    assert!(loaded_metadata.groups[&b"g"[..]].pending.values().all(|pending| pending.deliveries == 2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+use redis_starter_rust::redis::storage::Storage;
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ttl_of_missing_and_persistent_keys() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    for command in ["TTL", "PTTL", "EXPIRETIME", "PEXPIRETIME"] {
+        assert_reply(&mut client, &[command, "missing"], ":-2\r\n");
+    }
+    assert_reply(&mut client, &["EXPIRE", "missing", "10"], ":0\r\n");
+    assert_reply(&mut client, &["PERSIST", "missing"], ":0\r\n");
+
+    assert_reply(&mut client, &["RPUSH", "list", "a"], ":1\r\n");
+    for command in ["TTL", "PTTL", "EXPIRETIME", "PEXPIRETIME"] {
+        assert_reply(&mut client, &[command, "list"], ":-1\r\n");
+    }
+    assert_reply(&mut client, &["PERSIST", "list"], ":0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_expire_family_on_every_type() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "list", "a"], ":1\r\n");
+    assert_reply(&mut client, &["HSET", "hash", "f", "v"], ":1\r\n");
+    assert_reply(&mut client, &["XADD", "stream", "1-1", "f", "v"], "$3\r\n1-1\r\n");
+    assert_reply(&mut client, &["SET", "string", "v"], "+OK\r\n");
+
+    assert_reply(&mut client, &["EXPIRE", "list", "100"], ":1\r\n");
+    assert_reply(&mut client, &["TTL", "list"], ":100\r\n");
+    let now = Storage::get_current_time_ms() as i64;
+    let at = (now / 1000 + 200).to_string();
+    assert_reply(&mut client, &["EXPIREAT", "hash", &at], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRETIME", "hash"], &format!(":{}\r\n", at));
+    assert_reply(&mut client, &["PEXPIRE", "stream", "300"], ":1\r\n");
+    let pttl = redis.db(0).expire_time(b"stream") - Storage::get_current_time_ms() as i64;
+    assert!((0..=300).contains(&pttl));
+    assert_reply(&mut client, &["PEXPIREAT", "string", &(now + 300).to_string()], ":1\r\n");
+    assert_reply(&mut client, &["PEXPIRETIME", "string"], &format!(":{}\r\n", now + 300));
+
+    thread::sleep(Duration::from_millis(400));
+    assert_reply(&mut client, &["TYPE", "stream"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["TYPE", "string"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["LLEN", "list"], ":1\r\n");
+    assert_reply(&mut client, &["HGET", "hash", "f"], "$1\r\nv\r\n");
+
+    assert_reply(&mut client, &["PERSIST", "list"], ":1\r\n");
+    assert_reply(&mut client, &["TTL", "list"], ":-1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "hash", "0"], ":1\r\n");
+    assert_reply(&mut client, &["TYPE", "hash"], "$4\r\nnone\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_expire_conditions() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "k", "m"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "100", "XX"], ":0\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "100", "GT"], ":0\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "100", "NX"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "200", "NX"], ":0\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "50", "GT"], ":0\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "200", "XX", "GT"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "300", "LT"], ":0\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "150", "LT"], ":1\r\n");
+    assert_reply(&mut client, &["TTL", "k"], ":150\r\n");
+
+    assert_reply(&mut client, &["EXPIRE", "k", "10", "NX", "XX"],
+        "-ERR NX and XX, GT or LT options at the same time are not compatible\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "10", "GT", "LT"],
+        "-ERR GT and LT options at the same time are not compatible\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "10", "SOON"], "-ERR Unsupported option SOON\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "ten"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "9223372036854775807"],
+        "-ERR invalid expire time in 'expire' command\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_overwrites_reset_ttl_and_updates_keep_it() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "s", "v", "EX", "100"], "+OK\r\n");
+    assert_reply(&mut client, &["SET", "s", "w"], "+OK\r\n");
+    assert_reply(&mut client, &["TTL", "s"], ":-1\r\n");
+
+    assert_reply(&mut client, &["HSET", "h", "a", "1"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "h", "100"], ":1\r\n");
+    assert_reply(&mut client, &["HSET", "h", "b", "2"], ":1\r\n");
+    assert_reply(&mut client, &["TTL", "h"], ":100\r\n");
+
+    // A key that empties out takes its TTL with it.
+    assert_reply(&mut client, &["HDEL", "h", "a", "b"], ":2\r\n");
+    assert_reply(&mut client, &["HSET", "h", "c", "3"], ":1\r\n");
+    assert_reply(&mut client, &["TTL", "h"], ":-1\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_expire_is_replicated_as_absolute_time() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "k", "m"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIREAT", "k", "4000000000"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "10", "NX"], ":0\r\n");
+    assert_reply(&mut client, &["PERSIST", "k"], ":1\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["SADD", "k", "m"]);
+    expected.extend(encode(&["PEXPIREAT", "k", "4000000000000"]));
+    expected.extend(encode(&["PERSIST", "k"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_active_expire_cycle_removes_unread_keys() {
+    let redis = Redis::new(RedisConfig::default());
+    for i in 0..100 {
+        redis.set(format!("gone:{}", i).as_bytes(), "v".into(), Some(10));
+    }
+    for i in 0..10 {
+        redis.set(format!("kept:{}", i).as_bytes(), "v".into(), Some(60_000));
+    }
+    redis.set(b"plain", "v".into(), None);
+    thread::sleep(Duration::from_millis(50));
+
+    // KEYS already hides the expired keys, though nothing has removed them yet.
+    assert_eq!(redis.keys(b"*").len(), 11);
+    assert!(redis.db(0).expire_time(b"gone:0") > 0);
+
+    let mut removed = 0;
+    for _ in 0..20 {
+        removed += redis.active_expire_cycle(Duration::from_millis(25));
+    }
+    assert_eq!(removed, 100);
+    assert_eq!(redis.db(0).expire_time(b"gone:0"), -2);
+    assert!(redis.db(0).expire_time(b"kept:0") > 0);
+    assert_eq!(redis.active_expire_cycle(Duration::from_millis(25)), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_expired_keys_are_replicated_as_del() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    redis.set(b"unread", "v".into(), Some(10));
+    redis.set(b"read", "v".into(), Some(10));
+    thread::sleep(Duration::from_millis(50));
+    assert_reply(&mut client, &["GET", "read"], "$-1\r\n");
+    while redis.active_expire_cycle(Duration::from_millis(25)) == 0 {}
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["DEL", "read"]);
+    expected.extend(encode(&["DEL", "unread"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replica_leaves_active_expiry_to_its_master() {
+    let config = RedisConfig {
+        replicaof_host: Some("localhost".to_string()),
+        replicaof_port: Some("6379".to_string()),
+        ..RedisConfig::default()
+    };
+    let redis = Redis::new(config);
+    redis.set(b"k", "v".into(), Some(10));
+    thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(redis.active_expire_cycle(Duration::from_millis(25)), 0);
+    assert!(redis.db(0).expire_time(b"k") > 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_active_expire_runs_in_the_background() {
+    let config = RedisConfig { hz: 100, ..RedisConfig::default() };
+    let redis = Arc::new(Redis::new(config));
+    Redis::start_active_expire(redis.clone());
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["CONFIG", "GET", "hz"], "$3\r\n100\r\n");
+    assert_reply(&mut client, &["SET", "k", "v", "PX", "20"], "+OK\r\n");
+    assert_reply(&mut client, &["RPUSH", "l", "a"], ":1\r\n");
+    assert_reply(&mut client, &["PEXPIRE", "l", "20"], ":1\r\n");
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(redis.db(0).expire_time(b"k"), -2);
+    assert_eq!(redis.db(0).expire_time(b"l"), -2);
+    client.shutdown();
+}
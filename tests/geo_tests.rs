@@ -0,0 +1,163 @@
+use std::io::Write;
+use std::sync::Arc;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, read_reply, start_client};
+
+const SICILY: [&str; 8] = ["GEOADD", "Sicily", "13.361389", "38.115556", "Palermo", "15.087269", "37.502669", "Catania"];
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_geoadd_geodist_and_geohash() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &SICILY, ":2\r\n");
+    assert_reply(&mut client, &["TYPE", "Sicily"], "$4\r\nzset\r\n");
+    // The score is the position's 52-bit geohash.
+    assert_reply(&mut client, &["ZSCORE", "Sicily", "Palermo"], "$16\r\n3479099956230698\r\n");
+    assert_reply(&mut client, &["GEODIST", "Sicily", "Palermo", "Catania"], "$11\r\n166274.1516\r\n");
+    assert_reply(&mut client, &["GEODIST", "Sicily", "Palermo", "Catania", "km"], "$8\r\n166.2742\r\n");
+    assert_reply(&mut client, &["GEODIST", "Sicily", "Palermo", "Catania", "MI"], "$8\r\n103.3182\r\n");
+    assert_reply(&mut client, &["GEODIST", "Sicily", "Foo", "Bar"], "$-1\r\n");
+    assert_reply(&mut client, &["GEOHASH", "Sicily", "Palermo", "Catania", "Nowhere"],
+        "*3\r\n$11\r\nsqc8b49rny0\r\n$11\r\nsqdtr74hyu0\r\n$-1\r\n");
+
+    assert_reply(&mut client, &["GEOADD", "Sicily", "NX", "0", "0", "Palermo"], ":0\r\n");
+    assert_reply(&mut client, &["GEOADD", "Sicily", "XX", "CH", "13.361389", "38.115556", "Palermo", "1", "1", "New"], ":0\r\n");
+    assert_reply(&mut client, &["GEOADD", "Sicily", "XX", "CH", "13", "38", "Palermo"], ":1\r\n");
+    assert_reply(&mut client, &["ZCARD", "Sicily"], ":2\r\n");
+
+    let positions = redis.db(0).geopos(b"Sicily", &["Catania".into(), "Nowhere".into()]).unwrap();
+    let (longitude, latitude) = positions[0].unwrap();
+    assert!((longitude - 15.087269).abs() < 1e-5 && (latitude - 37.502669).abs() < 1e-5);
+    assert_eq!(positions[1], None);
+    let reply = format!("*2\r\n*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n*-1\r\n",
+        longitude.to_string().len(), longitude, latitude.to_string().len(), latitude);
+    assert_reply(&mut client, &["GEOPOS", "Sicily", "Catania", "Nowhere"], &reply);
+    assert_reply(&mut client, &["GEOPOS", "missing", "a"], "*1\r\n*-1\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_geo_argument_errors() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["GEOADD", "k", "1", "2", "a", "3"],
+        "-ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... \r\n");
+    assert_reply(&mut client, &["GEOADD", "k", "NX", "XX", "1", "2", "a"],
+        "-ERR XX and NX options at the same time are not compatible\r\n");
+    assert_reply(&mut client, &["GEOADD", "k", "x", "2", "a"], "-ERR value is not a valid float\r\n");
+    assert_reply(&mut client, &["GEOADD", "k", "181", "2", "a"],
+        "-ERR invalid longitude,latitude pair 181.000000,2.000000\r\n");
+    assert_reply(&mut client, &["GEOADD", "k", "0", "86", "a"],
+        "-ERR invalid longitude,latitude pair 0.000000,86.000000\r\n");
+    assert_reply(&mut client, &["EXISTS", "k"], ":0\r\n");
+
+    assert_reply(&mut client, &["GEODIST", "k", "a", "b", "parsec"], "-ERR unsupported unit provided. please use M, KM, FT, MI\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMLONLAT", "0", "0"],
+        "-ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "BYRADIUS", "1", "m"],
+        "-ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMMEMBER", "a", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "m"],
+        "-ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMLONLAT", "0", "0", "BYRADIUS", "-1", "m"], "-ERR radius cannot be negative\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMLONLAT", "0", "0", "BYBOX", "1", "x", "m"], "-ERR need numeric width and height\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "m", "COUNT", "0"], "-ERR COUNT must be > 0\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "m", "ANY"],
+        "-ERR the ANY argument requires COUNT argument\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "k", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "m", "STOREDIST"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["GEOSEARCHSTORE", "d", "k", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "m", "WITHDIST"], "-ERR syntax error\r\n");
+
+    assert_reply(&mut client, &["GEOSEARCH", "missing", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "m"], "*0\r\n");
+    assert_reply(&mut client, &SICILY, ":2\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMMEMBER", "Rome", "BYRADIUS", "1", "m"],
+        "-ERR could not decode requested zset member\r\n");
+    assert_reply(&mut client, &["SET", "plain", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["GEOPOS", "plain", "a"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_geosearch() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &SICILY, ":2\r\n");
+    assert_reply(&mut client, &["GEOADD", "Sicily", "12.758489", "38.788135", "edge1", "17.241510", "38.788135", "edge2"], ":2\r\n");
+
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "ASC"],
+        "*2\r\n$7\r\nCatania\r\n$7\r\nPalermo\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "DESC", "WITHDIST"],
+        "*2\r\n*2\r\n$7\r\nPalermo\r\n$8\r\n190.4424\r\n*2\r\n$7\r\nCatania\r\n$7\r\n56.4413\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "ASC", "WITHDIST", "WITHHASH"],
+        "*4\r\n*3\r\n$7\r\nCatania\r\n$7\r\n56.4413\r\n:3479447370796909\r\n\
+         *3\r\n$7\r\nPalermo\r\n$8\r\n190.4424\r\n:3479099956230698\r\n\
+         *3\r\n$5\r\nedge2\r\n$8\r\n279.7403\r\n:3481342659049484\r\n\
+         *3\r\n$5\r\nedge1\r\n$8\r\n279.7405\r\n:3479273021651468\r\n");
+    // COUNT alone sorts by distance so it keeps the nearest.
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "COUNT", "1"],
+        "*1\r\n$7\r\nCatania\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "170", "km", "ASC", "WITHDIST"],
+        "*3\r\n*2\r\n$7\r\nPalermo\r\n$6\r\n0.0000\r\n*2\r\n$5\r\nedge1\r\n$7\r\n91.4007\r\n*2\r\n$7\r\nCatania\r\n$8\r\n166.2742\r\n");
+
+    // ANY stops at the first matches found, whichever they are.
+    client.clear_read_data();
+    client.write_all(&encode(&["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "COUNT", "2", "ANY"])).unwrap();
+    assert!(read_reply(&client, 1000).unwrap().starts_with(b"*2\r\n"));
+
+    let (longitude, latitude) = redis.db(0).geopos(b"Sicily", &["Catania".into()]).unwrap()[0].unwrap();
+    let coordinates = format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+        longitude.to_string().len(), longitude, latitude.to_string().len(), latitude);
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "100", "km", "WITHCOORD"],
+        &format!("*1\r\n*2\r\n$7\r\nCatania\r\n{}", coordinates));
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_geosearchstore() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &SICILY, ":2\r\n");
+    assert_reply(&mut client, &["GEOADD", "Sicily", "12.758489", "38.788135", "edge1", "17.241510", "38.788135", "edge2"], ":2\r\n");
+
+    assert_reply(&mut client, &["GEOSEARCHSTORE", "key1", "Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "ASC", "COUNT", "3"], ":3\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "key1", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "ASC"],
+        "*3\r\n$7\r\nCatania\r\n$7\r\nPalermo\r\n$5\r\nedge2\r\n");
+    assert_reply(&mut client, &["ZSCORE", "key1", "Palermo"], "$16\r\n3479099956230698\r\n");
+
+    assert_reply(&mut client, &["GEOSEARCHSTORE", "key2", "Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "ASC", "COUNT", "3", "STOREDIST"], ":3\r\n");
+    assert_reply(&mut client, &["ZRANGE", "key2", "0", "-1", "WITHSCORES"],
+        "*6\r\n$7\r\nCatania\r\n$18\r\n56.441257870158204\r\n$7\r\nPalermo\r\n$18\r\n190.44242984775784\r\n$5\r\nedge2\r\n$17\r\n279.7403417843143\r\n");
+
+    // An empty result deletes the destination.
+    assert_reply(&mut client, &["GEOSEARCHSTORE", "key2", "Sicily", "FROMLONLAT", "0", "0", "BYRADIUS", "1", "km"], ":0\r\n");
+    assert_reply(&mut client, &["EXISTS", "key2"], ":0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_geo_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &SICILY, ":2\r\n");
+    assert_reply(&mut client, &["GEOADD", "Sicily", "NX", "0", "0", "Palermo"], ":0\r\n");
+    assert_reply(&mut client, &["GEOSEARCH", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "1", "km"], "*1\r\n$7\r\nPalermo\r\n");
+    assert_reply(&mut client, &["GEOSEARCHSTORE", "near", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "1", "km"], ":1\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&SICILY);
+    expected.extend(encode(&["GEOSEARCHSTORE", "near", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "1", "km"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hset_and_reads() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["HSET", "user", "name", "ada", "lang", "rust"], ":2\r\n");
+    assert_reply(&mut client, &["HSET", "user", "name", "grace"], ":0\r\n");
+    assert_reply(&mut client, &["HGET", "user", "name"], "$5\r\ngrace\r\n");
+    assert_reply(&mut client, &["HGET", "user", "missing"], "$-1\r\n");
+    assert_reply(&mut client, &["HMGET", "user", "lang", "missing"], "*2\r\n$4\r\nrust\r\n$-1\r\n");
+    assert_reply(&mut client, &["HLEN", "user"], ":2\r\n");
+    assert_reply(&mut client, &["HEXISTS", "user", "lang"], ":1\r\n");
+    assert_reply(&mut client, &["HEXISTS", "user", "age"], ":0\r\n");
+    assert_reply(&mut client, &["HSTRLEN", "user", "name"], ":5\r\n");
+    assert_reply(&mut client, &["TYPE", "user"], "$4\r\nhash\r\n");
+
+    let mut all = redis.db(0).hgetall(b"user").unwrap();
+    all.sort();
+    assert_eq!(all, vec![("lang".into(), "rust".into()), ("name".into(), "grace".into())]);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hdel_removes_emptied_hash() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["HSET", "h", "a", "1", "b", "2"], ":2\r\n");
+    assert_reply(&mut client, &["HDEL", "h", "a", "zzz"], ":1\r\n");
+    assert_reply(&mut client, &["HDEL", "h", "b"], ":1\r\n");
+    assert_reply(&mut client, &["TYPE", "h"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["HGETALL", "h"], "*0\r\n");
+    assert_reply(&mut client, &["HDEL", "h", "b"], ":0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hash_increments() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["HINCRBY", "h", "n", "5"], ":5\r\n");
+    assert_reply(&mut client, &["HINCRBY", "h", "n", "-7"], ":-2\r\n");
+    assert_reply(&mut client, &["HINCRBY", "h", "n", "x"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["HSET", "h", "big", "9223372036854775807"], ":1\r\n");
+    assert_reply(&mut client, &["HINCRBY", "h", "big", "1"], "-ERR increment or decrement would overflow\r\n");
+
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "f", "10.5"], "$4\r\n10.5\r\n");
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "f", "0.1"], "$4\r\n10.6\r\n");
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "f", "-5"], "$3\r\n5.6\r\n");
+    assert_reply(&mut client, &["HGET", "h", "f"], "$3\r\n5.6\r\n");
+    assert_reply(&mut client, &["HSET", "h", "g", "10.50"], ":1\r\n");
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "g", "0.1"], "$4\r\n10.6\r\n");
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "n", "2"], "$1\r\n0\r\n");
+    assert_reply(&mut client, &["HSET", "h", "s", "abc"], ":1\r\n");
+    assert_reply(&mut client, &["HINCRBY", "h", "s", "1"], "-ERR hash value is not an integer\r\n");
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "s", "1"], "-ERR hash value is not a float\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hsetnx_and_hrandfield() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["HSETNX", "h", "a", "1"], ":1\r\n");
+    assert_reply(&mut client, &["HSETNX", "h", "a", "2"], ":0\r\n");
+    assert_reply(&mut client, &["HGET", "h", "a"], "$1\r\n1\r\n");
+
+    assert_reply(&mut client, &["HRANDFIELD", "h"], "$1\r\na\r\n");
+    assert_reply(&mut client, &["HRANDFIELD", "h", "5", "WITHVALUES"], "*2\r\n$1\r\na\r\n$1\r\n1\r\n");
+    assert_reply(&mut client, &["HRANDFIELD", "h", "-3"], "*3\r\n$1\r\na\r\n$1\r\na\r\n$1\r\na\r\n");
+    assert_reply(&mut client, &["HRANDFIELD", "nohash"], "$-1\r\n");
+    assert_reply(&mut client, &["HRANDFIELD", "nohash", "2"], "*0\r\n");
+
+    assert_eq!(redis.db(0).hrandfield(b"h", 0).unwrap().len(), 0);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hash_wrong_type() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "s", "v"], "+OK\r\n");
+    let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    assert_reply(&mut client, &["HSET", "s", "f", "v"], wrongtype);
+    assert_reply(&mut client, &["HGET", "s", "f"], wrongtype);
+    assert_reply(&mut client, &["HGETALL", "s"], wrongtype);
+    assert_reply(&mut client, &["HDEL", "s", "f"], wrongtype);
+    assert_reply(&mut client, &["HINCRBY", "s", "f", "1"], wrongtype);
+
+    assert_reply(&mut client, &["HSET", "h", "f", "v"], ":1\r\n");
+    assert_reply(&mut client, &["LPUSH", "h", "x"], wrongtype);
+    assert_reply(&mut client, &["GET", "h"], wrongtype);
+    assert_reply(&mut client, &["GET", "missing"], "$-1\r\n");
+    assert_reply(&mut client, &["HSET", "h", "f"], "-ERR wrong number of arguments for 'hset' command\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hash_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["HSET", "h", "a", "1"], ":1\r\n");
+    assert_reply(&mut client, &["HGET", "h", "a"], "$1\r\n1\r\n");
+    assert_reply(&mut client, &["HINCRBYFLOAT", "h", "a", "0.5"], "$3\r\n1.5\r\n");
+    assert_reply(&mut client, &["HDEL", "h", "missing"], ":0\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["HSET", "h", "a", "1"]);
+    expected.extend(encode(&["HSET", "h", "a", "1.5"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pfadd_and_pfcount() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["PFADD", "hll", "a", "b", "c", "d", "e", "f", "g"], ":1\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "hll"], ":7\r\n");
+    assert_reply(&mut client, &["PFADD", "hll", "a", "b"], ":0\r\n");
+    assert_reply(&mut client, &["TYPE", "hll"], "$6\r\nstring\r\n");
+    assert_eq!(&redis.db(0).get(b"hll").unwrap()[..4], b"HYLL");
+
+    // Creating an empty one counts as a change.
+    assert_reply(&mut client, &["PFADD", "empty"], ":1\r\n");
+    assert_reply(&mut client, &["PFADD", "empty"], ":0\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "empty"], ":0\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "missing"], ":0\r\n");
+
+    assert_reply(&mut client, &["PFADD", "other", "1", "2", "3", "a"], ":1\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "hll", "other", "missing"], ":10\r\n");
+
+    assert_reply(&mut client, &["SET", "plain", "not a hyperloglog"], "+OK\r\n");
+    assert_reply(&mut client, &["PFADD", "plain", "x"], "-WRONGTYPE Key is not a valid HyperLogLog string value.\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "hll", "plain"], "-WRONGTYPE Key is not a valid HyperLogLog string value.\r\n");
+    assert_reply(&mut client, &["SADD", "set", "x"], ":1\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "set"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    // A sparse HyperLogLog cut short is corrupt rather than of the wrong type.
+    assert_reply(&mut client, &["APPEND", "empty", "\u{1}"], ":19\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "empty"], "-INVALIDOBJ Corrupted HLL object detected\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pfcount_error_stays_small() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    let total = 100_000;
+    for batch in 0..100 {
+        let elements: Vec<String> = (0..total / 100).map(|i| format!("visitor:{}", batch * 1000 + i)).collect();
+        let mut args = vec!["PFADD", "visitors"];
+        args.extend(elements.iter().map(|element| element.as_str()));
+        assert_reply(&mut client, &args, ":1\r\n");
+    }
+    // A dense HyperLogLog is 16 bytes of header and 12288 of registers, whatever it counts.
+    assert_reply(&mut client, &["STRLEN", "visitors"], ":12304\r\n");
+    let estimate = redis.db(0).pfcount(&["visitors".into()]).unwrap() as f64;
+    assert!((estimate - total as f64).abs() / (total as f64) < 0.02, "estimate {}", estimate);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pfmerge() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["PFADD", "hll1", "foo", "bar", "zap", "a"], ":1\r\n");
+    assert_reply(&mut client, &["PFADD", "hll2", "a", "b", "c", "foo"], ":1\r\n");
+    assert_reply(&mut client, &["PFMERGE", "hll3", "hll1", "hll2"], "+OK\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "hll3"], ":6\r\n");
+    assert_reply(&mut client, &["STRLEN", "hll3"], ":12304\r\n");
+
+    // The destination's own elements are part of the union.
+    assert_reply(&mut client, &["PFADD", "hll4", "x"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "hll4", "100"], ":1\r\n");
+    assert_reply(&mut client, &["PFMERGE", "hll4", "hll1", "missing"], "+OK\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "hll4"], ":5\r\n");
+    assert_reply(&mut client, &["TTL", "hll4"], ":100\r\n");
+    assert_reply(&mut client, &["PFMERGE", "new"], "+OK\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "new"], ":0\r\n");
+
+    // The value is a plain string, so copying its bytes elsewhere (as RDB files and full
+    // resyncs do) keeps the HyperLogLog intact.
+    let bytes = redis.db(0).get(b"hll1").unwrap();
+    redis.db(1).set(b"copy", bytes, None);
+    assert_eq!(redis.db(1).pfcount(&["copy".into()]).unwrap(), 4);
+
+    assert_reply(&mut client, &["SET", "plain", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["PFMERGE", "hll3", "plain"], "-WRONGTYPE Key is not a valid HyperLogLog string value.\r\n");
+    assert_reply(&mut client, &["PFMERGE", "plain", "hll1"], "-WRONGTYPE Key is not a valid HyperLogLog string value.\r\n");
+    assert_reply(&mut client, &["GET", "plain"], "$1\r\nx\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hyperloglog_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["PFADD", "a", "x", "y"], ":1\r\n");
+    assert_reply(&mut client, &["PFADD", "a", "x"], ":0\r\n");
+    assert_reply(&mut client, &["PFCOUNT", "a"], ":2\r\n");
+    assert_reply(&mut client, &["PFMERGE", "b", "a"], "+OK\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["PFADD", "a", "x", "y"]);
+    expected.extend(encode(&["PFMERGE", "b", "a"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use bytes::Bytes;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+fn sorted(mut keys: Vec<Bytes>) -> Vec<Bytes> {
+    keys.sort();
+    keys
+}
+
+/// Walks the whole keyspace with SCAN, calling `between` after every step.
+fn scan_all(redis: &Redis, count: usize, pattern: Option<&[u8]>, type_name: Option<&str>, mut between: impl FnMut(usize)) -> Vec<Bytes> {
+    let (mut cursor, mut keys, mut steps) = (0, Vec::new(), 0);
+    loop {
+        let (next, batch) = redis.db(0).scan(cursor, count, pattern, type_name);
+        keys.extend(batch);
+        steps += 1;
+        between(steps);
+        if next == 0 {
+            return keys;
+        }
+        cursor = next;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_keys_glob_patterns() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    for key in ["user:1", "user:2", "user:10", "users", "hello", "hallo", "hxllo", "a*b"] {
+        redis.set(key.as_bytes(), "v".into(), None);
+    }
+    assert_eq!(sorted(redis.keys(b"user:*")), vec!["user:1", "user:10", "user:2"]);
+    assert_eq!(sorted(redis.keys(b"user:?")), vec!["user:1", "user:2"]);
+    assert_eq!(sorted(redis.keys(b"h[ae]llo")), vec!["hallo", "hello"]);
+    assert_eq!(sorted(redis.keys(b"h[^e]llo")), vec!["hallo", "hxllo"]);
+    assert_eq!(sorted(redis.keys(b"h[a-f]llo")), vec!["hallo", "hello"]);
+    assert_eq!(redis.keys(b"a\\*b"), vec!["a*b"]);
+    assert_eq!(redis.keys(b"*").len(), 8);
+
+    assert_reply(&mut client, &["KEYS", "use?s"], "*1\r\n$5\r\nusers\r\n");
+    assert_reply(&mut client, &["KEYS", "nothing*"], "*0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_scan_visits_every_key_with_filters() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    for i in 0..50 {
+        redis.set(format!("str:{}", i).as_bytes(), "v".into(), None);
+    }
+    for i in 0..20 {
+        redis.db(0).sadd(format!("set:{}", i).as_bytes(), &["m".into()]).unwrap();
+    }
+
+    let all: HashSet<Bytes> = scan_all(&redis, 7, None, None, |_| {}).into_iter().collect();
+    assert_eq!(all.len(), 70);
+    let matched = scan_all(&redis, 5, Some(b"str:1*"), None, |_| {});
+    assert_eq!(matched.len(), 11);
+    let sets = scan_all(&redis, 10, None, Some("set"), |_| {});
+    assert_eq!(sets.len(), 20);
+    assert!(sets.iter().all(|key| key.starts_with(b"set:")));
+
+    // A single step big enough for everything finishes the iteration.
+    let (next, keys) = redis.db(0).scan(0, 1000, Some(b"set:1?"), None);
+    assert_eq!((next, keys.len()), (0, 10));
+
+    assert_reply(&mut client, &["SCAN", "0", "MATCH", "set:0", "COUNT", "1000"], "*2\r\n$1\r\n0\r\n*1\r\n$5\r\nset:0\r\n");
+    assert_reply(&mut client, &["SCAN", "abc"], "-ERR invalid cursor\r\n");
+    assert_reply(&mut client, &["SCAN", "0", "COUNT", "0"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["SCAN", "0", "MATCH"], "-ERR syntax error\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_scan_cursor_survives_writes() {
+    let redis = Redis::new(RedisConfig::default());
+    for i in 0..100 {
+        redis.set(format!("stable:{}", i).as_bytes(), "v".into(), None);
+    }
+    for i in 0..100 {
+        redis.set(format!("doomed:{}", i).as_bytes(), "v".into(), None);
+    }
+
+    let mut added = 0;
+    let seen: HashSet<Bytes> = scan_all(&redis, 10, None, None, |step| {
+        // Churn the keyspace between steps: new keys come in, old ones go.
+        for _ in 0..5 {
+            redis.set(format!("new:{}", added).as_bytes(), "v".into(), None);
+            added += 1;
+        }
+        redis.db(0).remove(format!("doomed:{}", step).as_bytes());
+    }).into_iter().collect();
+
+    for i in 0..100 {
+        assert!(seen.contains(format!("stable:{}", i).as_bytes()), "stable:{} was never returned", i);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_del_exists_and_dbsize_on_every_type() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "string", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["RPUSH", "list", "a"], ":1\r\n");
+    assert_reply(&mut client, &["HSET", "hash", "f", "v"], ":1\r\n");
+    assert_reply(&mut client, &["SADD", "set", "m"], ":1\r\n");
+    assert_reply(&mut client, &["ZADD", "zset", "1", "m"], ":1\r\n");
+    assert_reply(&mut client, &["XADD", "stream", "1-1", "f", "v"], "$3\r\n1-1\r\n");
+    assert_reply(&mut client, &["SET", "short", "v", "PX", "10"], "+OK\r\n");
+    std::thread::sleep(std::time::Duration::from_millis(30));
+
+    // Expired keys no longer count, even before anything removed them.
+    assert_reply(&mut client, &["DBSIZE"], ":6\r\n");
+    assert_reply(&mut client, &["EXISTS", "string", "list", "string", "short", "missing"], ":3\r\n");
+    assert_reply(&mut client, &["TOUCH", "hash", "set", "missing"], ":2\r\n");
+    assert_reply(&mut client, &["DEL", "string", "list", "hash", "missing"], ":3\r\n");
+    assert_reply(&mut client, &["UNLINK", "set", "zset", "stream"], ":3\r\n");
+    assert_reply(&mut client, &["DEL", "string"], ":0\r\n");
+    assert_reply(&mut client, &["DBSIZE"], ":0\r\n");
+    assert_reply(&mut client, &["RANDOMKEY"], "$-1\r\n");
+    assert_reply(&mut client, &["SET", "only", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["RANDOMKEY"], "$4\r\nonly\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_emptied_lists_are_removed() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "l", "a"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "l", "100"], ":1\r\n");
+    assert_reply(&mut client, &["LPOP", "l"], "$1\r\na\r\n");
+    assert_reply(&mut client, &["EXISTS", "l"], ":0\r\n");
+    assert_reply(&mut client, &["RPUSH", "l", "b"], ":1\r\n");
+    assert_reply(&mut client, &["TTL", "l"], ":-1\r\n");
+    assert_reply(&mut client, &["RPOP", "l"], "$1\r\nb\r\n");
+    assert_reply(&mut client, &["TYPE", "l"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["RPUSH", "l", "c"], ":1\r\n");
+    assert_reply(&mut client, &["LTRIM", "l", "1", "-1"], "+OK\r\n");
+    assert_reply(&mut client, &["DBSIZE"], ":0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rename_and_copy() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["HSET", "h", "f", "v"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "h", "100"], ":1\r\n");
+    assert_reply(&mut client, &["RENAME", "h", "h2"], "+OK\r\n");
+    assert_reply(&mut client, &["EXISTS", "h"], ":0\r\n");
+    assert_reply(&mut client, &["HGET", "h2", "f"], "$1\r\nv\r\n");
+    assert!(redis.db(0).expire_time(b"h2") > 0);
+    assert_reply(&mut client, &["RENAME", "h", "x"], "-ERR no such key\r\n");
+    assert_reply(&mut client, &["RENAME", "h2", "h2"], "+OK\r\n");
+
+    assert_reply(&mut client, &["SET", "s", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["RENAMENX", "s", "h2"], ":0\r\n");
+    assert_reply(&mut client, &["RENAMENX", "s", "s2"], ":1\r\n");
+    // RENAME replaces whatever the new name held.
+    assert_reply(&mut client, &["RENAME", "s2", "h2"], "+OK\r\n");
+    assert_reply(&mut client, &["TYPE", "h2"], "$6\r\nstring\r\n");
+    assert_reply(&mut client, &["TTL", "h2"], ":-1\r\n");
+
+    // Copies are independent of the original.
+    assert_reply(&mut client, &["SADD", "set", "a"], ":1\r\n");
+    assert_reply(&mut client, &["COPY", "set", "copy"], ":1\r\n");
+    assert_reply(&mut client, &["SADD", "copy", "b"], ":1\r\n");
+    assert_reply(&mut client, &["SCARD", "set"], ":1\r\n");
+    assert_reply(&mut client, &["COPY", "set", "copy"], ":0\r\n");
+    assert_reply(&mut client, &["COPY", "set", "copy", "REPLACE"], ":1\r\n");
+    assert_reply(&mut client, &["SCARD", "copy"], ":1\r\n");
+    assert_reply(&mut client, &["COPY", "missing", "copy", "REPLACE"], ":0\r\n");
+    assert_reply(&mut client, &["COPY", "set", "set"], "-ERR source and destination objects are the same\r\n");
+
+    assert_reply(&mut client, &["COPY", "set", "set", "DB", "1"], ":1\r\n");
+    assert_eq!(redis.db(1).smembers(b"set").unwrap().len(), 1);
+    assert_reply(&mut client, &["COPY", "set", "set", "DB", "99"], "-ERR DB index is out of range\r\n");
+    assert_reply(&mut client, &["COPY", "set", "other", "DB"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["COPY", "set", "other", "LATER"], "-ERR syntax error\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_keyspace_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "a", "m"], ":1\r\n");
+    assert_reply(&mut client, &["COPY", "a", "b"], ":1\r\n");
+    assert_reply(&mut client, &["RENAME", "b", "c"], "+OK\r\n");
+    assert_reply(&mut client, &["DEL", "missing"], ":0\r\n");
+    assert_reply(&mut client, &["EXISTS", "a"], ":1\r\n");
+    assert_reply(&mut client, &["DEL", "a", "c"], ":2\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["SADD", "a", "m"]);
+    expected.extend(encode(&["COPY", "a", "b"]));
+    expected.extend(encode(&["RENAME", "b", "c"]));
+    expected.extend(encode(&["DEL", "a", "c"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
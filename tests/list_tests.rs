@@ -1,13 +1,16 @@
-use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::Arc;
 use std::io::{Write, Read};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
 use redis_starter_rust::redis::core::Redis;
 use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+use redis_starter_rust::redis::storage::ListEnd;
 use redis_starter_rust::client_handler::ClientHandler;
 
 mod utils;
 use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
 
 fn read_response(stream: &mut MockTcpStream) -> String {
     let mut buffer = [0; 1024];
@@ -15,12 +18,12 @@ fn read_response(stream: &mut MockTcpStream) -> String {
     String::from_utf8_lossy(&buffer[..bytes_read]).into_owned()
 }
 
-#[test]
-fn test_basic_list_push_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_basic_list_push_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Test LPUSH
     {
@@ -43,12 +46,12 @@ fn test_basic_list_push_operations() {
     assert!(client.wait_for_pattern("*2\r\n$5\r\nfirst\r\n$4\r\nlast\r\n", 1000));
 }
 
-#[test]
-fn test_list_pop_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_pop_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Setup test data
     client.write_all(b"*3\r\n$5\r\nLPUSH\r\n$6\r\nmylist\r\n$5\r\nfirst\r\n").unwrap();
@@ -73,12 +76,12 @@ fn test_list_pop_operations() {
     assert!(client.wait_for_pattern(":1\r\n", 1000));
 }
 
-#[test]
-fn test_empty_list_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_empty_list_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Test LPOP on empty list
     client.write_all(b"*2\r\n$4\r\nLPOP\r\n$9\r\nemptylist\r\n").unwrap();
@@ -97,12 +100,12 @@ fn test_empty_list_operations() {
     assert!(client.wait_for_pattern("*0\r\n", 1000));
 }
 
-#[test]
-fn test_list_range_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_range_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Setup test data
     client.write_all(b"*3\r\n$5\r\nRPUSH\r\n$6\r\nmylist\r\n$3\r\none\r\n").unwrap();
@@ -126,12 +129,12 @@ fn test_list_range_operations() {
     assert!(client.wait_for_pattern("*2\r\n$5\r\nthree\r\n$4\r\nfour\r\n", 1000));
 }
 
-#[test]
-fn test_list_trim_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_trim_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Setup test data
     client.write_all(b"*3\r\n$5\r\nRPUSH\r\n$6\r\nmylist\r\n$3\r\none\r\n").unwrap();
@@ -155,12 +158,12 @@ fn test_list_trim_operations() {
     assert!(client.wait_for_pattern("*2\r\n$3\r\ntwo\r\n$5\r\nthree\r\n", 1000));
 }
 
-#[test]
-fn test_list_position_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_position_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Setup test data
     client.write_all(b"*3\r\n$5\r\nRPUSH\r\n$6\r\nmylist\r\n$3\r\none\r\n").unwrap();
@@ -188,12 +191,12 @@ fn test_list_position_operations() {
     assert!(client.wait_for_pattern(":-1\r\n", 1000));
 }
 
-#[test]
-fn test_list_insert_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_insert_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Setup test data
     client.write_all(b"*3\r\n$5\r\nRPUSH\r\n$6\r\nmylist\r\n$3\r\none\r\n").unwrap();
@@ -219,12 +222,12 @@ fn test_list_insert_operations() {
     assert!(client.wait_for_pattern("*4\r\n$3\r\none\r\n$3\r\ntwo\r\n$5\r\nthree\r\n$4\r\nfour\r\n", 1000));
 }
 
-#[test]
-fn test_list_set_and_get_operations() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_set_and_get_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let (mut client, server) = MockTcpStream::new_pair();
     let mut handler = ClientHandler::new(server, Arc::clone(&redis));
-    let handle = handler.start();
+    let _handle = handler.start();
 
     // Setup test data
     client.write_all(b"*3\r\n$5\r\nRPUSH\r\n$6\r\nmylist\r\n$3\r\none\r\n").unwrap();
@@ -252,3 +255,210 @@ fn test_list_set_and_get_operations() {
     client.write_all(b"*3\r\n$6\r\nLINDEX\r\n$6\r\nmylist\r\n$1\r\n5\r\n").unwrap();
     assert!(client.wait_for_pattern("$-1\r\n", 1000));
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_variadic_push_and_pop_with_count() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["LPUSH", "list", "a", "b", "c"], ":3\r\n");
+    assert_reply(&mut client, &["RPUSH", "list", "d", "e"], ":5\r\n");
+    assert_reply(&mut client, &["LRANGE", "list", "0", "-1"], "*5\r\n$1\r\nc\r\n$1\r\nb\r\n$1\r\na\r\n$1\r\nd\r\n$1\r\ne\r\n");
+    assert_reply(&mut client, &["LPUSHX", "missing", "a"], ":0\r\n");
+    assert_reply(&mut client, &["RPUSHX", "missing", "a", "b"], ":0\r\n");
+    assert_reply(&mut client, &["EXISTS", "missing"], ":0\r\n");
+    assert_reply(&mut client, &["LPUSHX", "list", "x", "y"], ":7\r\n");
+    assert_reply(&mut client, &["RPUSHX", "list", "z"], ":8\r\n");
+
+    assert_reply(&mut client, &["LPOP", "list", "3"], "*3\r\n$1\r\ny\r\n$1\r\nx\r\n$1\r\nc\r\n");
+    assert_reply(&mut client, &["RPOP", "list", "2"], "*2\r\n$1\r\nz\r\n$1\r\ne\r\n");
+    assert_reply(&mut client, &["LPOP", "list", "0"], "*0\r\n");
+    assert_reply(&mut client, &["RPOP", "list", "10"], "*3\r\n$1\r\nd\r\n$1\r\na\r\n$1\r\nb\r\n");
+    assert_reply(&mut client, &["EXISTS", "list"], ":0\r\n");
+    assert_reply(&mut client, &["LPOP", "list", "1"], "*-1\r\n");
+    assert_reply(&mut client, &["LPOP", "list"], "$-1\r\n");
+    assert_reply(&mut client, &["LPOP", "list", "-1"], "-ERR value is out of range, must be positive\r\n");
+
+    assert_reply(&mut client, &["SET", "string", "x"], "+OK\r\n");
+    let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    assert_reply(&mut client, &["LPUSHX", "string", "a"], wrongtype);
+    assert_reply(&mut client, &["RPOP", "string"], wrongtype);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lrem() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "list", "a", "b", "a", "c", "a", "b", "a"], ":7\r\n");
+    assert_reply(&mut client, &["LREM", "list", "2", "a"], ":2\r\n");
+    assert_reply(&mut client, &["LRANGE", "list", "0", "-1"], "*5\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\na\r\n");
+    assert_reply(&mut client, &["LREM", "list", "-1", "b"], ":1\r\n");
+    assert_reply(&mut client, &["LRANGE", "list", "0", "-1"], "*4\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n$1\r\na\r\n");
+    assert_reply(&mut client, &["LREM", "list", "0", "a"], ":2\r\n");
+    assert_reply(&mut client, &["LREM", "list", "0", "nothing"], ":0\r\n");
+    assert_reply(&mut client, &["LREM", "list", "x", "b"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["LREM", "list", "0", "b"], ":1\r\n");
+    assert_reply(&mut client, &["LREM", "list", "0", "c"], ":1\r\n");
+    assert_reply(&mut client, &["EXISTS", "list"], ":0\r\n");
+    assert_reply(&mut client, &["LREM", "list", "0", "c"], ":0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lmove_and_rpoplpush() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "jobs", "1", "2", "3"], ":3\r\n");
+    assert_reply(&mut client, &["RPOPLPUSH", "jobs", "processing"], "$1\r\n3\r\n");
+    assert_reply(&mut client, &["LMOVE", "jobs", "processing", "LEFT", "RIGHT"], "$1\r\n1\r\n");
+    assert_reply(&mut client, &["LRANGE", "processing", "0", "-1"], "*2\r\n$1\r\n3\r\n$1\r\n1\r\n");
+    assert_reply(&mut client, &["LMOVE", "jobs", "processing", "right", "left"], "$1\r\n2\r\n");
+    assert_reply(&mut client, &["EXISTS", "jobs"], ":0\r\n");
+    assert_reply(&mut client, &["LMOVE", "jobs", "processing", "LEFT", "LEFT"], "$-1\r\n");
+    assert_reply(&mut client, &["LMOVE", "jobs", "processing", "UP", "LEFT"], "-ERR syntax error\r\n");
+
+    // Moving within one list rotates it, and a single element list keeps its TTL.
+    assert_reply(&mut client, &["LMOVE", "processing", "processing", "LEFT", "RIGHT"], "$1\r\n2\r\n");
+    assert_reply(&mut client, &["LRANGE", "processing", "0", "-1"], "*3\r\n$1\r\n3\r\n$1\r\n1\r\n$1\r\n2\r\n");
+    assert_reply(&mut client, &["RPUSH", "single", "x"], ":1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "single", "100"], ":1\r\n");
+    assert_reply(&mut client, &["RPOPLPUSH", "single", "single"], "$1\r\nx\r\n");
+    assert_reply(&mut client, &["TTL", "single"], ":100\r\n");
+
+    // Nothing moves unless both keys hold lists.
+    assert_reply(&mut client, &["SET", "string", "x"], "+OK\r\n");
+    let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    assert_reply(&mut client, &["LMOVE", "processing", "string", "LEFT", "LEFT"], wrongtype);
+    assert_reply(&mut client, &["LMOVE", "string", "processing", "LEFT", "LEFT"], wrongtype);
+    assert_reply(&mut client, &["LLEN", "processing"], ":3\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lmpop() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["LMPOP", "2", "a", "b", "LEFT"], "*-1\r\n");
+    assert_reply(&mut client, &["RPUSH", "b", "1", "2", "3"], ":3\r\n");
+    assert_reply(&mut client, &["LMPOP", "2", "a", "b", "LEFT"], "*2\r\n$1\r\nb\r\n*1\r\n$1\r\n1\r\n");
+    assert_reply(&mut client, &["LMPOP", "2", "a", "b", "RIGHT", "COUNT", "5"], "*2\r\n$1\r\nb\r\n*2\r\n$1\r\n3\r\n$1\r\n2\r\n");
+    assert_reply(&mut client, &["EXISTS", "b"], ":0\r\n");
+
+    assert_reply(&mut client, &["LMPOP", "0", "a", "LEFT"], "-ERR numkeys should be greater than 0\r\n");
+    assert_reply(&mut client, &["LMPOP", "x", "a", "LEFT"], "-ERR numkeys should be greater than 0\r\n");
+    assert_reply(&mut client, &["LMPOP", "3", "a", "LEFT"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["LMPOP", "1", "a", "MIDDLE"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["LMPOP", "1", "a", "LEFT", "COUNT", "0"], "-ERR count should be greater than 0\r\n");
+    assert_reply(&mut client, &["LMPOP", "1", "a", "LEFT", "COUNT"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["SET", "string", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["LMPOP", "1", "string", "LEFT"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lpos_options() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "list", "a", "b", "c", "1", "2", "3", "c", "c"], ":8\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c"], ":2\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "RANK", "2"], ":6\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "RANK", "-1"], ":7\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "COUNT", "2"], "*2\r\n:2\r\n:6\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "COUNT", "0"], "*3\r\n:2\r\n:6\r\n:7\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "RANK", "-1", "COUNT", "2"], "*2\r\n:7\r\n:6\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "COUNT", "0", "MAXLEN", "7"], "*2\r\n:2\r\n:6\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "RANK", "-1", "MAXLEN", "1"], ":7\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "a", "RANK", "-1", "MAXLEN", "2"], ":-1\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "z", "COUNT", "0"], "*0\r\n");
+    assert_reply(&mut client, &["LPOS", "missing", "z", "COUNT", "0"], "*0\r\n");
+
+    assert_reply(&mut client, &["LPOS", "list", "c", "RANK", "0"],
+        "-ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "COUNT", "-1"], "-ERR COUNT can't be negative\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "MAXLEN", "-1"], "-ERR MAXLEN can't be negative\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "RANK"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["LPOS", "list", "c", "FIRST", "1"], "-ERR syntax error\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["RPUSH", "a", "1", "2", "3", "4"], ":4\r\n");
+    assert_reply(&mut client, &["LPUSHX", "missing", "1"], ":0\r\n");
+    assert_reply(&mut client, &["LPOP", "a", "0"], "*0\r\n");
+    assert_reply(&mut client, &["LPOP", "missing"], "$-1\r\n");
+    assert_reply(&mut client, &["RPOPLPUSH", "a", "b"], "$1\r\n4\r\n");
+    assert_reply(&mut client, &["LREM", "a", "0", "nothing"], ":0\r\n");
+    assert_reply(&mut client, &["LMPOP", "2", "missing", "a", "LEFT", "COUNT", "2"], "*2\r\n$1\r\na\r\n*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+    assert_reply(&mut client, &["LREM", "a", "1", "3"], ":1\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["RPUSH", "a", "1", "2", "3", "4"]);
+    expected.extend(encode(&["RPOPLPUSH", "a", "b"]));
+    expected.extend(encode(&["LPOP", "a", "2"]));
+    expected.extend(encode(&["LREM", "a", "1", "3"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
+
+/// Times `rounds` head pushes and pops on `key`, through storage so only the list itself is measured.
+fn time_head_operations(redis: &Redis, key: &[u8], rounds: usize) -> Duration {
+    let storage = redis.db(0);
+    let start = Instant::now();
+    for i in 0..rounds {
+        storage.push(key, ListEnd::Left, &[Bytes::from(i.to_string())], false).unwrap();
+    }
+    for _ in 0..rounds {
+        storage.pop(key, ListEnd::Left, 1).unwrap();
+    }
+    start.elapsed()
+}
+
+/// Prints how head pushes and pops on a million element list compare with an empty one; with a
+/// deque they take about as long. Run with `cargo test --test list_tests -- --ignored --nocapture`.
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "benchmark, prints timings"]
+async fn bench_head_operations_do_not_depend_on_list_length() {
+    let redis = Redis::new(RedisConfig::default());
+    let storage = redis.db(0);
+    let queue_len = 1_000_000;
+    let rounds = 10_000;
+
+    let start = Instant::now();
+    for i in 0..queue_len {
+        storage.push(b"queue", ListEnd::Left, &[Bytes::from(i.to_string())], false).unwrap();
+    }
+    println!("LPUSH x {}: {:?}", queue_len, start.elapsed());
+
+    let short = time_head_operations(&redis, b"short", rounds);
+    let long = time_head_operations(&redis, b"queue", rounds);
+    println!("{} LPUSH + LPOP on an empty list: {:?}, on a {} element list: {:?}", rounds, short, queue_len, long);
+
+    // Access by index stays cheap in the middle of the list too.
+    let start = Instant::now();
+    for i in 0..rounds as i64 {
+        let index = queue_len as i64 / 2 + i;
+        storage.lset(b"queue", index, Bytes::from("x")).unwrap();
+        assert_eq!(storage.lindex(b"queue", index).unwrap(), Bytes::from("x"));
+    }
+    assert_eq!(storage.lrange(b"queue", 500_000, 500_099).len(), 100);
+    println!("{} LSET + LINDEX in the middle: {:?}", rounds, start.elapsed());
+
+    let start = Instant::now();
+    while storage.pop(b"queue", ListEnd::Right, 1).unwrap().is_some() {}
+    println!("RPOP x {}: {:?}", queue_len, start.elapsed());
+    assert_eq!(storage.llen(b"queue"), 0);
+    assert!(!storage.contains_key(b"queue"));
+}
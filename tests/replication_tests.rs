@@ -1,26 +1,26 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::io::Write;
-use redis_starter_rust::redis::replication::ReplicationManager;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
 use redis_starter_rust::redis::core::Redis;
 use redis_starter_rust::client_handler::ClientHandler;
 use crate::utils::mock_tcp_stream::MockTcpStream;
 
 mod utils;
 
-#[test]
-fn given_replication_manager_when_command_enqueued_then_sent_to_replica() {
+#[tokio::test(flavor = "multi_thread")]
+async fn given_replication_manager_when_command_enqueued_then_sent_to_replica() {
     // Arrange
-    let mut manager = ReplicationManager::new();
+    let manager = ReplicationManager::new();
     
     // Create separate streams for replica connection
     let (replica_stream, mut _replica_server) = MockTcpStream::new_pair();
-    manager.add_replica("localhost".to_string(), "6379".to_string(), Box::new(replica_stream));
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
 
     // Act
     let command = "*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n";
-    manager.enqueue_for_replication(command);
+    manager.enqueue_for_replication(0, command.as_bytes());
     manager.send_pending_commands();
 
     // Wait for the command to be sent
@@ -31,24 +31,24 @@ fn given_replication_manager_when_command_enqueued_then_sent_to_replica() {
     assert_eq!(read_data, command.as_bytes().to_vec());
 }
 
-#[test]
-fn given_replication_manager_when_multiple_commands_enqueued_then_all_sent_to_replica() {
+#[tokio::test(flavor = "multi_thread")]
+async fn given_replication_manager_when_multiple_commands_enqueued_then_all_sent_to_replica() {
     // Arrange
-    let mut manager = ReplicationManager::new();
+    let manager = ReplicationManager::new();
     
     // Create separate streams for replica connection
     let (replica_stream, mut _replica_server) = MockTcpStream::new_pair();
-    manager.add_replica("localhost".to_string(), "6379".to_string(), Box::new(replica_stream));
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
 
     // Act
-    let commands = vec![
+    let commands = [
         "*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n",
         "*3\r\n$3\r\nSET\r\n$4\r\nkey2\r\n$6\r\nvalue2\r\n",
         "*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
     ];
 
     for command in commands.iter() {
-        manager.enqueue_for_replication(command);
+        manager.enqueue_for_replication(0, command.as_bytes());
     }
     manager.send_pending_commands();
 
@@ -61,17 +61,18 @@ fn given_replication_manager_when_multiple_commands_enqueued_then_all_sent_to_re
     assert_eq!(read_data, expected_data);
 }
 
-#[test]
-fn test_wait_command_with_ack() {
-    let mut manager = ReplicationManager::new();
+#[tokio::test(flavor = "multi_thread")]
+async fn test_wait_command_with_ack() {
+    let manager = ReplicationManager::new();
     
     // Create separate streams for master and replica
     let (mut master_stream, master_server) = MockTcpStream::new_pair();
     let (replica_stream, mut _replica_server) = MockTcpStream::new_pair();
-    manager.add_replica("127.0.0.1".to_string(), "8080".to_string(), Box::new(replica_stream.clone()));
+    // Register the replica under the address the mock reports as its peer, so its ACKs are matched
+    manager.add_replica("127.0.0.1".to_string(), "6379".to_string(), Connection::new(replica_stream.clone()).unwrap());
 
     // Create a Redis instance with this ReplicationManager
-    let redis = Arc::new(Mutex::new(Redis::new_with_replication(manager)));
+    let redis = Arc::new(Redis::new_with_replication(manager));
 
     // Start replication sync
     ReplicationManager::start_replication_sync(redis.clone());
@@ -120,17 +121,17 @@ fn test_wait_command_with_ack() {
     master_stream.shutdown();
 }
 
-#[test]
-fn test_wait_command_timeout() {
-    let mut manager = ReplicationManager::new();
+#[tokio::test(flavor = "multi_thread")]
+async fn test_wait_command_timeout() {
+    let manager = ReplicationManager::new();
     
     // Create separate streams for master and replica
     let (mut master_stream, master_server) = MockTcpStream::new_pair();
     let (replica_stream, _replica_server) = MockTcpStream::new_pair();
-    manager.add_replica("127.0.0.1".to_string(), "8080".to_string(), Box::new(replica_stream.clone()));
+    manager.add_replica("127.0.0.1".to_string(), "8080".to_string(), Connection::new(replica_stream.clone()).unwrap());
 
     // Create a Redis instance with this ReplicationManager
-    let redis = Arc::new(Mutex::new(Redis::new_with_replication(manager)));
+    let redis = Arc::new(Redis::new_with_replication(manager));
 
     // Start replication sync
     ReplicationManager::start_replication_sync(redis.clone());
@@ -169,13 +170,13 @@ fn test_wait_command_timeout() {
     master_stream.shutdown();
 }
 
-#[test]
-fn test_wait_command_no_replicas() {
-    let mut manager = ReplicationManager::new();
+#[tokio::test(flavor = "multi_thread")]
+async fn test_wait_command_no_replicas() {
+    let manager = ReplicationManager::new();
     let (mut master_stream, master_server) = MockTcpStream::new_pair();
     
     // Create a Redis instance with this ReplicationManager (no replicas added)
-    let redis = Arc::new(Mutex::new(Redis::new_with_replication(manager)));
+    let redis = Arc::new(Redis::new_with_replication(manager));
 
     // Create client handler
     let mut client_handler = ClientHandler::new(master_server, redis.clone());
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use bytes::Bytes;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+use redis_starter_rust::redis::storage::SetOp;
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+fn sorted(mut members: Vec<Bytes>) -> Vec<Bytes> {
+    members.sort();
+    members
+}
+
+fn members(items: &[&'static str]) -> Vec<Bytes> {
+    items.iter().map(|item| Bytes::from_static(item.as_bytes())).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sadd_srem_and_membership() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "tags", "rust", "redis", "rust"], ":2\r\n");
+    assert_reply(&mut client, &["SADD", "tags", "redis", "tokio"], ":1\r\n");
+    assert_reply(&mut client, &["SCARD", "tags"], ":3\r\n");
+    assert_reply(&mut client, &["SISMEMBER", "tags", "tokio"], ":1\r\n");
+    assert_reply(&mut client, &["SISMEMBER", "tags", "go"], ":0\r\n");
+    assert_reply(&mut client, &["SMISMEMBER", "tags", "go", "rust"], "*2\r\n:0\r\n:1\r\n");
+    assert_reply(&mut client, &["TYPE", "tags"], "$3\r\nset\r\n");
+    assert_eq!(sorted(redis.db(0).smembers(b"tags").unwrap()), members(&["redis", "rust", "tokio"]));
+
+    assert_reply(&mut client, &["SREM", "tags", "rust", "go"], ":1\r\n");
+    assert_reply(&mut client, &["SREM", "tags", "redis", "tokio"], ":2\r\n");
+    assert_reply(&mut client, &["TYPE", "tags"], "$4\r\nnone\r\n");
+    assert_reply(&mut client, &["SMEMBERS", "tags"], "*0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_spop_srandmember_and_smove() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "s", "a", "b", "c"], ":3\r\n");
+    assert_eq!(redis.db(0).srandmember(b"s", 10).unwrap().len(), 3);
+    assert_eq!(redis.db(0).srandmember(b"s", -10).unwrap().len(), 10);
+    assert_reply(&mut client, &["SRANDMEMBER", "missing"], "$-1\r\n");
+    assert_reply(&mut client, &["SRANDMEMBER", "missing", "3"], "*0\r\n");
+
+    let popped = redis.db(0).spop(b"s", 2).unwrap();
+    assert_eq!(popped.len(), 2);
+    assert_eq!(redis.db(0).scard(b"s").unwrap(), 1);
+    let left = ["a", "b", "c"].into_iter().find(|member| !popped.iter().any(|popped| popped == member)).unwrap();
+    assert_reply(&mut client, &["SPOP", "s", "5"], &format!("*1\r\n$1\r\n{}\r\n", left));
+    assert_reply(&mut client, &["SPOP", "s"], "$-1\r\n");
+    assert_reply(&mut client, &["TYPE", "s"], "$4\r\nnone\r\n");
+
+    assert_reply(&mut client, &["SADD", "from", "x", "y"], ":2\r\n");
+    assert_reply(&mut client, &["SMOVE", "from", "to", "x"], ":1\r\n");
+    assert_reply(&mut client, &["SMOVE", "from", "to", "x"], ":0\r\n");
+    assert_reply(&mut client, &["SMOVE", "from", "to", "y"], ":1\r\n");
+    assert_reply(&mut client, &["TYPE", "from"], "$4\r\nnone\r\n");
+    assert_eq!(sorted(redis.db(0).smembers(b"to").unwrap()), members(&["x", "y"]));
+
+    assert_reply(&mut client, &["SET", "str", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["SMOVE", "to", "str", "x"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["SCARD", "to"], ":2\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_set_algebra() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "a", "1", "2", "3", "4"], ":4\r\n");
+    assert_reply(&mut client, &["SADD", "b", "3", "4", "5"], ":3\r\n");
+    assert_reply(&mut client, &["SADD", "c", "4", "6"], ":2\r\n");
+
+    let inter = redis.db(0).set_operation(SetOp::Inter, &members(&["a", "b", "c"])).unwrap();
+    assert_eq!(sorted(inter.into_iter().collect()), members(&["4"]));
+    let union = redis.db(0).set_operation(SetOp::Union, &members(&["a", "b", "c"])).unwrap();
+    assert_eq!(sorted(union.into_iter().collect()), members(&["1", "2", "3", "4", "5", "6"]));
+    let diff = redis.db(0).set_operation(SetOp::Diff, &members(&["a", "b", "c"])).unwrap();
+    assert_eq!(sorted(diff.into_iter().collect()), members(&["1", "2"]));
+
+    assert_reply(&mut client, &["SINTER", "a", "b", "c"], "*1\r\n$1\r\n4\r\n");
+    assert_reply(&mut client, &["SINTER", "a", "missing"], "*0\r\n");
+    assert_reply(&mut client, &["SDIFF", "c", "a"], "*1\r\n$1\r\n6\r\n");
+    assert_reply(&mut client, &["SINTERCARD", "2", "a", "b"], ":2\r\n");
+    assert_reply(&mut client, &["SINTERCARD", "2", "a", "b", "LIMIT", "1"], ":1\r\n");
+    assert_reply(&mut client, &["SINTERCARD", "3", "a", "b"], "-ERR Number of keys can't be greater than number of args\r\n");
+    assert_reply(&mut client, &["SINTERCARD", "0", "a"], "-ERR numkeys should be greater than 0\r\n");
+
+    assert_reply(&mut client, &["SUNIONSTORE", "dest", "b", "c"], ":4\r\n");
+    assert_eq!(sorted(redis.db(0).smembers(b"dest").unwrap()), members(&["3", "4", "5", "6"]));
+    assert_reply(&mut client, &["SINTERSTORE", "dest", "a", "dest"], ":2\r\n");
+    assert_eq!(sorted(redis.db(0).smembers(b"dest").unwrap()), members(&["3", "4"]));
+    assert_reply(&mut client, &["SDIFFSTORE", "dest", "c", "c"], ":0\r\n");
+    assert_reply(&mut client, &["TYPE", "dest"], "$4\r\nnone\r\n");
+
+    assert_reply(&mut client, &["SET", "str", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["SUNION", "a", "str"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["SINTERSTORE", "str", "a", "b"], ":2\r\n");
+    assert_reply(&mut client, &["TYPE", "str"], "$3\r\nset\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_set_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SADD", "s", "only"], ":1\r\n");
+    assert_reply(&mut client, &["SADD", "s", "only"], ":0\r\n");
+    assert_reply(&mut client, &["SPOP", "s"], "$4\r\nonly\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["SADD", "s", "only"]);
+    expected.extend(encode(&["SREM", "s", "only"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
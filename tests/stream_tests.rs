@@ -1,80 +1,74 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use redis_starter_rust::redis::{
     core::Redis,
     storage::Storage,
     config::RedisConfig,
+    replication::{Connection, ReplicationManager},
 };
 
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
 // Stream ID Generation Tests
 #[test]
 fn test_xadd_auto_sequence_zero_time() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
     // When time part is 0, first sequence should be 1
-    let redis_guard = redis.lock().unwrap();
-    let result = redis_guard.storage.xadd("mystream", "0-*", fields.clone());
+    let result = redis.db(0).xadd(b"mystream", "0-*", fields.clone());
     assert_eq!(result.unwrap(), "0-1");
 }
 
 #[test]
 fn test_xadd_auto_sequence_new_time() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
     // For a new time part, sequence should start at 0
-    let redis_guard = redis.lock().unwrap();
-    let result = redis_guard.storage.xadd("mystream", "5-*", fields.clone());
+    let result = redis.db(0).xadd(b"mystream", "5-*", fields.clone());
     assert_eq!(result.unwrap(), "5-0");
 }
 
 #[test]
 fn test_xadd_auto_sequence_increment() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut fields = vec![("foo".into(), "bar".into())];
     
     // First entry with time part 5
-    let redis_guard = redis.lock().unwrap();
-    let result1 = redis_guard.storage.xadd("mystream", "5-*", fields.clone());
+    let result1 = redis.db(0).xadd(b"mystream", "5-*", fields.clone());
     assert_eq!(result1.unwrap(), "5-0");
 
     // Second entry with same time part should increment sequence
-    fields.insert("bar".to_string(), "baz".to_string());
-    let result2 = redis_guard.storage.xadd("mystream", "5-*", fields.clone());
+    fields.push(("bar".into(), "baz".into()));
+    let result2 = redis.db(0).xadd(b"mystream", "5-*", fields.clone());
     assert_eq!(result2.unwrap(), "5-1");
 }
 
 #[test]
 fn test_xadd_auto_sequence_multiple_time_parts() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
     // Add entries with different time parts
-    let redis_guard = redis.lock().unwrap();
-    let result1 = redis_guard.storage.xadd("mystream", "5-*", fields.clone());
+    let result1 = redis.db(0).xadd(b"mystream", "5-*", fields.clone());
     assert_eq!(result1.unwrap(), "5-0");
 
-    let result2 = redis_guard.storage.xadd("mystream", "6-*", fields.clone());
+    let result2 = redis.db(0).xadd(b"mystream", "6-*", fields.clone());
     assert_eq!(result2.unwrap(), "6-0");
 
     // Going back to time part 5 should fail since it's less than 6
-    let result3 = redis_guard.storage.xadd("mystream", "5-*", fields.clone());
+    let result3 = redis.db(0).xadd(b"mystream", "5-*", fields.clone());
     assert!(result3.is_err());
 }
 
 #[test]
 fn test_xadd_auto_generate_full_id() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
-    let redis_guard = redis.lock().unwrap();
-    let result = redis_guard.storage.xadd("mystream", "*", fields.clone());
+    let result = redis.db(0).xadd(b"mystream", "*", fields.clone());
     assert!(result.is_ok());
     
     let id = result.unwrap();
@@ -92,41 +86,35 @@ fn test_xadd_auto_generate_full_id() {
 
 #[test]
 fn test_xrange_basic() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let redis_guard = redis.lock().unwrap();
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Add test entries
-    let mut fields1 = HashMap::new();
-    fields1.insert("temperature".to_string(), "36".to_string());
-    fields1.insert("humidity".to_string(), "95".to_string());
+    let fields1 = vec![("temperature".into(), "36".into()), ("humidity".into(), "95".into())];
     
-    let mut fields2 = HashMap::new();
-    fields2.insert("temperature".to_string(), "37".to_string());
-    fields2.insert("humidity".to_string(), "94".to_string());
+    let fields2 = vec![("temperature".into(), "37".into()), ("humidity".into(), "94".into())];
 
     // Add entries
-    let _ = redis_guard.storage.xadd("mystream", "1526985054069-0", fields1);
-    let _ = redis_guard.storage.xadd("mystream", "1526985054079-0", fields2);
+    let _ = redis.db(0).xadd(b"mystream", "1526985054069-0", fields1);
+    let _ = redis.db(0).xadd(b"mystream", "1526985054079-0", fields2);
 
     // Test XRANGE
-    let result = redis_guard.storage.xrange("mystream", "1526985054069-0", "1526985054079-0").unwrap();
+    let result = redis.db(0).xrange(b"mystream", (1526985054069, 0), (1526985054079, 0), None, false).unwrap();
     assert_eq!(result.len(), 2);
     
     // Verify first entry
     assert_eq!(result[0].id, "1526985054069-0");
-    assert_eq!(result[0].fields.get("temperature").unwrap(), "36");
-    assert_eq!(result[0].fields.get("humidity").unwrap(), "95");
+    assert_eq!(result[0].get(b"temperature").unwrap(), "36");
+    assert_eq!(result[0].get(b"humidity").unwrap(), "95");
     
     // Verify second entry
     assert_eq!(result[1].id, "1526985054079-0");
-    assert_eq!(result[1].fields.get("temperature").unwrap(), "37");
-    assert_eq!(result[1].fields.get("humidity").unwrap(), "94");
+    assert_eq!(result[1].get(b"temperature").unwrap(), "37");
+    assert_eq!(result[1].get(b"humidity").unwrap(), "94");
 }
 
 #[test]
 fn test_xrange_with_minus() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let redis_guard = redis.lock().unwrap();
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Add test entries
     let entries = vec![
@@ -136,26 +124,24 @@ fn test_xrange_with_minus() {
     ];
 
     for (id, value) in entries {
-        let mut fields = HashMap::new();
-        fields.insert("value".to_string(), value.to_string());
-        let _ = redis_guard.storage.xadd("stream", id, fields);
+        let fields = vec![("value".into(), value.into())];
+        let _ = redis.db(0).xadd(b"stream", id, fields);
     }
 
     // Query from beginning to specific ID
-    let result = redis_guard.storage.xrange("stream", "-", "2000-0").unwrap();
+    let result = redis.db(0).xrange(b"stream", (0, 0), (2000, 0), None, false).unwrap();
     assert_eq!(result.len(), 2);
     
     // Verify entries
     assert_eq!(result[0].id, "1000-0");
-    assert_eq!(result[0].fields.get("value").unwrap(), "1");
+    assert_eq!(result[0].get(b"value").unwrap(), "1");
     assert_eq!(result[1].id, "2000-0");
-    assert_eq!(result[1].fields.get("value").unwrap(), "2");
+    assert_eq!(result[1].get(b"value").unwrap(), "2");
 }
 
 #[test]
 fn test_xrange_with_plus() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let redis_guard = redis.lock().unwrap();
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Add test entries
     let entries = vec![
@@ -165,88 +151,266 @@ fn test_xrange_with_plus() {
     ];
 
     for (id, value) in entries {
-        let mut fields = HashMap::new();
-        fields.insert("value".to_string(), value.to_string());
-        let _ = redis_guard.storage.xadd("stream", id, fields);
+        let fields = vec![("value".into(), value.into())];
+        let _ = redis.db(0).xadd(b"stream", id, fields);
     }
 
     // Query from specific ID to end
-    let result = redis_guard.storage.xrange("stream", "2000-0", "+").unwrap();
+    let result = redis.db(0).xrange(b"stream", (2000, 0), (u64::MAX, u64::MAX), None, false).unwrap();
     assert_eq!(result.len(), 2);
     
     // Verify entries
     assert_eq!(result[0].id, "2000-0");
-    assert_eq!(result[0].fields.get("value").unwrap(), "2");
+    assert_eq!(result[0].get(b"value").unwrap(), "2");
     assert_eq!(result[1].id, "3000-0");
-    assert_eq!(result[1].fields.get("value").unwrap(), "3");
+    assert_eq!(result[1].get(b"value").unwrap(), "3");
 }
 
 #[test]
 fn test_xadd_explicit_id() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("sensor".to_string(), "1".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("sensor".into(), "1".into())];
     
-    let redis_guard = redis.lock().unwrap();
     
     // Add with explicit ID
-    let result = redis_guard.storage.xadd("mystream", "1526919030474-55", fields.clone());
+    let result = redis.db(0).xadd(b"mystream", "1526919030474-55", fields.clone());
     assert_eq!(result.unwrap(), "1526919030474-55");
     
     // Verify can't add lower ID
-    let result = redis_guard.storage.xadd("mystream", "1526919030474-54", fields.clone());
+    let result = redis.db(0).xadd(b"mystream", "1526919030474-54", fields.clone());
     assert!(result.is_err());
     
     // Can add higher sequence number
-    let result = redis_guard.storage.xadd("mystream", "1526919030474-56", fields.clone());
+    let result = redis.db(0).xadd(b"mystream", "1526919030474-56", fields.clone());
     assert_eq!(result.unwrap(), "1526919030474-56");
 }
 
 #[test]
 #[should_panic]
 fn test_xadd_invalid_id_format() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
-    let redis_guard = redis.lock().unwrap();
     
     // Test invalid ID format
-    let _ = redis_guard.storage.xadd("mystream", "invalid-id", fields.clone());
+    let _ = redis.db(0).xadd(b"mystream", "invalid-id", fields.clone());
 }
 
 #[test]
 #[should_panic]
 fn test_xadd_invalid_timestamp() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
-    let redis_guard = redis.lock().unwrap();
     
     // Test invalid millisecond timestamp
-    let _ = redis_guard.storage.xadd("mystream", "xyz-0", fields.clone());
+    let _ = redis.db(0).xadd(b"mystream", "xyz-0", fields.clone());
 }
 
 #[test]
 #[should_panic]
 fn test_xadd_invalid_sequence() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let mut fields = HashMap::new();
-    fields.insert("foo".to_string(), "bar".to_string());
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
     
-    let redis_guard = redis.lock().unwrap();
     
     // Test invalid sequence number
-    let _ = redis_guard.storage.xadd("mystream", "1526919030474-xyz", fields.clone());
+    let _ = redis.db(0).xadd(b"mystream", "1526919030474-xyz", fields.clone());
 }
 
 #[test]
 fn test_xrange_nonexistent_stream() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
-    let redis_guard = redis.lock().unwrap();
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Test range query on non-existent stream
-    let result = redis_guard.storage.xrange("nonexistent", "-", "+").unwrap_or(Vec::new());
+    let result = redis.db(0).xrange(b"nonexistent", (0, 0), (u64::MAX, u64::MAX), None, false).unwrap_or_default();
     assert_eq!(result.len(), 0);
-}
\ No newline at end of file
+}
+// Deletion and trimming
+#[test]
+fn test_deleting_entries_keeps_the_last_id() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
+    let storage = redis.db(0);
+
+    storage.xadd(b"mystream", "1-1", fields.clone()).unwrap();
+    storage.xadd(b"mystream", "2-1", fields.clone()).unwrap();
+    assert_eq!(storage.xdel(b"mystream", &[(2, 1), (7, 0)]).unwrap(), 1);
+
+    // The deleted top entry's ID can't be reused, and auto sequences carry on after it
+    assert!(storage.xadd(b"mystream", "2-1", fields.clone()).is_err());
+    assert_eq!(storage.xadd(b"mystream", "2-*", fields.clone()).unwrap(), "2-2");
+
+    // An emptied stream is still a stream, and still remembers
+    assert_eq!(storage.xdel(b"mystream", &[(1, 1), (2, 2)]).unwrap(), 2);
+    assert_eq!(storage.xlen(b"mystream").unwrap(), 0);
+    assert_eq!(storage.get_type(b"mystream"), "stream");
+    assert_eq!(storage.get_last_stream_id(b"mystream").unwrap(), "2-2");
+    assert!(storage.xadd(b"mystream", "2-2", fields.clone()).is_err());
+    assert_eq!(storage.xadd(b"mystream", "3-*", fields).unwrap(), "3-0");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xlen_xdel_and_exact_xtrim() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = start_client(&redis);
+
+    for id in ["1-0", "2-0", "3-0", "4-0", "5-0"] {
+        assert_reply(&mut client, &["XADD", "s", id, "f", "v"], &format!("$3\r\n{}\r\n", id));
+    }
+    assert_reply(&mut client, &["XLEN", "s"], ":5\r\n");
+    assert_reply(&mut client, &["XLEN", "missing"], ":0\r\n");
+    assert_reply(&mut client, &["XDEL", "s", "2", "9-9", "2-0"], ":1\r\n");
+    assert_reply(&mut client, &["XDEL", "missing", "1-0"], ":0\r\n");
+    assert_reply(&mut client, &["XDEL", "s", "x-1"], "-ERR Invalid stream ID specified as stream command argument\r\n");
+
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "3"], ":1\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "=", "5"], ":0\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MINID", "4-1"], ":2\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "-", "+"], "*1\r\n*2\r\n$3\r\n5-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "0"], ":1\r\n");
+    assert_reply(&mut client, &["XLEN", "s"], ":0\r\n");
+    assert_reply(&mut client, &["TYPE", "s"], "$6\r\nstream\r\n");
+    assert_reply(&mut client, &["XADD", "s", "5-0", "f", "v"],
+        "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n");
+    assert_reply(&mut client, &["XTRIM", "missing", "MAXLEN", "0"], ":0\r\n");
+
+    assert_reply(&mut client, &["SET", "str", "x"], "+OK\r\n");
+    for command in [&["XLEN", "str"][..], &["XDEL", "str", "1-0"], &["XTRIM", "str", "MAXLEN", "0"]] {
+        assert_reply(&mut client, command, "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    }
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_approximate_xtrim_evicts_whole_nodes() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let fields = vec![("foo".into(), "bar".into())];
+    for i in 1..=250 {
+        redis.db(0).xadd(b"s", &format!("{}-0", i), fields.clone()).unwrap();
+    }
+    let mut client = start_client(&redis);
+
+    // 130 over, only one node's worth can go
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "~", "120"], ":100\r\n");
+    assert_reply(&mut client, &["XLEN", "s"], ":150\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "~", "100"], ":0\r\n");
+    // A LIMIT below a node's size evicts nothing
+    assert_reply(&mut client, &["XTRIM", "s", "MINID", "~", "250", "LIMIT", "50"], ":0\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MINID", "~", "250", "LIMIT", "100"], ":100\r\n");
+    assert_reply(&mut client, &["XLEN", "s"], ":50\r\n");
+
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "=", "10", "LIMIT", "5"],
+        "-ERR syntax error, LIMIT cannot be used without the special ~ option\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "-1"], "-ERR The MAXLEN argument must be >= 0.\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "~", "1", "LIMIT", "-1"], "-ERR The LIMIT argument must be >= 0.\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MINID", "nope"], "-ERR Invalid stream ID specified as stream command argument\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MAXLEN", "1", "extra"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "SIZE", "1"], "-ERR syntax error\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xadd_nomkstream_and_trimming() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["XADD", "s", "NOMKSTREAM", "1-0", "f", "v"], "$-1\r\n");
+    assert_reply(&mut client, &["TYPE", "s"], "$4\r\nnone\r\n");
+
+    assert_reply(&mut client, &["XADD", "s", "1", "f", "v"], "$3\r\n1-0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "2-0", "f", "v"], "$3\r\n2-0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "NOMKSTREAM", "MAXLEN", "2", "3-0", "f", "v"], "$3\r\n3-0\r\n");
+    assert_reply(&mut client, &["XLEN", "s"], ":2\r\n");
+    assert_reply(&mut client, &["XADD", "s", "MINID", "=", "4", "4-*", "f", "v"], "$3\r\n4-0\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "-", "+"], "*1\r\n*2\r\n$3\r\n4-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n");
+    // Trimming the new entry away too still moves the last ID on
+    assert_reply(&mut client, &["XADD", "s", "MAXLEN", "0", "5-*", "f", "v"], "$3\r\n5-0\r\n");
+    assert_reply(&mut client, &["XLEN", "s"], ":0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "5-*", "f", "v"], "$3\r\n5-1\r\n");
+
+    assert_reply(&mut client, &["XADD", "s", "x-1", "f", "v"], "-ERR Invalid stream ID specified as stream command argument\r\n");
+    assert_reply(&mut client, &["XADD", "s", "MAXLEN", "1", "LIMIT", "1", "*", "f", "v"],
+        "-ERR syntax error, LIMIT cannot be used without the special ~ option\r\n");
+    assert_reply(&mut client, &["XADD", "s", "*", "f", "v", "g"], "-ERR wrong number of arguments for 'xadd' command\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_stream_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["XADD", "s", "1-1", "a", "1"], "$3\r\n1-1\r\n");
+    assert_reply(&mut client, &["XADD", "s", "2-*", "b", "2"], "$3\r\n2-0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "MAXLEN", "~", "1", "3-*", "c", "3"], "$3\r\n3-0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "MAXLEN", "2", "4-0", "d", "4"], "$3\r\n4-0\r\n");
+    assert_reply(&mut client, &["XADD", "missing", "NOMKSTREAM", "*", "e", "5"], "$-1\r\n");
+    assert_reply(&mut client, &["XDEL", "s", "9-9"], ":0\r\n");
+    assert_reply(&mut client, &["XDEL", "s", "3-0"], ":1\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MINID", "0"], ":0\r\n");
+    assert_reply(&mut client, &["XADD", "s", "5-0", "f", "6"], "$3\r\n5-0\r\n");
+    assert_reply(&mut client, &["XTRIM", "s", "MINID", "5"], ":1\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["XADD", "s", "1-1", "a", "1"]);
+    expected.extend(encode(&["XADD", "s", "2-0", "b", "2"]));
+    // The approximate trim evicted nothing, the exact one is replayed as the length it left
+    expected.extend(encode(&["XADD", "s", "3-0", "c", "3"]));
+    expected.extend(encode(&["XADD", "s", "MAXLEN", "=", "2", "4-0", "d", "4"]));
+    expected.extend(encode(&["XDEL", "s", "3-0"]));
+    expected.extend(encode(&["XADD", "s", "5-0", "f", "6"]));
+    expected.extend(encode(&["XTRIM", "s", "MAXLEN", "=", "1"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xrange_and_xrevrange_paginate() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
+    let mut client = start_client(&redis);
+    for id in ["1-0", "1-1", "2-0", "3-0", "3-1"] {
+        assert_reply(&mut client, &["XADD", "s", id, "f", id], &format!("$3\r\n{}\r\n", id));
+    }
+    let entries = |ids: &[&str]| {
+        let mut reply = format!("*{}\r\n", ids.len());
+        for id in ids {
+            reply.push_str(&format!("*2\r\n$3\r\n{0}\r\n*2\r\n$1\r\nf\r\n$3\r\n{0}\r\n", id));
+        }
+        reply
+    };
+
+    // Pages of two, each starting right after the last ID of the previous one
+    assert_reply(&mut client, &["XRANGE", "s", "-", "+", "COUNT", "2"], &entries(&["1-0", "1-1"]));
+    assert_reply(&mut client, &["XRANGE", "s", "(1-1", "+", "COUNT", "2"], &entries(&["2-0", "3-0"]));
+    assert_reply(&mut client, &["XRANGE", "s", "(3-0", "+", "COUNT", "2"], &entries(&["3-1"]));
+    assert_reply(&mut client, &["XRANGE", "s", "(3-1", "+", "COUNT", "2"], "*0\r\n");
+    assert_reply(&mut client, &["XREVRANGE", "s", "+", "-", "COUNT", "2"], &entries(&["3-1", "3-0"]));
+    assert_reply(&mut client, &["XREVRANGE", "s", "(3-0", "-", "COUNT", "2"], &entries(&["2-0", "1-1"]));
+    assert_reply(&mut client, &["XREVRANGE", "s", "(1-0", "-"], "*0\r\n");
+
+    // Without a sequence a start takes in the whole millisecond from sequence 0, an end up to the last
+    assert_reply(&mut client, &["XRANGE", "s", "1", "1"], &entries(&["1-0", "1-1"]));
+    assert_reply(&mut client, &["XREVRANGE", "s", "3", "2"], &entries(&["3-1", "3-0", "2-0"]));
+    assert_reply(&mut client, &["XRANGE", "s", "(1", "2"], &entries(&["1-1", "2-0"]));
+    assert_reply(&mut client, &["XRANGE", "s", "(1-1", "(3-0"], &entries(&["2-0"]));
+    assert_reply(&mut client, &["XRANGE", "s", "3", "1"], "*0\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "-", "+", "COUNT", "0"], "*-1\r\n");
+    assert_reply(&mut client, &["XRANGE", "missing", "-", "+"], "*0\r\n");
+
+    assert_reply(&mut client, &["XRANGE", "s", "(18446744073709551615-18446744073709551615", "+"],
+        "-ERR invalid start ID for the interval\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "-", "(0-0"], "-ERR invalid end ID for the interval\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "(-", "+"], "-ERR Invalid stream ID specified as stream command argument\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "1-x", "+"], "-ERR Invalid stream ID specified as stream command argument\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "-", "+", "COUNT", "x"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["XRANGE", "s", "-", "+", "LIMIT", "1"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["XREVRANGE", "s", "+"], "-ERR wrong number of arguments for 'xrevrange' command\r\n");
+    assert_reply(&mut client, &["SET", "str", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["XREVRANGE", "str", "+", "-"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
@@ -0,0 +1,377 @@
+use std::sync::Arc;
+use bytes::Bytes;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::commands::RedisCommand;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+use redis_starter_rust::redis::storage::Storage;
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, read_reply, send, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_append_strlen_and_ranges() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["APPEND", "s", "Hello"], ":5\r\n");
+    assert_reply(&mut client, &["APPEND", "s", " World"], ":11\r\n");
+    assert_reply(&mut client, &["STRLEN", "s"], ":11\r\n");
+    assert_reply(&mut client, &["STRLEN", "missing"], ":0\r\n");
+
+    assert_reply(&mut client, &["GETRANGE", "s", "0", "4"], "$5\r\nHello\r\n");
+    assert_reply(&mut client, &["GETRANGE", "s", "-5", "-1"], "$5\r\nWorld\r\n");
+    assert_reply(&mut client, &["GETRANGE", "s", "6", "100"], "$5\r\nWorld\r\n");
+    assert_reply(&mut client, &["GETRANGE", "s", "5", "2"], "$0\r\n\r\n");
+    assert_reply(&mut client, &["GETRANGE", "s", "-1", "-5"], "$0\r\n\r\n");
+    assert_reply(&mut client, &["GETRANGE", "missing", "0", "-1"], "$0\r\n\r\n");
+
+    assert_reply(&mut client, &["SETRANGE", "s", "6", "Redis"], ":11\r\n");
+    assert_reply(&mut client, &["GET", "s"], "$11\r\nHello Redis\r\n");
+    assert_reply(&mut client, &["SETRANGE", "padded", "3", "x"], ":4\r\n");
+    assert_reply(&mut client, &["GET", "padded"], "$4\r\n\0\0\0x\r\n");
+    assert_reply(&mut client, &["SETRANGE", "empty", "5", ""], ":0\r\n");
+    assert_reply(&mut client, &["EXISTS", "empty"], ":0\r\n");
+    assert_reply(&mut client, &["SETRANGE", "s", "-1", "x"], "-ERR offset is out of range\r\n");
+    assert_reply(&mut client, &["SETRANGE", "s", "536870911", "xy"],
+        "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n");
+
+    // Updating a string in place keeps its TTL.
+    assert_reply(&mut client, &["EXPIRE", "s", "100"], ":1\r\n");
+    assert_reply(&mut client, &["APPEND", "s", "!"], ":12\r\n");
+    assert!(redis.db(0).expire_time(b"s") > 0);
+
+    assert_reply(&mut client, &["RPUSH", "list", "a"], ":1\r\n");
+    assert_reply(&mut client, &["APPEND", "list", "x"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["STRLEN", "list"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mget_mset_and_msetnx() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["MSET", "a", "1", "b", "2"], "+OK\r\n");
+    assert_reply(&mut client, &["RPUSH", "list", "x"], ":1\r\n");
+    assert_reply(&mut client, &["MGET", "a", "missing", "list", "b"], "*4\r\n$1\r\n1\r\n$-1\r\n$-1\r\n$1\r\n2\r\n");
+
+    assert_reply(&mut client, &["MSETNX", "b", "3", "c", "3"], ":0\r\n");
+    assert_reply(&mut client, &["EXISTS", "c"], ":0\r\n");
+    assert_reply(&mut client, &["MSETNX", "c", "3", "d", "4"], ":1\r\n");
+    assert_reply(&mut client, &["MGET", "c", "d"], "*2\r\n$1\r\n3\r\n$1\r\n4\r\n");
+    client.shutdown();
+}
+
+#[test]
+fn test_mset_is_atomic() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    redis.set(b"x", "start".into(), None);
+    redis.set(b"y", "start".into(), None);
+    let writer = {
+        let redis = Arc::clone(&redis);
+        std::thread::spawn(move || {
+            for round in 0..2000 {
+                let value = Bytes::from(round.to_string());
+                let mset = RedisCommand::MSet {
+                    pairs: vec![("x".into(), value.clone()), ("y".into(), value)],
+                    nx: false,
+                    original_resp: Bytes::new(),
+                };
+                redis.execute_command(&mut 0, &mset, None);
+            }
+        })
+    };
+
+    // A reader never sees one half of an MSET without the other.
+    let mget = RedisCommand::MGet { keys: vec!["x".into(), "y".into()] };
+    while !writer.is_finished() {
+        let reply = redis.execute_command(&mut 0, &mget, None).format();
+        let parts: Vec<&[u8]> = reply.split(|&byte| byte == b'\n').collect();
+        assert_eq!(parts[2], parts[4], "{:?}", String::from_utf8_lossy(&reply));
+    }
+    writer.join().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_getset_getdel_getex_setnx_setex() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["GETSET", "k", "v1"], "$-1\r\n");
+    assert_reply(&mut client, &["EXPIRE", "k", "100"], ":1\r\n");
+    assert_reply(&mut client, &["GETSET", "k", "v2"], "$2\r\nv1\r\n");
+    assert_reply(&mut client, &["TTL", "k"], ":-1\r\n");
+    assert_reply(&mut client, &["GETDEL", "k"], "$2\r\nv2\r\n");
+    assert_reply(&mut client, &["GETDEL", "k"], "$-1\r\n");
+
+    assert_reply(&mut client, &["SETNX", "k", "first"], ":1\r\n");
+    assert_reply(&mut client, &["SETNX", "k", "second"], ":0\r\n");
+    assert_reply(&mut client, &["GETEX", "k"], "$5\r\nfirst\r\n");
+    assert_reply(&mut client, &["GETEX", "k", "EX", "100"], "$5\r\nfirst\r\n");
+    assert_reply(&mut client, &["TTL", "k"], ":100\r\n");
+    assert_reply(&mut client, &["GETEX", "k", "PERSIST"], "$5\r\nfirst\r\n");
+    assert_reply(&mut client, &["TTL", "k"], ":-1\r\n");
+    let at = (Storage::get_current_time_ms() + 50_000).to_string();
+    assert_reply(&mut client, &["GETEX", "k", "PXAT", &at], "$5\r\nfirst\r\n");
+    assert_reply(&mut client, &["PEXPIRETIME", "k"], &format!(":{}\r\n", at));
+    assert_reply(&mut client, &["GETEX", "missing", "EX", "10"], "$-1\r\n");
+    assert_reply(&mut client, &["GETEX", "k", "EX", "0"], "-ERR invalid expire time in 'getex' command\r\n");
+    assert_reply(&mut client, &["GETEX", "k", "EX", "1", "PERSIST"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["GETEX", "k", "SOON", "1"], "-ERR syntax error\r\n");
+
+    assert_reply(&mut client, &["SETEX", "e", "100", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["TTL", "e"], ":100\r\n");
+    assert_reply(&mut client, &["PSETEX", "e", "100000", "w"], "+OK\r\n");
+    assert_reply(&mut client, &["GET", "e"], "$1\r\nw\r\n");
+    assert_reply(&mut client, &["TTL", "e"], ":100\r\n");
+    assert_reply(&mut client, &["SETEX", "e", "0", "v"], "-ERR invalid expire time in 'setex' command\r\n");
+    assert_reply(&mut client, &["PSETEX", "e", "-5", "v"], "-ERR invalid expire time in 'psetex' command\r\n");
+    assert_reply(&mut client, &["SETEX", "e", "soon", "v"], "-ERR value is not an integer or out of range\r\n");
+
+    assert_reply(&mut client, &["SADD", "set", "m"], ":1\r\n");
+    assert_reply(&mut client, &["GETDEL", "set"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["GETSET", "set", "v"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lcs() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["MSET", "key1", "ohmytext", "key2", "mynewtext"], "+OK\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "key2"], "$6\r\nmytext\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "key2", "LEN"], ":6\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "key2", "IDX"],
+        "*4\r\n$7\r\nmatches\r\n*2\r\n*2\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n*2\r\n*2\r\n:2\r\n:3\r\n*2\r\n:0\r\n:1\r\n$3\r\nlen\r\n:6\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "key2", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"],
+        "*4\r\n$7\r\nmatches\r\n*1\r\n*3\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n:4\r\n$3\r\nlen\r\n:6\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "missing"], "$0\r\n\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "key2", "LEN", "IDX"],
+        "-ERR If you want both the length and indexes, please just use IDX.\r\n");
+    assert_reply(&mut client, &["SADD", "set", "m"], ":1\r\n");
+    assert_reply(&mut client, &["LCS", "key1", "set"], "-ERR The specified keys must contain string values\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_string_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["MSET", "a", "1", "b", "2"], "+OK\r\n");
+    assert_reply(&mut client, &["MSETNX", "a", "3"], ":0\r\n");
+    assert_reply(&mut client, &["APPEND", "a", "x"], ":2\r\n");
+    assert_reply(&mut client, &["SETRANGE", "a", "0", "y"], ":2\r\n");
+    assert_reply(&mut client, &["GETDEL", "b"], "$1\r\n2\r\n");
+    assert_reply(&mut client, &["GETDEL", "b"], "$-1\r\n");
+    assert_reply(&mut client, &["SETNX", "a", "z"], ":0\r\n");
+    assert_reply(&mut client, &["GETSET", "c", "v"], "$-1\r\n");
+    assert_reply(&mut client, &["PSETEX", "d", "100000", "v"], "+OK\r\n");
+    let when = redis.db(0).expire_time(b"d").to_string();
+    assert_reply(&mut client, &["GETEX", "c", "PXAT", "4000000000000"], "$1\r\nv\r\n");
+    assert_reply(&mut client, &["GETEX", "c", "PERSIST"], "$1\r\nv\r\n");
+    assert_reply(&mut client, &["GETEX", "c"], "$1\r\nv\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["MSET", "a", "1", "b", "2"]);
+    expected.extend(encode(&["APPEND", "a", "x"]));
+    expected.extend(encode(&["SETRANGE", "a", "0", "y"]));
+    expected.extend(encode(&["DEL", "b"]));
+    expected.extend(encode(&["GETSET", "c", "v"]));
+    expected.extend(encode(&["SET", "d", "v", "PXAT", &when]));
+    expected.extend(encode(&["PEXPIREAT", "c", "4000000000000"]));
+    expected.extend(encode(&["PERSIST", "c"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_integer_counters() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["INCR", "n"], ":1\r\n");
+    assert_reply(&mut client, &["INCRBY", "n", "41"], ":42\r\n");
+    assert_reply(&mut client, &["DECR", "n"], ":41\r\n");
+    assert_reply(&mut client, &["DECRBY", "n", "50"], ":-9\r\n");
+    assert_reply(&mut client, &["DECRBY", "fresh", "3"], ":-3\r\n");
+    assert_reply(&mut client, &["GET", "n"], "$2\r\n-9\r\n");
+
+    // Counting keeps the TTL.
+    assert_reply(&mut client, &["EXPIRE", "n", "100"], ":1\r\n");
+    assert_reply(&mut client, &["INCR", "n"], ":-8\r\n");
+    assert_reply(&mut client, &["TTL", "n"], ":100\r\n");
+
+    assert_reply(&mut client, &["SET", "max", "9223372036854775807"], "+OK\r\n");
+    assert_reply(&mut client, &["INCR", "max"], "-ERR increment or decrement would overflow\r\n");
+    assert_reply(&mut client, &["GET", "max"], "$19\r\n9223372036854775807\r\n");
+    assert_reply(&mut client, &["SET", "min", "-9223372036854775808"], "+OK\r\n");
+    assert_reply(&mut client, &["DECR", "min"], "-ERR increment or decrement would overflow\r\n");
+    assert_reply(&mut client, &["INCRBY", "min", "-1"], "-ERR increment or decrement would overflow\r\n");
+    assert_reply(&mut client, &["DECRBY", "n", "-9223372036854775808"], "-ERR decrement would overflow\r\n");
+
+    for stored in ["abc", "1.5", "+1", "007", " 1", ""] {
+        assert_reply(&mut client, &["SET", "bad", stored], "+OK\r\n");
+        assert_reply(&mut client, &["INCR", "bad"], "-ERR value is not an integer or out of range\r\n");
+    }
+    assert_reply(&mut client, &["INCRBY", "n", "x"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["SADD", "set", "m"], ":1\r\n");
+    assert_reply(&mut client, &["INCR", "set"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_incrbyfloat() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "f", "10.50"], "+OK\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "0.1"], "$4\r\n10.6\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "-5"], "$3\r\n5.6\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "0.4"], "$1\r\n6\r\n");
+    assert_reply(&mut client, &["SET", "e", "5.0e3"], "+OK\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "e", "2.0e2"], "$4\r\n5200\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "fresh", "1.5"], "$3\r\n1.5\r\n");
+    // Large results are plain decimals, never in exponent form. Redis adds in a long double,
+    // so only the leading digits are the same as here.
+    assert_reply(&mut client, &["INCRBYFLOAT", "big", "1e20"], "$21\r\n100000000000000000000\r\n");
+    send(&mut client, &["INCRBYFLOAT", "huge", "1e300"]);
+    let reply = read_reply(&client, 1000).unwrap();
+    let reply = String::from_utf8(reply).unwrap();
+    let digits = reply.strip_prefix("$301\r\n").and_then(|rest| rest.strip_suffix("\r\n")).unwrap();
+    assert!(digits.starts_with("1000000000000000") && digits.bytes().all(|b| b.is_ascii_digit()), "{}", reply);
+
+    assert_reply(&mut client, &["EXPIRE", "f", "100"], ":1\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "1"], "$1\r\n7\r\n");
+    assert_reply(&mut client, &["TTL", "f"], ":100\r\n");
+
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "abc"], "-ERR value is not a valid float\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "inf"], "-ERR value is not a valid float\r\n");
+    assert_reply(&mut client, &["SET", "big", "1.7e308"], "+OK\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "big", "1.7e308"], "-ERR increment would produce NaN or Infinity\r\n");
+    assert_reply(&mut client, &["SET", "text", "hello"], "+OK\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "text", "1"], "-ERR value is not a valid float\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_counters_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["INCR", "n"], ":1\r\n");
+    assert_reply(&mut client, &["DECRBY", "n", "5"], ":-4\r\n");
+    assert_reply(&mut client, &["SET", "s", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["INCR", "s"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["INCRBYFLOAT", "f", "0.5"], "$3\r\n0.5\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["INCR", "n"]);
+    expected.extend(encode(&["DECRBY", "n", "5"]));
+    expected.extend(encode(&["SET", "s", "x"]));
+    expected.extend(encode(&["SET", "f", "0.5", "KEEPTTL"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_set_conditions_and_get() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "k", "a", "XX"], "$-1\r\n");
+    assert_reply(&mut client, &["SET", "k", "a", "NX"], "+OK\r\n");
+    assert_reply(&mut client, &["SET", "k", "b", "nx"], "$-1\r\n");
+    assert_reply(&mut client, &["SET", "k", "b", "XX", "GET"], "$1\r\na\r\n");
+    // GET still reports the old value when the condition stops the write.
+    assert_reply(&mut client, &["SET", "k", "c", "GET", "NX"], "$1\r\nb\r\n");
+    assert_reply(&mut client, &["SET", "new", "v", "GET"], "$-1\r\n");
+    assert_reply(&mut client, &["GET", "k"], "$1\r\nb\r\n");
+
+    assert_reply(&mut client, &["SADD", "set", "m"], ":1\r\n");
+    assert_reply(&mut client, &["SET", "set", "v", "GET"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["TYPE", "set"], "$3\r\nset\r\n");
+    assert_reply(&mut client, &["SET", "set", "v"], "+OK\r\n");
+
+    // The usual lock: only one client gets it, and it goes away on its own.
+    assert_reply(&mut client, &["SET", "lock", "token1", "NX", "PX", "30000"], "+OK\r\n");
+    assert_reply(&mut client, &["SET", "lock", "token2", "NX", "PX", "30000"], "$-1\r\n");
+    assert_reply(&mut client, &["GET", "lock"], "$6\r\ntoken1\r\n");
+    assert!(redis.db(0).expire_time(b"lock") > Storage::get_current_time_ms() as i64);
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_set_expiry_options() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "k", "v", "EXAT", "4000000000"], "+OK\r\n");
+    assert_eq!(redis.db(0).expire_time(b"k"), 4_000_000_000_000);
+    assert_reply(&mut client, &["SET", "k", "v", "PXAT", "4000000000123"], "+OK\r\n");
+    assert_eq!(redis.db(0).expire_time(b"k"), 4_000_000_000_123);
+    assert_reply(&mut client, &["SET", "k", "w", "KEEPTTL"], "+OK\r\n");
+    assert_eq!(redis.db(0).expire_time(b"k"), 4_000_000_000_123);
+    assert_reply(&mut client, &["SET", "k", "x"], "+OK\r\n");
+    assert_reply(&mut client, &["TTL", "k"], ":-1\r\n");
+    assert_reply(&mut client, &["SET", "k", "y", "EX", "100", "GET"], "$1\r\nx\r\n");
+    assert_reply(&mut client, &["TTL", "k"], ":100\r\n");
+
+    // A time already in the past sets a key that is gone straight away.
+    assert_reply(&mut client, &["SET", "k", "z", "PXAT", "1"], "+OK\r\n");
+    assert_reply(&mut client, &["EXISTS", "k"], ":0\r\n");
+
+    for args in [
+        &["SET", "k", "v", "NX", "XX"][..],
+        &["SET", "k", "v", "EX", "10", "PX", "100"],
+        &["SET", "k", "v", "KEEPTTL", "EX", "10"],
+        &["SET", "k", "v", "PXAT", "10", "KEEPTTL"],
+        &["SET", "k", "v", "EX"],
+        &["SET", "k", "v", "FOREVER"],
+    ] {
+        assert_reply(&mut client, args, "-ERR syntax error\r\n");
+    }
+    assert_reply(&mut client, &["SET", "k", "v", "EX", "0"], "-ERR invalid expire time in 'set' command\r\n");
+    assert_reply(&mut client, &["SET", "k", "v", "PX", "-5"], "-ERR invalid expire time in 'set' command\r\n");
+    assert_reply(&mut client, &["SET", "k", "v", "EX", "9223372036854775807"], "-ERR invalid expire time in 'set' command\r\n");
+    assert_reply(&mut client, &["SET", "k", "v", "EX", "ten"], "-ERR value is not an integer or out of range\r\n");
+    assert_reply(&mut client, &["EXISTS", "k"], ":0\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_conditional_set_is_replicated_by_outcome() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["SET", "lock", "a", "NX", "PX", "30000"], "+OK\r\n");
+    let when = redis.db(0).expire_time(b"lock").to_string();
+    assert_reply(&mut client, &["SET", "lock", "b", "NX", "PX", "30000"], "$-1\r\n");
+    assert_reply(&mut client, &["SET", "other", "b", "XX"], "$-1\r\n");
+    assert_reply(&mut client, &["SET", "lock", "c", "XX", "GET", "KEEPTTL"], "$1\r\na\r\n");
+    assert_reply(&mut client, &["SET", "plain", "v", "EXAT", "4000000000"], "+OK\r\n");
+    assert_reply(&mut client, &["SET", "plain", "w", "GET"], "$1\r\nv\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["SET", "lock", "a", "PXAT", &when]);
+    expected.extend(encode(&["SET", "lock", "c", "PXAT", &when]));
+    expected.extend(encode(&["SET", "plain", "v", "EXAT", "4000000000"]));
+    expected.extend(encode(&["SET", "plain", "w"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}
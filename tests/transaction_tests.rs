@@ -1,4 +1,4 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use redis_starter_rust::redis::core::Redis;
 use redis_starter_rust::redis::config::RedisConfig;
 use redis_starter_rust::client_handler::ClientHandler;
@@ -6,14 +6,14 @@ use crate::utils::mock_tcp_stream::MockTcpStream;
 
 mod utils;
 
-#[test]
-fn test_multi_exec_basic() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multi_exec_basic() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let mock_stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(mock_stream.clone(), Arc::clone(&redis));
     
     // Start client handler
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Start transaction
     mock_stream.read_data.lock().unwrap().extend(b"*1\r\n$5\r\nMULTI\r\n");
@@ -37,21 +37,21 @@ fn test_multi_exec_basic() {
     assert!(mock_stream.wait_for_write(":42\r\n", 1000));
 
     // Verify final value
-    let value = redis.lock().unwrap().get("foo").unwrap();
+    let value = redis.get(b"foo").unwrap();
     assert_eq!(value, "42");
 
     // Cleanup
     mock_stream.shutdown();
 }
 
-#[test]
-fn test_nested_multi() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_nested_multi() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let mock_stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(mock_stream.clone(), Arc::clone(&redis));
     
     // Start client handler
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Start first transaction
     mock_stream.read_data.lock().unwrap().extend(b"*1\r\n$5\r\nMULTI\r\n");
@@ -66,14 +66,14 @@ fn test_nested_multi() {
     mock_stream.shutdown();
 }
 
-#[test]
-fn test_exec_without_multi() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_exec_without_multi() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let mock_stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(mock_stream.clone(), Arc::clone(&redis));
     
     // Start client handler
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Try EXEC without MULTI
     mock_stream.read_data.lock().unwrap().extend(b"*1\r\n$4\r\nEXEC\r\n");
@@ -83,14 +83,14 @@ fn test_exec_without_multi() {
     mock_stream.shutdown();
 }
 
-#[test]
-fn test_discard_transaction() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_discard_transaction() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let mock_stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(mock_stream.clone(), Arc::clone(&redis));
     
     // Start client handler
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Start transaction
     mock_stream.read_data.lock().unwrap().extend(b"*1\r\n$5\r\nMULTI\r\n");
@@ -107,20 +107,20 @@ fn test_discard_transaction() {
     assert!(mock_stream.wait_for_write("+OK\r\n", 1000));
 
     // Verify key was not set
-    assert!(redis.lock().unwrap().get("foo").is_none());
+    assert!(redis.get(b"foo").is_none());
 
     // Cleanup
     mock_stream.shutdown();
 }
 
-#[test]
-fn test_discard_without_multi() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_discard_without_multi() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
     let mock_stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(mock_stream.clone(), Arc::clone(&redis));
     
     // Start client handler
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Try DISCARD without MULTI
     mock_stream.read_data.lock().unwrap().extend(b"*1\r\n$7\r\nDISCARD\r\n");
@@ -1,8 +1,13 @@
+// Shared by several test crates, each of which only uses part of it.
+#![allow(dead_code)]
+
 use std::io::{Read, Write, Result};
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
+use std::task::{Context, Poll};
+use std::thread;
 use std::time::{Duration, Instant};
-use redis_starter_rust::client_handler::ClientHandler;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 #[derive(Clone)]
 pub struct MockTcpStream {
@@ -33,8 +38,8 @@ impl MockTcpStream {
         };
 
         let server = MockTcpStream {
-            read_data: read_data,
-            write_data: write_data,
+            read_data,
+            write_data,
             is_server: true,
             shutdown,
         };
@@ -42,11 +47,18 @@ impl MockTcpStream {
         (client, server)
     }
 
+    /// Waits for `pattern` to show up in what the other end has written to this stream.
+    pub fn wait_for_pattern(&self, pattern: &str, timeout_ms: u64) -> bool {
+        Self::wait_for_pattern_in(&self.read_data, &self.shutdown, pattern, timeout_ms)
+    }
+
+    /// Waits for `pattern` to show up in what has been written through this stream.
+    /// Used when the handler under test owns this very end of the pair.
     pub fn wait_for_write(&self, pattern: &str, timeout_ms: u64) -> bool {
-        self.wait_for_pattern(pattern, timeout_ms)
+        Self::wait_for_pattern_in(&self.write_data, &self.shutdown, pattern, timeout_ms)
     }
 
-    pub fn wait_for_pattern(&self, pattern: &str, timeout_ms: u64) -> bool {
+    fn wait_for_pattern_in(buffer: &Arc<Mutex<Vec<u8>>>, shutdown: &Arc<Mutex<bool>>, pattern: &str, timeout_ms: u64) -> bool {
         let start_time = Instant::now();
         let pattern_bytes = pattern.as_bytes();
         
@@ -57,13 +69,13 @@ impl MockTcpStream {
                 return false;
             }
 
-            if *self.shutdown.lock().unwrap() {
+            if *shutdown.lock().unwrap() {
                 #[cfg(debug_assertions)]
                 println!("[MockTcpStream::wait_for_pattern] Stream is shutdown");
                 return false;
             }
 
-            let data = self.read_data.lock().unwrap();
+            let data = buffer.lock().unwrap();
             if data.windows(pattern_bytes.len()).any(|window| window == pattern_bytes) {
                 #[cfg(debug_assertions)]
                 println!("[MockTcpStream::wait_for_pattern] Pattern found");
@@ -135,12 +147,47 @@ impl Write for MockTcpStream {
     }
 }
 
+// The async side is what the handlers under test use; the sync Read/Write above is for the test itself.
+impl AsyncRead for MockTcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        if *self.shutdown.lock().unwrap() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut data = self.read_data.lock().unwrap();
+        if data.is_empty() {
+            // No readiness events for a Vec, check again shortly.
+            let waker = cx.waker().clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                waker.wake();
+            });
+            return Poll::Pending;
+        }
+
+        let n = std::cmp::min(buf.remaining(), data.len());
+        buf.put_slice(&data[..n]);
+        data.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MockTcpStream {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
 impl redis_starter_rust::redis::replication::TcpStreamTrait for MockTcpStream {
     fn peer_addr(&self) -> Result<std::net::SocketAddr> {
         Ok("127.0.0.1:6379".parse().unwrap())
     }
-
-    fn try_clone(&self) -> Result<Box<dyn redis_starter_rust::redis::replication::TcpStreamTrait>> {
-        Ok(Box::new(self.clone()))
-    }
 }
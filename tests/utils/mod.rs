@@ -1 +1,2 @@
 pub mod mock_tcp_stream;
+pub mod resp;
@@ -0,0 +1,98 @@
+// Shared by several test crates, each of which only uses part of it.
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use redis_starter_rust::client_handler::ClientHandler;
+use redis_starter_rust::redis::core::Redis;
+use super::mock_tcp_stream::MockTcpStream;
+
+/// Encodes a request the way a client sends it: an array of bulk strings.
+pub fn encode(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    out
+}
+
+/// Connects a client to a fresh handler on `redis`.
+pub fn start_client(redis: &Arc<Redis>) -> MockTcpStream {
+    let (client, server) = MockTcpStream::new_pair();
+    let mut handler = ClientHandler::new(server, Arc::clone(redis));
+    let _handle = handler.start();
+    client
+}
+
+/// Sends a command without waiting for its reply.
+pub fn send(client: &mut MockTcpStream, args: &[&str]) {
+    client.clear_read_data();
+    client.write_all(&encode(args)).unwrap();
+}
+
+/// Waits until `count` clients are blocked on `key` in database 0.
+pub fn wait_until_blocked(redis: &Redis, key: &str, count: usize) {
+    let start = Instant::now();
+    while redis.blocked.waiting(0, key.as_bytes()) != count {
+        assert!(start.elapsed() < Duration::from_secs(1), "{} clients never blocked on {}", count, key);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Sends one command and checks that its reply is exactly `expected`.
+pub fn assert_reply(client: &mut MockTcpStream, args: &[&str], expected: &str) {
+    client.clear_read_data();
+    client.write_all(&encode(args)).unwrap();
+    let reply = read_reply(client, 1000);
+    assert!(reply.as_deref() == Some(expected.as_bytes()), "{:?} should reply {:?}, got {:?}",
+        args, expected, reply.as_deref().map(String::from_utf8_lossy));
+}
+
+/// Waits for the next reply on `client`, for a command sent earlier, and checks that it is
+/// exactly `expected`.
+pub fn assert_next_reply(client: &MockTcpStream, expected: &str) {
+    let reply = read_reply(client, 1000);
+    assert!(reply.as_deref() == Some(expected.as_bytes()), "expected {:?}, got {:?}",
+        expected, reply.as_deref().map(String::from_utf8_lossy));
+}
+
+/// Waits for one complete reply and takes it off the stream. Anything that came after it must
+/// not be part of the same reply, so it's left for whoever reads next.
+pub fn read_reply(client: &MockTcpStream, timeout_ms: u64) -> Option<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    loop {
+        {
+            let mut data = client.read_data.lock().unwrap();
+            if let Some(len) = frame_len(&data) {
+                return Some(data.drain(..len).collect());
+            }
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// The length of the RESP value at the start of `data`, if all of it is there.
+fn frame_len(data: &[u8]) -> Option<usize> {
+    let line_end = data.windows(2).position(|window| window == b"\r\n")?;
+    let header = std::str::from_utf8(&data[1..line_end]).ok()?;
+    let rest = line_end + 2;
+    match data.first()? {
+        b'$' => match header.parse::<i64>().ok()? {
+            len if len < 0 => Some(rest),
+            len => (data.len() >= rest + len as usize + 2).then_some(rest + len as usize + 2),
+        },
+        b'*' => {
+            let mut end = rest;
+            for _ in 0..header.parse::<i64>().ok()?.max(0) {
+                end += frame_len(&data[end..])?;
+            }
+            Some(end)
+        },
+        _ => Some(rest),
+    }
+}
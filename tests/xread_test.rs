@@ -1,7 +1,6 @@
-use std::collections::HashMap;
-use std::thread;
 use std::time::{Duration, Instant};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use bytes::Bytes;
 
 use redis_starter_rust::redis::{
     core::Redis,
@@ -13,6 +12,7 @@ use redis_starter_rust::client_handler::ClientHandler;
 use crate::utils::mock_tcp_stream::MockTcpStream;
 
 mod utils;
+use utils::resp::{assert_next_reply, assert_reply, send, start_client, wait_until_blocked};
 
 // Core XRead Handler Tests
 #[test]
@@ -24,29 +24,30 @@ fn test_xread_parse_stream_id() {
     assert!(Storage::parse_stream_id("invalid").is_err());
 }
 
-#[test]
-fn test_xread_with_dollar_id() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_with_dollar_id() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Add some test data
     {
-        let redis_guard = redis.lock().unwrap();
-        let fields = HashMap::new();
-        redis_guard.storage.xadd("mystream", "1-0", fields).unwrap();
+        let redis_guard = &redis;
+        let fields = Vec::new();
+        redis_guard.db(0).xadd(b"mystream", "1-0", fields).unwrap();
         
-        let fields = HashMap::new();
-        redis_guard.storage.xadd("mystream", "2-0", fields).unwrap();
+        let fields = Vec::new();
+        redis_guard.db(0).xadd(b"mystream", "2-0", fields).unwrap();
     }
 
     let request = XReadRequest {
-        keys: vec!["mystream".to_string()],
+        db: 0,
+        keys: vec![Bytes::from("mystream")],
         ids: vec!["$".to_string()],
         block: None,
         count: None,
     };
 
     let mut handler = XReadHandler::new(redis.clone(), request);
-    let results = handler.run_loop().unwrap();
+    let results = handler.run_loop().await.unwrap();
     
     #[cfg(debug_assertions)]
     println!("[test_xread_with_dollar_id] results: {:?}", results);
@@ -55,32 +56,33 @@ fn test_xread_with_dollar_id() {
     assert!(results.is_empty());
 }
 
-#[test]
-fn test_xread_with_count_limit() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_with_count_limit() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Add test data
     {
-        let redis_guard = redis.lock().unwrap();
-        let fields = HashMap::new();
-        redis_guard.storage.xadd("mystream", "1-0", fields).unwrap();
+        let redis_guard = &redis;
+        let fields = Vec::new();
+        redis_guard.db(0).xadd(b"mystream", "1-0", fields).unwrap();
         
-        let fields = HashMap::new();
-        redis_guard.storage.xadd("mystream", "2-0", fields).unwrap();
+        let fields = Vec::new();
+        redis_guard.db(0).xadd(b"mystream", "2-0", fields).unwrap();
         
-        let fields = HashMap::new();
-        redis_guard.storage.xadd("mystream", "3-0", fields).unwrap();
+        let fields = Vec::new();
+        redis_guard.db(0).xadd(b"mystream", "3-0", fields).unwrap();
     }
 
     let request = XReadRequest {
-        keys: vec!["mystream".to_string()],
+        db: 0,
+        keys: vec![Bytes::from("mystream")],
         ids: vec!["0-0".to_string()],
         block: None,
         count: Some(2),
     };
 
     let mut handler = XReadHandler::new(redis.clone(), request);
-    let results = handler.run_loop().unwrap();
+    let results = handler.run_loop().await.unwrap();
     
     assert_eq!(results.len(), 1);
     let (_, entries) = &results[0];
@@ -89,11 +91,12 @@ fn test_xread_with_count_limit() {
     assert_eq!(entries[1].id, "2-0");
 }
 
-#[test]
-fn test_xread_blocking_timeout_handler_logic() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_blocking_timeout_handler_logic() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     let request = XReadRequest {
-        keys: vec!["mystream".to_string()],
+        db: 0,
+        keys: vec![Bytes::from("mystream")],
         ids: vec!["$".to_string()],
         block: Some(100), // 100ms timeout
         count: None,
@@ -101,7 +104,7 @@ fn test_xread_blocking_timeout_handler_logic() {
 
     let mut handler = XReadHandler::new(redis.clone(), request);
     let start = Instant::now();
-    let results = handler.run_loop().unwrap();
+    let results = handler.run_loop().await.unwrap();
     let elapsed = start.elapsed();
 
     #[cfg(debug_assertions)]
@@ -115,33 +118,32 @@ fn test_xread_blocking_timeout_handler_logic() {
     assert!(results.is_empty());
 }
 
-#[test]
-fn test_xread_multiple_streams() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::new())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_multiple_streams() {
+    let redis = Arc::new(Redis::new(RedisConfig::new()));
     
     // Add test data
     {
-        let redis_guard = redis.lock().unwrap();
-        let mut fields = HashMap::new();
-        fields.insert("field1".to_string(), "value1".to_string());
-        redis_guard.storage.xadd("stream1", "1-0", fields).unwrap();
+        let redis_guard = &redis;
+        let fields = vec![("field1".into(), "value1".into())];
+        redis_guard.db(0).xadd(b"stream1", "1-0", fields).unwrap();
         
-        let mut fields = HashMap::new();
-        fields.insert("field1".to_string(), "value2".to_string());
-        redis_guard.storage.xadd("stream2", "1-0", fields).unwrap();
+        let fields = vec![("field1".into(), "value2".into())];
+        redis_guard.db(0).xadd(b"stream2", "1-0", fields).unwrap();
     }
 
     // Test 1: Both streams have entries
     {
         let request = XReadRequest {
-            keys: vec!["stream1".to_string(), "stream2".to_string()],
+            db: 0,
+            keys: vec![Bytes::from("stream1"), Bytes::from("stream2")],
             ids: vec!["0-0".to_string(), "0-0".to_string()],
             block: None,
             count: None,
         };
 
         let mut handler = XReadHandler::new(redis.clone(), request);
-        let results = handler.run_loop().unwrap();
+        let results = handler.run_loop().await.unwrap();
 
         #[cfg(debug_assertions)]
         println!("[test_xread_multiple_streams::test1] Results: {:?}\n", results);
@@ -153,27 +155,28 @@ fn test_xread_multiple_streams() {
         assert_eq!(stream_name, "stream1");
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].id, "1-0");
-        assert_eq!(entries[0].fields["field1"], "value1");
+        assert_eq!(entries[0].get(b"field1").unwrap(), "value1");
         
         // Check second stream
         let (stream_name, entries) = &results[1];
         assert_eq!(stream_name, "stream2");
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].id, "1-0");
-        assert_eq!(entries[0].fields["field1"], "value2");
+        assert_eq!(entries[0].get(b"field1").unwrap(), "value2");
     }
 
     // Test 2: Only one stream has entries
     {
         let request = XReadRequest {
-            keys: vec!["stream1".to_string(), "stream2".to_string()],
+            db: 0,
+            keys: vec![Bytes::from("stream1"), Bytes::from("stream2")],
             ids: vec!["0-0".to_string(), "1-0".to_string()], // stream2 has no new entries after 1-0
             block: None,
             count: None,
         };
 
         let mut handler = XReadHandler::new(redis.clone(), request);
-        let results = handler.run_loop().unwrap();
+        let results = handler.run_loop().await.unwrap();
 
         #[cfg(debug_assertions)]
         println!("[test_xread_multiple_streams::test2] Results: {:?}\n", results);
@@ -185,20 +188,21 @@ fn test_xread_multiple_streams() {
         assert_eq!(stream_name, "stream1");
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].id, "1-0");
-        assert_eq!(entries[0].fields["field1"], "value1");
+        assert_eq!(entries[0].get(b"field1").unwrap(), "value1");
     }
 
     // Test 3: No streams have entries
     {
         let request = XReadRequest {
-            keys: vec!["stream1".to_string(), "stream2".to_string()],
+            db: 0,
+            keys: vec![Bytes::from("stream1"), Bytes::from("stream2")],
             ids: vec!["1-0".to_string(), "1-0".to_string()], // No new entries after 1-0
             block: None,
             count: None,
         };
 
         let mut handler = XReadHandler::new(redis.clone(), request);
-        let results = handler.run_loop().unwrap();
+        let results = handler.run_loop().await.unwrap();
 
         #[cfg(debug_assertions)]
         println!("[test_xread_multiple_streams::test3] Results: {:?}\n", results);
@@ -208,23 +212,21 @@ fn test_xread_multiple_streams() {
 }
 
 // Protocol-Level XREAD Tests
-#[test]
-fn test_xread_blocking_with_new_data() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
-    let redis_clone = Arc::clone(&redis);
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_blocking_with_new_data() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
 
     // Add initial data that should not be returned
     {
-        let mut fields = std::collections::HashMap::new();
-        fields.insert("field1".to_string(), "old_value".to_string());
-        let redis = redis.lock().unwrap();
-        redis.storage.xadd("mystream", "*", fields).unwrap();
+        let fields = vec![("field1".into(), "old_value".into())];
+        let redis = &redis;
+        redis.db(0).xadd(b"mystream", "*", fields).unwrap();
     }
 
     // Create mock stream
     let stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(stream.clone(), redis.clone());
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Write XREAD command with BLOCK option
     let xread_command = "*6\r\n$5\r\nXREAD\r\n$5\r\nBLOCK\r\n$4\r\n1000\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
@@ -233,15 +235,10 @@ fn test_xread_blocking_with_new_data() {
         read_data.extend_from_slice(xread_command.as_bytes());
     }
 
-    // Start a thread that will add new data after a delay
-    thread::spawn(move || {
-        thread::sleep(Duration::from_millis(100));
-        let mut fields = std::collections::HashMap::new();
-        fields.insert("field1".to_string(), "new_value".to_string());
-        
-        let redis = redis_clone.lock().unwrap();
-        redis.storage.xadd("mystream", "*", fields).unwrap();
-    });
+    // Another client adds new data once the XREAD is blocked
+    wait_until_blocked(&redis, "mystream", 1);
+    let mut writer = start_client(&redis);
+    send(&mut writer, &["XADD", "mystream", "*", "field1", "new_value"]);
 
     // Wait for response
     assert!(stream.wait_for_write("*1\r\n", 1000), 
@@ -261,14 +258,14 @@ fn test_xread_blocking_with_new_data() {
     stream.shutdown();
 }
 
-#[test]
-fn test_xread_non_blocking_empty() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_non_blocking_empty() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
 
     // Create mock stream
     let stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(stream.clone(), redis.clone());
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Write non-blocking XREAD command
     let xread_command = "*4\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
@@ -285,14 +282,14 @@ fn test_xread_non_blocking_empty() {
     stream.shutdown();
 }
 
-#[test]
-fn test_xread_blocking_timeout() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_blocking_timeout() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
 
     // Create mock stream
     let stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(stream.clone(), redis.clone());
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Write XREAD command with BLOCK option
     let xread_command = "*6\r\n$5\r\nXREAD\r\n$5\r\nBLOCK\r\n$3\r\n100\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
@@ -309,71 +306,50 @@ fn test_xread_blocking_timeout() {
     stream.shutdown();
 }
 
-#[test]
-fn test_xread_blocking_multiple_streams_protocol() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
-    let redis_clone = Arc::clone(&redis);
-
-    // Create mock stream
-    let stream = MockTcpStream::new();
-    let mut client_handler = ClientHandler::new(stream.clone(), redis.clone());
-    let handle = client_handler.start();
-
-    // Write XREAD command with multiple streams
-    let xread_command = "*8\r\n$5\r\nXREAD\r\n$5\r\nBLOCK\r\n$4\r\n1000\r\n$7\r\nSTREAMS\r\n$7\r\nstream1\r\n$7\r\nstream2\r\n$1\r\n$\r\n$1\r\n$\r\n";
-    {
-        let mut read_data = stream.read_data.lock().unwrap();
-        read_data.extend_from_slice(xread_command.as_bytes());
-    }
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_blocking_multiple_streams_protocol() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut reader = start_client(&redis);
+    send(&mut reader, &["XREAD", "BLOCK", "1000", "STREAMS", "stream1", "stream2", "$", "$"]);
 
-    // Start a thread that will add data after a delay
-    thread::spawn(move || {
-        thread::sleep(Duration::from_millis(100));
-        let mut fields = std::collections::HashMap::new();
-        
-        let redis = redis_clone.lock().unwrap();
-        
-        // Add to first stream
-        fields.insert("field1".to_string(), "value1".to_string());
-        redis.storage.xadd("stream1", "*", fields.clone()).unwrap();
-        
-        // Add to second stream
-        fields.insert("field1".to_string(), "value2".to_string());
-        redis.storage.xadd("stream2", "*", fields).unwrap();
-    });
+    // An XADD to the second stream wakes the reader too
+    wait_until_blocked(&redis, "stream2", 1);
+    let mut writer = start_client(&redis);
+    send(&mut writer, &["XADD", "stream2", "1-1", "field1", "value2"]);
 
-    // Wait for response
-    assert!(stream.wait_for_write("*2\r\n", 1000) || 
-           stream.wait_for_write("*-1\r\n", 1000), 
-        "Response should be either array of two streams or nil");
+    assert_next_reply(&reader, "*1\r\n*2\r\n$7\r\nstream2\r\n*1\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$6\r\nfield1\r\n$6\r\nvalue2\r\n");
+    reader.shutdown();
+}
 
-    // Check the response
-    let written_data = stream.get_written_data();
-    let response = String::from_utf8_lossy(&written_data);
-    
-    // If we got data, verify the format
-    if response != "*-1\r\n" {
-        assert!(response.contains("stream1"), "Response should contain stream1");
-        assert!(response.contains("stream2"), "Response should contain stream2");
-        assert!(response.contains("value1"), "Response should contain value1");
-        assert!(response.contains("value2"), "Response should contain value2");
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_block_wakes_every_reader_in_order() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut readers: Vec<MockTcpStream> = (0..3).map(|_| start_client(&redis)).collect();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        send(reader, &["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]);
+        wait_until_blocked(&redis, "s", i + 1);
     }
 
-    // Clean shutdown
-    stream.shutdown();
+    // Reading doesn't take the entry away, so one XADD is passed down to every reader.
+    let mut writer = start_client(&redis);
+    assert_reply(&mut writer, &["XADD", "s", "1-1", "f", "v"], "$3\r\n1-1\r\n");
+    for reader in &readers {
+        assert_next_reply(reader, "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n");
+    }
+    assert_eq!(redis.blocked.waiting(0, b"s"), 0);
 }
 
-#[test]
-fn test_xread_empty_vs_nil_response() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_empty_vs_nil_response() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
 
     // Create mock stream
     let stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(stream.clone(), redis.clone());
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Test 1: Non-blocking read with $ (should return nil)
-    let xread_command = "*5\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
+    let xread_command = "*4\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
     {
         let mut read_data = stream.read_data.lock().unwrap();
         read_data.extend_from_slice(xread_command.as_bytes());
@@ -386,7 +362,7 @@ fn test_xread_empty_vs_nil_response() {
     stream.clear_read_data();
 
     // Test 2: Non-blocking read with non-existent ID (should also return nil)
-    let xread_command = "*5\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$3\r\n0-0\r\n";
+    let xread_command = "*4\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$3\r\n0-0\r\n";
     {
         let mut read_data = stream.read_data.lock().unwrap();
         read_data.extend_from_slice(xread_command.as_bytes());
@@ -411,17 +387,17 @@ fn test_xread_empty_vs_nil_response() {
     stream.shutdown();
 }
 
-#[test]
-fn test_xread_empty_responses() {
-    let redis = Arc::new(Mutex::new(Redis::new(RedisConfig::default())));
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xread_empty_responses() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
 
     // Create mock stream
     let stream = MockTcpStream::new();
     let mut client_handler = ClientHandler::new(stream.clone(), redis.clone());
-    let handle = client_handler.start();
+    let _handle = client_handler.start();
 
     // Test 1: Non-blocking read with $ (should return nil)
-    let xread_command = "*5\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
+    let xread_command = "*4\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$1\r\n$\r\n";
     {
         let mut read_data = stream.read_data.lock().unwrap();
         read_data.extend_from_slice(xread_command.as_bytes());
@@ -434,7 +410,7 @@ fn test_xread_empty_responses() {
     stream.clear_read_data();
 
     // Test 2: Non-blocking read with non-existent ID (should also return nil)
-    let xread_command = "*5\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$3\r\n0-0\r\n";
+    let xread_command = "*4\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$8\r\nmystream\r\n$3\r\n0-0\r\n";
     {
         let mut read_data = stream.read_data.lock().unwrap();
         read_data.extend_from_slice(xread_command.as_bytes());
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use redis_starter_rust::redis::core::Redis;
+use redis_starter_rust::redis::config::RedisConfig;
+use redis_starter_rust::redis::replication::{Connection, ReplicationManager};
+
+mod utils;
+use utils::mock_tcp_stream::MockTcpStream;
+use utils::resp::{assert_reply, encode, start_client};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_zadd_flags_and_scores() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["ZADD", "board", "10", "ada", "20", "bob"], ":2\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "NX", "5", "ada", "30", "cy"], ":1\r\n");
+    assert_reply(&mut client, &["ZSCORE", "board", "ada"], "$2\r\n10\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "XX", "CH", "15", "ada", "1", "nobody"], ":1\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "GT", "CH", "12", "ada", "25", "bob"], ":1\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "LT", "1", "cy"], ":0\r\n");
+    assert_reply(&mut client, &["ZMSCORE", "board", "ada", "bob", "cy", "nobody"],
+        "*4\r\n$2\r\n15\r\n$2\r\n25\r\n$1\r\n1\r\n$-1\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "INCR", "2.5", "ada"], "$4\r\n17.5\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "INCR", "NX", "1", "ada"], "$-1\r\n");
+    assert_reply(&mut client, &["ZINCRBY", "board", "-0.5", "ada"], "$2\r\n17\r\n");
+    assert_reply(&mut client, &["ZINCRBY", "board", "+inf", "new"], "$3\r\ninf\r\n");
+    assert_reply(&mut client, &["ZINCRBY", "board", "-inf", "new"], "-ERR resulting score is not a number (NaN)\r\n");
+    assert_reply(&mut client, &["ZCARD", "board"], ":4\r\n");
+    assert_reply(&mut client, &["ZCOUNT", "board", "(1", "25"], ":2\r\n");
+    assert_reply(&mut client, &["TYPE", "board"], "$4\r\nzset\r\n");
+    assert_eq!(redis.db(0).keys(b"*").len(), 1);
+
+    // Scores are replied to the way Redis formats doubles, %.17g
+    assert_reply(&mut client, &["ZADD", "huge", "1e300", "x", "-0.00001", "y"], ":2\r\n");
+    assert_reply(&mut client, &["ZSCORE", "huge", "x"], "$23\r\n1.0000000000000001e+300\r\n");
+    assert_reply(&mut client, &["ZSCORE", "huge", "y"], "$23\r\n-1.0000000000000001e-05\r\n");
+
+    assert_reply(&mut client, &["ZADD", "board", "NX", "XX", "1", "a"], "-ERR XX and NX options at the same time are not compatible\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "GT", "LT", "1", "a"], "-ERR GT, LT, and/or NX options at the same time are not compatible\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "INCR", "1", "a", "2", "b"], "-ERR INCR option supports a single increment-element pair\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "abc", "a"], "-ERR value is not a valid float\r\n");
+    assert_reply(&mut client, &["ZADD", "board", "1", "a", "2"], "-ERR syntax error\r\n");
+
+    assert_reply(&mut client, &["ZREM", "board", "ada", "bob", "nobody"], ":2\r\n");
+    assert_reply(&mut client, &["ZREM", "board", "cy", "new"], ":2\r\n");
+    assert_reply(&mut client, &["TYPE", "board"], "$4\r\nnone\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_zrange_variants_and_rank() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d"], ":4\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "0", "1"], "*2\r\n$1\r\na\r\n$1\r\nb\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "-1", "-1", "WITHSCORES"], "*2\r\n$1\r\nd\r\n$1\r\n4\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "0", "0", "REV"], "*1\r\n$1\r\nd\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "(1", "3", "BYSCORE"], "*2\r\n$1\r\nb\r\n$1\r\nc\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "+inf", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2"],
+        "*2\r\n$1\r\nc\r\n$1\r\nb\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"],
+        "-ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX\r\n");
+    assert_reply(&mut client, &["ZRANGE", "z", "x", "1", "BYSCORE"], "-ERR min or max is not a float\r\n");
+
+    assert_reply(&mut client, &["ZADD", "names", "0", "apple", "0", "banana", "0", "cherry"], ":3\r\n");
+    assert_reply(&mut client, &["ZRANGE", "names", "[b", "+", "BYLEX"], "*2\r\n$6\r\nbanana\r\n$6\r\ncherry\r\n");
+    assert_reply(&mut client, &["ZRANGE", "names", "(cherry", "-", "BYLEX", "REV"], "*2\r\n$6\r\nbanana\r\n$5\r\napple\r\n");
+    assert_reply(&mut client, &["ZRANGE", "names", "b", "+", "BYLEX"], "-ERR min or max not valid string range item\r\n");
+    assert_reply(&mut client, &["ZRANGE", "names", "-", "+", "BYLEX", "WITHSCORES"],
+        "-ERR syntax error, WITHSCORES not supported in combination with BYLEX\r\n");
+
+    assert_reply(&mut client, &["ZRANK", "z", "c"], ":2\r\n");
+    assert_reply(&mut client, &["ZREVRANK", "z", "c"], ":1\r\n");
+    assert_reply(&mut client, &["ZRANK", "z", "c", "WITHSCORE"], "*2\r\n:2\r\n$1\r\n3\r\n");
+    assert_reply(&mut client, &["ZRANK", "z", "missing"], "$-1\r\n");
+
+    assert_reply(&mut client, &["ZRANGESTORE", "top", "z", "2", "+inf", "BYSCORE", "LIMIT", "0", "2"], ":2\r\n");
+    assert_reply(&mut client, &["ZRANGE", "top", "0", "-1", "WITHSCORES"], "*4\r\n$1\r\nb\r\n$1\r\n2\r\n$1\r\nc\r\n$1\r\n3\r\n");
+    assert_reply(&mut client, &["ZRANGESTORE", "top", "z", "10", "20"], ":0\r\n");
+    assert_reply(&mut client, &["TYPE", "top"], "$4\r\nnone\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_zpop_and_store_operations() {
+    let redis = Arc::new(Redis::new(RedisConfig::default()));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["ZADD", "q", "1", "a", "2", "b", "3", "c"], ":3\r\n");
+    assert_reply(&mut client, &["ZPOPMIN", "q"], "*2\r\n$1\r\na\r\n$1\r\n1\r\n");
+    assert_reply(&mut client, &["ZPOPMAX", "q", "5"], "*4\r\n$1\r\nc\r\n$1\r\n3\r\n$1\r\nb\r\n$1\r\n2\r\n");
+    assert_reply(&mut client, &["ZPOPMIN", "q"], "*0\r\n");
+    assert_reply(&mut client, &["TYPE", "q"], "$4\r\nnone\r\n");
+
+    assert_reply(&mut client, &["ZADD", "x", "1", "a", "2", "b"], ":2\r\n");
+    assert_reply(&mut client, &["ZADD", "y", "10", "b", "20", "c"], ":2\r\n");
+    assert_reply(&mut client, &["SADD", "s", "a", "c"], ":2\r\n");
+    assert_reply(&mut client, &["ZUNIONSTORE", "u", "2", "x", "y"], ":3\r\n");
+    assert_reply(&mut client, &["ZRANGE", "u", "0", "-1", "WITHSCORES"],
+        "*6\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nb\r\n$2\r\n12\r\n$1\r\nc\r\n$2\r\n20\r\n");
+    assert_reply(&mut client, &["ZINTERSTORE", "i", "2", "x", "y", "WEIGHTS", "2", "0.5", "AGGREGATE", "MAX"], ":1\r\n");
+    assert_reply(&mut client, &["ZSCORE", "i", "b"], "$1\r\n5\r\n");
+    assert_reply(&mut client, &["ZUNIONSTORE", "u", "2", "x", "s", "AGGREGATE", "MIN"], ":3\r\n");
+    assert_reply(&mut client, &["ZRANGE", "u", "0", "-1", "WITHSCORES"],
+        "*6\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nc\r\n$1\r\n1\r\n$1\r\nb\r\n$1\r\n2\r\n");
+    assert_reply(&mut client, &["ZINTERSTORE", "i", "2", "x", "missing"], ":0\r\n");
+    assert_reply(&mut client, &["TYPE", "i"], "$4\r\nnone\r\n");
+
+    assert_reply(&mut client, &["ZUNIONSTORE", "u", "0", "x"], "-ERR at least 1 input key is needed for 'zunionstore' command\r\n");
+    assert_reply(&mut client, &["ZUNIONSTORE", "u", "2", "x", "y", "WEIGHTS", "1", "nan"], "-ERR weight value is not a float\r\n");
+    assert_reply(&mut client, &["ZUNIONSTORE", "u", "1", "x", "AGGREGATE", "AVG"], "-ERR syntax error\r\n");
+    assert_reply(&mut client, &["SET", "str", "v"], "+OK\r\n");
+    assert_reply(&mut client, &["ZUNIONSTORE", "u", "2", "x", "str"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    assert_reply(&mut client, &["ZADD", "str", "1", "a"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+    client.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_zset_writes_are_replicated() {
+    let manager = ReplicationManager::new();
+    let (replica_stream, replica_server) = MockTcpStream::new_pair();
+    manager.add_replica("localhost".to_string(), "6379".to_string(), Connection::new(replica_stream).unwrap());
+    let redis = Arc::new(Redis::new_with_replication(manager));
+    let mut client = start_client(&redis);
+
+    assert_reply(&mut client, &["ZADD", "z", "1", "a"], ":1\r\n");
+    assert_reply(&mut client, &["ZADD", "z", "1", "a"], ":0\r\n");
+    assert_reply(&mut client, &["ZSCORE", "z", "a"], "$1\r\n1\r\n");
+    assert_reply(&mut client, &["ZINCRBY", "z", "2", "a"], "$1\r\n3\r\n");
+    assert_reply(&mut client, &["ZPOPMIN", "z"], "*2\r\n$1\r\na\r\n$1\r\n3\r\n");
+    assert_reply(&mut client, &["ZPOPMIN", "z"], "*0\r\n");
+    redis.replication.send_pending_commands();
+
+    let mut expected = encode(&["ZADD", "z", "1", "a"]);
+    expected.extend(encode(&["ZINCRBY", "z", "2", "a"]));
+    expected.extend(encode(&["ZPOPMIN", "z"]));
+    assert!(replica_server.wait_for_pattern(&String::from_utf8(expected.clone()).unwrap(), 1000));
+    assert_eq!(*replica_server.read_data.lock().unwrap(), expected);
+    client.shutdown();
+}